@@ -0,0 +1,450 @@
+//! Run with `cargo bench`. Measures how long a proposal takes end to end
+//! against acceptors served over loopback tcp, how many requests one acceptor
+//! handles per second and how much persisting its state costs for different
+//! value sizes.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use futures::StreamExt;
+use single_decree_paxos::{
+    auth::{Credentials, Signed},
+    instance::InstanceId,
+    instances::Instances,
+    latency::LatencySummary,
+    lease::{HeartbeatRequest, HeartbeatResponse},
+    membership::MemberUpdate,
+    paxos::{
+        AcceptChunk, AcceptRequest, AcceptResponse, AcceptedProposal, AcceptorError,
+        AcceptorService, Digest, Health, Hello, HelloResponse, Paxos, PaxosBuilder, PrepareRequest,
+        PrepareResponse, RelayAcceptRequest, RelayLearnRequest, RelayedAcceptResponse, StateDump,
+        StateTransfer,
+    },
+    proposal::ProposalId,
+    rejoin::{Announced, Announcement},
+    transport,
+};
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tarpc::{
+    context,
+    server::{self, Channel},
+};
+use tokio::{net::TcpListener, runtime::Runtime, sync::Mutex};
+
+const ACCEPTORS: usize = 3;
+
+/// Serves the acceptors of every instance the benchmarks propose in.
+#[derive(Clone)]
+struct BenchServer {
+    instances: Instances,
+}
+
+impl BenchServer {
+    async fn acceptor(&self, instance: &InstanceId) -> Result<Arc<Mutex<Paxos>>, AcceptorError> {
+        self.instances
+            .get(instance)
+            .await
+            .map_err(AcceptorError::from)
+    }
+}
+
+#[tarpc::server]
+impl AcceptorService for BenchServer {
+    async fn hello(
+        self,
+        _: context::Context,
+        request: Hello,
+    ) -> Result<HelloResponse, AcceptorError> {
+        request.answer()
+    }
+
+    async fn prepare(
+        self,
+        _: context::Context,
+        _: Credentials,
+        request: PrepareRequest,
+    ) -> Result<Signed<PrepareResponse>, AcceptorError> {
+        let sealed = request.clone();
+        let acceptor = self.acceptor(&request.instance).await?;
+        let mut acceptor = acceptor.lock().await;
+        let response = acceptor.on_prepare(request).await?;
+        Ok(acceptor.seal("prepare", &sealed, response))
+    }
+
+    async fn accept(
+        self,
+        _: context::Context,
+        _: Credentials,
+        request: AcceptRequest,
+    ) -> Result<Signed<AcceptResponse>, AcceptorError> {
+        let sealed = request.clone();
+        let acceptor = self.acceptor(&request.instance).await?;
+        let mut acceptor = acceptor.lock().await;
+        let response = acceptor.on_accept(request).await?;
+        Ok(acceptor.seal("accept", &sealed, response))
+    }
+
+    async fn accept_chunk(
+        self,
+        _: context::Context,
+        _: Credentials,
+        chunk: AcceptChunk,
+    ) -> Result<Signed<Option<AcceptResponse>>, AcceptorError> {
+        let sealed = chunk.clone();
+        let acceptor = self.acceptor(&chunk.instance).await?;
+        let mut acceptor = acceptor.lock().await;
+        let response = acceptor.on_accept_chunk(chunk).await?;
+        Ok(acceptor.seal("accept_chunk", &sealed, response))
+    }
+
+    async fn relay_accept(
+        self,
+        _: context::Context,
+        _: Credentials,
+        request: RelayAcceptRequest,
+    ) -> Result<Vec<RelayedAcceptResponse>, AcceptorError> {
+        let acceptor = self.acceptor(&request.accept.instance).await?;
+        let forward = acceptor.lock().await.begin_relay_accept(request).await;
+        Ok(forward.run().await)
+    }
+
+    async fn relay_learn(
+        self,
+        _: context::Context,
+        _: Credentials,
+        request: RelayLearnRequest,
+    ) -> Result<(), AcceptorError> {
+        let acceptor = self.acceptor(&request.decided.instance).await?;
+        let forward = acceptor.lock().await.begin_relay_learn(request).await?;
+        tokio::spawn(forward.run());
+        Ok(())
+    }
+
+    async fn digest(
+        self,
+        _: context::Context,
+        _: Credentials,
+        instance: InstanceId,
+    ) -> Result<Digest, AcceptorError> {
+        let acceptor = self.acceptor(&instance).await?;
+        let digest = acceptor.lock().await.on_digest();
+        Ok(digest)
+    }
+
+    async fn fetch_decided(
+        self,
+        _: context::Context,
+        _: Credentials,
+        instance: InstanceId,
+    ) -> Result<Signed<Option<Vec<u8>>>, AcceptorError> {
+        let acceptor = self.acceptor(&instance).await?;
+        let acceptor = acceptor.lock().await;
+        let value = acceptor.on_fetch_decided();
+        Ok(acceptor.seal("fetch_decided", &instance, value))
+    }
+
+    async fn fetch_accepted(
+        self,
+        _: context::Context,
+        _: Credentials,
+        instance: InstanceId,
+    ) -> Result<Signed<AcceptedProposal>, AcceptorError> {
+        let acceptor = self.acceptor(&instance).await?;
+        let acceptor = acceptor.lock().await;
+        let accepted = acceptor.on_fetch_accepted()?;
+        Ok(acceptor.seal("fetch_accepted", &instance, accepted))
+    }
+
+    async fn fetch_state(
+        self,
+        _: context::Context,
+        _: Credentials,
+        from: InstanceId,
+    ) -> Result<Signed<StateTransfer>, AcceptorError> {
+        // The bench acceptors have no message key, answers go unsealed.
+        let transfer = self.instances.fetch_state(&from).await?;
+        Ok(Signed {
+            value: transfer,
+            mac: None,
+            unsealed: false,
+        })
+    }
+
+    async fn latencies(
+        self,
+        _: context::Context,
+        _: Credentials,
+    ) -> Result<HashMap<SocketAddr, LatencySummary>, AcceptorError> {
+        let acceptor = self.acceptor(&InstanceId::default()).await?;
+        let latencies = acceptor.lock().await.on_latencies();
+        Ok(latencies)
+    }
+
+    async fn health(self, _: context::Context, _: Credentials) -> Result<Health, AcceptorError> {
+        let acceptor = self.acceptor(&InstanceId::default()).await?;
+        let health = acceptor.lock().await.on_health();
+        Ok(health)
+    }
+
+    async fn dump_state(
+        self,
+        _: context::Context,
+        _: Credentials,
+        instance: InstanceId,
+    ) -> Result<StateDump, AcceptorError> {
+        let acceptor = self.acceptor(&instance).await?;
+        let dump = acceptor.lock().await.on_dump_state();
+        Ok(dump)
+    }
+
+    async fn reset_state(
+        self,
+        _: context::Context,
+        _: Credentials,
+        _: InstanceId,
+        _: String,
+    ) -> Result<(), AcceptorError> {
+        Err(AcceptorError::rejected("resetting state is disabled"))
+    }
+
+    async fn heartbeat(
+        self,
+        _: context::Context,
+        _: Credentials,
+        request: HeartbeatRequest,
+    ) -> Result<HeartbeatResponse, AcceptorError> {
+        let acceptor = self.acceptor(&request.instance).await?;
+        let mut acceptor = acceptor.lock().await;
+        acceptor
+            .on_heartbeat(request)
+            .await
+            .map_err(AcceptorError::from)
+    }
+
+    async fn ping(
+        self,
+        _: context::Context,
+        _: Credentials,
+        _: Vec<MemberUpdate>,
+    ) -> Result<Vec<MemberUpdate>, AcceptorError> {
+        Err(AcceptorError::rejected("failure detection is disabled"))
+    }
+
+    async fn ping_req(
+        self,
+        _: context::Context,
+        _: Credentials,
+        _: SocketAddr,
+        _: Vec<MemberUpdate>,
+    ) -> Result<Vec<MemberUpdate>, AcceptorError> {
+        Err(AcceptorError::rejected("failure detection is disabled"))
+    }
+
+    async fn create_instance(
+        self,
+        _: context::Context,
+        _: Credentials,
+        instance: InstanceId,
+    ) -> Result<(), AcceptorError> {
+        self.instances
+            .create(&instance)
+            .await
+            .map_err(AcceptorError::from)?;
+        Ok(())
+    }
+
+    async fn announce(
+        self,
+        _: context::Context,
+        _: Credentials,
+        _: Announcement,
+    ) -> Result<Announced, AcceptorError> {
+        Err(AcceptorError::rejected("rejoin is disabled"))
+    }
+}
+
+fn data_dir(name: &str) -> PathBuf {
+    let data_dir = std::env::temp_dir().join(format!("paxos-bench-{name}-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&data_dir);
+    std::fs::create_dir_all(&data_dir).unwrap();
+    data_dir
+}
+
+async fn free_addr() -> SocketAddr {
+    TcpListener::bind("127.0.0.1:0")
+        .await
+        .unwrap()
+        .local_addr()
+        .unwrap()
+}
+
+/// Starts the acceptors on loopback and returns their addresses.
+async fn start_cluster(data_dir: &Path) -> Vec<SocketAddr> {
+    let mut acceptors = Vec::with_capacity(ACCEPTORS);
+    for _ in 0..ACCEPTORS {
+        acceptors.push(free_addr().await);
+    }
+
+    for (i, addr) in acceptors.iter().enumerate() {
+        let instances = Instances::new(
+            Paxos::builder(i as u32 + 1, *addr, acceptors.clone()).data_dir(data_dir),
+        );
+        let listener = transport::listen(*addr, None).await.unwrap();
+
+        tokio::spawn(
+            listener
+                .map(|connection| server::BaseChannel::with_defaults(transport::framed(connection)))
+                .map(move |channel| {
+                    let server = BenchServer {
+                        instances: instances.clone(),
+                    };
+                    channel.execute(server.serve())
+                })
+                .buffer_unordered(16)
+                .for_each(|_| async {}),
+        );
+    }
+
+    acceptors
+}
+
+/// A proposer outside the cluster, so every request it sends goes over the network.
+fn proposer(acceptors: &[SocketAddr], data_dir: &Path) -> PaxosBuilder {
+    Paxos::builder(
+        1000,
+        SocketAddr::from(([127, 0, 0, 1], 1)),
+        acceptors.to_vec(),
+    )
+    .data_dir(data_dir)
+}
+
+fn propose_latency(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let data_dir = data_dir("propose");
+    let acceptors = runtime.block_on(start_cluster(&data_dir));
+    let builder = proposer(&acceptors, &data_dir);
+
+    let mut group = c.benchmark_group("propose");
+    for size in [16, 1024, 64 * 1024] {
+        group.throughput(Throughput::Bytes(size as u64));
+        group.bench_with_input(BenchmarkId::new("loopback", size), &size, |b, &size| {
+            let mut next = 0u64;
+            b.iter_custom(|iters| {
+                let first = next;
+                next += iters;
+
+                runtime.block_on(async {
+                    let mut elapsed = Duration::ZERO;
+                    for i in first..first + iters {
+                        // Every proposal is made in an instance of its own,
+                        // a decided instance would answer without a round.
+                        let mut node = builder
+                            .clone()
+                            .instance(InstanceId::new(format!("bench-{size}-{i}")).unwrap())
+                            .build()
+                            .await
+                            .unwrap();
+
+                        // Connect before starting the clock.
+                        node.cluster_health().await;
+
+                        let started_at = Instant::now();
+                        node.propose(vec![0; size]).await.unwrap();
+                        elapsed += started_at.elapsed();
+                    }
+                    elapsed
+                })
+            });
+        });
+    }
+    group.finish();
+
+    let _ = std::fs::remove_dir_all(&data_dir);
+}
+
+async fn acceptor(data_dir: &Path) -> Paxos {
+    let addr = SocketAddr::from(([127, 0, 0, 1], 1));
+    Paxos::builder(1, addr, vec![addr])
+        .data_dir(data_dir)
+        .build()
+        .await
+        .unwrap()
+}
+
+fn acceptor_throughput(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let data_dir = data_dir("acceptor");
+    let mut paxos = runtime.block_on(acceptor(&data_dir));
+    let mut proposal_id = ProposalId::ZERO;
+
+    let mut group = c.benchmark_group("acceptor");
+    group.throughput(Throughput::Elements(1));
+
+    group.bench_function("prepare", |b| {
+        b.iter(|| {
+            proposal_id = proposal_id.next();
+            let request = PrepareRequest {
+                instance: InstanceId::default(),
+                proposal_id,
+                proposer: None,
+            };
+            runtime.block_on(paxos.on_prepare(request)).unwrap()
+        });
+    });
+
+    group.bench_function("accept", |b| {
+        b.iter(|| {
+            proposal_id = proposal_id.next();
+            let request = AcceptRequest {
+                instance: InstanceId::default(),
+                proposal_id,
+                proposal_value: b"value".to_vec(),
+            };
+            runtime.block_on(paxos.on_accept(request)).unwrap()
+        });
+    });
+
+    group.finish();
+
+    let _ = std::fs::remove_dir_all(&data_dir);
+}
+
+/// Accepting a value rewrites and syncs the state file, so this is mostly the
+/// cost of persisting values of each size.
+fn persistence(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let data_dir = data_dir("persistence");
+    let mut paxos = runtime.block_on(acceptor(&data_dir));
+    let mut proposal_id = ProposalId::ZERO;
+
+    let mut group = c.benchmark_group("persist");
+    for size in [16, 1024, 64 * 1024, 1024 * 1024] {
+        let value = vec![0; size];
+        group.throughput(Throughput::Bytes(size as u64));
+        group.bench_with_input(BenchmarkId::new("accept", size), &value, |b, value| {
+            b.iter(|| {
+                proposal_id = proposal_id.next();
+                let request = AcceptRequest {
+                    instance: InstanceId::default(),
+                    proposal_id,
+                    proposal_value: value.clone(),
+                };
+                runtime.block_on(paxos.on_accept(request)).unwrap()
+            });
+        });
+    }
+    group.finish();
+
+    let _ = std::fs::remove_dir_all(&data_dir);
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().measurement_time(Duration::from_secs(10));
+    targets = propose_latency, acceptor_throughput, persistence
+}
+criterion_main!(benches);
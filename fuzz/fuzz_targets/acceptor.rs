@@ -0,0 +1,160 @@
+//! Drives an acceptor with arbitrary prepare and accept requests, values
+//! included, and keeps its state file in memory the way the node writes it.
+//! After every request the acceptor's invariants must hold and an acceptor
+//! restarted from the file must know what the running one does.
+//!
+//! Run with `cargo +nightly fuzz run acceptor` from the repository root.
+
+#![no_main]
+
+use libfuzzer_sys::{arbitrary::Arbitrary, fuzz_target};
+use single_decree_paxos::{
+    format,
+    paxos::PromisePolicy,
+    proposal::ProposalId,
+    protocol::{Acceptor, Persist},
+};
+
+#[derive(Debug, Arbitrary)]
+struct Input {
+    greater_or_equal: bool,
+    witness: bool,
+    requests: Vec<Request>,
+}
+
+#[derive(Debug, Clone, Arbitrary)]
+enum Request {
+    Prepare(u64),
+    Accept(u64, Value),
+    Learn(u64, Value),
+    /// Delivers the previous request again.
+    Duplicate,
+}
+
+#[derive(Debug, Clone, Arbitrary)]
+enum Value {
+    Bytes(Vec<u8>),
+    /// Values larger than the fuzzer's inputs, up to 1 MiB.
+    Repeated(u8, u16),
+}
+
+impl Value {
+    fn bytes(&self) -> Vec<u8> {
+        match self {
+            Value::Bytes(bytes) => bytes.clone(),
+            Value::Repeated(byte, len) => vec![*byte; usize::from(*len) << 4],
+        }
+    }
+}
+
+/// The state file, written the way [single_decree_paxos::paxos::Paxos] writes
+/// it: promises over the start of the file, states over all of it.
+#[derive(Default)]
+struct Storage {
+    file: Vec<u8>,
+}
+
+impl Storage {
+    fn persist(&mut self, acceptor: &Acceptor, persist: Persist) {
+        match persist {
+            Persist::Nothing => {}
+            Persist::Promise => {
+                let promise = format::encode_promise(acceptor.promised);
+                if self.file.len() < promise.len() {
+                    self.file.resize(promise.len(), 0);
+                }
+                self.file[..promise.len()].copy_from_slice(&promise);
+            }
+            Persist::State => self.file = format::encode(&acceptor.state()),
+        }
+    }
+
+    fn restart(&self, acceptor: &Acceptor) -> Acceptor {
+        let (state, _) = format::decode(&self.file).expect("the acceptor wrote a valid state file");
+        Acceptor::restore(acceptor.policy, acceptor.witness, state)
+    }
+}
+
+fn apply(acceptor: &mut Acceptor, request: &Request) -> Persist {
+    let before = acceptor.clone();
+    match request {
+        Request::Prepare(id) => {
+            let id = ProposalId::new(*id);
+            let step = acceptor.on_prepare(id);
+            assert_eq!(step.response.proposal_id, acceptor.promised);
+            assert_eq!(step.response.accepted_id, acceptor.accepted_id);
+            if step.response.promised {
+                assert!(id >= before.promised);
+            }
+            step.persist
+        }
+        Request::Accept(id, value) => {
+            let id = ProposalId::new(*id);
+            let value = value.bytes();
+            let step = acceptor.on_accept(id, value.clone());
+            if step.persist == Persist::State {
+                assert!(id >= before.promised);
+                assert_eq!(acceptor.accepted_id, id);
+                assert_eq!(acceptor.accepted_value, (!acceptor.witness).then_some(value));
+            } else {
+                assert!(id < before.promised);
+                assert_eq!(step.response.proposal_id, before.promised);
+            }
+            step.persist
+        }
+        Request::Learn(id, value) => {
+            let persist = acceptor.learn(ProposalId::new(*id), value.bytes());
+            assert_eq!(acceptor.accepted_value, Some(value.bytes()));
+            persist
+        }
+        Request::Duplicate => unreachable!("duplicates are resolved by the caller"),
+    }
+}
+
+fuzz_target!(|input: Input| {
+    let policy = if input.greater_or_equal {
+        PromisePolicy::GreaterOrEqual
+    } else {
+        PromisePolicy::StrictlyGreater
+    };
+    let mut acceptor = Acceptor::restore(policy, input.witness, None);
+    let mut storage = Storage::default();
+    let mut previous = None;
+
+    for request in input.requests {
+        let request = match request {
+            Request::Duplicate => match previous.clone() {
+                None => continue,
+                Some(previous) => previous,
+            },
+            request => request,
+        };
+
+        let before = acceptor.clone();
+        let persist = apply(&mut acceptor, &request);
+
+        // Promises never go back and nothing is accepted above them.
+        assert!(acceptor.promised >= before.promised);
+        assert!(acceptor.accepted_id <= acceptor.promised);
+        match persist {
+            Persist::Nothing => assert_eq!(acceptor, before),
+            Persist::Promise => {
+                assert!(acceptor.promised > before.promised);
+                assert_eq!(acceptor.accepted_id, before.accepted_id);
+                assert_eq!(acceptor.accepted_value, before.accepted_value);
+            }
+            Persist::State => {}
+        }
+
+        // A crash right after the answer is sent loses nothing it said.
+        storage.persist(&acceptor, persist);
+        let restarted = storage.restart(&acceptor);
+        assert_eq!(restarted.promised, acceptor.promised);
+        assert_eq!(restarted.accepted_id, acceptor.accepted_id);
+        if !acceptor.witness {
+            assert_eq!(restarted.accepted_value, acceptor.accepted_value);
+        }
+
+        previous = Some(request);
+    }
+});
@@ -0,0 +1,31 @@
+//! Feeds arbitrary bytes to the state file decoder an acceptor runs on
+//! startup. It must never panic, and whatever it accepts has to be a state an
+//! acceptor could have written that survives migration unchanged.
+//!
+//! Run with `cargo +nightly fuzz run state_file` from the repository root.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use single_decree_paxos::format;
+
+fuzz_target!(|contents: &[u8]| {
+    let Ok((state, version)) = format::decode(contents) else {
+        return;
+    };
+
+    let Some(state) = state else {
+        assert!(contents.is_empty());
+        return;
+    };
+    assert!(state.accepted_id <= state.proposal_id);
+    assert!(version <= format::CURRENT_VERSION);
+
+    // Migrating the file keeps what it says.
+    let (_, records) = format::split(contents).unwrap();
+    let upgraded = format::upgrade(version, records);
+    assert_eq!(
+        format::decode(&upgraded).unwrap(),
+        (Some(state), format::CURRENT_VERSION)
+    );
+});
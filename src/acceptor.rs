@@ -0,0 +1,561 @@
+//! The rpc server acceptors answer proposers with, see [AcceptorService].
+
+use futures::{Stream, StreamExt};
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
+};
+use tarpc::{
+    context,
+    server::{self, incoming::Incoming, Channel},
+};
+use tokio::{
+    sync::{Mutex, OwnedSemaphorePermit},
+    time::Instant,
+};
+use tokio_rustls::rustls::Certificate;
+use tracing::debug;
+
+use crate::{
+    auth::{Authenticator, Credentials, Signed},
+    backpressure::InflightRequests,
+    instance::InstanceId,
+    instances::Instances,
+    latency::LatencySummary,
+    lease::{HeartbeatRequest, HeartbeatResponse},
+    membership::{MemberUpdate, Membership},
+    paxos::{
+        AcceptChunk, AcceptRequest, AcceptResponse, AcceptedProposal, AcceptorError,
+        AcceptorService, Digest, Health, Hello, HelloResponse, Paxos, PrepareRequest,
+        PrepareResponse, RelayAcceptRequest, RelayLearnRequest, RelayedAcceptResponse, StateDump,
+        StateTransfer, PROTOCOL_VERSION,
+    },
+    ratelimit::RateLimiter,
+    rejoin::{Announced, Announcement, Rejoin},
+    timeout,
+    transport::{self, BoxedConnection},
+};
+
+/// Serves [AcceptorService] for the instances of a node, one per connection.
+/// Shared by the node binary and `paxos-acceptor`.
+#[derive(Clone)]
+pub struct AcceptorServer {
+    /// The instance this node proposes to, answers node wide requests.
+    paxos: Arc<Mutex<Paxos>>,
+    instances: Instances,
+    authenticator: Arc<Authenticator>,
+    /// The certificate presented by the proposer on the other side of the connection.
+    peer_certificate: Option<Certificate>,
+    /// Set when failure detection is enabled.
+    membership: Option<Membership>,
+
+    /// Whether admin calls that break the protocol's guarantees are allowed.
+    allow_unsafe_admin: bool,
+
+    /// Limits the protocol requests of this connection and of all of them.
+    rate_limiter: RateLimiter,
+
+    /// Records the generations peers announce.
+    rejoin: Rejoin,
+
+    /// Caps the protocol requests handled at once, over every connection.
+    inflight: InflightRequests,
+
+    /// The protocol version the proposer on the other side of the connection
+    /// said hello in.
+    peer_version: Arc<AtomicU32>,
+}
+
+impl AcceptorServer {
+    /// A server without failure detection, unsafe admin calls or limits.
+    pub fn new(
+        paxos: Arc<Mutex<Paxos>>,
+        instances: Instances,
+        authenticator: Arc<Authenticator>,
+        rejoin: Rejoin,
+    ) -> Self {
+        Self {
+            paxos,
+            instances,
+            authenticator,
+            peer_certificate: None,
+            membership: None,
+            allow_unsafe_admin: false,
+            rate_limiter: RateLimiter::default(),
+            rejoin,
+            inflight: InflightRequests::default(),
+            peer_version: Arc::new(AtomicU32::new(PROTOCOL_VERSION)),
+        }
+    }
+
+    /// Answers pings, set when failure detection is enabled.
+    pub fn membership(mut self, membership: Option<Membership>) -> Self {
+        self.membership = membership;
+        self
+    }
+
+    /// Accepts admin calls that wipe acceptor state.
+    pub fn allow_unsafe_admin(mut self, allow_unsafe_admin: bool) -> Self {
+        self.allow_unsafe_admin = allow_unsafe_admin;
+        self
+    }
+
+    pub fn rate_limiter(mut self, rate_limiter: RateLimiter) -> Self {
+        self.rate_limiter = rate_limiter;
+        self
+    }
+
+    pub fn inflight(mut self, inflight: InflightRequests) -> Self {
+        self.inflight = inflight;
+        self
+    }
+
+    /// The server of a new connection, whose peer presented `peer_certificate`.
+    /// It starts a rate limit bucket of its own.
+    pub fn for_connection(&self, peer_certificate: Option<Certificate>) -> Self {
+        Self {
+            peer_certificate,
+            rate_limiter: self.rate_limiter.for_connection(),
+            peer_version: Arc::new(AtomicU32::new(PROTOCOL_VERSION)),
+            ..self.clone()
+        }
+    }
+
+    /// Serves the connections `listener` accepts until it ends.
+    pub async fn run(self, listener: impl Stream<Item = BoxedConnection>) {
+        // Probes get a connection of their own when failure detection is enabled.
+        let channels_per_peer = if self.membership.is_some() { 2 } else { 1 };
+
+        listener
+            .map(|connection| server::BaseChannel::with_defaults(transport::framed(connection)))
+            // Limit channels to 1 per IP or local process, 2 with probes.
+            .max_channels_per_key(channels_per_peer, |t| {
+                let connection = t.transport().get_ref();
+                (
+                    connection.peer_addr().ok().map(|addr| addr.ip()),
+                    connection.peer_pid(),
+                )
+            })
+            // serve is generated by the service attribute. It takes as input any type implementing
+            // the generated World trait.
+            .map(|channel| {
+                let server = self.for_connection(channel.transport().get_ref().peer_certificate());
+                channel.execute(server.serve())
+            })
+            // Max 10 channels.
+            .buffer_unordered(10)
+            .for_each(|_| async {})
+            .await;
+    }
+
+    fn failure_detector(&self) -> Result<&Membership, AcceptorError> {
+        self.membership
+            .as_ref()
+            .ok_or_else(|| AcceptorError::rejected("failure detection is disabled"))
+    }
+
+    /// Authenticates a message that isn't rate limited.
+    fn authenticate<T: Serialize>(
+        &self,
+        credentials: &Credentials,
+        kind: &str,
+        message: &T,
+    ) -> Result<(), AcceptorError> {
+        self.authenticator
+            .authenticate_message(credentials, self.peer_certificate.as_ref(), kind, message)
+            .map_err(AcceptorError::rejected)
+    }
+
+    /// Authenticates a protocol message and counts it against the rate limits.
+    fn authenticate_message<T: Serialize>(
+        &self,
+        credentials: &Credentials,
+        kind: &str,
+        message: &T,
+    ) -> Result<(), AcceptorError> {
+        self.authenticator
+            .authenticate_message(credentials, self.peer_certificate.as_ref(), kind, message)
+            .map_err(AcceptorError::rejected)?;
+
+        // Only requests that authenticated count against the limits, so
+        // anyone else can't use up the tokens of the proposers.
+        self.rate_limiter.check().map_err(|retry_after| {
+            debug!(kind, ?retry_after, "throttled request");
+            AcceptorError::throttled(retry_after)
+        })
+    }
+
+    /// Takes a slot for a protocol request, waiting for one no longer than
+    /// the caller waits for the response.
+    async fn admit(
+        &self,
+        kind: &str,
+        deadline: Instant,
+    ) -> Result<Option<OwnedSemaphorePermit>, AcceptorError> {
+        self.check_deadline(kind, deadline)?;
+        self.inflight.acquire_until(deadline).await.map_err(|_| {
+            debug!(kind, "request expired waiting for a slot");
+            AcceptorError::TimedOut
+        })
+    }
+
+    /// Fails a request its caller already gave up on, so it doesn't cost a
+    /// write nobody waits for.
+    fn check_deadline(&self, kind: &str, deadline: Instant) -> Result<(), AcceptorError> {
+        if Instant::now() >= deadline {
+            debug!(kind, "abandoning expired request");
+            return Err(AcceptorError::TimedOut);
+        }
+        Ok(())
+    }
+
+    /// The instance a request is for. Proposers from before
+    /// [AcceptorService::create_instance] only reach the instances that were
+    /// created already, the ids named in requests create nothing.
+    async fn acceptor(&self, instance: &InstanceId) -> Result<Arc<Mutex<Paxos>>, AcceptorError> {
+        self.instances
+            .get(instance)
+            .await
+            .map_err(AcceptorError::from)
+    }
+
+    fn peer_version(&self) -> u32 {
+        self.peer_version.load(Ordering::Relaxed)
+    }
+
+    /// Seals the answer to `request`, or leaves it bare for a proposer from
+    /// before answers were sealed.
+    fn seal<Req: Serialize, T: Serialize>(&self, kind: &str, request: &Req, value: T) -> Signed<T> {
+        self.authenticator
+            .seal(kind, request, value)
+            .for_version(self.peer_version())
+    }
+}
+
+#[tarpc::server]
+impl AcceptorService for AcceptorServer {
+    async fn hello(
+        self,
+        _: context::Context,
+        request: Hello,
+    ) -> Result<HelloResponse, AcceptorError> {
+        let response = request.answer()?;
+        self.peer_version
+            .store(request.protocol_version, Ordering::Relaxed);
+        Ok(response)
+    }
+
+    async fn prepare(
+        self,
+        ctx: context::Context,
+        credentials: Credentials,
+        request: PrepareRequest,
+    ) -> Result<Signed<PrepareResponse>, AcceptorError> {
+        self.authenticate_message(&credentials, "prepare", &request)?;
+        let sealed = request.clone();
+        let deadline = timeout::deadline_of(&ctx);
+        let _permit = self.admit("prepare", deadline).await?;
+
+        let acceptor = self.acceptor(&request.instance).await?;
+        let (response, durable) = {
+            let mut acceptor = acceptor.lock().await;
+            // Waiting for the instance may have taken the rest of the time.
+            self.check_deadline("prepare", deadline)?;
+            acceptor
+                .begin_prepare(request)
+                .await
+                .map_err(AcceptorError::from)?
+        };
+
+        // Other requests for the instance can be handled while the promise is synced.
+        durable
+            .wait()
+            .await
+            .map_err(|err| AcceptorError::storage(&err))?;
+
+        // The promise stands either way, but a late answer would only be dropped.
+        self.check_deadline("prepare", deadline)?;
+        Ok(self.seal("prepare", &sealed, response))
+    }
+
+    async fn accept(
+        self,
+        ctx: context::Context,
+        credentials: Credentials,
+        request: AcceptRequest,
+    ) -> Result<Signed<AcceptResponse>, AcceptorError> {
+        self.authenticate_message(&credentials, "accept", &request)?;
+        let sealed = request.clone();
+        let deadline = timeout::deadline_of(&ctx);
+        let _permit = self.admit("accept", deadline).await?;
+
+        let acceptor = self.acceptor(&request.instance).await?;
+        let (response, durable) = {
+            let mut acceptor = acceptor.lock().await;
+            self.check_deadline("accept", deadline)?;
+            acceptor
+                .begin_accept(request)
+                .await
+                .map_err(AcceptorError::from)?
+        };
+
+        durable
+            .wait()
+            .await
+            .map_err(|err| AcceptorError::storage(&err))?;
+
+        self.check_deadline("accept", deadline)?;
+        Ok(self.seal("accept", &sealed, response))
+    }
+
+    async fn accept_chunk(
+        self,
+        ctx: context::Context,
+        credentials: Credentials,
+        chunk: AcceptChunk,
+    ) -> Result<Signed<Option<AcceptResponse>>, AcceptorError> {
+        self.authenticate_message(&credentials, "accept_chunk", &chunk)?;
+        let sealed = chunk.clone();
+        let deadline = timeout::deadline_of(&ctx);
+        let _permit = self.admit("accept_chunk", deadline).await?;
+
+        let acceptor = self.acceptor(&chunk.instance).await?;
+        let mut acceptor = acceptor.lock().await;
+        self.check_deadline("accept_chunk", deadline)?;
+        let response = acceptor
+            .on_accept_chunk(chunk)
+            .await
+            .map_err(AcceptorError::from)?;
+
+        self.check_deadline("accept_chunk", deadline)?;
+        Ok(self.seal("accept_chunk", &sealed, response))
+    }
+
+    async fn relay_accept(
+        self,
+        ctx: context::Context,
+        credentials: Credentials,
+        request: RelayAcceptRequest,
+    ) -> Result<Vec<RelayedAcceptResponse>, AcceptorError> {
+        self.authenticate_message(&credentials, "relay_accept", &request)?;
+        let deadline = timeout::deadline_of(&ctx);
+        let _permit = self.admit("relay_accept", deadline).await?;
+
+        let acceptor = self.acceptor(&request.accept.instance).await?;
+        let mut acceptor = acceptor.lock().await;
+        self.check_deadline("relay_accept", deadline)?;
+
+        // Forwarding waits on the other acceptors, the node is let go first.
+        let forward = acceptor.begin_relay_accept(request).await;
+        drop(acceptor);
+        Ok(forward.run().await)
+    }
+
+    async fn relay_learn(
+        self,
+        _: context::Context,
+        credentials: Credentials,
+        request: RelayLearnRequest,
+    ) -> Result<(), AcceptorError> {
+        self.authenticate_message(&credentials, "relay_learn", &request)?;
+
+        let acceptor = self.acceptor(&request.decided.instance).await?;
+        let forward = acceptor.lock().await.begin_relay_learn(request).await?;
+        tokio::spawn(forward.run());
+        Ok(())
+    }
+
+    async fn digest(
+        self,
+        _: context::Context,
+        credentials: Credentials,
+        instance: InstanceId,
+    ) -> Result<Digest, AcceptorError> {
+        self.authenticate_message(&credentials, "digest", &instance)?;
+
+        let acceptor = self.acceptor(&instance).await?;
+        let digest = acceptor.lock().await.on_digest();
+        Ok(digest)
+    }
+
+    async fn fetch_decided(
+        self,
+        _: context::Context,
+        credentials: Credentials,
+        instance: InstanceId,
+    ) -> Result<Signed<Option<Vec<u8>>>, AcceptorError> {
+        self.authenticate_message(&credentials, "fetch_decided", &instance)?;
+
+        let acceptor = self.acceptor(&instance).await?;
+        let value = acceptor.lock().await.on_fetch_decided();
+        Ok(self.seal("fetch_decided", &instance, value))
+    }
+
+    async fn fetch_accepted(
+        self,
+        _: context::Context,
+        credentials: Credentials,
+        instance: InstanceId,
+    ) -> Result<Signed<AcceptedProposal>, AcceptorError> {
+        self.authenticate_message(&credentials, "fetch_accepted", &instance)?;
+
+        let acceptor = self.acceptor(&instance).await?;
+        let accepted = acceptor.lock().await.on_fetch_accepted()?;
+        Ok(self.seal("fetch_accepted", &instance, accepted))
+    }
+
+    async fn fetch_state(
+        self,
+        _: context::Context,
+        credentials: Credentials,
+        from: InstanceId,
+    ) -> Result<Signed<StateTransfer>, AcceptorError> {
+        self.authenticate_message(&credentials, "fetch_state", &from)?;
+
+        let transfer = self
+            .instances
+            .fetch_state(&from)
+            .await
+            .map_err(AcceptorError::from)?;
+        Ok(self.seal("fetch_state", &from, transfer))
+    }
+
+    async fn latencies(
+        self,
+        _: context::Context,
+        credentials: Credentials,
+    ) -> Result<HashMap<SocketAddr, LatencySummary>, AcceptorError> {
+        self.authenticate(&credentials, "latencies", &())?;
+
+        Ok(self.paxos.lock().await.on_latencies())
+    }
+
+    async fn health(
+        self,
+        _: context::Context,
+        credentials: Credentials,
+    ) -> Result<Health, AcceptorError> {
+        self.authenticate(&credentials, "health", &())?;
+
+        Ok(self.paxos.lock().await.on_health())
+    }
+
+    async fn dump_state(
+        self,
+        _: context::Context,
+        credentials: Credentials,
+        instance: InstanceId,
+    ) -> Result<StateDump, AcceptorError> {
+        self.authenticate(&credentials, "dump_state", &instance)?;
+
+        let acceptor = self.acceptor(&instance).await?;
+        let dump = acceptor.lock().await.on_dump_state();
+        Ok(dump)
+    }
+
+    async fn reset_state(
+        self,
+        _: context::Context,
+        credentials: Credentials,
+        instance: InstanceId,
+        confirmation: String,
+    ) -> Result<(), AcceptorError> {
+        self.authenticate(&credentials, "reset_state", &(&instance, &confirmation))?;
+
+        if !self.allow_unsafe_admin {
+            return Err(AcceptorError::rejected(
+                "resetting state is disabled, see --allow-unsafe-admin",
+            ));
+        }
+
+        let acceptor = self.acceptor(&instance).await?;
+        let mut acceptor = acceptor.lock().await;
+
+        let expected = acceptor.reset_confirmation();
+        if confirmation != expected {
+            return Err(AcceptorError::InvalidRequest {
+                reason: format!("the confirmation must be {expected:?}"),
+            });
+        }
+
+        acceptor.reset_state().await.map_err(AcceptorError::from)
+    }
+
+    async fn heartbeat(
+        self,
+        _: context::Context,
+        credentials: Credentials,
+        request: HeartbeatRequest,
+    ) -> Result<HeartbeatResponse, AcceptorError> {
+        self.authenticate(&credentials, "heartbeat", &request)?;
+
+        let acceptor = self.acceptor(&request.instance).await?;
+        let response = acceptor
+            .lock()
+            .await
+            .on_heartbeat(request)
+            .await
+            .map_err(AcceptorError::from)?;
+        Ok(response)
+    }
+
+    async fn ping(
+        self,
+        _: context::Context,
+        credentials: Credentials,
+        updates: Vec<MemberUpdate>,
+    ) -> Result<Vec<MemberUpdate>, AcceptorError> {
+        self.authenticate(&credentials, "ping", &updates)?;
+
+        Ok(self.failure_detector()?.on_ping(updates))
+    }
+
+    async fn ping_req(
+        self,
+        _: context::Context,
+        credentials: Credentials,
+        target: SocketAddr,
+        updates: Vec<MemberUpdate>,
+    ) -> Result<Vec<MemberUpdate>, AcceptorError> {
+        self.authenticate(&credentials, "ping_req", &(target, &updates))?;
+
+        self.failure_detector()?
+            .on_ping_req(target, updates)
+            .await
+            .map_err(|err| AcceptorError::Unreachable {
+                reason: format!("{err:#}"),
+            })
+    }
+
+    async fn create_instance(
+        self,
+        ctx: context::Context,
+        credentials: Credentials,
+        instance: InstanceId,
+    ) -> Result<(), AcceptorError> {
+        self.authenticate_message(&credentials, "create_instance", &instance)?;
+        let deadline = timeout::deadline_of(&ctx);
+        let _permit = self.admit("create_instance", deadline).await?;
+
+        self.instances
+            .create(&instance)
+            .await
+            .map_err(AcceptorError::from)?;
+        Ok(())
+    }
+
+    async fn announce(
+        self,
+        _: context::Context,
+        credentials: Credentials,
+        announcement: Announcement,
+    ) -> Result<Announced, AcceptorError> {
+        self.authenticate_message(&credentials, "announce", &announcement)?;
+        self.rejoin.on_announce(announcement).await
+    }
+}
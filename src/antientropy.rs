@@ -0,0 +1,105 @@
+//! Background repair between acceptors. An acceptor that missed the decision
+//! of an instance, because it was down or the messages telling it were lost,
+//! only finds out once a proposer asks it. Anti-entropy compares its digest
+//! with its peers' every so often and learns the decided value from the ones
+//! that know it.
+
+use anyhow::{anyhow, Context, Result};
+use rand::Rng;
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+use crate::instances::Instances;
+
+/// How often a node checks its instances against its peers.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AntiEntropyConfig {
+    /// The average time between two passes.
+    pub interval: Duration,
+
+    /// How far a pass may start from `interval`, as a fraction of it, so the
+    /// nodes of a cluster started together don't all ask at once.
+    pub jitter: f64,
+}
+
+impl Default for AntiEntropyConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(30),
+            jitter: 0.5,
+        }
+    }
+}
+
+impl AntiEntropyConfig {
+    /// Reads ANTI_ENTROPY_INTERVAL_MS and ANTI_ENTROPY_JITTER. Returns None
+    /// when anti-entropy isn't enabled, that is when ANTI_ENTROPY_INTERVAL_MS
+    /// isn't set.
+    pub fn from_env() -> Result<Option<Self>> {
+        let Ok(value) = std::env::var("ANTI_ENTROPY_INTERVAL_MS") else {
+            return Ok(None);
+        };
+
+        let mut config = Self::default();
+
+        let millis: u64 = value
+            .parse()
+            .context("ANTI_ENTROPY_INTERVAL_MS must be an integer")?;
+        if millis == 0 {
+            return Err(anyhow!("ANTI_ENTROPY_INTERVAL_MS must be greater than 0"));
+        }
+        config.interval = Duration::from_millis(millis);
+
+        if let Ok(value) = std::env::var("ANTI_ENTROPY_JITTER") {
+            config.jitter = value
+                .parse()
+                .context("ANTI_ENTROPY_JITTER must be a number")?;
+            if !(0.0..=1.0).contains(&config.jitter) {
+                return Err(anyhow!("ANTI_ENTROPY_JITTER must be between 0 and 1"));
+            }
+        }
+
+        Ok(Some(config))
+    }
+
+    /// How long to wait before the next pass, within `jitter` of `interval`.
+    pub fn next_delay(&self) -> Duration {
+        let spread = rand::thread_rng().gen_range(-self.jitter..=self.jitter);
+        self.interval.mul_f64(1.0 + spread)
+    }
+}
+
+/// Repairs every instance open in `instances`, one pass every
+/// [AntiEntropyConfig::next_delay]. Instances already decided cost nothing.
+pub async fn run(instances: Instances, config: AntiEntropyConfig) {
+    loop {
+        tokio::time::sleep(config.next_delay()).await;
+
+        let mut checked = 0;
+        let mut repaired = 0;
+
+        for instance in instances.ids().await {
+            let node = match instances.get(&instance).await {
+                Err(err) => {
+                    warn!(%instance, ?err, "opening instance for anti-entropy");
+                    continue;
+                }
+                Ok(node) => node,
+            };
+
+            let result = node.lock().await.repair().await;
+            checked += 1;
+            match result {
+                Ok(true) => repaired += 1,
+                Ok(false) => {}
+                Err(err) => warn!(%instance, ?err, "repairing instance"),
+            }
+        }
+
+        if repaired > 0 {
+            info!(checked, repaired, "anti-entropy repaired instances");
+        } else {
+            debug!(checked, "anti-entropy found nothing to repair");
+        }
+    }
+}
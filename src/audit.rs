@@ -0,0 +1,174 @@
+use tracing::error;
+
+use crate::{
+    paxos::{AcceptResponse, PrepareResponse, PromisePolicy},
+    proposal::ProposalId,
+};
+
+/// Shadows an acceptor with a separate implementation of the acceptance rules.
+/// After every transition the acceptor's answer and state are compared with
+/// what the rules say they should be, and the process is aborted if they
+/// differ. Meant for staging, where halting a node is better than letting a
+/// bug in the acceptor go unnoticed until two values are chosen.
+///
+/// The rules are written from the paper and kept apart from [crate::paxos] on
+/// purpose, a refactoring of the acceptor must not change them as well.
+#[derive(Debug)]
+pub(crate) struct Auditor {
+    policy: PromisePolicy,
+
+    /// The highest proposal id the acceptor has promised or accepted.
+    highest: ProposalId,
+
+    /// The value of the last accepted proposal.
+    accepted: Option<Vec<u8>>,
+
+    /// The value the instance was decided on.
+    decided: Option<Vec<u8>>,
+}
+
+impl Auditor {
+    /// Starts from the state the acceptor recovered from disk.
+    pub(crate) fn new(
+        policy: PromisePolicy,
+        proposal_id: ProposalId,
+        proposal_value: Option<Vec<u8>>,
+        decided: Option<Vec<u8>>,
+    ) -> Self {
+        Self {
+            policy,
+            highest: proposal_id,
+            accepted: proposal_value,
+            decided,
+        }
+    }
+
+    /// Applies a prepare request for `proposal_id` and checks the acceptor
+    /// handled it the same way. `response` is None when the acceptor failed
+    /// to handle it, its state must have moved all the same.
+    pub(crate) fn prepare(
+        &mut self,
+        proposal_id: ProposalId,
+        response: Option<&PrepareResponse>,
+        state: (ProposalId, Option<&[u8]>),
+    ) {
+        let promised = match self.policy {
+            PromisePolicy::StrictlyGreater => proposal_id > self.highest,
+            PromisePolicy::GreaterOrEqual => proposal_id >= self.highest,
+        };
+        self.highest = self.highest.max(proposal_id);
+
+        if let Some(response) = response {
+            if response.promised != promised {
+                halt(format!(
+                    "prepare {proposal_id}: acceptor promised={} expected promised={promised}",
+                    response.promised
+                ));
+            }
+
+            if response.proposal_id != self.highest {
+                halt(format!(
+                    "prepare {proposal_id}: acceptor answered with proposal id {} expected {}",
+                    response.proposal_id, self.highest
+                ));
+            }
+
+            if response.proposal_value.as_deref() != self.accepted.as_deref() {
+                halt(format!(
+                    "prepare {proposal_id}: acceptor answered with a different accepted value"
+                ));
+            }
+        }
+
+        self.check_state("prepare", proposal_id, state);
+    }
+
+    /// Applies an accept request for `proposal_id` and checks the acceptor
+    /// handled it the same way.
+    pub(crate) fn accept(
+        &mut self,
+        proposal_id: ProposalId,
+        value: &[u8],
+        response: Option<&AcceptResponse>,
+        state: (ProposalId, Option<&[u8]>),
+    ) {
+        // A proposal is accepted unless the acceptor promised a higher one.
+        let accepted = proposal_id >= self.highest;
+        if accepted {
+            self.highest = proposal_id;
+            self.accepted = Some(value.to_vec());
+        }
+
+        if let Some(response) = response {
+            let acceptor_accepted = response.proposal_id == proposal_id;
+            if acceptor_accepted != accepted {
+                halt(format!(
+                    "accept {proposal_id}: acceptor accepted={acceptor_accepted} expected accepted={accepted}"
+                ));
+            }
+
+            if !accepted && response.proposal_id != self.highest {
+                halt(format!(
+                    "accept {proposal_id}: acceptor rejected with proposal id {} expected {}",
+                    response.proposal_id, self.highest
+                ));
+            }
+        }
+
+        self.check_state("accept", proposal_id, state);
+    }
+
+    /// Applies a decided value the acceptor learned from its peers. The
+    /// acceptor adopts it as its accepted value.
+    pub(crate) fn learn(
+        &mut self,
+        proposal_id: ProposalId,
+        value: &[u8],
+        state: (ProposalId, Option<&[u8]>),
+    ) {
+        self.highest = self.highest.max(proposal_id);
+        self.accepted = Some(value.to_vec());
+
+        self.check_state("learn", proposal_id, state);
+    }
+
+    /// Checks a decided value is never replaced by a different one.
+    pub(crate) fn decide(&mut self, value: &[u8]) {
+        match &self.decided {
+            Some(decided) if decided.as_slice() != value => {
+                halt("decided value changed".to_owned());
+            }
+            Some(_) => {}
+            None => self.decided = Some(value.to_vec()),
+        }
+    }
+
+    fn check_state(
+        &self,
+        transition: &str,
+        proposal_id: ProposalId,
+        state: (ProposalId, Option<&[u8]>),
+    ) {
+        let (highest, accepted) = state;
+
+        if highest != self.highest {
+            halt(format!(
+                "{transition} {proposal_id}: acceptor is at proposal id {highest} expected {}",
+                self.highest
+            ));
+        }
+
+        if accepted != self.accepted.as_deref() {
+            halt(format!(
+                "{transition} {proposal_id}: acceptor holds a different accepted value"
+            ));
+        }
+    }
+}
+
+/// Stops the process right away. Unwinding would only stop the task that
+/// found the mismatch and let the node keep answering requests.
+fn halt(message: String) -> ! {
+    error!(%message, "acceptor disagrees with the audit rules, halting");
+    std::process::abort();
+}
@@ -0,0 +1,407 @@
+use anyhow::{anyhow, Context, Result};
+use ring::hmac;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::{fmt, net::SocketAddr};
+use tokio_rustls::rustls::Certificate;
+
+use crate::{
+    genesis::{hex_decode, hex_encode, Genesis},
+    paxos, tls,
+};
+
+/// Credentials a proposer attaches to every request it sends to an acceptor.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Credentials {
+    pub token: Option<String>,
+
+    /// The digest of the genesis document the sender booted from.
+    #[serde(default)]
+    pub genesis: Option<String>,
+
+    /// Signs the messages sent with [Credentials::sign]. Never sent.
+    #[serde(skip)]
+    pub message_key: Option<MessageKey>,
+
+    /// The hex encoded HMAC of the message the credentials were sent with.
+    #[serde(default)]
+    pub mac: Option<String>,
+}
+
+impl Credentials {
+    /// Reads the token from the AUTH_TOKEN env variable.
+    pub fn from_env() -> Self {
+        Self {
+            token: std::env::var("AUTH_TOKEN").ok(),
+            ..Self::default()
+        }
+    }
+
+    /// Like [Credentials::from_env], also reading the MESSAGE_KEY and tying
+    /// the credentials to the genesis document when there is one.
+    pub fn for_cluster(genesis: Option<&Genesis>) -> Result<Self> {
+        let mut credentials = Self::from_env();
+        credentials.message_key = MessageKey::from_env()?;
+        credentials.genesis = genesis.map(Genesis::digest).transpose()?;
+        Ok(credentials)
+    }
+
+    /// The credentials to send `message` with, carrying its HMAC when a
+    /// message key is set. `kind` names the rpc so a message can't be replayed
+    /// as another one with the same encoding.
+    pub fn sign<T: Serialize>(&self, kind: &str, message: &T) -> Self {
+        let mut credentials = self.clone();
+        if let Some(key) = &self.message_key {
+            credentials.mac = Some(hex_encode(key.tag(kind, message).as_ref()));
+        }
+        credentials
+    }
+
+    /// Checks `mac` is the HMAC of `message` sent as `kind`. Fails without a
+    /// message key, nothing could be checked.
+    pub fn verify<T: Serialize>(&self, kind: &str, message: &T, mac: Option<&str>) -> Result<()> {
+        let key = self
+            .message_key
+            .as_ref()
+            .ok_or_else(|| anyhow!("unauthenticated: no message key to check the mac with"))?;
+        let mac = mac.ok_or_else(|| anyhow!("unauthenticated: missing message mac"))?;
+        key.verify(kind, message, mac)
+    }
+
+    /// The HMAC `acceptor` sends `response` to `request` with, so whoever
+    /// passes it on can't make it up or alter it. None without a message key.
+    pub fn sign_response<Req: Serialize, Resp: Serialize>(
+        &self,
+        kind: &str,
+        acceptor: SocketAddr,
+        request: &Req,
+        response: &Resp,
+    ) -> Option<String> {
+        self.sign(kind, &(acceptor, request, response)).mac
+    }
+
+    /// Attaches the HMAC of `request` and `value` to the answer to `request`,
+    /// see [Signed].
+    pub fn seal<Req: Serialize, T: Serialize>(
+        &self,
+        kind: &str,
+        request: &Req,
+        value: T,
+    ) -> Signed<T> {
+        seal(self.message_key.as_ref(), kind, request, value)
+    }
+
+    /// The value of the answer to `request`. Fails when a message key is set
+    /// and the answer wasn't sealed with it for this request.
+    pub fn open<Req: Serialize, T: Serialize>(
+        &self,
+        kind: &str,
+        request: &Req,
+        signed: Signed<T>,
+    ) -> Result<T> {
+        if let Some(key) = &self.message_key {
+            let mac = signed
+                .mac
+                .as_ref()
+                .ok_or_else(|| anyhow!("unauthenticated: missing response mac"))?;
+            key.verify(&response_kind(kind), &(request, &signed.value), mac)?;
+        }
+        Ok(signed.value)
+    }
+
+    /// Checks a mac made with [Credentials::sign_response].
+    pub fn verify_response<Req: Serialize, Resp: Serialize>(
+        &self,
+        kind: &str,
+        acceptor: SocketAddr,
+        request: &Req,
+        response: &Resp,
+        mac: Option<&str>,
+    ) -> Result<()> {
+        self.verify(kind, &(acceptor, request, response), mac)
+    }
+}
+
+/// A key every node of a cluster shares to authenticate protocol messages
+/// with HMAC-SHA256, so messages from outside the cluster can't be injected
+/// even where the transport isn't authenticated.
+#[derive(Clone)]
+pub struct MessageKey(hmac::Key);
+
+impl fmt::Debug for MessageKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("MessageKey(..)")
+    }
+}
+
+impl MessageKey {
+    pub fn new(key: &[u8]) -> Self {
+        Self(hmac::Key::new(hmac::HMAC_SHA256, key))
+    }
+
+    /// Reads the hex encoded key from MESSAGE_KEY, None when it isn't set.
+    pub fn from_env() -> Result<Option<Self>> {
+        match std::env::var("MESSAGE_KEY") {
+            Err(_) => Ok(None),
+            Ok(hex) => {
+                let key = hex_decode(hex.trim()).context("parsing MESSAGE_KEY")?;
+                if key.len() < 32 {
+                    return Err(anyhow!("MESSAGE_KEY must be at least 32 bytes"));
+                }
+                Ok(Some(Self::new(&key)))
+            }
+        }
+    }
+
+    fn tag<T: Serialize>(&self, kind: &str, message: &T) -> hmac::Tag {
+        hmac::sign(&self.0, &signed_bytes(kind, message))
+    }
+
+    fn verify<T: Serialize>(&self, kind: &str, message: &T, mac: &str) -> Result<()> {
+        let mac = hex_decode(mac).context("unauthenticated: malformed message mac")?;
+        hmac::verify(&self.0, &signed_bytes(kind, message), &mac)
+            .map_err(|_| anyhow!("unauthenticated: invalid message mac"))
+    }
+}
+
+/// An acceptor's answer along with the HMAC of it and the request it answers,
+/// so a proxy can neither make answers up nor hand out the answer to another
+/// request. Unsigned when the acceptor has no message key.
+///
+/// Nodes before [crate::paxos::SEALED_ANSWERS_VERSION] send and expect the
+/// bare value. Such answers are decoded with `unsealed` set, and answers with
+/// `unsealed` set are encoded bare, see [Signed::for_version].
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Signed<T> {
+    pub value: T,
+    pub mac: Option<String>,
+    #[cfg_attr(feature = "schema", schemars(skip))]
+    pub unsealed: bool,
+}
+
+impl<T> Signed<T> {
+    /// The answer as a peer speaking `version` expects it: bare before
+    /// answers were sealed.
+    pub fn for_version(self, version: u32) -> Self {
+        if version >= paxos::SEALED_ANSWERS_VERSION {
+            return self;
+        }
+
+        Self {
+            value: self.value,
+            mac: None,
+            unsealed: true,
+        }
+    }
+}
+
+/// How a [Signed] answer goes over the wire.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum SignedWire<T> {
+    Sealed {
+        value: T,
+        #[serde(default)]
+        mac: Option<String>,
+    },
+    Bare(T),
+}
+
+impl<T: Serialize> Serialize for Signed<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if self.unsealed {
+            return self.value.serialize(serializer);
+        }
+
+        SignedWire::Sealed {
+            value: &self.value,
+            mac: self.mac.clone(),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for Signed<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(match SignedWire::deserialize(deserializer)? {
+            SignedWire::Sealed { value, mac } => Signed {
+                value,
+                mac,
+                unsealed: false,
+            },
+            SignedWire::Bare(value) => Signed {
+                value,
+                mac: None,
+                unsealed: true,
+            },
+        })
+    }
+}
+
+fn seal<Req: Serialize, T: Serialize>(
+    key: Option<&MessageKey>,
+    kind: &str,
+    request: &Req,
+    value: T,
+) -> Signed<T> {
+    let mac = key.map(|key| hex_encode(key.tag(&response_kind(kind), &(request, &value)).as_ref()));
+    Signed {
+        value,
+        mac,
+        unsealed: false,
+    }
+}
+
+/// Answers are signed under another kind than requests, so neither can be
+/// passed off as the other.
+fn response_kind(kind: &str) -> String {
+    format!("{kind}_response")
+}
+
+/// Messages are signed in the encoding they are sent in.
+fn signed_bytes<T: Serialize>(kind: &str, message: &T) -> Vec<u8> {
+    let mut bytes = kind.as_bytes().to_vec();
+    bytes.push(0);
+    serde_json::to_writer(&mut bytes, message).expect("protocol messages serialize");
+    bytes
+}
+
+/// Decides whether a request may reach the acceptor.
+#[derive(Debug, Default)]
+pub struct Authenticator {
+    /// The token every request must carry. Not checked when None.
+    token: Option<String>,
+
+    /// The client certificates allowed to connect to this acceptor. Not checked when empty.
+    allowed_certificates: Vec<Certificate>,
+
+    /// The digest of the genesis document every request must come from. Not checked when None.
+    genesis: Option<String>,
+
+    /// The key protocol messages must be signed with. Not checked when None.
+    message_key: Option<MessageKey>,
+}
+
+impl Authenticator {
+    pub fn new(token: Option<String>, allowed_certificates: Vec<Certificate>) -> Self {
+        Self {
+            token,
+            allowed_certificates,
+            genesis: None,
+            message_key: None,
+        }
+    }
+
+    /// Rejects protocol messages that weren't signed with `key`.
+    pub fn set_message_key(&mut self, key: MessageKey) {
+        self.message_key = Some(key);
+    }
+
+    /// Attaches the HMAC of `request` and `value` to the answer to `request`,
+    /// see [Signed].
+    pub fn seal<Req: Serialize, T: Serialize>(
+        &self,
+        kind: &str,
+        request: &Req,
+        value: T,
+    ) -> Signed<T> {
+        seal(self.message_key.as_ref(), kind, request, value)
+    }
+
+    /// Rejects requests from nodes that booted from a different genesis document.
+    pub fn set_genesis(&mut self, digest: String) {
+        self.genesis = Some(digest);
+    }
+
+    /// Reads the expected token from AUTH_TOKEN, the allowed client certificates
+    /// from AUTH_ALLOWED_CERTS, a comma separated list of PEM files, and the
+    /// message key from MESSAGE_KEY.
+    pub fn from_env() -> Result<Self> {
+        let token = std::env::var("AUTH_TOKEN").ok();
+
+        let mut allowed_certificates = Vec::new();
+        if let Ok(paths) = std::env::var("AUTH_ALLOWED_CERTS") {
+            for path in paths.split(',').filter(|path| !path.is_empty()) {
+                allowed_certificates.extend(tls::load_certs(path.as_ref())?);
+            }
+        }
+
+        let mut authenticator = Self::new(token, allowed_certificates);
+        if let Some(key) = MessageKey::from_env()? {
+            authenticator.set_message_key(key);
+        }
+
+        Ok(authenticator)
+    }
+
+    /// Like [Authenticator::authenticate] for a protocol message, which must
+    /// also carry a valid HMAC when a message key is set.
+    pub fn authenticate_message<T: Serialize>(
+        &self,
+        credentials: &Credentials,
+        peer_certificate: Option<&Certificate>,
+        kind: &str,
+        message: &T,
+    ) -> Result<()> {
+        self.authenticate(credentials, peer_certificate)?;
+
+        if let Some(key) = &self.message_key {
+            let mac = credentials
+                .mac
+                .as_ref()
+                .ok_or_else(|| anyhow!("unauthenticated: missing message mac"))?;
+            key.verify(kind, message, mac)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn authenticate(
+        &self,
+        credentials: &Credentials,
+        peer_certificate: Option<&Certificate>,
+    ) -> Result<()> {
+        if let Some(expected) = &self.token {
+            let token = credentials
+                .token
+                .as_ref()
+                .ok_or_else(|| anyhow!("unauthenticated: missing token"))?;
+
+            if !constant_time_eq(expected.as_bytes(), token.as_bytes()) {
+                return Err(anyhow!("unauthenticated: invalid token"));
+            }
+        }
+
+        if let Some(expected) = &self.genesis {
+            if credentials.genesis.as_ref() != Some(expected) {
+                return Err(anyhow!(
+                    "unauthenticated: sender booted from a different genesis: expected={expected} got={:?}",
+                    credentials.genesis
+                ));
+            }
+        }
+
+        if !self.allowed_certificates.is_empty() {
+            let certificate = peer_certificate
+                .ok_or_else(|| anyhow!("unauthenticated: missing client certificate"))?;
+
+            if !self.allowed_certificates.contains(certificate) {
+                return Err(anyhow!(
+                    "unauthenticated: client certificate is not allowed"
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Compares without returning early so the time taken does not leak how much of the token matched.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
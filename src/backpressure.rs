@@ -0,0 +1,125 @@
+//! Caps on the work a node takes on at once. Without them a burst of client
+//! traffic opens a request future per proposal and a round of rpcs per
+//! instance, and memory grows until the burst is over.
+
+use anyhow::{anyhow, Context, Result};
+use std::{fmt, sync::Arc};
+use tokio::{
+    sync::{OwnedSemaphorePermit, Semaphore},
+    time::{error::Elapsed, Instant},
+};
+
+/// How much work a node takes on at once. Nothing is capped by default.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Limits {
+    /// The most rpcs to acceptors in flight at once, over every instance.
+    /// Rpcs over the cap wait for a slot within their deadline.
+    pub max_outstanding_rpcs: Option<usize>,
+
+    /// The most proposals waiting in the queue or being proposed. Proposals
+    /// over the cap are turned away with [Overloaded].
+    pub max_pending_proposals: Option<usize>,
+
+    /// The most protocol requests an acceptor handles at once. Requests over
+    /// the cap wait for a slot until their caller's deadline.
+    pub max_inflight_requests: Option<usize>,
+}
+
+impl Limits {
+    /// Reads MAX_OUTSTANDING_RPCS, MAX_PENDING_PROPOSALS and MAX_INFLIGHT_REQUESTS.
+    pub fn from_env() -> Result<Self> {
+        Ok(Self {
+            max_outstanding_rpcs: limit_from_env("MAX_OUTSTANDING_RPCS")?,
+            max_pending_proposals: limit_from_env("MAX_PENDING_PROPOSALS")?,
+            max_inflight_requests: limit_from_env("MAX_INFLIGHT_REQUESTS")?,
+        })
+    }
+}
+
+fn limit_from_env(name: &str) -> Result<Option<usize>> {
+    let Ok(value) = std::env::var(name) else {
+        return Ok(None);
+    };
+
+    let limit: usize = value
+        .parse()
+        .with_context(|| format!("{name} must be an integer"))?;
+    if limit == 0 {
+        return Err(anyhow!("{name} must be at least 1"));
+    }
+
+    Ok(Some(limit))
+}
+
+/// Slots for the rpcs a node sends. Clones share the slots, so the nodes of
+/// every instance built from one [crate::paxos::PaxosBuilder] share the cap.
+#[derive(Debug, Clone, Default)]
+pub struct RpcPermits {
+    semaphore: Option<Arc<Semaphore>>,
+}
+
+impl RpcPermits {
+    pub fn new(max: usize) -> Self {
+        Self {
+            semaphore: Some(Arc::new(Semaphore::new(max))),
+        }
+    }
+
+    /// Waits for a slot, the rpc is sent while the permit is held. Returns
+    /// None right away when rpcs aren't capped.
+    pub async fn acquire(&self) -> Option<OwnedSemaphorePermit> {
+        let semaphore = Arc::clone(self.semaphore.as_ref()?);
+        // The semaphore is never closed.
+        semaphore.acquire_owned().await.ok()
+    }
+}
+
+/// Slots for the requests an acceptor handles at once, shared by every
+/// connection. A request only waits for a slot until its deadline, its
+/// caller stopped waiting for the response by then.
+#[derive(Debug, Clone, Default)]
+pub struct InflightRequests {
+    semaphore: Option<Arc<Semaphore>>,
+}
+
+impl InflightRequests {
+    pub fn new(max: usize) -> Self {
+        Self {
+            semaphore: Some(Arc::new(Semaphore::new(max))),
+        }
+    }
+
+    /// Waits for a slot until `deadline`, the request is handled while the
+    /// permit is held. Returns None right away when requests aren't capped.
+    pub async fn acquire_until(
+        &self,
+        deadline: Instant,
+    ) -> Result<Option<OwnedSemaphorePermit>, Elapsed> {
+        let Some(semaphore) = &self.semaphore else {
+            return Ok(None);
+        };
+        // The semaphore is never closed.
+        tokio::time::timeout_at(deadline, Arc::clone(semaphore).acquire_owned())
+            .await
+            .map(Result::ok)
+    }
+}
+
+/// Returned when a proposal is turned away because `limit` proposals are
+/// already pending. Nothing was proposed, the client can try again later.
+#[derive(Debug)]
+pub struct Overloaded {
+    pub limit: usize,
+}
+
+impl fmt::Display for Overloaded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "node is overloaded, {} proposals are already pending",
+            self.limit
+        )
+    }
+}
+
+impl std::error::Error for Overloaded {}
@@ -0,0 +1,298 @@
+//! Runs an acceptor without the node's http and client rpc servers, the
+//! counterpart of `paxos-proposer`. Builds without the `server` feature:
+//! `cargo install --no-default-features --bin paxos-acceptor`.
+//!
+//! Reads CONFIG, GENESIS, GENESIS_KEYS and the env variables the node binary
+//! configures its acceptor with.
+
+use anyhow::{bail, Context};
+use clap::Parser;
+use single_decree_paxos::{
+    acceptor::AcceptorServer,
+    antientropy::{self, AntiEntropyConfig},
+    auth::{Authenticator, Credentials},
+    backpressure::{InflightRequests, Limits},
+    cli::{AcceptorArgs, ClusterArgs, ConfigArgs},
+    encryption::StateKey,
+    genesis,
+    instances::Instances,
+    keepalive::KeepaliveConfig,
+    membership::{Membership, MembershipConfig},
+    metrics::{Recorder, StatsdRecorder},
+    paxos::{self, Paxos},
+    ratelimit::{RateLimiter, RateLimits},
+    rejoin::{self, Rejoin},
+    tls::TlsConfig,
+    transport::{self, Connector, Endpoint, Resolver},
+};
+use std::{net::SocketAddr, path::PathBuf, sync::Arc, time::Duration};
+use tokio::{select, sync::Mutex};
+use tracing::{error, info, warn};
+
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Parser)]
+#[command(version, about = "Runs a single decree paxos acceptor")]
+struct Cli {
+    #[command(flatten)]
+    config: ConfigArgs,
+
+    #[command(flatten)]
+    acceptor: AcceptorArgs,
+
+    #[command(flatten)]
+    cluster: ClusterArgs,
+}
+
+#[tokio::main]
+async fn main() {
+    tracing_subscriber::fmt::init();
+
+    let cli = Cli::parse();
+    if let Err(err) = run(cli).await {
+        error!("{err:#}");
+        std::process::exit(1);
+    }
+}
+
+async fn run(cli: Cli) -> anyhow::Result<()> {
+    let mut config = cli.config.load_config().await?;
+    let genesis = cli.config.load_genesis(&config).await?;
+
+    let id = cli.acceptor.id(&config)?;
+    let listen = match cli.acceptor.listen.or_else(|| config.listen.take()) {
+        Some(listen) => listen,
+        None => format!("127.0.0.1:800{id}")
+            .parse()
+            .context("can't derive rpc address from id, pass --listen")?,
+    };
+    let addr = listen.lookup().await.context("resolving listen address")?;
+
+    let tls = TlsConfig::from_env()?;
+    let tls_acceptor = tls.as_ref().map(|tls| tls.acceptor()).transpose()?;
+
+    let mut authenticator = Authenticator::from_env()?;
+    if let Some(genesis) = &genesis {
+        authenticator.set_genesis(genesis.digest()?);
+    }
+    let authenticator = Arc::new(authenticator);
+
+    let data_dir = cli
+        .acceptor
+        .data_dir
+        .or_else(|| config.data_dir.take())
+        .unwrap_or_else(|| PathBuf::from("."));
+    tokio::fs::create_dir_all(&data_dir)
+        .await
+        .context("creating data dir")?;
+
+    let (acceptors, resolver, instance) =
+        cli.cluster.resolve(&mut config, genesis.as_ref()).await?;
+    let connector = Connector::from_env(tls.as_ref())?.with_resolver(resolver.clone());
+    let credentials = Credentials::for_cluster(genesis.as_ref())?;
+
+    let quorum = genesis.as_ref().map(|genesis| genesis.quorum());
+    paxos::check_topology(&acceptors, quorum, Some(addr))?;
+
+    let membership = MembershipConfig::from_env()?.map(|config| {
+        info!(probe_interval = ?config.probe_interval, "failure detection enabled");
+        Membership::new(
+            addr,
+            &acceptors,
+            connector.clone(),
+            credentials.clone(),
+            config,
+        )
+    });
+
+    // Until the acceptor knows whether it lost its data it votes in nothing.
+    let cluster = match &genesis {
+        Some(genesis) => genesis.cluster_id.clone(),
+        None => rejoin::cluster_from_acceptors(&acceptors),
+    };
+    let rejoin = Rejoin::open(&data_dir, cluster)
+        .await
+        .context("reading rejoin records")?;
+    let peers: Vec<SocketAddr> = acceptors
+        .iter()
+        .copied()
+        .filter(|acceptor| *acceptor != addr)
+        .collect();
+
+    genesis::check_manifest(&data_dir, genesis.as_ref(), id)
+        .await
+        .context("checking the data dir manifest")?;
+
+    let mut builder = Paxos::builder(id, addr, acceptors)
+        .instance(instance.clone())
+        .connector(connector.clone())
+        .credentials(credentials.clone())
+        .rejoin(rejoin.clone())
+        .data_dir(data_dir)
+        .timeouts(config.timeouts().with_env()?);
+
+    if let Some(genesis) = &genesis {
+        builder = builder.quorum(genesis.quorum());
+    }
+
+    if let Some(membership) = &membership {
+        builder = builder.membership(membership.clone());
+        tokio::spawn(membership.clone().run());
+    }
+
+    if let Some(statsd) = statsd_from_env(&resolver).await? {
+        builder = builder.metrics(Arc::new(statsd) as Arc<dyn Recorder>);
+    }
+
+    if let Ok(millis) = std::env::var("GROUP_COMMIT_MS") {
+        builder = builder.group_commit(Duration::from_millis(
+            millis
+                .parse()
+                .context("GROUP_COMMIT_MS must be an integer")?,
+        ));
+    }
+
+    if let Ok(size) = std::env::var("MAX_VALUE_SIZE") {
+        builder =
+            builder.max_value_size(size.parse().context("MAX_VALUE_SIZE must be an integer")?);
+    }
+
+    if let Some(key) = StateKey::from_env()? {
+        builder = builder.state_key(key);
+    }
+
+    if let Ok(audit) = std::env::var("AUDIT") {
+        builder = builder.audit(audit.parse().context("AUDIT must be true or false")?);
+    }
+
+    if let Some(witnesses) = acceptors_from_env("WITNESSES", &resolver).await? {
+        builder = builder.witnesses(witnesses);
+    }
+
+    if let Some(keepalive) = KeepaliveConfig::from_env()? {
+        builder = builder.keepalive(keepalive);
+    }
+
+    // Acceptors serve every instance proposers created, the one configured is
+    // the one healed in the background.
+    let instances = Instances::new(builder);
+    let paxos = instances
+        .create(&instance)
+        .await
+        .context("instantiating paxos instance")?;
+
+    tokio::spawn(heal(Arc::clone(&paxos)));
+
+    tokio::spawn({
+        let (rejoin, instances) = (rejoin.clone(), instances.clone());
+        async move {
+            if let Err(err) = rejoin.boot(id, &peers, &connector, &credentials).await {
+                error!("finding out whether the node lost its data: {err:#}");
+                std::process::exit(1);
+            }
+            rejoin::run(instances, rejoin).await
+        }
+    });
+
+    if let Some(config) = AntiEntropyConfig::from_env()? {
+        info!(interval = ?config.interval, jitter = config.jitter, "anti-entropy enabled");
+        tokio::spawn(antientropy::run(instances.clone(), config));
+    }
+
+    if cli.acceptor.allow_unsafe_admin {
+        warn!("unsafe admin calls are enabled, this node can be wiped");
+    }
+    let rate_limits = RateLimits::from_env()?;
+    if rate_limits != RateLimits::default() {
+        info!(?rate_limits, "rate limiting acceptor requests");
+    }
+    let inflight = match Limits::from_env()?.max_inflight_requests {
+        None => InflightRequests::default(),
+        Some(max) => {
+            info!(max, "capping the requests the acceptor handles at once");
+            InflightRequests::new(max)
+        }
+    };
+
+    let listener = transport::listen_endpoint(&listen, tls.as_ref(), tls_acceptor)
+        .await
+        .context("listening on server addr")?;
+    info!(addr = %listen, tls = tls.is_some(), quic = transport::quic_from_env()?, "starting rpc server");
+
+    let server = AcceptorServer::new(paxos, instances.clone(), authenticator, rejoin)
+        .membership(membership)
+        .allow_unsafe_admin(cli.acceptor.allow_unsafe_admin)
+        .rate_limiter(RateLimiter::new(rate_limits))
+        .inflight(inflight);
+
+    select! {
+        _ = server.run(listener) => bail!("rpc server exited"),
+        _ = tokio::signal::ctrl_c() => {
+            info!("received shutdown signal, no longer accepting requests");
+        }
+    }
+
+    match tokio::time::timeout(SHUTDOWN_TIMEOUT, instances.shutdown()).await {
+        Err(_) => bail!("in flight requests did not finish within {SHUTDOWN_TIMEOUT:?}"),
+        Ok(result) => result.context("flushing state on shutdown")?,
+    }
+    info!("shut down cleanly");
+    Ok(())
+}
+
+/// Periodically checks whether the cluster decided on a value this acceptor
+/// missed, like the node does.
+async fn heal(paxos: Arc<Mutex<Paxos>>) {
+    let mut interval = tokio::time::interval(Duration::from_secs(5));
+
+    loop {
+        interval.tick().await;
+
+        let Some(fetch) = paxos.lock().await.begin_heal() else {
+            continue;
+        };
+        let healed = fetch.run().await;
+
+        if let Err(err) = paxos.lock().await.finish_heal(healed).await {
+            warn!(?err, "healing acceptor state");
+        }
+    }
+}
+
+/// The addresses of the acceptors listed in `var`, written like ACCEPTORS.
+async fn acceptors_from_env(
+    var: &str,
+    resolver: &Resolver,
+) -> anyhow::Result<Option<Vec<SocketAddr>>> {
+    let Ok(endpoints) = std::env::var(var) else {
+        return Ok(None);
+    };
+
+    let mut addrs = Vec::new();
+    for endpoint in Endpoint::parse_list(&endpoints).with_context(|| format!("parsing {var}"))? {
+        let addr = resolver
+            .known_addr(&endpoint)
+            .await
+            .with_context(|| format!("resolving {endpoint} in {var}"))?;
+        addrs.push(addr);
+    }
+    Ok(Some(addrs))
+}
+
+/// The StatsD recorder configured with STATSD_ADDR and STATSD_PREFIX.
+async fn statsd_from_env(resolver: &Resolver) -> anyhow::Result<Option<StatsdRecorder>> {
+    let Ok(endpoint) = std::env::var("STATSD_ADDR") else {
+        return Ok(None);
+    };
+    let endpoint: Endpoint = endpoint.parse().context("parsing STATSD_ADDR")?;
+    if let Endpoint::Unix(_) = endpoint {
+        bail!("STATSD_ADDR must be an address or host name and port, not a unix socket");
+    }
+
+    let addr = resolver
+        .known_addr(&endpoint)
+        .await
+        .context("resolving STATSD_ADDR")?;
+    let prefix = std::env::var("STATSD_PREFIX").unwrap_or_default();
+    Ok(Some(StatsdRecorder::new(addr, prefix)?))
+}
@@ -0,0 +1,175 @@
+//! Proposes values and reports on acceptors without the node's servers. Meant
+//! for deployments whose acceptors run `paxos-acceptor` or `single-decree-paxos
+//! node --acceptor-only`, and builds without the `server` feature:
+//! `cargo install --no-default-features --bin paxos-proposer`.
+//!
+//! Reads the same CONFIG, GENESIS, GENESIS_KEYS and env variables as the node binary.
+
+use clap::{Args, Parser, Subcommand};
+use single_decree_paxos::{
+    auth::Credentials,
+    cli::{self, ClusterArgs, ConfigArgs},
+    client::PaxosClient,
+    config::Config,
+    genesis::Genesis,
+    instance::InstanceId,
+    paxos::Paxos,
+    queue::Priority,
+    retry::RetryPolicy,
+    tls::TlsConfig,
+    transport::{Connector, Endpoint, Resolver},
+};
+use std::net::SocketAddr;
+use tracing::error;
+
+#[derive(Parser)]
+#[command(version, about = "Proposes values to a single decree paxos cluster")]
+struct Cli {
+    #[command(flatten)]
+    config: ConfigArgs,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Proposes a value to the acceptors and prints the outcome.
+    Propose(ProposeArgs),
+
+    /// Prints the state of each acceptor.
+    Status(ClusterArgs),
+}
+
+#[derive(Args)]
+struct ProposeArgs {
+    /// The value to propose.
+    #[arg(long)]
+    value: String,
+
+    /// Submit the value through the client rpc server of this node instead of
+    /// running the protocol against the acceptors directly.
+    #[arg(long, env = "NODE")]
+    node: Option<Endpoint>,
+
+    /// The priority of the proposal on the node, only used with --node.
+    #[arg(long, default_value = "normal")]
+    priority: Priority,
+
+    #[command(flatten)]
+    cluster: ClusterArgs,
+}
+
+/// What every command needs to reach the cluster.
+struct Cluster {
+    acceptors: Vec<SocketAddr>,
+    instance: InstanceId,
+    connector: Connector,
+    credentials: Credentials,
+    config: Config,
+    genesis: Option<Genesis>,
+}
+
+#[tokio::main]
+async fn main() {
+    tracing_subscriber::fmt::init();
+
+    let cli = Cli::parse();
+    if let Err(err) = run(cli).await {
+        error!("{err:#}");
+        std::process::exit(1);
+    }
+}
+
+async fn run(cli: Cli) -> anyhow::Result<()> {
+    let config = cli.config.load_config().await?;
+    let genesis = cli.config.load_genesis(&config).await?;
+
+    match cli.command {
+        Command::Propose(args) => {
+            if let Some(node) = args.node {
+                return propose_through(node, args.value, args.priority, genesis.as_ref()).await;
+            }
+            let cluster = Cluster::connect(args.cluster, config, genesis).await?;
+            propose(cluster, args.value).await
+        }
+        Command::Status(args) => status(Cluster::connect(args, config, genesis).await?).await,
+    }
+}
+
+impl Cluster {
+    async fn connect(
+        args: ClusterArgs,
+        mut config: Config,
+        genesis: Option<Genesis>,
+    ) -> anyhow::Result<Self> {
+        let tls = TlsConfig::from_env()?;
+        let (acceptors, resolver, instance) = args.resolve(&mut config, genesis.as_ref()).await?;
+
+        Ok(Self {
+            acceptors,
+            instance,
+            connector: Connector::from_env(tls.as_ref())?.with_resolver(resolver),
+            credentials: Credentials::for_cluster(genesis.as_ref())?,
+            config,
+            genesis,
+        })
+    }
+}
+
+async fn propose_through(
+    node: Endpoint,
+    value: String,
+    priority: Priority,
+    genesis: Option<&Genesis>,
+) -> anyhow::Result<()> {
+    let tls = TlsConfig::from_env()?;
+    let (addrs, resolver) = Resolver::resolve(std::slice::from_ref(&node)).await?;
+    let connector = Connector::from_env(tls.as_ref())?.with_resolver(resolver);
+
+    let client =
+        PaxosClient::connect(&connector, addrs[0], Credentials::for_cluster(genesis)?).await?;
+    let response = client
+        .propose_with_priority(value.into_bytes(), priority)
+        .await?;
+
+    cli::print_propose_response(&response);
+    Ok(())
+}
+
+async fn propose(cluster: Cluster, value: String) -> anyhow::Result<()> {
+    // This process is not an acceptor, its state files are thrown away.
+    let data_dir = std::env::temp_dir().join(format!("paxos-proposer-{}", std::process::id()));
+    tokio::fs::create_dir_all(&data_dir).await?;
+
+    let mut builder = Paxos::builder(0, "0.0.0.0:0".parse()?, cluster.acceptors)
+        .instance(cluster.instance)
+        .connector(cluster.connector)
+        .credentials(cluster.credentials)
+        .data_dir(&data_dir)
+        .timeouts(cluster.config.timeouts().with_env()?)
+        .retry_policy(RetryPolicy::from_env()?);
+
+    if let Some(genesis) = &cluster.genesis {
+        builder = builder.quorum(genesis.quorum());
+    }
+
+    let result = async { builder.build().await?.propose(value.into_bytes()).await }.await;
+
+    let _ = tokio::fs::remove_dir_all(&data_dir).await;
+
+    cli::print_decided(&result?);
+    Ok(())
+}
+
+async fn status(cluster: Cluster) -> anyhow::Result<()> {
+    cli::print_status(
+        &cluster.connector,
+        &cluster.credentials,
+        &cluster.acceptors,
+        &cluster.instance,
+        false,
+    )
+    .await;
+    Ok(())
+}
@@ -0,0 +1,187 @@
+//! Generates the schema of every wire message and storage record from the Rust
+//! types so external implementations and tooling can stay in sync with the code.
+//!
+//! Run with `cargo run --features schema --bin schema -- <output dir>` (default:
+//! `schema`). It writes `wire.json`, a json schema per rpc argument and response,
+//! `genesis.json` for genesis documents and `storage.md` for the binary files
+//! acceptors keep.
+
+use anyhow::{Context, Result};
+use schemars::{schema::RootSchema, schema_for};
+use std::{collections::BTreeMap, path::PathBuf};
+
+use single_decree_paxos::{
+    auth::{Credentials, Signed},
+    client::ProposeResponse,
+    dedup::RequestId,
+    genesis::SignedGenesis,
+    latency::LatencySummary,
+    lease::{HeartbeatRequest, HeartbeatResponse},
+    membership::MemberUpdate,
+    paxos::{
+        AcceptChunk, AcceptRequest, AcceptResponse, AcceptorError, Digest, Health, Hello,
+        HelloResponse, PrepareRequest, PrepareResponse, RelayAcceptRequest, RelayLearnRequest,
+        RelayedAcceptResponse, StateDump,
+    },
+};
+
+const STORAGE: &str = r#"# Storage records
+
+Acceptor `{id}` keeps its files in its data dir. For the default instance the
+files are named `acceptor_{id}.state`, `acceptor_{id}.proposer`,
+`acceptor_{id}.decided` and `acceptor_{id}.lease`, for any other instance
+`acceptor_{id}.{key}.state`, `acceptor_{id}.{key}.proposer`,
+`acceptor_{id}.{key}.decided` and `acceptor_{id}.{key}.lease` where `{key}`
+is the instance id with `/` replaced by `.`.
+
+## `.state`
+
+Version 2, the current version:
+
+| Offset | Size     | Type   | Description                                              |
+|--------|----------|--------|----------------------------------------------------------|
+| 0      | 7        | bytes  | `PAXSTAT`                                                |
+| 7      | 1        | u8     | The version of the file, 2                               |
+| 8      | 8        | u64 le | The highest proposal id the acceptor promised or accepted |
+| 16     | 8        | u64 le | The proposal id the value was accepted in. Absent when nothing was accepted |
+| 24     | variable | bytes  | The accepted value, up to the end of the file. Absent when nothing was accepted |
+
+An empty file means the acceptor has not promised anything yet. A file that
+ends after offset 16 holds only a promise.
+
+Witnesses record the proposal id they accepted without the value: the file
+ends right after offset 24, the value is empty and never encrypted.
+
+Version 1 files have no header:
+
+| Offset | Size     | Type   | Description                                              |
+|--------|----------|--------|----------------------------------------------------------|
+| 0      | 8        | u64 le | The highest proposal id the acceptor promised or accepted, which is also the one the value was accepted in |
+| 8      | variable | bytes  | The accepted value, up to the end of the file. Absent when nothing was accepted |
+
+Version 1 values are never encrypted. A file is version 1 unless it starts
+with `PAXSTAT`, only a proposal id of at least 2^56 could be mistaken for the
+header. Nodes migrate version 1 files to the current version when they start,
+`migrate` does it offline for a whole data dir.
+
+## `.proposer`
+
+| Offset | Size | Type   | Description                                                 |
+|--------|------|--------|-------------------------------------------------------------|
+| 0      | 8    | u64 le | The highest proposal id the node sent as a proposer          |
+
+Written before each prepare request goes out. An empty file means the node
+never proposed.
+
+## `.decided`
+
+| Offset | Size     | Type  | Description                                         |
+|--------|----------|-------|-----------------------------------------------------|
+| 0      | 8        | bytes | `PAXDECID`                                          |
+| 8      | variable | bytes | The decided value, up to the end of the file        |
+
+An empty file means the node has not learned the decided value yet, the
+marker tells an empty decided value apart from it. Files written before the
+marker existed hold just the value.
+
+## `.lease`
+
+| Offset | Size | Type   | Description                                                 |
+|--------|------|--------|-------------------------------------------------------------|
+| 0      | 4    | u32 le | The id of the node the acceptor granted the lease to        |
+| 4      | 8    | u64 le | When the lease expires, in milliseconds since the unix epoch |
+
+Written before a heartbeat is answered with a grant, so a restarted acceptor
+keeps refusing other proposers until the lease expires. An empty file means
+the acceptor never granted a lease or was reset.
+
+## Encrypted values
+
+Nodes started with a state key (`STATE_KEY`, `STATE_KEY_FILE` or
+`STATE_KEY_COMMAND`) encrypt the accepted value of version 2 `.state` files
+and the value in `.decided` files with AES-256-GCM. Proposal ids and the
+headers stay in the clear. The value is replaced by:
+
+| Offset | Size     | Type  | Description                                          |
+|--------|----------|-------|------------------------------------------------------|
+| 0      | 12       | bytes | A random nonce                                       |
+| 12     | variable | bytes | The ciphertext, as long as the value                 |
+| end-16 | 16       | bytes | The authentication tag                               |
+
+The additional authenticated data binds the value to where it is stored, so a
+value copied into another file or instance fails to decrypt:
+
+| File       | Additional authenticated data                                   |
+|------------|-----------------------------------------------------------------|
+| `.state`   | The file name without the directory, e.g. `acceptor_1.state`, followed by the accepted proposal id as 8 bytes u64 le |
+| `.decided` | The file name without the directory, e.g. `acceptor_1.decided`  |
+
+A state key can't be added to or removed from an acceptor that already
+persisted values.
+
+## `manifest.toml`
+
+Written by `init`, or on first boot when the node is started with a genesis
+document. A node refuses to start from a data dir with a manifest unless it
+has the same id and was started with the same genesis document.
+
+| Key          | Type   | Description                                                   |
+|--------------|--------|---------------------------------------------------------------|
+| `version`    | u32    | The format of the manifest, 1 when missing                    |
+| `genesis`    | string | Hex encoded sha256 of the json encoding of the `[genesis]` table |
+| `cluster_id` | string | The cluster id of the genesis document. Missing from older manifests |
+| `id`         | u32    | The id of the node the data dir belongs to. Missing from older manifests |
+"#;
+
+fn main() -> Result<()> {
+    let dir = PathBuf::from(
+        std::env::args()
+            .nth(1)
+            .unwrap_or_else(|| "schema".to_owned()),
+    );
+    std::fs::create_dir_all(&dir).context("creating output dir")?;
+
+    let wire: BTreeMap<&str, RootSchema> = BTreeMap::from([
+        ("Credentials", schema_for!(Credentials)),
+        ("PrepareRequest", schema_for!(PrepareRequest)),
+        ("PrepareResponse", schema_for!(PrepareResponse)),
+        (
+            "SignedPrepareResponse",
+            schema_for!(Signed<PrepareResponse>),
+        ),
+        ("AcceptRequest", schema_for!(AcceptRequest)),
+        ("AcceptResponse", schema_for!(AcceptResponse)),
+        ("SignedAcceptResponse", schema_for!(Signed<AcceptResponse>)),
+        ("RelayAcceptRequest", schema_for!(RelayAcceptRequest)),
+        ("RelayLearnRequest", schema_for!(RelayLearnRequest)),
+        ("RelayedAcceptResponse", schema_for!(RelayedAcceptResponse)),
+        ("AcceptorError", schema_for!(AcceptorError)),
+        ("AcceptChunk", schema_for!(AcceptChunk)),
+        ("Hello", schema_for!(Hello)),
+        ("HelloResponse", schema_for!(HelloResponse)),
+        ("StateDump", schema_for!(StateDump)),
+        ("Digest", schema_for!(Digest)),
+        ("Health", schema_for!(Health)),
+        ("HeartbeatRequest", schema_for!(HeartbeatRequest)),
+        ("HeartbeatResponse", schema_for!(HeartbeatResponse)),
+        ("LatencySummary", schema_for!(LatencySummary)),
+        ("MemberUpdate", schema_for!(MemberUpdate)),
+        ("ProposeResponse", schema_for!(ProposeResponse)),
+        ("RequestId", schema_for!(RequestId)),
+    ]);
+
+    for (file, contents) in [
+        ("wire.json", serde_json::to_string_pretty(&wire)?),
+        (
+            "genesis.json",
+            serde_json::to_string_pretty(&schema_for!(SignedGenesis))?,
+        ),
+        ("storage.md", STORAGE.to_owned()),
+    ] {
+        let path = dir.join(file);
+        std::fs::write(&path, contents).with_context(|| format!("writing {}", path.display()))?;
+        println!("wrote {}", path.display());
+    }
+
+    Ok(())
+}
@@ -0,0 +1,409 @@
+//! Long running soak test. Runs a local 3 node cluster, proposes random values
+//! while killing and restarting nodes and partitioning the links between them,
+//! and checks after every recovery that:
+//!
+//! - the cluster never decided on two different values
+//! - acceptors never lose a promise or a decided value they persisted
+//!
+//! Every node talks to its peers through a proxy owned by this process so links
+//! can be cut without touching the nodes. Each epoch restarts the cluster on a
+//! new instance so many decisions are exercised over a single run.
+//!
+//! Build the node binary first, then run with `cargo run --bin soak`.
+//! SOAK_DURATION_SECS (default: run forever), SOAK_SEED and SOAK_BINARY
+//! (default: the node binary next to this one) configure the run.
+
+use anyhow::{anyhow, Context, Result};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use single_decree_paxos::{format, proposal::ProposalId};
+use std::{
+    collections::{HashMap, HashSet},
+    net::SocketAddr,
+    path::PathBuf,
+    process::Stdio,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    process::{Child, Command},
+    select,
+    sync::watch,
+};
+use tracing::warn;
+
+const NODES: u32 = 3;
+
+/// How many actions run against an instance before moving on to a new one.
+const EPOCH_ITERATIONS: u64 = 200;
+
+const PROPOSE_TIMEOUT: Duration = Duration::from_secs(30);
+
+fn rpc_addr(id: u32) -> SocketAddr {
+    format!("127.0.0.1:800{id}").parse().unwrap()
+}
+
+fn http_addr(id: u32) -> SocketAddr {
+    format!("127.0.0.1:300{id}").parse().unwrap()
+}
+
+/// The address node `from` uses to reach node `to`.
+fn proxy_addr(from: u32, to: u32) -> SocketAddr {
+    format!("127.0.0.1:9{from}{to}0").parse().unwrap()
+}
+
+/// The links that are currently cut, as (from, to) pairs.
+type Partitions = Arc<Mutex<HashSet<(u32, u32)>>>;
+
+fn is_cut(partitions: &Partitions, from: u32, to: u32) -> bool {
+    partitions.lock().unwrap().contains(&(from, to))
+}
+
+/// Forwards connections from node `from` to node `to` unless the link is cut.
+async fn run_proxy(from: u32, to: u32, partitions: Partitions, changed: watch::Receiver<()>) {
+    let listener = TcpListener::bind(proxy_addr(from, to))
+        .await
+        .expect("binding proxy");
+
+    loop {
+        let inbound = match listener.accept().await {
+            Err(err) => {
+                warn!(from, to, ?err, "proxy accept failed");
+                continue;
+            }
+            Ok((stream, _)) => stream,
+        };
+
+        if is_cut(&partitions, from, to) {
+            continue;
+        }
+
+        let partitions = Arc::clone(&partitions);
+        let mut changed = changed.clone();
+
+        tokio::spawn(async move {
+            let mut inbound = inbound;
+            let mut outbound = match TcpStream::connect(rpc_addr(to)).await {
+                Err(_) => return,
+                Ok(v) => v,
+            };
+
+            select! {
+                _ = tokio::io::copy_bidirectional(&mut inbound, &mut outbound) => {}
+                _ = async {
+                    while changed.changed().await.is_ok() {
+                        if is_cut(&partitions, from, to) {
+                            return;
+                        }
+                    }
+                } => {}
+            }
+        });
+    }
+}
+
+struct Cluster {
+    binary: PathBuf,
+    root: PathBuf,
+    instance: String,
+    processes: HashMap<u32, Child>,
+    partitions: Partitions,
+    partitions_changed: watch::Sender<()>,
+
+    /// The value the current instance decided on, once a client observes it.
+    chosen: Option<String>,
+
+    /// The highest promise each node has persisted for the current instance.
+    persisted_proposal_ids: HashMap<u32, ProposalId>,
+}
+
+impl Cluster {
+    fn node_dir(&self, id: u32) -> PathBuf {
+        self.root.join(format!("node-{id}"))
+    }
+
+    /// The path of a file node `id` keeps for the current instance.
+    fn instance_file(&self, id: u32, extension: &str) -> PathBuf {
+        self.node_dir(id)
+            .join(format!("acceptor_{id}.{}.{extension}", self.instance))
+    }
+
+    async fn start(&mut self, id: u32) -> Result<()> {
+        let acceptors: Vec<String> = (1..=NODES)
+            .map(|peer| {
+                if peer == id {
+                    rpc_addr(id).to_string()
+                } else {
+                    proxy_addr(id, peer).to_string()
+                }
+            })
+            .collect();
+
+        let dir = self.node_dir(id);
+        tokio::fs::create_dir_all(&dir)
+            .await
+            .context("creating node dir")?;
+
+        let child = Command::new(&self.binary)
+            .arg("node")
+            .current_dir(&dir)
+            .env("ID", id.to_string())
+            .env("INSTANCE", &self.instance)
+            .env("ACCEPTORS", acceptors.join(","))
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()
+            .with_context(|| format!("spawning node {id}: {}", self.binary.display()))?;
+
+        self.processes.insert(id, child);
+
+        Ok(())
+    }
+
+    async fn kill(&mut self, id: u32) -> Result<()> {
+        if let Some(mut child) = self.processes.remove(&id) {
+            child.kill().await.context("killing node")?;
+        }
+        Ok(())
+    }
+
+    fn set_link(&self, from: u32, to: u32, cut: bool) {
+        {
+            let mut partitions = self.partitions.lock().unwrap();
+            if cut {
+                partitions.insert((from, to));
+            } else {
+                partitions.remove(&(from, to));
+            }
+        }
+        let _ = self.partitions_changed.send(());
+    }
+
+    async fn start_epoch(&mut self, epoch: u64) -> Result<()> {
+        for id in 1..=NODES {
+            self.kill(id).await?;
+        }
+
+        self.partitions.lock().unwrap().clear();
+        let _ = self.partitions_changed.send(());
+
+        self.instance = format!("soak-{epoch}");
+        self.chosen = None;
+        self.persisted_proposal_ids.clear();
+
+        for id in 1..=NODES {
+            self.start(id).await?;
+        }
+
+        // Give the nodes time to bind their ports.
+        tokio::time::sleep(Duration::from_millis(500)).await;
+
+        Ok(())
+    }
+
+    fn observe_chosen(&mut self, value: String, source: &str) {
+        match &self.chosen {
+            None => self.chosen = Some(value),
+            Some(chosen) if *chosen == value => {}
+            Some(chosen) => panic!(
+                "agreement violated: instance={} chosen={chosen:?} {source} reported={value:?}",
+                self.instance
+            ),
+        }
+    }
+
+    /// Checks the invariants against what the nodes persisted.
+    async fn verify(&mut self) -> Result<()> {
+        for id in 1..=NODES {
+            let decided = read_file(self.instance_file(id, "decided")).await?;
+            if !decided.is_empty() {
+                let decided = String::from_utf8_lossy(&decided).into_owned();
+                self.observe_chosen(decided, &format!("node {id} decided file"));
+            }
+
+            let contents = read_file(self.instance_file(id, "state")).await?;
+            let (_, state) = format::split(&contents)?;
+            if state.len() >= ProposalId::ENCODED_LEN {
+                let proposal_id =
+                    ProposalId::from_bytes(state[..ProposalId::ENCODED_LEN].try_into().unwrap());
+                let previous = self.persisted_proposal_ids.entry(id).or_default();
+                if proposal_id < *previous {
+                    panic!(
+                        "durability violated: node {id} persisted proposal id went from {previous} to {proposal_id}"
+                    );
+                }
+                *previous = proposal_id;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+async fn read_file(path: PathBuf) -> Result<Vec<u8>> {
+    match tokio::fs::read(&path).await {
+        Ok(v) => Ok(v),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(err) => Err(err).with_context(|| format!("reading {}", path.display())),
+    }
+}
+
+/// Sends a propose request to the http server of node `id` and returns the response body.
+async fn propose(id: u32, value: &str) -> Result<String> {
+    let mut stream = TcpStream::connect(http_addr(id))
+        .await
+        .context("connecting to http server")?;
+
+    let request = format!(
+        "POST / HTTP/1.1\r\nhost: {}\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{value}",
+        http_addr(id),
+        value.len()
+    );
+    stream.write_all(request.as_bytes()).await?;
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await?;
+
+    let (_, body) = response
+        .split_once("\r\n\r\n")
+        .ok_or_else(|| anyhow!("malformed http response: {response}"))?;
+
+    Ok(body.to_owned())
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    tracing_subscriber::fmt::init();
+
+    let duration = std::env::var("SOAK_DURATION_SECS")
+        .ok()
+        .map(|secs| secs.parse().map(Duration::from_secs))
+        .transpose()
+        .context("SOAK_DURATION_SECS must be an integer")?;
+
+    let seed: u64 = match std::env::var("SOAK_SEED") {
+        Ok(seed) => seed.parse().context("SOAK_SEED must be an integer")?,
+        Err(_) => rand::random(),
+    };
+
+    let binary = match std::env::var("SOAK_BINARY") {
+        Ok(binary) => PathBuf::from(binary),
+        Err(_) => std::env::current_exe()?.with_file_name("single-decree-paxos"),
+    };
+
+    println!("starting soak test: seed={seed} duration={duration:?}");
+
+    let mut rng = StdRng::seed_from_u64(seed);
+
+    let partitions: Partitions = Arc::default();
+    let (partitions_changed, changed) = watch::channel(());
+
+    for from in 1..=NODES {
+        for to in 1..=NODES {
+            if from != to {
+                tokio::spawn(run_proxy(
+                    from,
+                    to,
+                    Arc::clone(&partitions),
+                    changed.clone(),
+                ));
+            }
+        }
+    }
+
+    let mut cluster = Cluster {
+        binary,
+        root: std::env::temp_dir().join(format!("paxos-soak-{seed}")),
+        instance: String::new(),
+        processes: HashMap::new(),
+        partitions,
+        partitions_changed,
+        chosen: None,
+        persisted_proposal_ids: HashMap::new(),
+    };
+
+    let started_at = Instant::now();
+    let mut epoch = 0;
+    let mut iteration: u64 = 0;
+
+    cluster.start_epoch(epoch).await?;
+
+    while duration.map_or(true, |duration| started_at.elapsed() < duration) {
+        iteration += 1;
+
+        if iteration % EPOCH_ITERATIONS == 0 {
+            epoch += 1;
+            println!(
+                "epoch {epoch}: elapsed={:?} previous chosen={:?}",
+                started_at.elapsed(),
+                cluster.chosen
+            );
+            cluster.start_epoch(epoch).await?;
+            continue;
+        }
+
+        match rng.gen_range(0..100) {
+            0..=69 => {
+                let id = rng.gen_range(1..=NODES);
+                let value = format!("value-{}", rng.gen::<u32>());
+
+                match tokio::time::timeout(PROPOSE_TIMEOUT, propose(id, &value)).await {
+                    Err(_) => println!("propose timed out: node={id}"),
+                    Ok(Err(err)) => println!("propose failed: node={id} {err:?}"),
+                    Ok(Ok(body)) => {
+                        if body == "value accepted" {
+                            cluster.observe_chosen(value, &format!("node {id} client"));
+                        } else if let Some(chosen) =
+                            body.strip_prefix("a value has already been accepted: ")
+                        {
+                            cluster.observe_chosen(chosen.to_owned(), &format!("node {id} client"));
+                        }
+                    }
+                }
+            }
+            70..=79 => {
+                let id = rng.gen_range(1..=NODES);
+                println!("killing node {id}");
+                cluster.verify().await?;
+                cluster.kill(id).await?;
+            }
+            80..=89 => {
+                let stopped: Vec<u32> = (1..=NODES)
+                    .filter(|id| !cluster.processes.contains_key(id))
+                    .collect();
+                for id in stopped {
+                    println!("restarting node {id}");
+                    cluster.start(id).await?;
+                }
+                tokio::time::sleep(Duration::from_millis(500)).await;
+                cluster.verify().await?;
+            }
+            _ => {
+                let from = rng.gen_range(1..=NODES);
+                let to = rng.gen_range(1..=NODES);
+                if from != to {
+                    let cut = !is_cut(&cluster.partitions, from, to);
+                    println!(
+                        "{} link {from} -> {to}",
+                        if cut { "cutting" } else { "healing" }
+                    );
+                    cluster.set_link(from, to, cut);
+                    if !cut {
+                        cluster.verify().await?;
+                    }
+                }
+            }
+        }
+    }
+
+    for id in 1..=NODES {
+        cluster.kill(id).await?;
+    }
+    cluster.verify().await?;
+
+    println!("soak test finished: iterations={iteration} epochs={epoch}");
+
+    Ok(())
+}
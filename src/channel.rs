@@ -0,0 +1,375 @@
+//! Nodes in the same process talking over tokio channels. Requests reach the
+//! acceptors as the values the proposer sent, without being encoded or going
+//! through a connection, so tests of the proposer's quorum logic only depend
+//! on what the acceptors answer.
+
+use anyhow::{anyhow, Result};
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU32, AtomicUsize, Ordering},
+        Arc, Mutex as StdMutex,
+    },
+};
+use tarpc::{
+    context,
+    server::{self, Channel},
+    transport::channel::{self, UnboundedChannel},
+    ClientMessage, Response,
+};
+use tokio::{
+    sync::{mpsc, Mutex},
+    task::{JoinHandle, JoinSet},
+};
+
+use crate::{
+    auth::{Credentials, Signed},
+    instance::InstanceId,
+    latency::LatencySummary,
+    lease::{HeartbeatRequest, HeartbeatResponse},
+    membership::MemberUpdate,
+    paxos::{
+        AcceptChunk, AcceptRequest, AcceptResponse, AcceptedProposal, AcceptorError,
+        AcceptorService, AcceptorServiceRequest, AcceptorServiceResponse, Digest, Health, Hello,
+        HelloResponse, Paxos, PrepareRequest, PrepareResponse, RelayAcceptRequest,
+        RelayLearnRequest, RelayedAcceptResponse, StateDump, StateTransfer,
+        CREATE_INSTANCE_VERSION, FETCH_ACCEPTED_VERSION, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION,
+    },
+    rejoin::{Announced, Announcement},
+};
+
+/// The client end of a channel to an acceptor.
+pub(crate) type ClientChannel =
+    UnboundedChannel<Response<AcceptorServiceResponse>, ClientMessage<AcceptorServiceRequest>>;
+
+type ServerChannel =
+    UnboundedChannel<ClientMessage<AcceptorServiceRequest>, Response<AcceptorServiceResponse>>;
+
+/// Routes the requests sent to an address to the acceptor served there.
+#[derive(Clone, Default)]
+pub struct ChannelNetwork {
+    acceptors: Arc<StdMutex<HashMap<SocketAddr, mpsc::UnboundedSender<ServerChannel>>>>,
+
+    /// Counts the requests acceptors got that their protocol version doesn't have.
+    unknown_requests: Arc<AtomicUsize>,
+}
+
+impl ChannelNetwork {
+    /// Answers the requests sent to `addr` with `paxos`, replacing the
+    /// acceptor served there before. Aborting the task takes it down.
+    pub fn serve(&self, addr: SocketAddr, paxos: Arc<Mutex<Paxos>>) -> JoinHandle<()> {
+        self.serve_version(addr, paxos, PROTOCOL_VERSION)
+    }
+
+    /// Like [ChannelNetwork::serve], with the acceptor answering like a build
+    /// that speaks at most `max_version`, to test rolling upgrades.
+    pub fn serve_version(
+        &self,
+        addr: SocketAddr,
+        paxos: Arc<Mutex<Paxos>>,
+        max_version: u32,
+    ) -> JoinHandle<()> {
+        let (sender, mut receiver) = mpsc::unbounded_channel::<ServerChannel>();
+        self.acceptors.lock().unwrap().insert(addr, sender);
+        let unknown_requests = Arc::clone(&self.unknown_requests);
+
+        tokio::spawn(async move {
+            // Dropped with the task, which closes the channels already open.
+            let mut channels = JoinSet::new();
+            while let Some(transport) = receiver.recv().await {
+                let server = ChannelServer {
+                    paxos: Arc::clone(&paxos),
+                    max_version,
+                    version: Arc::new(AtomicU32::new(max_version)),
+                    unknown_requests: Arc::clone(&unknown_requests),
+                };
+                channels
+                    .spawn(server::BaseChannel::with_defaults(transport).execute(server.serve()));
+            }
+        })
+    }
+
+    /// How many requests acceptors got that the protocol version they speak
+    /// doesn't have. A build that old couldn't have decoded them.
+    pub fn unknown_requests(&self) -> usize {
+        self.unknown_requests.load(Ordering::Relaxed)
+    }
+
+    /// Stops routing requests to `addr`, as if the acceptor went down.
+    /// Channels that are already open keep working until either end drops them.
+    pub fn close(&self, addr: SocketAddr) {
+        self.acceptors.lock().unwrap().remove(&addr);
+    }
+
+    pub(crate) fn connect(&self, addr: SocketAddr) -> Result<ClientChannel> {
+        let mut acceptors = self.acceptors.lock().unwrap();
+
+        let (client, server) = channel::unbounded();
+
+        let delivered = acceptors
+            .get(&addr)
+            .map(|acceptor| acceptor.send(server).is_ok())
+            .unwrap_or(false);
+
+        if !delivered {
+            acceptors.remove(&addr);
+            return Err(anyhow!("connecting to {addr}: connection refused"));
+        }
+
+        Ok(client)
+    }
+}
+
+/// Serves an acceptor without authenticating anyone.
+#[derive(Clone)]
+struct ChannelServer {
+    paxos: Arc<Mutex<Paxos>>,
+
+    /// The newest protocol version the acceptor speaks.
+    max_version: u32,
+
+    /// The version the proposer on the other side said hello in.
+    version: Arc<AtomicU32>,
+
+    unknown_requests: Arc<AtomicUsize>,
+}
+
+impl ChannelServer {
+    fn version(&self) -> u32 {
+        self.version.load(Ordering::Relaxed)
+    }
+}
+
+#[tarpc::server]
+impl AcceptorService for ChannelServer {
+    async fn hello(
+        self,
+        _: context::Context,
+        request: Hello,
+    ) -> Result<HelloResponse, AcceptorError> {
+        if !(MIN_PROTOCOL_VERSION..=self.max_version).contains(&request.protocol_version) {
+            return Err(AcceptorError::UnsupportedVersion {
+                version: request.protocol_version,
+                min_supported: MIN_PROTOCOL_VERSION,
+                max_supported: self.max_version,
+            });
+        }
+
+        self.version
+            .store(request.protocol_version, Ordering::Relaxed);
+        Ok(HelloResponse {
+            protocol_version: self.max_version,
+            min_protocol_version: MIN_PROTOCOL_VERSION,
+        })
+    }
+
+    async fn prepare(
+        self,
+        _: context::Context,
+        _: Credentials,
+        request: PrepareRequest,
+    ) -> Result<Signed<PrepareResponse>, AcceptorError> {
+        let sealed = request.clone();
+        let mut paxos = self.paxos.lock().await;
+        let response = paxos.on_prepare(request).await?;
+        Ok(paxos
+            .seal("prepare", &sealed, response)
+            .for_version(self.version()))
+    }
+
+    async fn accept(
+        self,
+        _: context::Context,
+        _: Credentials,
+        request: AcceptRequest,
+    ) -> Result<Signed<AcceptResponse>, AcceptorError> {
+        let sealed = request.clone();
+        let mut paxos = self.paxos.lock().await;
+        let response = paxos.on_accept(request).await?;
+        Ok(paxos
+            .seal("accept", &sealed, response)
+            .for_version(self.version()))
+    }
+
+    async fn accept_chunk(
+        self,
+        _: context::Context,
+        _: Credentials,
+        chunk: AcceptChunk,
+    ) -> Result<Signed<Option<AcceptResponse>>, AcceptorError> {
+        let sealed = chunk.clone();
+        let mut paxos = self.paxos.lock().await;
+        let response = paxos.on_accept_chunk(chunk).await?;
+        Ok(paxos
+            .seal("accept_chunk", &sealed, response)
+            .for_version(self.version()))
+    }
+
+    async fn relay_accept(
+        self,
+        _: context::Context,
+        _: Credentials,
+        request: RelayAcceptRequest,
+    ) -> Result<Vec<RelayedAcceptResponse>, AcceptorError> {
+        let forward = self.paxos.lock().await.begin_relay_accept(request).await;
+        Ok(forward.run().await)
+    }
+
+    async fn relay_learn(
+        self,
+        _: context::Context,
+        _: Credentials,
+        request: RelayLearnRequest,
+    ) -> Result<(), AcceptorError> {
+        let forward = self.paxos.lock().await.begin_relay_learn(request).await?;
+        tokio::spawn(forward.run());
+        Ok(())
+    }
+
+    async fn digest(
+        self,
+        _: context::Context,
+        _: Credentials,
+        _: InstanceId,
+    ) -> Result<Digest, AcceptorError> {
+        let digest = self.paxos.lock().await.on_digest();
+        Ok(digest)
+    }
+
+    async fn fetch_decided(
+        self,
+        _: context::Context,
+        _: Credentials,
+        instance: InstanceId,
+    ) -> Result<Signed<Option<Vec<u8>>>, AcceptorError> {
+        let paxos = self.paxos.lock().await;
+        let value = paxos.on_fetch_decided();
+        Ok(paxos
+            .seal("fetch_decided", &instance, value)
+            .for_version(self.version()))
+    }
+
+    async fn fetch_accepted(
+        self,
+        _: context::Context,
+        _: Credentials,
+        instance: InstanceId,
+    ) -> Result<Signed<AcceptedProposal>, AcceptorError> {
+        if self.version() < FETCH_ACCEPTED_VERSION {
+            self.unknown_requests.fetch_add(1, Ordering::Relaxed);
+            return Err(AcceptorError::InvalidRequest {
+                reason: "unknown rpc fetch_accepted".to_owned(),
+            });
+        }
+
+        let paxos = self.paxos.lock().await;
+        let accepted = paxos.on_fetch_accepted()?;
+        Ok(paxos.seal("fetch_accepted", &instance, accepted))
+    }
+
+    async fn fetch_state(
+        self,
+        _: context::Context,
+        _: Credentials,
+        from: InstanceId,
+    ) -> Result<Signed<StateTransfer>, AcceptorError> {
+        let paxos = self.paxos.lock().await;
+        let transfer = paxos.on_fetch_state(&from);
+        Ok(paxos
+            .seal("fetch_state", &from, transfer)
+            .for_version(self.version()))
+    }
+
+    async fn latencies(
+        self,
+        _: context::Context,
+        _: Credentials,
+    ) -> Result<HashMap<SocketAddr, LatencySummary>, AcceptorError> {
+        let latencies = self.paxos.lock().await.on_latencies();
+        Ok(latencies)
+    }
+
+    async fn health(self, _: context::Context, _: Credentials) -> Result<Health, AcceptorError> {
+        let health = self.paxos.lock().await.on_health();
+        Ok(health)
+    }
+
+    async fn dump_state(
+        self,
+        _: context::Context,
+        _: Credentials,
+        _: InstanceId,
+    ) -> Result<StateDump, AcceptorError> {
+        let dump = self.paxos.lock().await.on_dump_state();
+        Ok(dump)
+    }
+
+    async fn reset_state(
+        self,
+        _: context::Context,
+        _: Credentials,
+        _: InstanceId,
+        _: String,
+    ) -> Result<(), AcceptorError> {
+        Err(AcceptorError::rejected("resetting state is disabled"))
+    }
+
+    async fn heartbeat(
+        self,
+        _: context::Context,
+        _: Credentials,
+        request: HeartbeatRequest,
+    ) -> Result<HeartbeatResponse, AcceptorError> {
+        let mut paxos = self.paxos.lock().await;
+        paxos
+            .on_heartbeat(request)
+            .await
+            .map_err(AcceptorError::from)
+    }
+
+    async fn ping(
+        self,
+        _: context::Context,
+        _: Credentials,
+        _: Vec<MemberUpdate>,
+    ) -> Result<Vec<MemberUpdate>, AcceptorError> {
+        Err(AcceptorError::rejected("failure detection is disabled"))
+    }
+
+    async fn ping_req(
+        self,
+        _: context::Context,
+        _: Credentials,
+        _: SocketAddr,
+        _: Vec<MemberUpdate>,
+    ) -> Result<Vec<MemberUpdate>, AcceptorError> {
+        Err(AcceptorError::rejected("failure detection is disabled"))
+    }
+
+    async fn create_instance(
+        self,
+        _: context::Context,
+        _: Credentials,
+        _: InstanceId,
+    ) -> Result<(), AcceptorError> {
+        if self.version() < CREATE_INSTANCE_VERSION {
+            self.unknown_requests.fetch_add(1, Ordering::Relaxed);
+            return Err(AcceptorError::InvalidRequest {
+                reason: "unknown rpc create_instance".to_owned(),
+            });
+        }
+
+        // Serves the one node whatever the instance.
+        Ok(())
+    }
+
+    async fn announce(
+        self,
+        _: context::Context,
+        _: Credentials,
+        _: Announcement,
+    ) -> Result<Announced, AcceptorError> {
+        Err(AcceptorError::rejected("rejoin is disabled"))
+    }
+}
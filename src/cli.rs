@@ -0,0 +1,200 @@
+//! What the node binary, `paxos-acceptor` and `paxos-proposer` share: the
+//! flags describing the cluster and the node, and the output of the
+//! `propose` and `status` commands, so all of them read the same settings
+//! and print the same lines.
+
+use anyhow::{anyhow, Result};
+use clap::Args;
+use std::{net::SocketAddr, path::PathBuf};
+use tarpc::context;
+
+use crate::{
+    auth::Credentials,
+    client::ProposeResponse,
+    config::Config,
+    genesis::{Genesis, SignedGenesis},
+    instance::InstanceId,
+    paxos::{self, Decided},
+    transport::{Connector, Endpoint, Resolver},
+};
+
+/// Where the description of the cluster is read from.
+#[derive(Args)]
+pub struct ConfigArgs {
+    /// A toml file describing the cluster. Flags and env variables take precedence over it.
+    #[arg(long, env = "CONFIG", global = true)]
+    pub config: Option<PathBuf>,
+
+    /// The signed genesis document the cluster was bootstrapped from.
+    #[arg(long, env = "GENESIS", global = true)]
+    pub genesis: Option<PathBuf>,
+
+    /// The hex encoded public keys that must have signed the genesis document.
+    #[arg(long, env = "GENESIS_KEYS", value_delimiter = ',', global = true)]
+    pub genesis_keys: Vec<String>,
+}
+
+impl ConfigArgs {
+    /// The config file, or the defaults when none was passed.
+    pub async fn load_config(&self) -> Result<Config> {
+        match &self.config {
+            None => Ok(Config::default()),
+            Some(path) => Config::load(path).await,
+        }
+    }
+
+    /// The genesis document passed or named in `config`, checked against
+    /// the keys passed or the ones in `config`.
+    pub async fn load_genesis(&self, config: &Config) -> Result<Option<Genesis>> {
+        let genesis_keys = if self.genesis_keys.is_empty() {
+            &config.genesis_keys
+        } else {
+            &self.genesis_keys
+        };
+
+        match self.genesis.as_ref().or(config.genesis.as_ref()) {
+            None => Ok(None),
+            Some(path) => Ok(Some(SignedGenesis::load(path, genesis_keys).await?.genesis)),
+        }
+    }
+}
+
+/// Which acceptors and instance a command is about.
+#[derive(Args)]
+pub struct ClusterArgs {
+    /// The rpc address or host name and port of every acceptor, comma
+    /// separated. Host names are looked up again whenever a connection to the
+    /// acceptor is opened. Defaults to 127.0.0.1:8001,127.0.0.1:8002,127.0.0.1:8003.
+    #[arg(long, env = "ACCEPTORS", value_delimiter = ',')]
+    pub acceptors: Vec<Endpoint>,
+
+    /// The consensus instance. Defaults to `default`.
+    #[arg(long, env = "INSTANCE")]
+    pub instance: Option<InstanceId>,
+}
+
+impl ClusterArgs {
+    /// Fills in the settings that were not passed with the ones from the config
+    /// file, or from the genesis document for the acceptors, and resolves the
+    /// acceptors configured by host name.
+    pub async fn resolve(
+        self,
+        config: &mut Config,
+        genesis: Option<&Genesis>,
+    ) -> Result<(Vec<SocketAddr>, Resolver, InstanceId)> {
+        let (acceptors, resolver) = config.resolve_acceptors(self.acceptors, genesis).await?;
+
+        let instance = self
+            .instance
+            .or_else(|| config.instance.take())
+            .unwrap_or_default();
+
+        Ok((acceptors, resolver, instance))
+    }
+}
+
+/// How a node running an acceptor is identified and where it keeps its state.
+#[derive(Args)]
+pub struct AcceptorArgs {
+    /// Identifies the node, used to name its state files.
+    #[arg(long, env = "ID")]
+    pub id: Option<u32>,
+
+    /// The address the rpc server listens on, or unix:<path> to listen on a
+    /// unix socket. Defaults to 127.0.0.1:800{id}.
+    #[arg(long, env = "LISTEN")]
+    pub listen: Option<Endpoint>,
+
+    /// The directory the state files are kept in. Defaults to the working directory.
+    #[arg(long, env = "DATA_DIR")]
+    pub data_dir: Option<PathBuf>,
+
+    /// Accept admin calls that wipe acceptor state, see `reset-state`. Never
+    /// set this in production, a wiped acceptor can let two values be chosen.
+    #[arg(long, env = "ALLOW_UNSAFE_ADMIN")]
+    pub allow_unsafe_admin: bool,
+}
+
+impl AcceptorArgs {
+    /// The node id passed, or the one in `config`.
+    pub fn id(&self, config: &Config) -> Result<u32> {
+        self.id
+            .or(config.id)
+            .ok_or_else(|| anyhow!("the node id must be passed with --id, ID or the config file"))
+    }
+}
+
+/// Prints what a node answered to a proposal submitted through its client rpc server.
+pub fn print_propose_response(response: &ProposeResponse) {
+    println!("decided: {}", String::from_utf8_lossy(&response.value));
+    if let Some(timing) = &response.timing {
+        println!("timing: {timing:?}");
+    }
+}
+
+/// Prints the outcome of a proposal run against the acceptors directly.
+pub fn print_decided(decided: &Decided) {
+    match decided {
+        Decided::Ours(_) => println!("value accepted"),
+        Decided::Other(value) => println!(
+            "a value has already been accepted: {}",
+            String::from_utf8_lossy(value)
+        ),
+    }
+}
+
+/// Prints one line per acceptor: its digest of `instance`, or its whole
+/// state when `state` is set. Acceptors that can't be reached get a line
+/// saying why.
+pub async fn print_status(
+    connector: &Connector,
+    credentials: &Credentials,
+    acceptors: &[SocketAddr],
+    instance: &InstanceId,
+    state: bool,
+) {
+    for &acceptor in acceptors {
+        let client = match paxos::connect(connector, acceptor).await {
+            Err(err) => {
+                println!("{acceptor}: unreachable: {err:#}");
+                continue;
+            }
+            Ok(v) => v,
+        };
+
+        if state {
+            match client
+                .dump_state(
+                    context::current(),
+                    credentials.sign("dump_state", instance),
+                    instance.clone(),
+                )
+                .await
+            {
+                Err(err) => println!("{acceptor}: rpc error: {err:?}"),
+                Ok(Err(err)) => println!("{acceptor}: error: {err}"),
+                Ok(Ok(dump)) => println!(
+                    "{acceptor}: {}",
+                    serde_json::to_string(&dump).expect("encoding state dump")
+                ),
+            }
+            continue;
+        }
+
+        match client
+            .digest(
+                context::current(),
+                credentials.sign("digest", instance),
+                instance.clone(),
+            )
+            .await
+        {
+            Err(err) => println!("{acceptor}: rpc error: {err:?}"),
+            Ok(Err(err)) => println!("{acceptor}: error: {err}"),
+            Ok(Ok(digest)) => println!(
+                "{acceptor}: proposal_id={} decided={}",
+                digest.proposal_id, digest.decided
+            ),
+        }
+    }
+}
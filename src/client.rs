@@ -0,0 +1,187 @@
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+use tarpc::{client::Config, context};
+
+use crate::{
+    auth::Credentials,
+    dedup::RequestId,
+    paxos::DecisionTiming,
+    queue::Priority,
+    transport::{self, Connector},
+};
+
+/// Served by every node so processes that are not part of the cluster can
+/// submit proposals without knowing the acceptors.
+#[tarpc::service]
+pub trait ClientService {
+    /// Proposes `value` and returns the value the cluster decided on. A
+    /// request sent again with the same `request_id` is only proposed once.
+    /// Nodes that aren't the leader forward the request to it, setting
+    /// `forwarded_by` to their id so the leader doesn't forward it again.
+    async fn propose(
+        credentials: Credentials,
+        value: Vec<u8>,
+        priority: Priority,
+        request_id: Option<RequestId>,
+        forwarded_by: Option<u32>,
+    ) -> Result<ProposeResponse, String>;
+
+    /// Sets `key` in the write-once map unless it was set already and
+    /// returns the value the key holds.
+    async fn put_once(
+        credentials: Credentials,
+        key: String,
+        value: Vec<u8>,
+    ) -> Result<Vec<u8>, String>;
+
+    /// Returns the value of `key` in the write-once map, None when it was never set.
+    async fn get(credentials: Credentials, key: String) -> Result<Option<Vec<u8>>, String>;
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct ProposeResponse {
+    /// The value the cluster decided on.
+    pub value: Vec<u8>,
+
+    /// How long the node took to get the value decided. None when the node
+    /// learned the value from another proposer.
+    pub timing: Option<DecisionTiming>,
+}
+
+/// Submits proposals through a single node.
+#[derive(Debug, Clone)]
+pub struct PaxosClient {
+    client: ClientServiceClient,
+    credentials: Credentials,
+    client_id: u64,
+    next_request_id: Arc<AtomicU64>,
+}
+
+impl PaxosClient {
+    pub async fn connect(
+        connector: &Connector,
+        addr: SocketAddr,
+        credentials: Credentials,
+    ) -> Result<Self> {
+        let connection = connector
+            .connect(addr)
+            .await
+            .context("initializing transport")?;
+
+        let client =
+            ClientServiceClient::new(Config::default(), transport::framed(connection)).spawn();
+
+        Ok(Self {
+            client,
+            credentials,
+            client_id: rand::random(),
+            next_request_id: Arc::default(),
+        })
+    }
+
+    /// A new id for a request. Pass it to [PaxosClient::propose_request] again
+    /// to retry the request without proposing it twice.
+    pub fn next_request_id(&self) -> RequestId {
+        RequestId {
+            client_id: self.client_id,
+            request_id: self.next_request_id.fetch_add(1, Ordering::Relaxed),
+        }
+    }
+
+    /// Proposes `value` and returns the value the cluster decided on, which is
+    /// a different one when another proposal won.
+    pub async fn propose(&self, value: Vec<u8>) -> Result<ProposeResponse> {
+        self.propose_with_priority(value, Priority::Normal).await
+    }
+
+    /// Like [PaxosClient::propose], high priority proposals are scheduled ahead
+    /// of normal ones on the node.
+    pub async fn propose_with_priority(
+        &self,
+        value: Vec<u8>,
+        priority: Priority,
+    ) -> Result<ProposeResponse> {
+        self.propose_request(self.next_request_id(), value, priority)
+            .await
+    }
+
+    /// Like [PaxosClient::propose_with_priority] with the id of the request
+    /// picked by the caller.
+    pub async fn propose_request(
+        &self,
+        request_id: RequestId,
+        value: Vec<u8>,
+        priority: Priority,
+    ) -> Result<ProposeResponse> {
+        self.send(Some(request_id), value, priority, None).await
+    }
+
+    /// Proposes on behalf of the node `forwarded_by`, which is not the leader.
+    pub async fn forward(
+        &self,
+        request_id: Option<RequestId>,
+        value: Vec<u8>,
+        priority: Priority,
+        forwarded_by: u32,
+    ) -> Result<ProposeResponse> {
+        self.send(request_id, value, priority, Some(forwarded_by))
+            .await
+    }
+
+    /// Sets `key` to `value` unless it was set already. Returns the value the
+    /// key holds, which is a different one when another write got there first.
+    pub async fn put_once(&self, key: &str, value: Vec<u8>) -> Result<Vec<u8>> {
+        self.client
+            .put_once(
+                context::current(),
+                self.credentials.clone(),
+                key.to_owned(),
+                value,
+            )
+            .await
+            .context("sending put request")?
+            .map_err(|err| anyhow!(err))
+    }
+
+    /// Returns the value of `key`, None when it was never set.
+    pub async fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        self.client
+            .get(context::current(), self.credentials.clone(), key.to_owned())
+            .await
+            .context("sending get request")?
+            .map_err(|err| anyhow!(err))
+    }
+
+    async fn send(
+        &self,
+        request_id: Option<RequestId>,
+        value: Vec<u8>,
+        priority: Priority,
+        forwarded_by: Option<u32>,
+    ) -> Result<ProposeResponse> {
+        // The node may retry several rounds, leave it the time to do so.
+        let mut ctx = context::current();
+        ctx.deadline = std::time::SystemTime::now() + std::time::Duration::from_secs(60);
+
+        self.client
+            .propose(
+                ctx,
+                self.credentials.clone(),
+                value,
+                priority,
+                request_id,
+                forwarded_by,
+            )
+            .await
+            .context("sending propose request")?
+            .map_err(|err| anyhow!(err))
+    }
+}
@@ -0,0 +1,137 @@
+use anyhow::{anyhow, Result};
+use std::{
+    fs::File,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::sync::{mpsc, oneshot};
+use tracing::warn;
+
+use crate::metrics::Recorder;
+
+type Waiter = oneshot::Sender<Result<(), String>>;
+
+/// Syncs a file once for every write made since the last sync instead of once
+/// per write. Writers register after their write and wait on the returned
+/// [Durable] before answering, every writer that registers while a sync is
+/// running is covered by the next one.
+#[derive(Debug, Clone)]
+pub(crate) struct GroupCommit {
+    sender: mpsc::UnboundedSender<Waiter>,
+    /// How many writes registered so far.
+    registered: Arc<AtomicU64>,
+    /// How many of them the last sync that succeeded covered.
+    synced: Arc<AtomicU64>,
+}
+
+impl GroupCommit {
+    /// Starts syncing `file`, waiting up to `interval` after the first write of
+    /// a group for others to join it.
+    pub(crate) fn spawn(file: File, interval: Duration, metrics: Arc<dyn Recorder>) -> Self {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let registered = Arc::new(AtomicU64::new(0));
+        let synced = Arc::new(AtomicU64::new(0));
+        tokio::spawn(run(
+            Arc::new(file),
+            interval,
+            metrics,
+            receiver,
+            (Arc::clone(&registered), Arc::clone(&synced)),
+        ));
+        Self {
+            sender,
+            registered,
+            synced,
+        }
+    }
+
+    /// Waits for a sync that starts after this call.
+    pub(crate) fn sync(&self) -> Durable {
+        self.registered.fetch_add(1, Ordering::AcqRel);
+        let (waiter, synced) = oneshot::channel();
+        // When the task is gone the receiver reports it.
+        let _ = self.sender.send(waiter);
+        Durable(Some(synced))
+    }
+
+    /// Waits for the writes registered so far, for answers that wrote nothing
+    /// but report what other writes left in memory. Done right away when
+    /// every write is on disk.
+    pub(crate) fn pending(&self) -> Durable {
+        if self.synced.load(Ordering::Acquire) >= self.registered.load(Ordering::Acquire) {
+            return Durable::done();
+        }
+
+        self.sync()
+    }
+}
+
+async fn run(
+    file: Arc<File>,
+    interval: Duration,
+    metrics: Arc<dyn Recorder>,
+    mut receiver: mpsc::UnboundedReceiver<Waiter>,
+    (registered, synced): (Arc<AtomicU64>, Arc<AtomicU64>),
+) {
+    while let Some(first) = receiver.recv().await {
+        if !interval.is_zero() {
+            tokio::time::sleep(interval).await;
+        }
+
+        let mut waiters = vec![first];
+        while let Ok(waiter) = receiver.try_recv() {
+            waiters.push(waiter);
+        }
+        // Writers register after writing, so the sync covers every write
+        // registered before it starts.
+        let covered = registered.load(Ordering::Acquire);
+
+        let file = Arc::clone(&file);
+        let result = match tokio::task::spawn_blocking(move || file.sync_all()).await {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(err)) => Err(format!("syncing state file: {err}")),
+            Err(err) => Err(format!("syncing state file: {err}")),
+        };
+
+        metrics.increment("paxos_fsyncs_total", 1);
+        metrics.increment("paxos_group_commit_writes_total", waiters.len() as u64);
+
+        match &result {
+            Ok(()) => {
+                synced.fetch_max(covered, Ordering::AcqRel);
+            }
+            Err(err) => warn!(%err, writes = waiters.len(), "group commit failed"),
+        }
+
+        for waiter in waiters {
+            // The writer may have given up waiting.
+            let _ = waiter.send(result.clone());
+        }
+    }
+}
+
+/// Resolves once the writes made before it was created are on disk.
+#[derive(Debug)]
+#[must_use = "the write isn't durable until waited on"]
+pub struct Durable(Option<oneshot::Receiver<Result<(), String>>>);
+
+impl Durable {
+    /// For writes that were synced already, or when nothing was written.
+    pub(crate) fn done() -> Self {
+        Self(None)
+    }
+
+    pub async fn wait(self) -> Result<()> {
+        let Some(synced) = self.0 else {
+            return Ok(());
+        };
+
+        synced
+            .await
+            .map_err(|_| anyhow!("group commit stopped before syncing"))?
+            .map_err(|err| anyhow!(err))
+    }
+}
@@ -0,0 +1,310 @@
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+use std::{
+    collections::HashSet,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+use tracing_subscriber::EnvFilter;
+
+use crate::{
+    genesis::Genesis,
+    instance::InstanceId,
+    timeout::Timeouts,
+    transport::{Endpoint, Resolver},
+};
+
+/// Describes a cluster and optionally the node reading the file. Proposers only
+/// need the cluster settings, acceptors also read the node settings.
+///
+/// ```toml
+/// id = 1
+/// listen = "127.0.0.1:8001"
+/// http_listen = "0.0.0.0:3001"
+/// client_listen = "[::]:7001"
+/// data_dir = "/var/lib/paxos"
+/// instance = "app/leader"
+/// acceptors = ["127.0.0.1:8001", "127.0.0.1:8002", "paxos-2.paxos.default.svc:8001"]
+/// genesis = "/etc/paxos/genesis.toml"
+/// genesis_keys = ["<hex encoded ed25519 public key>"]
+///
+/// [log]
+/// format = "json"
+/// filter = "info,single_decree_paxos=debug"
+///
+/// [timeouts]
+/// prepare_rpc_ms = 2000
+/// accept_phase_ms = 5000
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub id: Option<u32>,
+    /// An address or `unix:<path>`.
+    pub listen: Option<Endpoint>,
+    /// An address or host name and port, IPv6 addresses in brackets.
+    pub http_listen: Option<Endpoint>,
+    pub client_listen: Option<Endpoint>,
+    pub data_dir: Option<PathBuf>,
+    pub instance: Option<InstanceId>,
+
+    /// The rpc address, host name and port or unix socket of every acceptor.
+    #[serde(default)]
+    pub acceptors: Vec<Endpoint>,
+
+    /// The signed genesis document the cluster was bootstrapped from.
+    pub genesis: Option<PathBuf>,
+
+    /// The hex encoded public keys that must have signed the genesis document.
+    #[serde(default)]
+    pub genesis_keys: Vec<String>,
+
+    #[serde(default)]
+    pub timeouts: TimeoutsConfig,
+
+    #[serde(default)]
+    pub log: LogConfig,
+}
+
+/// How the binary logs. Flags and env variables take precedence.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LogConfig {
+    pub format: Option<LogFormat>,
+
+    /// Which events are logged, in the syntax of RUST_LOG.
+    pub filter: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Lines meant for people.
+    #[default]
+    Text,
+
+    /// One json object per line, with the fields of the event and of the
+    /// spans it happened in, e.g. `instance` and `proposal_id`.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            other => Err(anyhow!(
+                "unknown log format {other:?}, expected text or json"
+            )),
+        }
+    }
+}
+
+/// Overrides for the defaults in [Timeouts], in milliseconds.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TimeoutsConfig {
+    pub prepare_rpc_ms: Option<u64>,
+    pub prepare_phase_ms: Option<u64>,
+    pub accept_rpc_ms: Option<u64>,
+    pub accept_phase_ms: Option<u64>,
+}
+
+impl Config {
+    pub async fn load(path: &Path) -> Result<Self> {
+        let contents = tokio::fs::read_to_string(path)
+            .await
+            .with_context(|| format!("reading config file {}", path.display()))?;
+
+        let config: Config = toml::from_str(&contents)
+            .with_context(|| format!("parsing config file {}", path.display()))?;
+
+        config
+            .validate()
+            .with_context(|| format!("invalid config file {}", path.display()))?;
+
+        Ok(config)
+    }
+
+    /// Resolves the acceptors passed as `flags`, or the ones from the config
+    /// file or the genesis document when none were, or three local ones.
+    /// They must match the genesis membership when there is one.
+    pub async fn resolve_acceptors(
+        &mut self,
+        flags: Vec<Endpoint>,
+        genesis: Option<&Genesis>,
+    ) -> Result<(Vec<SocketAddr>, Resolver)> {
+        let endpoints = if !flags.is_empty() {
+            flags
+        } else if !self.acceptors.is_empty() {
+            std::mem::take(&mut self.acceptors)
+        } else if let Some(genesis) = genesis {
+            genesis
+                .acceptors
+                .iter()
+                .copied()
+                .map(Endpoint::Addr)
+                .collect()
+        } else {
+            (1..=3)
+                .map(|id| format!("127.0.0.1:800{id}").parse().unwrap())
+                .collect()
+        };
+
+        let (acceptors, resolver) = Resolver::resolve(&endpoints)
+            .await
+            .context("resolving acceptors")?;
+
+        if let Some(genesis) = genesis {
+            let configured: HashSet<_> = acceptors.iter().collect();
+            if configured != genesis.acceptors.iter().collect() {
+                return Err(anyhow!(
+                    "acceptors {acceptors:?} do not match the genesis membership {:?}",
+                    genesis.acceptors
+                ));
+            }
+        }
+
+        Ok((acceptors, resolver))
+    }
+
+    fn validate(&self) -> Result<()> {
+        let mut seen = HashSet::with_capacity(self.acceptors.len());
+        for acceptor in &self.acceptors {
+            if !seen.insert(acceptor) {
+                return Err(anyhow!("acceptor {acceptor} is listed more than once"));
+            }
+        }
+
+        // Host names can only be checked once they are resolved.
+        let no_hosts = !self
+            .acceptors
+            .iter()
+            .any(|acceptor| matches!(acceptor, Endpoint::Host { .. }));
+
+        if let Some(listen) = &self.listen {
+            if no_hosts && !self.acceptors.is_empty() && !self.acceptors.contains(listen) {
+                return Err(anyhow!(
+                    "listen address {listen} is not one of the acceptors: {:?}",
+                    self.acceptors
+                ));
+            }
+        }
+
+        if let Some(filter) = &self.log.filter {
+            EnvFilter::try_new(filter).with_context(|| format!("invalid log.filter {filter:?}"))?;
+        }
+
+        for (name, millis) in [
+            ("timeouts.prepare_rpc_ms", self.timeouts.prepare_rpc_ms),
+            ("timeouts.prepare_phase_ms", self.timeouts.prepare_phase_ms),
+            ("timeouts.accept_rpc_ms", self.timeouts.accept_rpc_ms),
+            ("timeouts.accept_phase_ms", self.timeouts.accept_phase_ms),
+        ] {
+            if millis == Some(0) {
+                return Err(anyhow!("{name} must be greater than 0"));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// What changed from this config to `new`, one line per field, for a node
+    /// reloading its config file. Only the timeouts and the log filter can
+    /// change while the node runs, fails when anything else did.
+    pub fn diff(&self, new: &Config) -> Result<Vec<String>> {
+        let immutable = [
+            ("id", self.id != new.id),
+            ("listen", self.listen != new.listen),
+            ("http_listen", self.http_listen != new.http_listen),
+            ("client_listen", self.client_listen != new.client_listen),
+            ("data_dir", self.data_dir != new.data_dir),
+            ("instance", self.instance != new.instance),
+            ("genesis", self.genesis != new.genesis),
+            ("log.format", self.log.format != new.log.format),
+        ];
+        let changed: Vec<_> = immutable
+            .iter()
+            .filter(|(_, changed)| *changed)
+            .map(|(name, _)| *name)
+            .collect();
+        if !changed.is_empty() {
+            return Err(anyhow!(
+                "{} can't change without restarting the node",
+                changed.join(", ")
+            ));
+        }
+        if self.acceptors != new.acceptors {
+            return Err(anyhow!(
+                "acceptors can't change without restarting the node, the membership is changed by reconfiguring the cluster"
+            ));
+        }
+
+        let mut diff = Vec::new();
+        for (name, old, new) in [
+            (
+                "timeouts.prepare_rpc_ms",
+                self.timeouts.prepare_rpc_ms,
+                new.timeouts.prepare_rpc_ms,
+            ),
+            (
+                "timeouts.prepare_phase_ms",
+                self.timeouts.prepare_phase_ms,
+                new.timeouts.prepare_phase_ms,
+            ),
+            (
+                "timeouts.accept_rpc_ms",
+                self.timeouts.accept_rpc_ms,
+                new.timeouts.accept_rpc_ms,
+            ),
+            (
+                "timeouts.accept_phase_ms",
+                self.timeouts.accept_phase_ms,
+                new.timeouts.accept_phase_ms,
+            ),
+        ] {
+            if old != new {
+                diff.push(format!("{name}: {} -> {}", unset(old), unset(new)));
+            }
+        }
+        if self.log.filter != new.log.filter {
+            diff.push(format!(
+                "log.filter: {} -> {}",
+                unset(self.log.filter.as_deref()),
+                unset(new.log.filter.as_deref())
+            ));
+        }
+
+        Ok(diff)
+    }
+
+    /// The timeouts to use before env variables are applied.
+    pub fn timeouts(&self) -> Timeouts {
+        let mut timeouts = Timeouts::default();
+
+        for (millis, timeout) in [
+            (self.timeouts.prepare_rpc_ms, &mut timeouts.prepare_rpc),
+            (self.timeouts.prepare_phase_ms, &mut timeouts.prepare_phase),
+            (self.timeouts.accept_rpc_ms, &mut timeouts.accept_rpc),
+            (self.timeouts.accept_phase_ms, &mut timeouts.accept_phase),
+        ] {
+            if let Some(millis) = millis {
+                *timeout = Duration::from_millis(millis);
+            }
+        }
+
+        timeouts
+    }
+}
+
+/// Formats a setting of the config file for [Config::diff].
+fn unset<T: std::fmt::Display>(value: Option<T>) -> String {
+    match value {
+        None => "unset".to_owned(),
+        Some(value) => value.to_string(),
+    }
+}
@@ -0,0 +1,130 @@
+//! Remembers the outcome of the proposals clients tagged with a [RequestId]
+//! so a client that retries after a timeout gets the outcome of the first
+//! attempt instead of proposing the value again.
+
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+    future::Future,
+    sync::{Arc, Mutex as StdMutex},
+};
+use tokio::sync::oneshot;
+
+/// How many decided requests are remembered by default.
+const DEFAULT_CAPACITY: usize = 10_000;
+
+/// Identifies a proposal across retries. Clients pick an id for themselves and
+/// number their requests, a retry reuses the id of the request it retries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct RequestId {
+    pub client_id: u64,
+    pub request_id: u64,
+}
+
+impl fmt::Display for RequestId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.client_id, self.request_id)
+    }
+}
+
+/// The value that was decided for a request, or why it wasn't.
+pub type Outcome = Result<Vec<u8>, String>;
+
+#[derive(Debug)]
+enum Entry {
+    /// The request is being proposed, the senders belong to its retries.
+    Pending(Vec<oneshot::Sender<Outcome>>),
+    Done(Vec<u8>),
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    entries: HashMap<RequestId, Entry>,
+    /// The decided requests, oldest first.
+    decided: VecDeque<RequestId>,
+}
+
+/// Runs each request once. Only decided requests are remembered, a request
+/// that failed is proposed again when retried.
+#[derive(Debug, Clone)]
+pub struct Deduplicator {
+    inner: Arc<StdMutex<Inner>>,
+    capacity: usize,
+}
+
+impl Default for Deduplicator {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY)
+    }
+}
+
+impl Deduplicator {
+    /// Remembers up to `capacity` decided requests, forgetting the oldest first.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            inner: Arc::default(),
+            capacity,
+        }
+    }
+
+    /// Runs `propose` unless `id` was decided or is being proposed, in which
+    /// case it waits for that outcome instead. `propose` runs to completion in
+    /// the background even if the caller gives up, so a retry finds its outcome.
+    pub async fn run<F>(&self, id: RequestId, propose: F) -> Outcome
+    where
+        F: Future<Output = Outcome> + Send + 'static,
+    {
+        let (sender, receiver) = oneshot::channel();
+
+        let first = {
+            let mut inner = self.inner.lock().unwrap();
+            match inner.entries.get_mut(&id) {
+                Some(Entry::Done(value)) => return Ok(value.clone()),
+                Some(Entry::Pending(waiting)) => {
+                    waiting.push(sender);
+                    false
+                }
+                None => {
+                    inner.entries.insert(id, Entry::Pending(vec![sender]));
+                    true
+                }
+            }
+        };
+
+        if first {
+            let dedup = self.clone();
+            tokio::spawn(async move {
+                let outcome = propose.await;
+                dedup.complete(id, outcome);
+            });
+        }
+
+        receiver
+            .await
+            .unwrap_or_else(|_| Err(format!("request {id} was dropped")))
+    }
+
+    fn complete(&self, id: RequestId, outcome: Outcome) {
+        let mut inner = self.inner.lock().unwrap();
+
+        let waiting = inner.entries.remove(&id);
+
+        if let Ok(value) = &outcome {
+            inner.entries.insert(id, Entry::Done(value.clone()));
+            inner.decided.push_back(id);
+            while inner.decided.len() > self.capacity {
+                if let Some(oldest) = inner.decided.pop_front() {
+                    inner.entries.remove(&oldest);
+                }
+            }
+        }
+
+        if let Some(Entry::Pending(waiting)) = waiting {
+            for sender in waiting {
+                let _ = sender.send(outcome.clone());
+            }
+        }
+    }
+}
@@ -0,0 +1,146 @@
+//! Encryption at rest for the values acceptors persist. Accepted and decided
+//! values may hold secrets, with a [StateKey] they are sealed with AES-256-GCM
+//! before they reach the state and decided files. Proposal ids stay readable,
+//! they say nothing about the values and promises rewrite them in place.
+
+use anyhow::{anyhow, Context, Result};
+use ring::{
+    aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN},
+    rand::{SecureRandom, SystemRandom},
+};
+use std::{fmt, path::Path, sync::Arc};
+
+use crate::{genesis::hex_decode, proposal::ProposalId};
+
+/// The length of a key in bytes.
+pub const KEY_LEN: usize = 32;
+
+/// The key values are sealed with. Every node can use its own key, values
+/// are only encrypted on disk.
+#[derive(Clone)]
+pub struct StateKey {
+    key: Arc<LessSafeKey>,
+}
+
+impl fmt::Debug for StateKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("StateKey(..)")
+    }
+}
+
+impl StateKey {
+    pub fn new(key: [u8; KEY_LEN]) -> Self {
+        let key = UnboundKey::new(&AES_256_GCM, &key).expect("key has the length AES-256 needs");
+        Self {
+            key: Arc::new(LessSafeKey::new(key)),
+        }
+    }
+
+    /// Parses a hex encoded key, surrounding whitespace is ignored.
+    pub fn from_hex(hex: &str) -> Result<Self> {
+        let key: [u8; KEY_LEN] = hex_decode(hex.trim())?.try_into().map_err(|key: Vec<u8>| {
+            anyhow!("expected a {KEY_LEN} byte key, found {} bytes", key.len())
+        })?;
+        Ok(Self::new(key))
+    }
+
+    /// Reads the key from STATE_KEY, the file at STATE_KEY_FILE or the output
+    /// of STATE_KEY_COMMAND, all hex encoded. The command is run with `sh -c`
+    /// and is the place to fetch the key from a KMS. None when none of them is set.
+    pub fn from_env() -> Result<Option<Self>> {
+        if let Ok(hex) = std::env::var("STATE_KEY") {
+            return Self::from_hex(&hex).context("parsing STATE_KEY").map(Some);
+        }
+
+        if let Ok(path) = std::env::var("STATE_KEY_FILE") {
+            return Self::from_file(Path::new(&path)).map(Some);
+        }
+
+        if let Ok(command) = std::env::var("STATE_KEY_COMMAND") {
+            return Self::from_command(&command).map(Some);
+        }
+
+        Ok(None)
+    }
+
+    fn from_file(path: &Path) -> Result<Self> {
+        let hex = std::fs::read_to_string(path)
+            .with_context(|| format!("reading state key file {}", path.display()))?;
+        Self::from_hex(&hex).with_context(|| format!("parsing state key file {}", path.display()))
+    }
+
+    fn from_command(command: &str) -> Result<Self> {
+        let output = std::process::Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .output()
+            .context("running STATE_KEY_COMMAND")?;
+
+        if !output.status.success() {
+            return Err(anyhow!(
+                "STATE_KEY_COMMAND exited with {}: {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+
+        let hex =
+            String::from_utf8(output.stdout).context("STATE_KEY_COMMAND printed non utf-8")?;
+        Self::from_hex(&hex).context("parsing the output of STATE_KEY_COMMAND")
+    }
+
+    /// Encrypts `value`, returning the nonce followed by the ciphertext and tag.
+    /// `aad` is authenticated but not stored, [StateKey::open] must get the same.
+    pub fn seal(&self, aad: &[u8], value: &[u8]) -> Result<Vec<u8>> {
+        let mut nonce = [0; NONCE_LEN];
+        SystemRandom::new()
+            .fill(&mut nonce)
+            .map_err(|_| anyhow!("generating nonce"))?;
+
+        let mut sealed = nonce.to_vec();
+        let mut in_out = value.to_vec();
+        self.key
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(aad),
+                &mut in_out,
+            )
+            .map_err(|_| anyhow!("encrypting value"))?;
+        sealed.extend_from_slice(&in_out);
+
+        Ok(sealed)
+    }
+
+    /// Decrypts what [StateKey::seal] returned. Fails when the key is wrong or
+    /// the bytes were changed or moved from another file.
+    pub fn open(&self, aad: &[u8], sealed: &[u8]) -> Result<Vec<u8>> {
+        if sealed.len() < NONCE_LEN + AES_256_GCM.tag_len() {
+            return Err(anyhow!("encrypted value is too short"));
+        }
+
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        let nonce = Nonce::try_assume_unique_for_key(nonce).expect("nonce has the right length");
+
+        let mut in_out = ciphertext.to_vec();
+        let len = self
+            .key
+            .open_in_place(nonce, Aad::from(aad), &mut in_out)
+            .map_err(|_| anyhow!("decrypting value, wrong key or the file is not encrypted"))?
+            .len();
+        in_out.truncate(len);
+
+        Ok(in_out)
+    }
+}
+
+/// What the value accepted in `accepted_id` is bound to in the state file.
+pub(crate) fn state_aad(file_prefix: &str, accepted_id: ProposalId) -> Vec<u8> {
+    let mut aad = format!("{file_prefix}.state").into_bytes();
+    aad.extend_from_slice(&accepted_id.to_bytes());
+    aad
+}
+
+/// What the value in the decided file is bound to.
+pub(crate) fn decided_aad(file_prefix: &str) -> Vec<u8> {
+    format!("{file_prefix}.decided").into_bytes()
+}
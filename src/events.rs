@@ -0,0 +1,39 @@
+use serde::Serialize;
+
+use crate::{instance::InstanceId, proposal::ProposalId};
+
+/// How many events a subscriber can fall behind before it misses some.
+pub(crate) const CAPACITY: usize = 256;
+
+/// What a node reports to the receivers returned by
+/// [Paxos::subscribe](crate::paxos::Paxos::subscribe). Acceptor events are sent
+/// once the acceptor changed its state, which may be before the change is on
+/// disk when group commit is on.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// The acceptor promised not to accept proposals below `proposal_id`.
+    Promised {
+        instance: InstanceId,
+        proposal_id: ProposalId,
+    },
+
+    /// The acceptor accepted `value` in proposal `proposal_id`.
+    Accepted {
+        instance: InstanceId,
+        proposal_id: ProposalId,
+        value: Vec<u8>,
+    },
+
+    /// The node learned that `value` was chosen.
+    Chosen {
+        instance: InstanceId,
+        value: Vec<u8>,
+    },
+
+    /// The node got the lease and is the only one proposing until it loses it.
+    LeaseAcquired { instance: InstanceId },
+
+    /// The node could no longer renew the lease.
+    LeaseLost { instance: InstanceId },
+}
@@ -0,0 +1,220 @@
+use futures::{Sink, SinkExt, Stream, StreamExt};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+    collections::HashMap,
+    fmt,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tarpc::transport::channel::{self, UnboundedChannel};
+use tokio::sync::mpsc;
+use tracing::debug;
+
+/// How often each fault happens to the messages sent over a link. Every
+/// probability is per message and the faults are independent of each other.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct LinkFaults {
+    /// The probability a message is lost.
+    pub drop: f64,
+
+    /// The probability a message is delivered twice.
+    pub duplicate: f64,
+
+    /// The probability a message is held back until the next message on the
+    /// link is delivered.
+    pub reorder: f64,
+
+    /// The probability a message is delayed by up to `max_delay`. Delayed
+    /// messages are also overtaken by the messages sent after them.
+    pub delay: f64,
+    pub max_delay: Duration,
+}
+
+/// Injects faults into the requests a node sends, configured per destination.
+/// Only the requests that change acceptor state, prepare and accept requests,
+/// are affected so tests can check acceptors and proposers cope with lost,
+/// duplicated and stale messages without losing every connection. Faults are
+/// drawn from a seeded rng and can be changed while the node runs, for
+/// example to heal a link.
+#[derive(Clone)]
+pub struct FaultInjector {
+    inner: Arc<Mutex<Inner>>,
+}
+
+struct Inner {
+    rng: StdRng,
+    default: LinkFaults,
+    links: HashMap<SocketAddr, LinkFaults>,
+}
+
+impl fmt::Debug for FaultInjector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let inner = self.inner.lock().unwrap();
+        f.debug_struct("FaultInjector")
+            .field("default", &inner.default)
+            .field("links", &inner.links)
+            .finish()
+    }
+}
+
+/// What happens to one message.
+#[derive(Debug)]
+struct Plan {
+    drop: bool,
+    copies: usize,
+    hold: bool,
+    delay: Option<Duration>,
+}
+
+impl FaultInjector {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Inner {
+                rng: StdRng::seed_from_u64(seed),
+                default: LinkFaults::default(),
+                links: HashMap::new(),
+            })),
+        }
+    }
+
+    /// The faults of the links that don't have their own.
+    pub fn set_default(&self, faults: LinkFaults) {
+        self.inner.lock().unwrap().default = faults;
+    }
+
+    /// The faults of the link to `to`.
+    pub fn set_link(&self, to: SocketAddr, faults: LinkFaults) {
+        self.inner.lock().unwrap().links.insert(to, faults);
+    }
+
+    /// Makes the link to `to` use the default faults again.
+    pub fn clear_link(&self, to: SocketAddr) {
+        self.inner.lock().unwrap().links.remove(&to);
+    }
+
+    fn plan(&self, to: SocketAddr) -> Plan {
+        let mut inner = self.inner.lock().unwrap();
+        let faults = inner.links.get(&to).copied().unwrap_or(inner.default);
+        let rng = &mut inner.rng;
+
+        Plan {
+            drop: rng.gen_bool(faults.drop),
+            copies: if rng.gen_bool(faults.duplicate) { 2 } else { 1 },
+            hold: rng.gen_bool(faults.reorder),
+            delay: rng
+                .gen_bool(faults.delay)
+                .then(|| rng.gen_range(Duration::ZERO..=faults.max_delay)),
+        }
+    }
+
+    /// Puts the injector between a client and `transport`, the connection to
+    /// `to`. Faults are only applied to the messages `faulty` returns true for.
+    pub(crate) fn wrap<T, E, Item, SinkItem>(
+        &self,
+        to: SocketAddr,
+        transport: T,
+        faulty: fn(&SinkItem) -> bool,
+    ) -> UnboundedChannel<Item, SinkItem>
+    where
+        T: Stream<Item = Result<Item, E>> + Sink<SinkItem, Error = E> + Send + 'static,
+        E: Send + 'static,
+        Item: Send + 'static,
+        SinkItem: Serialize + DeserializeOwned + Send + 'static,
+    {
+        let (client, proxy) = channel::unbounded();
+        let (mut proxy_sink, mut proxy_stream) = proxy.split();
+        let (mut sink, mut stream) = transport.split();
+
+        // Every message headed to the connection goes through here so delayed
+        // messages can be sent from their own tasks.
+        let (outgoing, mut outgoing_receiver) = mpsc::unbounded_channel::<SinkItem>();
+        tokio::spawn(async move {
+            while let Some(message) = outgoing_receiver.recv().await {
+                if sink.send(message).await.is_err() {
+                    return;
+                }
+            }
+        });
+
+        let injector = self.clone();
+        tokio::spawn(async move {
+            let mut held = None;
+
+            while let Some(Ok(message)) = proxy_stream.next().await {
+                let overtaken = held.take();
+
+                if !faulty(&message) {
+                    if outgoing.send(message).is_err() {
+                        return;
+                    }
+                } else {
+                    injector.apply(to, message, &outgoing, &mut held);
+                }
+
+                // A message held back goes out right after the one that overtook it.
+                if let Some(message) = overtaken {
+                    if outgoing.send(message).is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+
+        tokio::spawn(async move {
+            while let Some(Ok(message)) = stream.next().await {
+                if proxy_sink.send(message).await.is_err() {
+                    return;
+                }
+            }
+        });
+
+        client
+    }
+
+    fn apply<SinkItem>(
+        &self,
+        to: SocketAddr,
+        message: SinkItem,
+        outgoing: &mpsc::UnboundedSender<SinkItem>,
+        held: &mut Option<SinkItem>,
+    ) where
+        SinkItem: Serialize + DeserializeOwned + Send + 'static,
+    {
+        let plan = self.plan(to);
+
+        if plan.drop {
+            debug!(%to, "dropping message");
+            return;
+        }
+
+        // Messages can't be cloned, they are copied through their encoding
+        // which is what goes over the wire anyway.
+        let mut copies = Vec::with_capacity(plan.copies);
+        if plan.copies > 1 {
+            debug!(%to, "duplicating message");
+            match serde_json::to_value(&message).and_then(serde_json::from_value) {
+                Ok(copy) => copies.push(copy),
+                Err(err) => debug!(%to, ?err, "copying message"),
+            }
+        }
+        copies.push(message);
+
+        for copy in copies {
+            if let Some(delay) = plan.delay {
+                debug!(%to, ?delay, "delaying message");
+                let outgoing = outgoing.clone();
+                tokio::spawn(async move {
+                    tokio::time::sleep(delay).await;
+                    let _ = outgoing.send(copy);
+                });
+            } else if plan.hold && held.is_none() {
+                debug!(%to, "holding message back");
+                *held = Some(copy);
+            } else {
+                let _ = outgoing.send(copy);
+            }
+        }
+    }
+}
@@ -0,0 +1,257 @@
+//! Versions of the acceptor state file. Versioned files start with a header
+//! of [MAGIC] followed by the version byte. Files written before the header
+//! existed are version 1, their first 8 bytes are a proposal id and only an id
+//! of at least 2^56 could be mistaken for the header.
+//!
+//! Version 1 files hold `[proposal id][value]`: acceptors kept a single id for
+//! what they promised and accepted, and a promise overwrote the id in front of
+//! a value accepted earlier. Version 2 added the header and the id the value
+//! was accepted in, `[proposal id][accepted id][value]`.
+//!
+//! A node reading an older version migrates the file on start, `migrate`
+//! does the same offline for a whole data dir.
+
+use anyhow::{anyhow, Context, Result};
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+use crate::proposal::ProposalId;
+
+/// The version written by this build.
+pub const CURRENT_VERSION: u8 = 2;
+
+const MAGIC: [u8; 7] = *b"PAXSTAT";
+
+/// The length of the header versioned files start with.
+pub const HEADER_LEN: usize = MAGIC.len() + 1;
+
+/// The header of files written by this build.
+pub fn header() -> [u8; HEADER_LEN] {
+    let mut header = [0; HEADER_LEN];
+    header[..MAGIC.len()].copy_from_slice(&MAGIC);
+    header[MAGIC.len()] = CURRENT_VERSION;
+    header
+}
+
+/// Splits the contents of a state file into its version and the records
+/// after the header. Fails on versions newer than this build understands.
+pub fn split(contents: &[u8]) -> Result<(u8, &[u8])> {
+    if contents.len() < HEADER_LEN || contents[..MAGIC.len()] != MAGIC {
+        return Ok((1, contents));
+    }
+
+    let version = contents[MAGIC.len()];
+    // Version 1 files have no header.
+    if version < 2 {
+        return Err(anyhow!("state file header has unknown version {version}"));
+    }
+    if version > CURRENT_VERSION {
+        return Err(anyhow!(
+            "state file version {version} is newer than the supported version {CURRENT_VERSION}"
+        ));
+    }
+
+    Ok((version, &contents[HEADER_LEN..]))
+}
+
+/// What an acceptor persisted in its state file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct State {
+    pub proposal_id: ProposalId,
+    pub accepted_id: ProposalId,
+    /// None when only a promise was persisted. Sealed values are returned as
+    /// stored, still encrypted.
+    pub proposal_value: Option<Vec<u8>>,
+}
+
+/// Decodes the contents of a state file and returns them with the version
+/// they were written in, None when the file is empty. Fails on contents no
+/// acceptor writes, which a torn write or a damaged disk leaves behind.
+pub fn decode(contents: &[u8]) -> Result<(Option<State>, u8)> {
+    if contents.is_empty() {
+        return Ok((None, CURRENT_VERSION));
+    }
+
+    let (version, records) = split(contents)?;
+
+    let id = |bytes: &[u8]| ProposalId::from_bytes(bytes.try_into().unwrap());
+    let len = ProposalId::ENCODED_LEN;
+
+    if version == 1 {
+        return match records.len() {
+            n if n < len => Err(anyhow!("state file records are {n} bytes, truncated")),
+            n if n == len => Ok((
+                Some(State {
+                    proposal_id: id(records),
+                    accepted_id: ProposalId::ZERO,
+                    proposal_value: None,
+                }),
+                version,
+            )),
+            // The id may be a promise made after the value was accepted, so
+            // it is only taken as the promise. The id the value was accepted
+            // in is unknown, ZERO ranks it below every value accepted since.
+            _ => Ok((
+                Some(State {
+                    proposal_id: id(&records[..len]),
+                    accepted_id: ProposalId::ZERO,
+                    proposal_value: Some(records[len..].to_vec()),
+                }),
+                version,
+            )),
+        };
+    }
+
+    let state = match records.len() {
+        n if n == len => State {
+            proposal_id: id(records),
+            accepted_id: ProposalId::ZERO,
+            proposal_value: None,
+        },
+        n if n >= 2 * len => State {
+            proposal_id: id(&records[..len]),
+            accepted_id: id(&records[len..2 * len]),
+            proposal_value: Some(records[2 * len..].to_vec()),
+        },
+        n => return Err(anyhow!("state file records are {n} bytes, truncated")),
+    };
+
+    // Accepting a proposal promises it too.
+    if state.accepted_id > state.proposal_id {
+        return Err(anyhow!(
+            "state file has proposal {} accepted above the promise for {}",
+            state.accepted_id,
+            state.proposal_id
+        ));
+    }
+
+    Ok((Some(state), version))
+}
+
+/// The contents of a state file holding `state`.
+pub fn encode(state: &State) -> Vec<u8> {
+    let mut contents = header().to_vec();
+    contents.extend_from_slice(&state.proposal_id.to_bytes());
+    if let Some(value) = &state.proposal_value {
+        contents.extend_from_slice(&state.accepted_id.to_bytes());
+        contents.extend_from_slice(value);
+    }
+    contents
+}
+
+/// What a promise writes over the start of the state file, leaving the
+/// accepted proposal after it in place. The header is rewritten with the id
+/// so a first promise isn't left behind a missing header, it never changes
+/// once written.
+pub fn encode_promise(promised: ProposalId) -> Vec<u8> {
+    encode(&State {
+        proposal_id: promised,
+        accepted_id: ProposalId::ZERO,
+        proposal_value: None,
+    })
+}
+
+/// Starts a decided file once a value is decided, so an empty decided value
+/// isn't taken for an undecided instance. Files written before the marker
+/// existed hold just the value.
+pub const DECIDED_MARKER: [u8; 8] = *b"PAXDECID";
+
+/// The contents of a decided file holding `value`, or its ciphertext.
+pub fn encode_decided(value: &[u8]) -> Vec<u8> {
+    let mut contents = DECIDED_MARKER.to_vec();
+    contents.extend_from_slice(value);
+    contents
+}
+
+/// The value, or its ciphertext, kept in a decided file. None while nothing
+/// was decided, that is while the file is empty.
+pub fn decode_decided(contents: &[u8]) -> Option<&[u8]> {
+    if contents.is_empty() {
+        return None;
+    }
+
+    Some(contents.strip_prefix(&DECIDED_MARKER).unwrap_or(contents))
+}
+
+/// Rewrites the records of a file of `version` in the current format,
+/// returning the whole file.
+pub fn upgrade(mut version: u8, records: &[u8]) -> Vec<u8> {
+    let mut records = records.to_vec();
+    let len = ProposalId::ENCODED_LEN;
+
+    while version < CURRENT_VERSION {
+        records = match version {
+            // Version 2 keeps the id the value was accepted in after the
+            // promise, unknown for version 1 values like in `decode`.
+            1 if records.len() > len => {
+                let mut upgraded = records[..len].to_vec();
+                upgraded.extend_from_slice(&ProposalId::ZERO.to_bytes());
+                upgraded.extend_from_slice(&records[len..]);
+                upgraded
+            }
+            1 => records,
+            _ => unreachable!("every older version has a migration"),
+        };
+        version += 1;
+    }
+
+    let mut contents = header().to_vec();
+    contents.extend_from_slice(&records);
+    contents
+}
+
+/// Migrates every state file in `data_dir` to the current version and returns
+/// the files that were rewritten. Only run it while the node is stopped.
+pub fn migrate_dir(data_dir: &Path) -> Result<Vec<PathBuf>> {
+    let entries = std::fs::read_dir(data_dir)
+        .with_context(|| format!("reading data dir {}", data_dir.display()))?;
+
+    let mut migrated = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        if path
+            .extension()
+            .map_or(true, |extension| extension != "state")
+        {
+            continue;
+        }
+
+        if migrate_file(&path).with_context(|| format!("migrating {}", path.display()))? {
+            migrated.push(path);
+        }
+    }
+
+    Ok(migrated)
+}
+
+/// Migrates the state file at `path`, returning false when it was current.
+/// The new contents are written next to it and renamed over it, so a crash
+/// leaves either version behind. The directory is synced after the rename so
+/// the migration itself survives a crash.
+fn migrate_file(path: &Path) -> Result<bool> {
+    let contents = std::fs::read(path).context("reading state file")?;
+    // Damaged files are left for an operator to look at.
+    decode(&contents)?;
+    let (version, records) = split(&contents)?;
+    if contents.is_empty() || version == CURRENT_VERSION {
+        return Ok(false);
+    }
+
+    let tmp = path.with_extension("state.tmp");
+    std::fs::write(&tmp, upgrade(version, records)).context("writing migrated state file")?;
+    std::fs::File::open(&tmp)
+        .and_then(|file| file.sync_all())
+        .context("syncing migrated state file")?;
+    std::fs::rename(&tmp, path).context("replacing state file")?;
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    std::fs::File::open(dir)
+        .and_then(|dir| dir.sync_all())
+        .context("syncing data dir")?;
+
+    info!(path = %path.display(), from = version, to = CURRENT_VERSION, "migrated state file");
+
+    Ok(true)
+}
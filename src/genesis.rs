@@ -0,0 +1,338 @@
+use anyhow::{anyhow, Context, Result};
+use ring::{
+    digest,
+    signature::{self, Ed25519KeyPair, KeyPair, UnparsedPublicKey},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashSet,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+};
+
+/// The document a cluster is bootstrapped from. Every node checks it was signed
+/// by all of the signing keys the operator trusts and refuses to talk to nodes that booted from a
+/// different one, so nodes configured with divergent memberships never form
+/// conflicting quorums.
+///
+/// ```toml
+/// [genesis]
+/// cluster_id = "prod-1"
+/// acceptors = ["10.0.0.1:8001", "10.0.0.2:8001", "10.0.0.3:8001"]
+/// quorum = 2
+/// signing_keys = ["<hex encoded ed25519 public key>"]
+///
+/// [[signatures]]
+/// key = "<hex encoded ed25519 public key>"
+/// signature = "<hex encoded signature>"
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SignedGenesis {
+    pub genesis: Genesis,
+
+    #[serde(default)]
+    pub signatures: Vec<Signature>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Genesis {
+    pub cluster_id: String,
+
+    /// The rpc address of every acceptor in the initial membership.
+    pub acceptors: Vec<SocketAddr>,
+
+    /// How many acceptors must respond for a phase to succeed. Defaults to a majority.
+    pub quorum: Option<usize>,
+
+    /// The hex encoded ed25519 public keys that must all sign the document.
+    /// Nodes only accept the document when these are the keys they were
+    /// configured to trust.
+    pub signing_keys: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Signature {
+    /// The hex encoded public key of the signer.
+    pub key: String,
+
+    /// The hex encoded signature of the genesis section.
+    pub signature: String,
+}
+
+/// The version of the manifest written by this build.
+const MANIFEST_VERSION: u32 = 1;
+
+/// What a node remembers about how it was bootstrapped.
+#[derive(Debug, Serialize, Deserialize)]
+struct Manifest {
+    #[serde(default = "first_manifest_version")]
+    version: u32,
+
+    /// The digest of the genesis document the node first booted from.
+    genesis: String,
+
+    /// The cluster id of that document. Missing from manifests written
+    /// before it was recorded.
+    #[serde(default)]
+    cluster_id: Option<String>,
+
+    /// The id of the node the data dir belongs to, missing like `cluster_id`.
+    #[serde(default)]
+    id: Option<u32>,
+}
+
+fn first_manifest_version() -> u32 {
+    1
+}
+
+impl Manifest {
+    fn new(genesis: &Genesis, id: u32) -> Result<Self> {
+        Ok(Self {
+            version: MANIFEST_VERSION,
+            genesis: genesis.digest()?,
+            cluster_id: Some(genesis.cluster_id.clone()),
+            id: Some(id),
+        })
+    }
+
+    /// Checks the data dir the manifest was found in belongs to node `id` of
+    /// the cluster bootstrapped from `genesis`.
+    fn check(&self, genesis: &Genesis, id: u32) -> Result<()> {
+        if self.version > MANIFEST_VERSION {
+            return Err(anyhow!(
+                "manifest version {} is newer than the supported version {MANIFEST_VERSION}",
+                self.version
+            ));
+        }
+
+        let digest = genesis.digest()?;
+        if self.genesis != digest {
+            return Err(anyhow!(
+                "genesis does not match the one the node booted from: cluster={:?} manifest={} genesis={digest}",
+                self.cluster_id,
+                self.genesis
+            ));
+        }
+
+        if let Some(recorded) = self.id {
+            if recorded != id {
+                return Err(anyhow!(
+                    "the data dir belongs to node {recorded}, not node {id}"
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Genesis {
+    /// The bytes that are signed. Json keeps the encoding independent of how the
+    /// document was formatted.
+    fn signed_bytes(&self) -> Result<Vec<u8>> {
+        serde_json::to_vec(self).context("encoding genesis")
+    }
+
+    /// Identifies the document, nodes only talk to nodes with the same digest.
+    pub fn digest(&self) -> Result<String> {
+        Ok(hex_encode(
+            digest::digest(&digest::SHA256, &self.signed_bytes()?).as_ref(),
+        ))
+    }
+
+    /// The quorum the cluster runs with.
+    pub fn quorum(&self) -> usize {
+        self.quorum.unwrap_or(self.acceptors.len() / 2 + 1)
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.cluster_id.is_empty() {
+            return Err(anyhow!("cluster_id must not be empty"));
+        }
+
+        if self.acceptors.is_empty() {
+            return Err(anyhow!("acceptors must not be empty"));
+        }
+
+        let unique: HashSet<_> = self.acceptors.iter().collect();
+        if unique.len() != self.acceptors.len() {
+            return Err(anyhow!("acceptors must not contain duplicates"));
+        }
+
+        // Any two quorums must intersect or two values could be chosen.
+        let quorum = self.quorum();
+        if quorum <= self.acceptors.len() / 2 || quorum > self.acceptors.len() {
+            return Err(anyhow!(
+                "quorum must be more than half of the {} acceptors and at most all of them: quorum={quorum}",
+                self.acceptors.len()
+            ));
+        }
+
+        if self.signing_keys.is_empty() {
+            return Err(anyhow!("signing_keys must not be empty"));
+        }
+
+        Ok(())
+    }
+}
+
+impl SignedGenesis {
+    /// Reads the document at `path` and checks it is valid and signed by all
+    /// of `trusted_keys`, see [SignedGenesis::verify].
+    pub async fn load(path: &Path, trusted_keys: &[String]) -> Result<Self> {
+        let contents = tokio::fs::read_to_string(path)
+            .await
+            .with_context(|| format!("reading genesis file {}", path.display()))?;
+
+        let genesis: SignedGenesis = toml::from_str(&contents)
+            .with_context(|| format!("parsing genesis file {}", path.display()))?;
+
+        genesis
+            .verify(trusted_keys)
+            .with_context(|| format!("invalid genesis file {}", path.display()))?;
+
+        Ok(genesis)
+    }
+
+    /// Checks the document is valid and signed by every one of `trusted_keys`,
+    /// the hex encoded public keys the operator configured. They never come
+    /// from the document, anyone can sign a document listing their own key.
+    pub fn verify(&self, trusted_keys: &[String]) -> Result<()> {
+        self.genesis.validate()?;
+
+        if trusted_keys.is_empty() {
+            return Err(anyhow!(
+                "no trusted signing keys to check the genesis document with, pass them with --genesis-keys"
+            ));
+        }
+
+        let listed: HashSet<_> = self.genesis.signing_keys.iter().collect();
+        let trusted: HashSet<_> = trusted_keys.iter().collect();
+        if listed != trusted {
+            return Err(anyhow!(
+                "signing_keys {:?} are not the trusted keys {trusted_keys:?}",
+                self.genesis.signing_keys
+            ));
+        }
+
+        let message = self.genesis.signed_bytes()?;
+
+        for key in trusted_keys {
+            let signature = self
+                .signatures
+                .iter()
+                .find(|signature| signature.key == *key)
+                .ok_or_else(|| anyhow!("missing signature from key {key}"))?;
+
+            let public_key = hex_decode(key).context("decoding signing key")?;
+            let signature_bytes = hex_decode(&signature.signature).context("decoding signature")?;
+
+            UnparsedPublicKey::new(&signature::ED25519, public_key)
+                .verify(&message, &signature_bytes)
+                .map_err(|_| anyhow!("invalid signature from key {key}"))?;
+        }
+
+        Ok(())
+    }
+
+    /// Signs the genesis section with the ed25519 key in `pkcs8`, replacing any
+    /// previous signature from the same key.
+    pub fn sign(&mut self, pkcs8: &[u8]) -> Result<()> {
+        let key_pair = Ed25519KeyPair::from_pkcs8_maybe_unchecked(pkcs8)
+            .map_err(|err| anyhow!("parsing signing key: {err}"))?;
+
+        let key = hex_encode(key_pair.public_key().as_ref());
+        let signature = hex_encode(key_pair.sign(&self.genesis.signed_bytes()?).as_ref());
+
+        self.signatures.retain(|existing| existing.key != key);
+        self.signatures.push(Signature { key, signature });
+
+        Ok(())
+    }
+
+    pub fn to_toml(&self) -> Result<String> {
+        toml::to_string(self).context("encoding genesis")
+    }
+}
+
+/// Records the genesis digest, cluster id and node id in the manifest in
+/// `data_dir` on first boot and checks they match on every boot after that.
+/// A node started without a genesis document can't use a data dir that has a
+/// manifest, it would talk to nodes of any cluster.
+pub async fn check_manifest(data_dir: &Path, genesis: Option<&Genesis>, id: u32) -> Result<()> {
+    let path = manifest_path(data_dir);
+
+    match (read_manifest(&path).await?, genesis) {
+        (None, None) => Ok(()),
+        (Some(manifest), None) => Err(anyhow!(
+            "the data dir was initialized for cluster {:?}, start the node with its genesis document",
+            manifest.cluster_id
+        )),
+        (Some(manifest), Some(genesis)) => manifest.check(genesis, id),
+        (None, Some(genesis)) => write_manifest(&path, &Manifest::new(genesis, id)?).await,
+    }
+}
+
+/// Prepares `data_dir` for node `id` of the cluster bootstrapped from
+/// `genesis` ahead of its first boot. Initializing a data dir again for the
+/// same node and cluster does nothing.
+pub async fn init(data_dir: &Path, genesis: &Genesis, id: u32) -> Result<()> {
+    tokio::fs::create_dir_all(data_dir)
+        .await
+        .with_context(|| format!("creating data dir {}", data_dir.display()))?;
+
+    let path = manifest_path(data_dir);
+    match read_manifest(&path).await? {
+        Some(manifest) => manifest
+            .check(genesis, id)
+            .context("the data dir was already initialized"),
+        None => write_manifest(&path, &Manifest::new(genesis, id)?).await,
+    }
+}
+
+fn manifest_path(data_dir: &Path) -> PathBuf {
+    data_dir.join("manifest.toml")
+}
+
+async fn read_manifest(path: &Path) -> Result<Option<Manifest>> {
+    match tokio::fs::read_to_string(path).await {
+        Ok(contents) => toml::from_str(&contents)
+            .with_context(|| format!("parsing manifest {}", path.display()))
+            .map(Some),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err).with_context(|| format!("reading manifest {}", path.display())),
+    }
+}
+
+async fn write_manifest(path: &Path, manifest: &Manifest) -> Result<()> {
+    let manifest = toml::to_string(manifest).context("encoding manifest")?;
+
+    tokio::fs::write(path, manifest)
+        .await
+        .with_context(|| format!("writing manifest {}", path.display()))
+}
+
+pub(crate) fn hex_encode(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+pub(crate) fn hex_decode(s: &str) -> Result<Vec<u8>> {
+    if s.len() % 2 != 0 {
+        return Err(anyhow!("hex string has an odd length"));
+    }
+
+    (0..s.len())
+        .step_by(2)
+        .map(|i| {
+            s.get(i..i + 2)
+                .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+                .ok_or_else(|| anyhow!("invalid hex string: {s}"))
+        })
+        .collect()
+}
@@ -0,0 +1,187 @@
+use anyhow::{anyhow, Result};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::{fmt, str::FromStr};
+
+/// Identifies a consensus instance. Ids are hierarchical, segments are separated by `/`
+/// so related instances can be grouped under a namespace, e.g. `app/feature/key`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct InstanceId(String);
+
+impl InstanceId {
+    pub const SEPARATOR: char = '/';
+
+    /// The longest id accepted, in bytes.
+    pub const MAX_LEN: usize = 255;
+
+    pub fn new(id: impl Into<String>) -> Result<Self> {
+        let id = id.into();
+        validate(&id)?;
+        Ok(Self(id))
+    }
+
+    /// Creates an id nested under this one.
+    pub fn child(&self, segment: &str) -> Result<Self> {
+        Self::new(format!("{}{}{segment}", self.0, Self::SEPARATOR))
+    }
+
+    pub fn segments(&self) -> impl Iterator<Item = &str> {
+        self.0.split(Self::SEPARATOR)
+    }
+
+    /// Returns true when `namespace` is this id or one of its ancestors.
+    pub fn is_in(&self, namespace: &InstanceId) -> bool {
+        let mut segments = self.segments();
+        namespace
+            .segments()
+            .all(|segment| segments.next() == Some(segment))
+    }
+
+    /// The id after this one when its last segment is a number, `log/8`
+    /// after `log/7`. Instances numbered this way form a log.
+    pub fn successor(&self) -> Option<Self> {
+        let (parent, last) = match self.0.rsplit_once(Self::SEPARATOR) {
+            Some((parent, last)) => (Some(parent), last),
+            None => (None, self.0.as_str()),
+        };
+
+        let number: u64 = last.parse().ok()?;
+        // `log/07` isn't followed by `log/8`.
+        if number.to_string() != last {
+            return None;
+        }
+
+        let next = number.checked_add(1)?;
+        match parent {
+            None => Self::new(next.to_string()).ok(),
+            Some(parent) => Self::new(format!("{parent}{}{next}", Self::SEPARATOR)).ok(),
+        }
+    }
+
+    /// A representation that can be used in file names. Segments can't contain `.`
+    /// so different ids always map to different keys.
+    pub fn storage_key(&self) -> String {
+        self.segments().collect::<Vec<_>>().join(".")
+    }
+
+    /// Encodes the id as a segment count followed by each length prefixed segment.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buffer = Vec::with_capacity(self.0.len() + 1);
+        let segments: Vec<&str> = self.segments().collect();
+        buffer.push(segments.len() as u8);
+        for segment in segments {
+            buffer.push(segment.len() as u8);
+            buffer.extend_from_slice(segment.as_bytes());
+        }
+        buffer
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let (&count, mut rest) = bytes
+            .split_first()
+            .ok_or_else(|| anyhow!("instance id is empty"))?;
+
+        let mut segments = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let (&len, tail) = rest
+                .split_first()
+                .ok_or_else(|| anyhow!("instance id is truncated"))?;
+            if tail.len() < len as usize {
+                return Err(anyhow!("instance id is truncated"));
+            }
+            let (segment, tail) = tail.split_at(len as usize);
+            segments.push(std::str::from_utf8(segment)?);
+            rest = tail;
+        }
+
+        if !rest.is_empty() {
+            return Err(anyhow!("instance id has trailing bytes"));
+        }
+
+        Self::new(segments.join("/"))
+    }
+}
+
+impl Default for InstanceId {
+    fn default() -> Self {
+        Self("default".to_owned())
+    }
+}
+
+impl fmt::Display for InstanceId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl FromStr for InstanceId {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Self::new(s)
+    }
+}
+
+fn validate(id: &str) -> Result<()> {
+    if id.is_empty() {
+        return Err(anyhow!("instance id must not be empty"));
+    }
+
+    if id.len() > InstanceId::MAX_LEN {
+        return Err(anyhow!(
+            "instance id must be at most {} bytes: {id}",
+            InstanceId::MAX_LEN
+        ));
+    }
+
+    for segment in id.split(InstanceId::SEPARATOR) {
+        if segment.is_empty() {
+            return Err(anyhow!("instance id must not have empty segments: {id}"));
+        }
+
+        if let Some(c) = segment
+            .chars()
+            .find(|c| !(c.is_ascii_alphanumeric() || *c == '_' || *c == '-'))
+        {
+            return Err(anyhow!("invalid character {c:?} in instance id: {id}"));
+        }
+    }
+
+    Ok(())
+}
+
+impl Serialize for InstanceId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.serialize_str(&self.0)
+        } else {
+            serializer.serialize_bytes(&self.to_bytes())
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for InstanceId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        if deserializer.is_human_readable() {
+            let id = String::deserialize(deserializer)?;
+            Self::new(id).map_err(de::Error::custom)
+        } else {
+            let bytes = <Vec<u8>>::deserialize(deserializer)?;
+            Self::from_bytes(&bytes).map_err(de::Error::custom)
+        }
+    }
+}
+
+#[cfg(feature = "schema")]
+impl schemars::JsonSchema for InstanceId {
+    fn schema_name() -> String {
+        "InstanceId".to_owned()
+    }
+
+    fn json_schema(gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        // Ids are strings in json, the only encoding used on the wire.
+        let mut schema = String::json_schema(gen).into_object();
+        schema.string().max_length = Some(Self::MAX_LEN as u32);
+        schema.string().pattern = Some("^[A-Za-z0-9_-]+(/[A-Za-z0-9_-]+)*$".to_owned());
+        schema.into()
+    }
+}
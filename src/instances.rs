@@ -0,0 +1,309 @@
+//! Many independent registers served from one process. Each one is a consensus
+//! instance, the group of a register: every rpc names the [InstanceId] it is
+//! for, acceptors keep a state file per instance, see
+//! [crate::paxos::file_prefix], and [Instances] is the group manager that
+//! opens, routes requests to and closes the instances.
+
+use anyhow::Result;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex as StdMutex},
+};
+use tokio::sync::Mutex;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    instance::InstanceId,
+    paxos::{self, AcceptorError, Decided, DecidedInstance, Paxos, PaxosBuilder, StateTransfer},
+    timeout::Timeouts,
+};
+
+/// The most decided values handed out in one [StateTransfer].
+const MAX_TRANSFER_INSTANCES: usize = 256;
+
+/// A [StateTransfer] takes no more values once it holds this many bytes.
+const MAX_TRANSFER_BYTES: usize = 4 << 20;
+
+/// The node of an instance. Locked while its files are being opened and
+/// while it is being closed, so they are never opened twice at once.
+type Slot = Arc<Mutex<SlotState>>;
+
+#[derive(Debug, Default)]
+struct SlotState {
+    node: Option<Arc<Mutex<Paxos>>>,
+    /// Set once the node was shut down and the slot left the map. Callers
+    /// that waited on it look the instance up again.
+    closed: bool,
+}
+
+/// Drives any number of instances from one handle. Every instance is a
+/// separate [Paxos] node with its own round counter, acceptor state and lock,
+/// so rounds for unrelated instances run concurrently instead of waiting on
+/// each other. The nodes share their connections to the acceptors.
+#[derive(Debug, Clone)]
+pub struct Instances {
+    /// Every instance is built from this, with the instance id replaced.
+    builder: Arc<StdMutex<PaxosBuilder>>,
+
+    /// None once the instances were shut down.
+    nodes: Arc<Mutex<Option<HashMap<InstanceId, Slot>>>>,
+}
+
+impl Instances {
+    pub fn new(builder: PaxosBuilder) -> Self {
+        Self {
+            builder: Arc::new(StdMutex::new(builder)),
+            nodes: Arc::new(Mutex::new(Some(HashMap::new()))),
+        }
+    }
+
+    /// Returns the node for `instance`, opening its state files the first time
+    /// the instance is used since the process started. Fails with
+    /// [AcceptorError::InvalidRequest] for an instance this node never
+    /// created, so the ids named in requests create no slots or files.
+    pub async fn get(&self, instance: &InstanceId) -> Result<Arc<Mutex<Paxos>>> {
+        self.open(instance, false).await
+    }
+
+    /// Like [Instances::get], but creates the instance when it doesn't exist.
+    pub async fn create(&self, instance: &InstanceId) -> Result<Arc<Mutex<Paxos>>> {
+        self.open(instance, true).await
+    }
+
+    async fn open(&self, instance: &InstanceId, create: bool) -> Result<Arc<Mutex<Paxos>>> {
+        loop {
+            let slot = {
+                let mut nodes = self.nodes.lock().await;
+                let nodes = nodes.as_mut().ok_or(AcceptorError::ShuttingDown)?;
+                match nodes.get(instance) {
+                    Some(slot) => Arc::clone(slot),
+                    None if !create && !self.builder().state_path(instance).exists() => {
+                        return Err(AcceptorError::InvalidRequest {
+                            reason: format!("unknown instance {instance}"),
+                        }
+                        .into());
+                    }
+                    None => Arc::clone(nodes.entry(instance.clone()).or_default()),
+                }
+            };
+
+            // The files are opened without holding the map, so instances that
+            // are already open are served meanwhile. Callers asking for the
+            // same instance wait for the first one to open it, or for it to be
+            // closed.
+            let mut slot = slot.lock().await;
+            if slot.closed {
+                continue;
+            }
+
+            if let Some(node) = &slot.node {
+                return Ok(Arc::clone(node));
+            }
+
+            let node = self.builder().instance(instance.clone()).build().await?;
+            let node = Arc::new(Mutex::new(node));
+            slot.node = Some(Arc::clone(&node));
+            return Ok(node);
+        }
+    }
+
+    /// The slots of the instances in the map.
+    async fn slots(&self) -> Vec<(InstanceId, Slot)> {
+        match &*self.nodes.lock().await {
+            None => Vec::new(),
+            Some(nodes) => nodes
+                .iter()
+                .map(|(id, slot)| (id.clone(), Arc::clone(slot)))
+                .collect(),
+        }
+    }
+
+    /// Proposes `value` in `instance` and returns the value decided in it.
+    /// Only waits for rounds of the same instance.
+    pub async fn propose(&self, instance: &InstanceId, value: Vec<u8>) -> Result<Decided> {
+        self.propose_with_cancel(instance, value, &CancellationToken::new())
+            .await
+    }
+
+    /// Like [Instances::propose], but gives up once `cancel` is cancelled, see
+    /// [Paxos::propose_with_cancel].
+    pub async fn propose_with_cancel(
+        &self,
+        instance: &InstanceId,
+        value: Vec<u8>,
+        cancel: &CancellationToken,
+    ) -> Result<Decided> {
+        let node = self.create(instance).await?;
+        paxos::propose_shared(&node, value, cancel).await
+    }
+
+    /// Answers a fetch state request with the values decided from `from` on.
+    pub async fn fetch_state(&self, from: &InstanceId) -> Result<StateTransfer> {
+        let mut transfer = StateTransfer::default();
+        let mut bytes = 0;
+        let mut next = Some(from.clone());
+
+        while let Some(instance) = next {
+            if transfer.decided.len() == MAX_TRANSFER_INSTANCES || bytes >= MAX_TRANSFER_BYTES {
+                transfer.more = true;
+                break;
+            }
+
+            let Some(decided) = self.decided(&instance).await? else {
+                break;
+            };
+            bytes += decided.value.len();
+            next = instance.successor();
+            transfer.decided.push(decided);
+        }
+
+        Ok(transfer)
+    }
+
+    /// The value decided in `instance`. An instance that isn't open is opened
+    /// through its slot to be read and closed again, so a transfer doesn't
+    /// leave every instance it went through open, and one without a decided
+    /// file isn't opened at all, so asking for arbitrary ids creates no files.
+    async fn decided(&self, instance: &InstanceId) -> Result<Option<DecidedInstance>> {
+        loop {
+            let slot = {
+                let mut nodes = self.nodes.lock().await;
+                let nodes = nodes.as_mut().ok_or(AcceptorError::ShuttingDown)?;
+                match nodes.get(instance) {
+                    Some(slot) => Arc::clone(slot),
+                    None if !self.builder().decided_path(instance).exists() => return Ok(None),
+                    None => Arc::clone(nodes.entry(instance.clone()).or_default()),
+                }
+            };
+
+            let mut state = slot.lock().await;
+            if state.closed {
+                continue;
+            }
+
+            if let Some(node) = &state.node {
+                return Ok(node.lock().await.decided_instance());
+            }
+
+            // Nobody can open the instance while the slot is held, so it is
+            // closed again before anyone else sees it.
+            let decided = {
+                let mut node = self.builder().instance(instance.clone()).build().await?;
+                let decided = node.decided_instance();
+                node.shutdown().await?;
+                decided
+            };
+            state.closed = true;
+            self.forget(instance, &slot).await;
+            return Ok(decided);
+        }
+    }
+
+    /// Removes `slot` from the map unless it was replaced already.
+    async fn forget(&self, instance: &InstanceId, slot: &Slot) {
+        if let Some(nodes) = self.nodes.lock().await.as_mut() {
+            if nodes
+                .get(instance)
+                .is_some_and(|current| Arc::ptr_eq(current, slot))
+            {
+                nodes.remove(instance);
+            }
+        }
+    }
+
+    /// Learns the values decided from `from` on from the acceptors, for a node
+    /// that missed them while it was down, and returns them in order.
+    pub async fn catch_up(&self, from: &InstanceId) -> Result<Vec<DecidedInstance>> {
+        let node = self.create(from).await?;
+        let fetch = node.lock().await.begin_fetch_state(from);
+        let transferred = fetch.run().await;
+
+        for decided in &transferred {
+            let node = self.create(&decided.instance).await?;
+            node.lock().await.learn_decided(decided.clone()).await?;
+        }
+
+        Ok(transferred)
+    }
+
+    /// The ids of the instances opened so far.
+    pub async fn ids(&self) -> Vec<InstanceId> {
+        let mut ids = Vec::new();
+        for (id, slot) in self.slots().await {
+            if slot.lock().await.node.is_some() {
+                ids.push(id);
+            }
+        }
+        ids
+    }
+
+    /// Replaces the timeouts of every instance, open or opened later. Rounds
+    /// already running keep the deadlines they started with.
+    pub async fn set_timeouts(&self, timeouts: Timeouts) {
+        {
+            let mut builder = self.builder.lock().unwrap();
+            *builder = builder.clone().timeouts(timeouts);
+        }
+
+        for (_, slot) in self.slots().await {
+            let node = slot.lock().await.node.clone();
+            if let Some(node) = node {
+                node.lock().await.set_timeouts(timeouts);
+            }
+        }
+    }
+
+    fn builder(&self) -> PaxosBuilder {
+        self.builder.lock().unwrap().clone()
+    }
+
+    /// Shuts `instance` down and forgets it, so a process serving many
+    /// instances doesn't keep the files of every one it ever used open. The
+    /// instance is opened from its files again the next time it is used.
+    ///
+    /// The instance stays in the map until its node is shut down. Callers
+    /// asking for it meanwhile wait and open it again afterwards, never while
+    /// the closing node still holds the files.
+    pub async fn close(&self, instance: &InstanceId) -> Result<()> {
+        let slot = match &*self.nodes.lock().await {
+            None => return Ok(()),
+            Some(nodes) => match nodes.get(instance) {
+                None => return Ok(()),
+                Some(slot) => Arc::clone(slot),
+            },
+        };
+
+        // Waits for the instance to finish opening.
+        let mut state = slot.lock().await;
+        if state.closed {
+            return Ok(());
+        }
+
+        if let Some(node) = &state.node {
+            node.lock().await.shutdown().await?;
+        }
+        state.node = None;
+        state.closed = true;
+        self.forget(instance, &slot).await;
+
+        Ok(())
+    }
+
+    /// Waits for every instance to finish the request it is handling and shuts
+    /// it down. No instance can be opened afterwards.
+    pub async fn shutdown(&self) -> Result<()> {
+        let Some(nodes) = self.nodes.lock().await.take() else {
+            return Ok(());
+        };
+
+        for slot in nodes.values() {
+            let mut state = slot.lock().await;
+            if let Some(node) = &state.node {
+                node.lock().await.shutdown().await?;
+            }
+            state.closed = true;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,70 @@
+//! Keepalives on the connections a node keeps to the other acceptors. A peer
+//! that went away is otherwise only noticed when a round sends it a request
+//! and waits out the rpc timeout. Pinging the cached connections finds out
+//! between rounds: a peer that misses enough pings is skipped by rounds, and
+//! reconnected to in the background until it answers again.
+
+use anyhow::{anyhow, Context, Result};
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeepaliveConfig {
+    /// How often every connection is pinged.
+    pub interval: Duration,
+
+    /// How long to wait for a peer to answer a ping.
+    pub timeout: Duration,
+
+    /// How many pings in a row a peer may miss before it is considered gone.
+    pub max_misses: u32,
+}
+
+impl Default for KeepaliveConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(1),
+            timeout: Duration::from_millis(500),
+            max_misses: 3,
+        }
+    }
+}
+
+impl KeepaliveConfig {
+    /// Reads KEEPALIVE_INTERVAL_MS, KEEPALIVE_TIMEOUT_MS and KEEPALIVE_MISSES.
+    /// Returns None when keepalives aren't enabled, that is when
+    /// KEEPALIVE_INTERVAL_MS isn't set.
+    pub fn from_env() -> Result<Option<Self>> {
+        let Ok(value) = std::env::var("KEEPALIVE_INTERVAL_MS") else {
+            return Ok(None);
+        };
+
+        let mut config = Self::default();
+
+        let millis: u64 = value
+            .parse()
+            .context("KEEPALIVE_INTERVAL_MS must be an integer")?;
+        if millis == 0 {
+            return Err(anyhow!("KEEPALIVE_INTERVAL_MS must be greater than 0"));
+        }
+        config.interval = Duration::from_millis(millis);
+        config.timeout = std::cmp::min(config.timeout, config.interval);
+
+        if let Ok(value) = std::env::var("KEEPALIVE_TIMEOUT_MS") {
+            let millis: u64 = value
+                .parse()
+                .context("KEEPALIVE_TIMEOUT_MS must be an integer")?;
+            config.timeout = Duration::from_millis(millis);
+        }
+
+        if let Ok(value) = std::env::var("KEEPALIVE_MISSES") {
+            config.max_misses = value
+                .parse()
+                .context("KEEPALIVE_MISSES must be an integer")?;
+            if config.max_misses == 0 {
+                return Err(anyhow!("KEEPALIVE_MISSES must be greater than 0"));
+            }
+        }
+
+        Ok(Some(config))
+    }
+}
@@ -0,0 +1,81 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, VecDeque},
+    net::SocketAddr,
+    time::Duration,
+};
+
+/// How many of the most recent samples are kept per peer.
+const WINDOW: usize = 128;
+
+/// Keeps the most recent rpc round trip times to each peer.
+#[derive(Debug, Default)]
+pub struct LatencyTracker {
+    samples: HashMap<SocketAddr, VecDeque<Duration>>,
+
+    /// Smoothed round trip time to each peer, weighing each new sample by 1/8
+    /// like TCP does.
+    estimates: HashMap<SocketAddr, Duration>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct LatencySummary {
+    pub samples: usize,
+    pub p50_micros: u64,
+    pub p99_micros: u64,
+    pub max_micros: u64,
+}
+
+impl LatencyTracker {
+    pub fn record(&mut self, peer: SocketAddr, rtt: Duration) {
+        let samples = self.samples.entry(peer).or_default();
+        if samples.len() == WINDOW {
+            samples.pop_front();
+        }
+        samples.push_back(rtt);
+
+        self.estimates
+            .entry(peer)
+            .and_modify(|estimate| *estimate = (*estimate * 7 + rtt) / 8)
+            .or_insert(rtt);
+    }
+
+    /// The smoothed round trip time to `peer`, None before the first sample.
+    pub fn estimate(&self, peer: SocketAddr) -> Option<Duration> {
+        self.estimates.get(&peer).copied()
+    }
+
+    /// The `count` peers with the lowest estimates, nearest first. Peers
+    /// without an estimate come first so they get one, ties keep the order of
+    /// `peers`.
+    pub fn nearest(&self, peers: &[SocketAddr], count: usize) -> Vec<SocketAddr> {
+        let mut peers = peers.to_vec();
+        peers.sort_by_key(|peer| self.estimate(*peer));
+        peers.truncate(count);
+        peers
+    }
+
+    pub fn summary(&self) -> HashMap<SocketAddr, LatencySummary> {
+        self.samples
+            .iter()
+            .filter(|(_, samples)| !samples.is_empty())
+            .map(|(peer, samples)| {
+                let mut sorted: Vec<Duration> = samples.iter().copied().collect();
+                sorted.sort_unstable();
+
+                let percentile = |p: usize| sorted[(sorted.len() - 1) * p / 100].as_micros() as u64;
+
+                (
+                    *peer,
+                    LatencySummary {
+                        samples: sorted.len(),
+                        p50_micros: percentile(50),
+                        p99_micros: percentile(99),
+                        max_micros: percentile(100),
+                    },
+                )
+            })
+            .collect()
+    }
+}
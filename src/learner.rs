@@ -0,0 +1,87 @@
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+use tokio::sync::mpsc;
+
+/// A batch of values decided in one instance.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Applied {
+    /// The position of the instance the batch was decided in.
+    pub slot: u64,
+    pub values: Vec<Vec<u8>>,
+}
+
+/// Hands out decided batches in slot order. Pipelined rounds can decide slot 3
+/// before slot 2, so batches decided ahead of a gap wait until it's filled.
+#[derive(Debug)]
+pub struct Learner {
+    /// The next slot to apply.
+    next: u64,
+
+    /// Batches decided ahead of `next`.
+    decided: BTreeMap<u64, Vec<u8>>,
+
+    apply: mpsc::UnboundedSender<Applied>,
+}
+
+impl Learner {
+    /// Returns the learner and the receiving end of the batches it applies.
+    pub fn new() -> (Self, mpsc::UnboundedReceiver<Applied>) {
+        let (apply, applied) = mpsc::unbounded_channel();
+        let learner = Self {
+            next: 0,
+            decided: BTreeMap::new(),
+            apply,
+        };
+        (learner, applied)
+    }
+
+    /// Records that `batch` was decided in `slot` and applies every batch that
+    /// is no longer waiting on an earlier slot.
+    pub fn learn(&mut self, slot: u64, batch: Vec<u8>) -> Result<()> {
+        if slot < self.next {
+            return Ok(());
+        }
+
+        self.decided.insert(slot, batch);
+
+        while let Some(batch) = self.decided.remove(&self.next) {
+            let values: Vec<Vec<u8>> = serde_json::from_slice(&batch)
+                .with_context(|| format!("decoding batch decided in slot {}", self.next))?;
+
+            // Skipped slots have nothing to apply. Nothing to do either when
+            // no one is listening.
+            if !values.is_empty() {
+                let _ = self.apply.send(Applied {
+                    slot: self.next,
+                    values,
+                });
+            }
+
+            self.next += 1;
+        }
+
+        Ok(())
+    }
+
+    /// The next slot to apply.
+    pub fn next(&self) -> u64 {
+        self.next
+    }
+
+    /// Whether a batch was learned in `slot`.
+    pub fn is_decided(&self, slot: u64) -> bool {
+        slot < self.next || self.decided.contains_key(&slot)
+    }
+
+    /// Whether batches were decided ahead of [Learner::next].
+    pub fn is_waiting(&self) -> bool {
+        !self.decided.is_empty()
+    }
+
+    /// One past the highest slot a batch was learned in.
+    pub fn frontier(&self) -> u64 {
+        self.decided
+            .last_key_value()
+            .map_or(self.next, |(slot, _)| slot + 1)
+    }
+}
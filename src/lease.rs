@@ -0,0 +1,240 @@
+//! Leader leases. A proposer that gets a lease from a quorum of acceptors is
+//! the only one proposing until the lease expires, and it keeps the lease by
+//! sending heartbeats. Other proposers wait instead of preempting its rounds.
+//!
+//! Acceptors enforce the leases they grant: while a grant is live they refuse
+//! prepare requests and heartbeats from every node but the holder. Grants are
+//! written to disk before they are answered, so a restarted acceptor keeps
+//! refusing the others until the grant expires. That lets the holder answer
+//! reads locally, see [crate::paxos::PaxosBuilder::lease_reads], as long as
+//! clocks run at about the same rate.
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    net::SocketAddr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::time::Instant;
+
+use crate::instance::InstanceId;
+
+/// How long leases last and how often the holder renews them.
+#[derive(Debug, Clone, Copy)]
+pub struct LeaseConfig {
+    /// How long an acceptor keeps a lease after a heartbeat.
+    pub duration: Duration,
+
+    /// How often the holder sends heartbeats. Must be well below `duration`
+    /// so a late heartbeat doesn't lose the lease.
+    pub heartbeat_interval: Duration,
+}
+
+impl LeaseConfig {
+    /// A lease of `duration` renewed three times per lease.
+    pub fn new(duration: Duration) -> Self {
+        Self {
+            duration,
+            heartbeat_interval: duration / 3,
+        }
+    }
+
+    /// Reads LEASE_MS and LEASE_HEARTBEAT_MS. Returns None when leases aren't
+    /// enabled, that is when LEASE_MS isn't set.
+    pub fn from_env() -> Result<Option<Self>> {
+        let Ok(value) = std::env::var("LEASE_MS") else {
+            return Ok(None);
+        };
+
+        let millis: u64 = value.parse().context("LEASE_MS must be an integer")?;
+        let mut config = Self::new(Duration::from_millis(millis));
+
+        if let Ok(value) = std::env::var("LEASE_HEARTBEAT_MS") {
+            let millis: u64 = value
+                .parse()
+                .context("LEASE_HEARTBEAT_MS must be an integer")?;
+            config.heartbeat_interval = Duration::from_millis(millis);
+        }
+
+        if config.heartbeat_interval.is_zero() || config.heartbeat_interval >= config.duration {
+            return Err(anyhow!(
+                "the lease heartbeat interval must be above zero and below LEASE_MS"
+            ));
+        }
+
+        Ok(Some(config))
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct HeartbeatRequest {
+    pub instance: InstanceId,
+
+    /// The id of the node asking for the lease.
+    pub proposer: u32,
+
+    /// How long the acceptor should keep the lease for.
+    pub duration: Duration,
+
+    /// Where the node asking for the lease serves clients, so the others can
+    /// forward proposals to it while it holds the lease.
+    #[serde(default)]
+    pub client_addr: Option<SocketAddr>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct HeartbeatResponse {
+    /// Whether the acceptor granted or renewed the lease.
+    pub granted: bool,
+
+    /// The node holding the lease after the heartbeat.
+    pub holder: u32,
+
+    /// How long until the lease expires on the acceptor.
+    pub remaining: Duration,
+
+    /// Where the node holding the lease serves clients, when it said so.
+    #[serde(default)]
+    pub holder_client_addr: Option<SocketAddr>,
+}
+
+/// The lease an acceptor granted.
+#[derive(Debug, Default)]
+pub(crate) struct Grant(Option<(u32, Option<SocketAddr>, Instant)>);
+
+/// The length of [Grant::encode].
+pub(crate) const GRANT_LEN: usize = 12;
+
+impl Grant {
+    /// Restores a grant written with [Grant::encode]. Grants that expired
+    /// while the acceptor was down are dropped.
+    pub(crate) fn decode(contents: &[u8]) -> Result<Self> {
+        if contents.is_empty() {
+            return Ok(Self::default());
+        }
+
+        let contents: [u8; GRANT_LEN] = contents.try_into().map_err(|_| {
+            anyhow!(
+                "lease file is {} bytes, expected {GRANT_LEN}",
+                contents.len()
+            )
+        })?;
+        let holder = u32::from_le_bytes(contents[..4].try_into().unwrap());
+        let expires_at = UNIX_EPOCH
+            + Duration::from_millis(u64::from_le_bytes(contents[4..].try_into().unwrap()));
+
+        Ok(match expires_at.duration_since(SystemTime::now()) {
+            Err(_) => Self::default(),
+            Ok(remaining) => Self(Some((holder, None, Instant::now() + remaining))),
+        })
+    }
+
+    /// What the lease file holds for the grant: the holder as u32 le followed
+    /// by when the grant expires, in milliseconds since the unix epoch as u64
+    /// le. Empty without a grant.
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        let Some((holder, _, expires_at)) = self.0 else {
+            return Vec::new();
+        };
+
+        let remaining = expires_at.saturating_duration_since(Instant::now());
+        // Rounded up, the grant must not end earlier after a restart.
+        let expires_at = (SystemTime::now() + remaining)
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64
+            + 1;
+
+        let mut contents = holder.to_le_bytes().to_vec();
+        contents.extend_from_slice(&expires_at.to_le_bytes());
+        contents
+    }
+
+    /// The holder of a live grant and how long it has left, unless that is
+    /// `proposer`. Anonymous proposers never hold a lease.
+    pub(crate) fn held_by_other(&self, proposer: Option<u32>) -> Option<(u32, Duration)> {
+        let (holder, _, expires_at) = self.0?;
+        let now = Instant::now();
+        (expires_at > now && Some(holder) != proposer).then(|| (holder, expires_at - now))
+    }
+
+    /// Grants the lease to the proposer of `request` unless another proposer
+    /// holds a lease that hasn't expired.
+    pub(crate) fn on_heartbeat(&mut self, request: &HeartbeatRequest) -> HeartbeatResponse {
+        let now = Instant::now();
+
+        if let Some((holder, holder_client_addr, expires_at)) = self.0 {
+            if holder != request.proposer && expires_at > now {
+                return HeartbeatResponse {
+                    granted: false,
+                    holder,
+                    remaining: expires_at - now,
+                    holder_client_addr,
+                };
+            }
+        }
+
+        self.0 = Some((
+            request.proposer,
+            request.client_addr,
+            now + request.duration,
+        ));
+
+        HeartbeatResponse {
+            granted: true,
+            holder: request.proposer,
+            remaining: request.duration,
+            holder_client_addr: request.client_addr,
+        }
+    }
+}
+
+/// Returned when a proposer refrains from proposing because another node
+/// holds the lease.
+#[derive(Debug)]
+pub struct LeaseHeld {
+    pub holder: u32,
+
+    /// How long until the lease expires unless the holder renews it.
+    pub remaining: Duration,
+
+    /// Where the holder serves clients, when it said so.
+    pub client_addr: Option<SocketAddr>,
+}
+
+impl fmt::Display for LeaseHeld {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "node {} holds the lease for another {:?}",
+            self.holder, self.remaining
+        )
+    }
+}
+
+impl std::error::Error for LeaseHeld {}
+
+/// Returned instead of [LeaseHeld] by nodes that forward proposals, when the
+/// holder of the lease can take them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NotLeader {
+    pub leader: u32,
+
+    /// Where the leader serves clients.
+    pub client_addr: SocketAddr,
+}
+
+impl fmt::Display for NotLeader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "node {} is the leader, propose to it at {}",
+            self.leader, self.client_addr
+        )
+    }
+}
+
+impl std::error::Error for NotLeader {}
@@ -0,0 +1,48 @@
+//! Single decree paxos. A [paxos::Paxos] node is both a proposer and, when its
+//! address is one of the acceptors, an acceptor that serves [paxos::AcceptorService].
+//! [instances::Instances] drives several instances concurrently from one handle.
+
+pub mod acceptor;
+pub mod antientropy;
+mod audit;
+pub mod auth;
+pub mod backpressure;
+pub mod channel;
+pub mod cli;
+pub mod client;
+pub mod commit;
+pub mod config;
+pub mod dedup;
+pub mod encryption;
+pub mod events;
+pub mod fault;
+pub mod format;
+pub mod genesis;
+pub mod instance;
+pub mod instances;
+pub mod keepalive;
+pub mod latency;
+pub mod learner;
+pub mod lease;
+pub mod map;
+pub mod membership;
+pub mod metrics;
+#[cfg(feature = "mmap")]
+pub mod mmap;
+pub mod paxos;
+pub mod proposal;
+pub mod protocol;
+pub mod queue;
+#[cfg(feature = "quic")]
+pub mod quic;
+pub mod ratelimit;
+pub mod register;
+pub mod rejoin;
+pub mod retry;
+#[cfg(feature = "sim")]
+pub mod sim;
+pub mod timeout;
+pub mod tls;
+pub mod transport;
+#[cfg(feature = "vertical")]
+pub mod vertical;
@@ -1,137 +1,1619 @@
 #![feature(inherent_associated_types)]
 
-use axum::{response::IntoResponse, routing::post, Extension, Router};
-use futures::{future, StreamExt};
-use tarpc::{
-    context, server,
-    server::{incoming::Incoming, Channel},
-    tokio_serde::formats::Json,
+use axum::{
+    body::Bytes,
+    extract::{Path, Query},
+    http::StatusCode,
+    response::IntoResponse,
+    routing::{get, post},
+    Extension, Json, Router,
 };
+use clap::{Args, Parser, Subcommand};
+use futures::StreamExt;
+use serde::Deserialize;
+use tarpc::{context, server, server::Channel};
 
-use std::{net::SocketAddr, sync::Arc};
+use std::{collections::HashMap, net::SocketAddr, path::PathBuf, sync::Arc, time::Duration};
 
 use tokio::{select, sync::Mutex};
+use tracing::{debug, error, info, warn};
+use tracing_subscriber::{
+    layer::{Layered, SubscriberExt},
+    reload,
+    util::SubscriberInitExt,
+    EnvFilter, Layer, Registry,
+};
 
-mod paxos;
-
-use paxos::{
-    AcceptRequest, AcceptResponse, AcceptorService, Paxos, PrepareRequest, PrepareResponse,
+use single_decree_paxos::{
+    acceptor::AcceptorServer,
+    antientropy::{self, AntiEntropyConfig},
+    auth::{Authenticator, Credentials},
+    backpressure::{InflightRequests, Limits},
+    cli::{self, AcceptorArgs, ClusterArgs, ConfigArgs},
+    client::{ClientService, PaxosClient, ProposeResponse},
+    config::{Config, LogFormat},
+    dedup::{Deduplicator, RequestId},
+    encryption::StateKey,
+    genesis::{self, Genesis, SignedGenesis},
+    instance::InstanceId,
+    instances::Instances,
+    keepalive::KeepaliveConfig,
+    latency::LatencySummary,
+    learner::Learner,
+    lease::{LeaseConfig, NotLeader},
+    map::{self, WriteOnceMap},
+    membership::{MemberUpdate, Membership, MembershipConfig},
+    metrics::{FanoutRecorder, PrometheusRecorder, Recorder, StatsdRecorder},
+    paxos::{self, AcceptorStatus, Decided, Paxos, PaxosBuilder},
+    queue::{BatchConfig, Priority, ProposalQueue, Rotation},
+    ratelimit::{RateLimiter, RateLimits},
+    rejoin::{self, Rejoin},
+    retry::{ContentionBackoff, RetryPolicy},
+    tls::TlsConfig,
+    transport::{self, Connector, Endpoint, Resolver},
 };
+use tokio_rustls::rustls::Certificate;
 
+/// Serves proposals submitted by processes outside the cluster.
 #[derive(Clone)]
-
-struct AcceptorServer {
+struct ClientServer {
+    id: u32,
     paxos: Arc<Mutex<Paxos>>,
+    connector: Connector,
+    queue: ProposalQueue,
+    dedup: Deduplicator,
+    map: WriteOnceMap,
+    authenticator: Arc<Authenticator>,
+    peer_certificate: Option<Certificate>,
 }
 
-impl AcceptorServer {
-    fn new(paxos: Arc<Mutex<Paxos>>) -> Self {
-        Self { paxos }
+#[tarpc::server]
+impl ClientService for ClientServer {
+    async fn propose(
+        self,
+        _: context::Context,
+        credentials: Credentials,
+        value: Vec<u8>,
+        priority: Priority,
+        request_id: Option<RequestId>,
+        forwarded_by: Option<u32>,
+    ) -> Result<ProposeResponse, String> {
+        self.authenticator
+            .authenticate(&credentials, self.peer_certificate.as_ref())
+            .map_err(|err| err.to_string())?;
+
+        if let Some(node) = forwarded_by {
+            debug!(node, "proposal forwarded by another node");
+        }
+
+        let paxos = Arc::clone(&self.paxos);
+        let dedup = self.dedup.clone();
+        let propose =
+            self.propose_or_forward(credentials, value, priority, request_id, forwarded_by);
+        let value = match request_id {
+            None => propose.await?,
+            Some(request_id) => dedup.run(request_id, propose).await?,
+        };
+
+        Ok(ProposeResponse {
+            value,
+            timing: paxos.lock().await.decision_timing(),
+        })
     }
-}
 
-#[tarpc::server]
-impl AcceptorService for AcceptorServer {
-    async fn prepare(
+    async fn put_once(
         self,
         _: context::Context,
-        request: PrepareRequest,
-    ) -> Result<PrepareResponse, String> {
-        let mut acceptor = self.paxos.lock().await;
+        credentials: Credentials,
+        key: String,
+        value: Vec<u8>,
+    ) -> Result<Vec<u8>, String> {
+        self.authenticator
+            .authenticate(&credentials, self.peer_certificate.as_ref())
+            .map_err(|err| err.to_string())?;
 
-        acceptor
-            .on_prepare(request)
+        self.map
+            .put_once(&key, value)
             .await
-            .map_err(|err| err.to_string())
+            .map(Decided::into_value)
+            .map_err(|err| format!("{err:#}"))
     }
 
-    async fn accept(
+    async fn get(
         self,
         _: context::Context,
-        request: AcceptRequest,
-    ) -> Result<AcceptResponse, String> {
-        let mut acceptor = self.paxos.lock().await;
+        credentials: Credentials,
+        key: String,
+    ) -> Result<Option<Vec<u8>>, String> {
+        self.authenticator
+            .authenticate(&credentials, self.peer_certificate.as_ref())
+            .map_err(|err| err.to_string())?;
 
-        acceptor
-            .on_accept(request)
+        self.map.get(&key).await.map_err(|err| format!("{err:#}"))
+    }
+}
+
+impl ClientServer {
+    /// Proposes `value` on this node, or on the leader when another node holds
+    /// the lease. Requests are forwarded at most once.
+    async fn propose_or_forward(
+        self,
+        credentials: Credentials,
+        value: Vec<u8>,
+        priority: Priority,
+        request_id: Option<RequestId>,
+        forwarded_by: Option<u32>,
+    ) -> Result<Vec<u8>, String> {
+        let err = match self.queue.propose(value.clone(), priority).await {
+            Ok(decided) => return Ok(decided.into_value()),
+            Err(err) => err,
+        };
+
+        let leader = match err.downcast::<NotLeader>() {
+            Ok(leader) if forwarded_by.is_none() => leader,
+            Ok(leader) => return Err(leader.to_string()),
+            Err(err) => return Err(format!("{err:#}")),
+        };
+
+        info!(leader = leader.leader, addr = %leader.client_addr, "forwarding proposal to the leader");
+        let client = PaxosClient::connect(&self.connector, leader.client_addr, credentials)
             .await
-            .map_err(|err| err.to_string())
+            .map_err(|err| format!("forwarding to the leader: {err:#}"))?;
+        let response = client
+            .forward(request_id, value, priority, self.id)
+            .await
+            .map_err(|err| format!("forwarding to the leader: {err:#}"))?;
+
+        Ok(response.value)
     }
 }
 
+/// Proposes `value` and returns the value the cluster decided on, which is
+/// another one when a different proposal won.
+async fn propose_decided(
+    queue: &ProposalQueue,
+    value: Vec<u8>,
+    priority: Priority,
+) -> Result<Vec<u8>, String> {
+    queue
+        .propose(value, priority)
+        .await
+        .map(Decided::into_value)
+        .map_err(|err| format!("{err:#}"))
+}
+
+#[derive(Parser)]
+#[command(version, about = "Single decree paxos")]
+struct Cli {
+    #[command(flatten)]
+    config: ConfigArgs,
+
+    /// `text` or `json`, which logs one json object per line for log aggregators.
+    #[arg(long, env = "LOG_FORMAT", global = true)]
+    log_format: Option<LogFormat>,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Runs a node with every role in one process sharing its state: the
+    /// acceptor serving proposers over rpc, the proposer of the values it
+    /// receives over http and client rpc, and the learner that catches up on
+    /// decisions it missed. With --acceptor-only it only runs the acceptor.
+    #[command(alias = "acceptor")]
+    Node(NodeArgs),
+
+    /// Proposes a value to the acceptors and prints the outcome.
+    Propose(ProposeArgs),
+
+    /// Prints the state of each acceptor.
+    Status(StatusArgs),
+
+    /// Adds a signature to a genesis document.
+    SignGenesis(SignGenesisArgs),
+
+    /// Prepares the data dir of a node before its first boot, recording the
+    /// node id and the cluster from the genesis document. The node refuses to
+    /// start from the data dir as another node or in another cluster.
+    Init(InitArgs),
+
+    /// Wipes the state of an acceptor started with --allow-unsafe-admin.
+    /// Only for test clusters.
+    ResetState(ResetStateArgs),
+
+    /// Runs a cluster of nodes on this machine for experimenting, each with its
+    /// own data dir. Ctrl-C stops every node.
+    LocalCluster(LocalClusterArgs),
+
+    /// Migrates the state files in a data dir to the format of this build.
+    /// Nodes migrate their files when they start, this does it ahead of time.
+    /// The node must be stopped.
+    Migrate(MigrateArgs),
+
+    /// Prints the state an acceptor persisted in its data dir without contacting it.
+    /// Only run it while the acceptor is stopped.
+    #[cfg(feature = "mmap")]
+    Inspect(InspectArgs),
+}
+
+/// Exits when the acceptors in `args` or the config file can't be resolved.
+async fn resolve_cluster(
+    args: ClusterArgs,
+    config: &mut Config,
+    genesis: Option<&Genesis>,
+) -> (Vec<SocketAddr>, Resolver, InstanceId) {
+    match args.resolve(config, genesis).await {
+        Err(err) => {
+            error!("{err:#}");
+            std::process::exit(1);
+        }
+        Ok(v) => v,
+    }
+}
+
+#[derive(Args)]
+struct NodeArgs {
+    #[command(flatten)]
+    acceptor: AcceptorArgs,
+
+    /// The address or host name and port the http server listens on.
+    /// Defaults to 0.0.0.0:300{id}, or [::]:300{id} when the rpc server
+    /// listens on an IPv6 address.
+    #[arg(long, env = "HTTP_LISTEN")]
+    http_listen: Option<Endpoint>,
+
+    /// Don't start the http server.
+    #[arg(long, env = "DISABLE_HTTP")]
+    disable_http: bool,
+
+    /// Only run the acceptor: no http or client rpc server, and the node
+    /// neither proposes nor renews a lease. Proposers such as
+    /// `paxos-proposer` reach it over rpc. `paxos-acceptor` does the same
+    /// without building the servers.
+    #[arg(long, env = "ACCEPTOR_ONLY")]
+    acceptor_only: bool,
+
+    /// The address or host name and port the client rpc server listens on.
+    /// Defaults to 0.0.0.0:700{id}, or [::]:700{id} like --http-listen.
+    #[arg(long, env = "CLIENT_LISTEN")]
+    client_listen: Option<Endpoint>,
+
+    #[command(flatten)]
+    cluster: ClusterArgs,
+}
+
+#[derive(Args)]
+struct ProposeArgs {
+    /// The value to propose.
+    #[arg(long)]
+    value: String,
+
+    /// Submit the value through the client rpc server of this node instead of
+    /// running the protocol against the acceptors directly.
+    #[arg(long, env = "NODE")]
+    node: Option<Endpoint>,
+
+    /// The priority of the proposal on the node, only used with --node.
+    #[arg(long, default_value = "normal")]
+    priority: Priority,
+
+    #[command(flatten)]
+    cluster: ClusterArgs,
+}
+
+#[derive(Args)]
+struct StatusArgs {
+    /// Print everything each acceptor persisted, as json, instead of a summary.
+    #[arg(long)]
+    state: bool,
+
+    #[command(flatten)]
+    cluster: ClusterArgs,
+}
+
+#[derive(Args)]
+struct ResetStateArgs {
+    /// The rpc address, host name and port or unix socket of the acceptor to reset.
+    acceptor: Endpoint,
+
+    /// Must be "reset acceptor <id> <instance>" for the acceptor being reset.
+    #[arg(long)]
+    confirm: String,
+
+    /// The consensus instance. Defaults to `default`.
+    #[arg(long, env = "INSTANCE")]
+    instance: Option<InstanceId>,
+}
+
+#[derive(Args)]
+struct LocalClusterArgs {
+    /// How many nodes to run.
+    #[arg(long, default_value_t = 3)]
+    nodes: u16,
+
+    /// Where the data dirs and logs of the nodes are kept, one directory per
+    /// node. Defaults to a directory in the system temp dir.
+    #[arg(long)]
+    dir: Option<PathBuf>,
+
+    /// The rpc port of the first node, the others use the ports after it.
+    #[arg(long, default_value_t = 8001)]
+    rpc_port: u16,
+
+    /// The http port of the first node, the others use the ports after it.
+    #[arg(long, default_value_t = 3001)]
+    http_port: u16,
+
+    /// The client rpc port of the first node, the others use the ports after it.
+    #[arg(long, default_value_t = 7001)]
+    client_port: u16,
+}
+
+#[derive(Args)]
+struct InitArgs {
+    /// The id of the node the data dir is for.
+    #[arg(long, env = "ID")]
+    id: Option<u32>,
+
+    /// The directory the state files are kept in. Defaults to the working directory.
+    #[arg(long, env = "DATA_DIR")]
+    data_dir: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct MigrateArgs {
+    /// The directory the state files are kept in.
+    #[arg(long, env = "DATA_DIR", default_value = ".")]
+    data_dir: PathBuf,
+}
+
+#[derive(Args)]
+struct SignGenesisArgs {
+    /// The genesis document, the signature is added to it in place.
+    #[arg(long)]
+    file: PathBuf,
+
+    /// A pkcs8 encoded ed25519 private key, e.g. from
+    /// `openssl genpkey -algorithm ed25519 -outform DER`.
+    #[arg(long)]
+    key: PathBuf,
+}
+
+#[cfg(feature = "mmap")]
+#[derive(Args)]
+struct InspectArgs {
+    /// The id of the acceptor whose files are read.
+    #[arg(long, env = "ID")]
+    id: u32,
+
+    /// The directory the state files are kept in.
+    #[arg(long, env = "DATA_DIR", default_value = ".")]
+    data_dir: PathBuf,
+
+    /// The consensus instance.
+    #[arg(long, env = "INSTANCE", default_value_t = InstanceId::default())]
+    instance: InstanceId,
+}
+
+/// How long shutdown waits for the request being handled to finish.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How often a node checks whether its config file changed.
+const CONFIG_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Replaces the log filter while the node runs.
+type LogFilter =
+    reload::Handle<EnvFilter, Layered<Vec<Box<dyn Layer<Registry> + Send + Sync>>, Registry>>;
+
+/// The filter from the config file, unless RUST_LOG is set, then info.
+fn log_filter(filter: Option<&str>) -> EnvFilter {
+    EnvFilter::try_from_default_env()
+        .ok()
+        .or_else(|| filter.and_then(|filter| EnvFilter::try_new(filter).ok()))
+        .unwrap_or_else(|| EnvFilter::new("info"))
+}
+
+/// Logs to stderr in `format`, filtered by RUST_LOG, then by the filter from
+/// the config file, then at info. Builds with the otel feature also export
+/// spans when OTEL_EXPORTER_OTLP_ENDPOINT is set.
+fn init_tracing(format: LogFormat, filter: Option<&str>) -> LogFilter {
+    let (filter, handle) = reload::Layer::new(log_filter(filter));
+
+    let logs = tracing_subscriber::fmt::layer().with_writer(std::io::stderr);
+    #[allow(unused_mut)]
+    let mut layers = vec![match format {
+        LogFormat::Json => logs.json().boxed(),
+        LogFormat::Text => logs.boxed(),
+    }];
+
+    #[cfg(feature = "otel")]
+    layers.extend(otel_layer());
+
+    tracing_subscriber::registry()
+        .with(layers)
+        .with(filter)
+        .init();
+
+    handle
+}
+
+/// Exports spans to the collector at OTEL_EXPORTER_OTLP_ENDPOINT. tarpc
+/// sends the trace context of the current span with every request and
+/// servers continue the trace, so a proposal shows up as one trace from
+/// the client call through the phases to the acceptors' writes.
+#[cfg(feature = "otel")]
+fn otel_layer() -> Option<Box<dyn Layer<tracing_subscriber::Registry> + Send + Sync>> {
+    use opentelemetry_otlp::WithExportConfig;
+
+    std::env::var("OTEL_EXPORTER_OTLP_ENDPOINT").ok()?;
+
+    let tracer = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(opentelemetry_otlp::new_exporter().tonic().with_env())
+        .install_batch(opentelemetry::runtime::Tokio)
+        .expect("installing the otlp exporter");
+
+    Some(tracing_opentelemetry::layer().with_tracer(tracer).boxed())
+}
+
 #[tokio::main]
 async fn main() {
-    let id: u32 = std::env::var("ID")
-        .expect("ID env variable is required")
-        .parse()
-        .expect("ID must be an integer");
+    let cli = Cli::parse();
+
+    let config = cli.config.load_config().await;
+
+    // Logging is set up from the config file, so errors reading it are only
+    // logged once it is.
+    let log = config.as_ref().map(|config| &config.log).ok();
+    let log_filter = init_tracing(
+        cli.log_format
+            .or_else(|| log.and_then(|log| log.format))
+            .unwrap_or_default(),
+        log.and_then(|log| log.filter.as_deref()),
+    );
+
+    let config = match config {
+        Err(err) => {
+            error!("{err:#}");
+            std::process::exit(1);
+        }
+        Ok(v) => v,
+    };
+
+    let genesis = match cli.config.load_genesis(&config).await {
+        Err(err) => {
+            error!("{err:#}");
+            std::process::exit(1);
+        }
+        Ok(v) => v,
+    };
+
+    match cli.command {
+        Command::Node(args) => {
+            let reload = cli.config.config.map(|path| Reload {
+                path,
+                config: config.clone(),
+                log_filter,
+            });
+            run_node(args, config, genesis, reload).await
+        }
+        Command::Propose(args) => run_propose(args, config, genesis).await,
+        Command::Status(args) => run_status(args, config, genesis).await,
+        Command::SignGenesis(args) => run_sign_genesis(args).await,
+        Command::Init(args) => run_init(args, config, genesis).await,
+        Command::Migrate(args) => run_migrate(args),
+        Command::ResetState(args) => run_reset_state(args, config, genesis).await,
+        Command::LocalCluster(args) => run_local_cluster(args).await,
+        #[cfg(feature = "mmap")]
+        Command::Inspect(args) => run_inspect(args),
+    }
+}
+
+/// The address a server listens on, `default` when none was configured. Exits
+/// when it doesn't resolve or is a unix socket.
+async fn bind_addr(
+    endpoint: Option<Endpoint>,
+    default: impl FnOnce() -> String,
+    flag: &str,
+) -> SocketAddr {
+    let endpoint = match endpoint {
+        Some(endpoint) => endpoint,
+        None => default()
+            .parse()
+            .unwrap_or_else(|_| panic!("can't derive the address from the id, pass {flag}")),
+    };
+
+    match endpoint.bind_addr().await {
+        Err(err) => {
+            error!("{flag}: {err:#}");
+            std::process::exit(1);
+        }
+        Ok(addr) => addr,
+    }
+}
+
+/// Resolves a single node passed on the command line. The resolver looks its
+/// host name up again when connecting, like the acceptors'.
+async fn resolve_one(endpoint: &Endpoint) -> (SocketAddr, Resolver) {
+    match Resolver::resolve(std::slice::from_ref(endpoint)).await {
+        Err(err) => {
+            error!("resolving {endpoint}: {err:#}");
+            std::process::exit(1);
+        }
+        Ok((addrs, resolver)) => (addrs[0], resolver),
+    }
+}
+
+/// The credentials sent to the acceptors, tied to the genesis document when there is one.
+fn credentials(genesis: Option<&Genesis>) -> Credentials {
+    Credentials::for_cluster(genesis).expect("reading credentials")
+}
+
+fn connector_from_env(tls: Option<&TlsConfig>) -> Connector {
+    Connector::from_env(tls).expect("building connector")
+}
+
+fn quic_from_env() -> bool {
+    transport::quic_from_env().expect("reading TRANSPORT")
+}
+
+/// Applies the settings read from the config file and env variables.
+async fn configure(
+    mut builder: PaxosBuilder,
+    config: &Config,
+    resolver: &Resolver,
+) -> PaxosBuilder {
+    builder = builder
+        .timeouts(config.timeouts().with_env().expect("reading timeouts"))
+        .retry_policy(RetryPolicy::from_env().expect("reading retry policy"))
+        .contention_backoff(ContentionBackoff::from_env().expect("reading contention backoff"));
+
+    if let Ok(fanout) = std::env::var("RELAY_FANOUT") {
+        builder = builder.relay_fanout(fanout.parse().expect("RELAY_FANOUT must be an integer"));
+    }
+
+    if let Ok(selection) = std::env::var("QUORUM_SELECTION") {
+        builder = builder.quorum_selection(selection.parse().expect("invalid QUORUM_SELECTION"));
+    }
+
+    if let Ok(size) = std::env::var("VALUE_CHUNK_SIZE") {
+        builder = builder.chunk_size(size.parse().expect("VALUE_CHUNK_SIZE must be an integer"));
+    }
+
+    if let Ok(size) = std::env::var("MAX_VALUE_SIZE") {
+        builder = builder.max_value_size(size.parse().expect("MAX_VALUE_SIZE must be an integer"));
+    }
+
+    if let Ok(millis) = std::env::var("DECISION_SLO_MS") {
+        builder = builder.decision_slo(Duration::from_millis(
+            millis.parse().expect("DECISION_SLO_MS must be an integer"),
+        ));
+    }
+
+    if let Ok(millis) = std::env::var("GROUP_COMMIT_MS") {
+        builder = builder.group_commit(Duration::from_millis(
+            millis.parse().expect("GROUP_COMMIT_MS must be an integer"),
+        ));
+    }
+
+    if let Some(lease) = LeaseConfig::from_env().expect("reading lease config") {
+        builder = builder.lease(lease);
+    }
+
+    if let Ok(reads) = std::env::var("LEASE_READS") {
+        builder = builder.lease_reads(reads.parse().expect("LEASE_READS must be true or false"));
+    }
+
+    if let Some(auxiliary) = acceptors_from_env("AUXILIARY_ACCEPTORS", resolver).await {
+        builder = builder.auxiliary_acceptors(auxiliary);
+    }
+
+    if let Some(witnesses) = acceptors_from_env("WITNESSES", resolver).await {
+        builder = builder.witnesses(witnesses);
+    }
 
-    assert!(id > 0 && id <= 3, "id must be between 0 and 3");
+    if let Some(max) = Limits::from_env()
+        .expect("reading limits")
+        .max_outstanding_rpcs
+    {
+        builder = builder.max_outstanding_rpcs(max);
+    }
+
+    if let Some(key) = StateKey::from_env().expect("reading state key") {
+        builder = builder.state_key(key);
+    }
+
+    if let Ok(audit) = std::env::var("AUDIT") {
+        builder = builder.audit(audit.parse().expect("AUDIT must be true or false"));
+    }
+
+    if let Some(keepalive) = KeepaliveConfig::from_env().expect("reading keepalive config") {
+        builder = builder.keepalive(keepalive);
+    }
+
+    builder
+}
 
-    let http_server_addr: SocketAddr = format!("0.0.0.0:300{id}").parse().unwrap();
+/// The addresses of the acceptors listed in `var`, written like ACCEPTORS.
+/// None when `var` isn't set.
+async fn acceptors_from_env(var: &str, resolver: &Resolver) -> Option<Vec<SocketAddr>> {
+    let endpoints = std::env::var(var).ok()?;
+    let endpoints = Endpoint::parse_list(&endpoints)
+        .unwrap_or_else(|err| panic!("{var} must be a comma separated list of acceptors: {err:#}"));
 
-    let rpc_server_addr: SocketAddr = format!("127.0.0.1:800{id}")
+    let mut addrs = Vec::with_capacity(endpoints.len());
+    for endpoint in &endpoints {
+        let addr = resolver
+            .known_addr(endpoint)
+            .await
+            .unwrap_or_else(|err| panic!("resolving {endpoint} in {var}: {err:#}"));
+        addrs.push(addr);
+    }
+    Some(addrs)
+}
+
+/// The StatsD recorder configured with STATSD_ADDR and STATSD_PREFIX, None when
+/// STATSD_ADDR isn't set.
+async fn statsd_from_env(resolver: &Resolver) -> Option<StatsdRecorder> {
+    let endpoint: Endpoint = std::env::var("STATSD_ADDR")
+        .ok()?
         .parse()
-        .expect("invalid socket addr");
+        .expect("STATSD_ADDR must be an address or host name and port");
+    if let Endpoint::Unix(_) = endpoint {
+        panic!("STATSD_ADDR must be an address or host name and port, not a unix socket");
+    }
+
+    let addr = resolver
+        .known_addr(&endpoint)
+        .await
+        .expect("resolving STATSD_ADDR");
+    let prefix = std::env::var("STATSD_PREFIX").unwrap_or_default();
+    let recorder = StatsdRecorder::new(addr, prefix).expect("creating statsd recorder");
+    Some(recorder)
+}
+
+async fn run_node(
+    args: NodeArgs,
+    mut config: Config,
+    genesis: Option<Genesis>,
+    reload: Option<Reload>,
+) {
+    let id = match args.acceptor.id(&config) {
+        Err(err) => {
+            error!("{err:#}");
+            std::process::exit(1);
+        }
+        Ok(v) => v,
+    };
+
+    let rpc_listen: Endpoint = args.acceptor.listen.or(config.listen).unwrap_or_else(|| {
+        format!("127.0.0.1:800{id}")
+            .parse()
+            .expect("can't derive rpc address from id, pass --listen")
+    });
+    let rpc_server_addr = rpc_listen.lookup().await.expect("resolving listen address");
+
+    // The other servers listen on every address of the family the rpc server uses.
+    let unspecified = match rpc_server_addr {
+        SocketAddr::V6(_) if !matches!(rpc_listen, Endpoint::Unix(_)) => "[::]",
+        _ => "0.0.0.0",
+    };
+
+    let http_server_addr = bind_addr(
+        args.http_listen.or(config.http_listen.take()),
+        || format!("{unspecified}:300{id}"),
+        "--http-listen",
+    )
+    .await;
 
-    let acceptors = (1..=3)
-        .map(|id| format!("127.0.0.1:800{id}").parse().unwrap())
+    let client_server_addr = bind_addr(
+        args.client_listen.or(config.client_listen.take()),
+        || format!("{unspecified}:700{id}"),
+        "--client-listen",
+    )
+    .await;
+
+    // Nodes that are not the leader forward proposals here. The address of a
+    // unix socket is only a placeholder, nodes on unix sockets share a host.
+    let advertised_client_addr = match (&rpc_listen, client_server_addr.ip().is_unspecified()) {
+        (_, false) => client_server_addr,
+        (Endpoint::Unix(_), true) => SocketAddr::from(([127, 0, 0, 1], client_server_addr.port())),
+        (_, true) => SocketAddr::new(rpc_server_addr.ip(), client_server_addr.port()),
+    };
+
+    let tls = TlsConfig::from_env().expect("reading tls config");
+
+    let tls_acceptor = tls
+        .as_ref()
+        .map(|tls| tls.acceptor())
+        .transpose()
+        .expect("building tls acceptor");
+
+    let mut authenticator = Authenticator::from_env().expect("reading auth config");
+    if let Some(genesis) = &genesis {
+        authenticator.set_genesis(genesis.digest().expect("hashing genesis"));
+    }
+    let authenticator = Arc::new(authenticator);
+
+    let data_dir = args
+        .acceptor
+        .data_dir
+        .or_else(|| config.data_dir.take())
+        .unwrap_or_else(|| PathBuf::from("."));
+
+    tokio::fs::create_dir_all(&data_dir)
+        .await
+        .expect("creating data dir");
+
+    let (acceptors, resolver, instance) =
+        resolve_cluster(args.cluster, &mut config, genesis.as_ref()).await;
+    let connector = connector_from_env(tls.as_ref()).with_resolver(resolver.clone());
+
+    let quorum = genesis.as_ref().map(|genesis| genesis.quorum());
+    if let Err(err) = paxos::check_topology(&acceptors, quorum, Some(rpc_server_addr)) {
+        error!("{err}");
+        std::process::exit(1);
+    }
+
+    let rotation =
+        Rotation::from_env(rpc_server_addr, &acceptors).expect("reading rotation config");
+
+    let membership = MembershipConfig::from_env()
+        .expect("reading gossip config")
+        .map(|config| {
+            info!(probe_interval = ?config.probe_interval, "failure detection enabled");
+            Membership::new(
+                rpc_server_addr,
+                &acceptors,
+                connector.clone(),
+                credentials(genesis.as_ref()),
+                config,
+            )
+        });
+
+    // Served on /metrics, and sent to StatsD too when STATSD_ADDR is set.
+    let prometheus = Arc::new(PrometheusRecorder::default());
+    let recorder: Arc<dyn Recorder> = match statsd_from_env(&resolver).await {
+        None => Arc::clone(&prometheus) as _,
+        Some(statsd) => Arc::new(FanoutRecorder::new(vec![
+            Arc::clone(&prometheus) as _,
+            Arc::new(statsd),
+        ])),
+    };
+
+    // Until the node knows whether it lost its data it votes in nothing. The
+    // rpc server has to answer the announcements of the peers meanwhile, so
+    // finding out happens in the background.
+    let cluster = match &genesis {
+        Some(genesis) => genesis.cluster_id.clone(),
+        None => rejoin::cluster_from_acceptors(&acceptors),
+    };
+    let rejoin = Rejoin::open(&data_dir, cluster)
+        .await
+        .expect("reading rejoin records");
+    let peers: Vec<SocketAddr> = acceptors
+        .iter()
+        .copied()
+        .filter(|acceptor| *acceptor != rpc_server_addr)
         .collect();
 
-    let paxos = Arc::new(Mutex::new(
-        Paxos::new(id, rpc_server_addr, acceptors)
-            .await
-            .expect("instantiating paxos instance"),
-    ));
+    let mut builder = Paxos::builder(id, rpc_server_addr, acceptors)
+        .metrics(recorder)
+        .instance(instance.clone())
+        .connector(connector.clone())
+        .credentials(credentials(genesis.as_ref()))
+        .forward_to_leader(advertised_client_addr)
+        .rejoin(rejoin.clone());
+
+    if let Some(membership) = &membership {
+        builder = builder.membership(membership.clone());
+        tokio::spawn(membership.clone().run());
+    }
+
+    if let Err(err) = genesis::check_manifest(&data_dir, genesis.as_ref(), id).await {
+        error!("checking the data dir manifest: {err:#}");
+        std::process::exit(1);
+    }
+
+    if let Some(genesis) = &genesis {
+        builder = builder.quorum(genesis.quorum());
+    }
+
+    // Acceptors serve every instance proposers created, the one configured is
+    // the one this node proposes to and reports on.
+    let instances = Instances::new(configure(builder.data_dir(data_dir), &config, &resolver).await);
+    let paxos = instances
+        .create(&instance)
+        .await
+        .expect("instantiating paxos instance");
+
+    tokio::spawn(heal(Arc::clone(&paxos)));
+
+    if let Some(reload) = reload {
+        tokio::spawn(reload.run(instances.clone()));
+    }
+
+    tokio::spawn({
+        let (rejoin, instances, connector, credentials) = (
+            rejoin.clone(),
+            instances.clone(),
+            connector.clone(),
+            credentials(genesis.as_ref()),
+        );
+        async move {
+            if let Err(err) = rejoin.boot(id, &peers, &connector, &credentials).await {
+                error!("finding out whether the node lost its data: {err:#}");
+                std::process::exit(1);
+            }
+            rejoin::run(instances, rejoin).await
+        }
+    });
+
+    if let Some(config) = AntiEntropyConfig::from_env().expect("reading anti-entropy config") {
+        info!(interval = ?config.interval, jitter = config.jitter, "anti-entropy enabled");
+        tokio::spawn(antientropy::run(instances.clone(), config));
+    }
+
+    let acceptor_only = args.acceptor_only;
+    if acceptor_only {
+        info!("running the acceptor only");
+    }
+
+    let lease = paxos.lock().await.lease_config();
+    if let (Some(lease), false) = (lease, acceptor_only) {
+        info!(duration = ?lease.duration, heartbeat_interval = ?lease.heartbeat_interval, "leases enabled");
+        tokio::spawn(renew_lease(Arc::clone(&paxos), lease.heartbeat_interval));
+    }
+
+    let batch = match (
+        BatchConfig::from_env().expect("reading batch config"),
+        rotation,
+    ) {
+        (None, None) => None,
+        // Rotating slots are only used to decide batches.
+        (batch, rotation) => Some(BatchConfig {
+            rotation,
+            ..batch.unwrap_or_default()
+        }),
+    };
+
+    let mut queue = match batch {
+        None => ProposalQueue::spawn(Arc::clone(&paxos)),
+        Some(batch) => {
+            info!(
+                max_size = batch.max_size,
+                linger = ?batch.linger,
+                pipeline = batch.pipeline,
+                "batching proposals"
+            );
+            if let Some(rotation) = batch.rotation {
+                info!(
+                    index = rotation.index,
+                    nodes = rotation.nodes,
+                    revoke_after = ?rotation.revoke_after,
+                    "rotating slots between nodes"
+                );
+            }
+            let (learner, mut applied) = Learner::new();
+            tokio::spawn(async move {
+                while let Some(applied) = applied.recv().await {
+                    info!(
+                        slot = applied.slot,
+                        values = applied.values.len(),
+                        "applied batch"
+                    );
+                }
+            });
+            ProposalQueue::spawn_batched(instances.clone(), instance.clone(), batch, learner)
+        }
+    };
+    if let Some(max) = Limits::from_env()
+        .expect("reading limits")
+        .max_pending_proposals
+    {
+        info!(max, "capping pending proposals");
+        queue = queue.max_pending(max);
+    }
+
+    let dedup = Deduplicator::default();
+    let map = WriteOnceMap::new(
+        instances.clone(),
+        map::DEFAULT_NAMESPACE.parse().expect("valid namespace"),
+    );
 
     let app = Router::new()
         .route("/", post(propose))
-        .layer(Extension(Arc::clone(&paxos)));
+        .route("/propose", post(propose_value))
+        .route("/value", get(decided_value))
+        .route("/keys/:key", get(get_key).put(put_key))
+        .route("/admin/latency", get(latency_matrix))
+        .route("/admin/health", get(cluster_health))
+        .route("/admin/members", get(members))
+        .route("/metrics", get(metrics))
+        .layer(Extension(membership.clone()))
+        .layer(Extension(Arc::clone(&paxos)))
+        .layer(Extension(queue.clone()))
+        .layer(Extension(dedup.clone()))
+        .layer(Extension(map.clone()))
+        .layer(Extension(prometheus));
 
-    println!("starting http server on {http_server_addr}");
-    println!("starting rpc server on {rpc_server_addr}");
+    let disable_http = args.disable_http || acceptor_only;
+    let allow_unsafe_admin = args.acceptor.allow_unsafe_admin;
+    if allow_unsafe_admin {
+        warn!("unsafe admin calls are enabled, this node can be wiped");
+    }
+    let rate_limits = RateLimits::from_env().expect("reading rate limits");
+    if rate_limits != RateLimits::default() {
+        info!(?rate_limits, "rate limiting acceptor requests");
+    }
+    let rate_limiter = RateLimiter::new(rate_limits);
+    let inflight = match Limits::from_env()
+        .expect("reading limits")
+        .max_inflight_requests
+    {
+        None => InflightRequests::default(),
+        Some(max) => {
+            info!(max, "capping the requests the acceptor handles at once");
+            InflightRequests::new(max)
+        }
+    };
+    if !disable_http {
+        info!(addr = %http_server_addr, "starting http server");
+    }
+    info!(addr = %rpc_listen, tls = tls.is_some(), quic = quic_from_env(), "starting rpc server");
+    if !acceptor_only {
+        info!(addr = %client_server_addr, "starting client rpc server");
+    }
 
     select! {
-      err =  axum::Server::bind(&http_server_addr).serve(app.into_make_service()) => {
+      err = async {
+        if disable_http {
+          std::future::pending().await
+        } else {
+          axum::Server::bind(&http_server_addr).serve(app.into_make_service()).await
+        }
+      } => {
         panic!("http server exited: err={err:?}");
       }
       _ = async {
-        let mut listener = tarpc::serde_transport::tcp::listen(&rpc_server_addr, Json::default)
+        let listener = transport::listen_endpoint(&rpc_listen, tls.as_ref(), tls_acceptor.clone())
         .await
         .expect("listening on server addr");
 
-        listener.config_mut().max_frame_length(usize::MAX);
+        AcceptorServer::new(
+            Arc::clone(&paxos),
+            instances.clone(),
+            Arc::clone(&authenticator),
+            rejoin.clone(),
+        )
+        .membership(membership.clone())
+        .allow_unsafe_admin(allow_unsafe_admin)
+        .rate_limiter(rate_limiter)
+        .inflight(inflight)
+        .run(listener)
+        .await;
+      } => {
+        panic!("rpc server exited");
+      }
+      _ = async {
+        if acceptor_only {
+          return std::future::pending().await;
+        }
+
+        let listener = transport::listen_endpoint(&Endpoint::Addr(client_server_addr), tls.as_ref(), tls_acceptor)
+        .await
+        .expect("listening on client addr");
 
         listener
-        // Ignore accept errors.
-        .filter_map(|r| future::ready(r.ok()))
-        .map(server::BaseChannel::with_defaults)
-        // Limit channels to 1 per IP.
-        .max_channels_per_key(1, |t| t.transport().peer_addr().unwrap().ip())
-        // serve is generated by the service attribute. It takes as input any type implementing
-        // the generated World trait.
+        .map(|connection| server::BaseChannel::with_defaults(transport::framed(connection)))
         .map(|channel| {
-            let server = AcceptorServer::new(Arc::clone(&paxos));
+            let server = ClientServer {
+                id,
+                paxos: Arc::clone(&paxos),
+                connector: connector.clone(),
+                queue: queue.clone(),
+                dedup: dedup.clone(),
+                map: map.clone(),
+                authenticator: Arc::clone(&authenticator),
+                peer_certificate: channel.transport().get_ref().peer_certificate(),
+            };
             channel.execute(server.serve())
         })
-        // Max 10 channels.
         .buffer_unordered(10)
         .for_each(|_| async {})
         .await;
       } => {
-        panic!("rpc server exited");
+        panic!("client rpc server exited");
+      }
+      _ = shutdown_signal() => {
+        info!("received shutdown signal, no longer accepting requests");
       }
     };
+
+    shutdown(instances).await
 }
 
-async fn propose(
-    Extension(paxos): Extension<Arc<Mutex<Paxos>>>,
-    value: String,
-) -> impl IntoResponse {
-    let mut paxos = paxos.lock().await;
+/// Resolves when the process receives SIGINT or SIGTERM.
+async fn shutdown_signal() {
+    let interrupt = async {
+        tokio::signal::ctrl_c().await.expect("listening for SIGINT");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("listening for SIGTERM")
+            .recv()
+            .await;
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    select! {
+        _ = interrupt => {}
+        _ = terminate => {}
+    }
+}
+
+/// Waits for the handlers using the nodes, if any, to finish, flushes the
+/// nodes' files and exits. Requests that were still waiting for a node are
+/// refused, to their senders it is as if the node went down before receiving them.
+async fn shutdown(instances: Instances) -> ! {
+    match tokio::time::timeout(SHUTDOWN_TIMEOUT, instances.shutdown()).await {
+        Err(_) => {
+            error!(timeout = ?SHUTDOWN_TIMEOUT, "in flight request did not finish in time, exiting anyway");
+            std::process::exit(1);
+        }
+        Ok(Err(err)) => {
+            error!(?err, "flushing state on shutdown");
+            std::process::exit(1);
+        }
+        Ok(Ok(())) => {}
+    }
+
+    info!("shut down cleanly");
+
+    // Exports the spans still buffered.
+    #[cfg(feature = "otel")]
+    let _ = tokio::task::spawn_blocking(opentelemetry::global::shutdown_tracer_provider).await;
+
+    std::process::exit(0);
+}
+
+async fn run_propose(args: ProposeArgs, mut config: Config, genesis: Option<Genesis>) {
+    let tls = TlsConfig::from_env().expect("reading tls config");
+
+    if let Some(node) = args.node {
+        let (node, resolver) = resolve_one(&node).await;
+        let connector = connector_from_env(tls.as_ref()).with_resolver(resolver);
+        let result =
+            match PaxosClient::connect(&connector, node, credentials(genesis.as_ref())).await {
+                Err(err) => Err(err),
+                Ok(client) => {
+                    client
+                        .propose_with_priority(args.value.into_bytes(), args.priority)
+                        .await
+                }
+            };
+
+        match result {
+            Ok(response) => cli::print_propose_response(&response),
+            Err(err) => {
+                error!("{err:?}");
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    // This process is not an acceptor, its state files are thrown away.
+    let data_dir = std::env::temp_dir().join(format!("paxos-proposer-{}", std::process::id()));
+    tokio::fs::create_dir_all(&data_dir)
+        .await
+        .expect("creating data dir");
+
+    let (acceptors, resolver, instance) =
+        resolve_cluster(args.cluster, &mut config, genesis.as_ref()).await;
+
+    let mut builder = Paxos::builder(0, "0.0.0.0:0".parse().unwrap(), acceptors)
+        .instance(instance)
+        .connector(connector_from_env(tls.as_ref()).with_resolver(resolver.clone()))
+        .credentials(credentials(genesis.as_ref()))
+        .data_dir(&data_dir);
+
+    if let Some(genesis) = &genesis {
+        builder = builder.quorum(genesis.quorum());
+    }
+
+    if let Some(statsd) = statsd_from_env(&resolver).await {
+        builder = builder.metrics(Arc::new(statsd));
+    }
+
+    let mut paxos = configure(builder, &config, &resolver)
+        .await
+        .build()
+        .await
+        .expect("instantiating paxos instance");
+
+    let result = paxos.propose(args.value.into_bytes()).await;
+
+    let _ = tokio::fs::remove_dir_all(&data_dir).await;
+
+    match result {
+        Ok(decided) => cli::print_decided(&decided),
+        Err(err) => {
+            error!("{err:?}");
+            std::process::exit(1);
+        }
+    }
+}
+
+async fn run_status(args: StatusArgs, mut config: Config, genesis: Option<Genesis>) {
+    let tls = TlsConfig::from_env().expect("reading tls config");
+    let credentials = credentials(genesis.as_ref());
+
+    let (acceptors, resolver, instance) =
+        resolve_cluster(args.cluster, &mut config, genesis.as_ref()).await;
+    let connector = connector_from_env(tls.as_ref()).with_resolver(resolver);
+
+    cli::print_status(&connector, &credentials, &acceptors, &instance, args.state).await;
+}
+
+async fn run_sign_genesis(args: SignGenesisArgs) {
+    let result: anyhow::Result<()> = async {
+        let contents = tokio::fs::read_to_string(&args.file).await?;
+        let mut genesis: SignedGenesis = toml::from_str(&contents)?;
+        let key = tokio::fs::read(&args.key).await?;
+
+        genesis.sign(&key)?;
+
+        tokio::fs::write(&args.file, genesis.to_toml()?).await?;
+        Ok(())
+    }
+    .await;
+
+    if let Err(err) = result {
+        error!("signing genesis: {err:#}");
+        std::process::exit(1);
+    }
+}
+
+async fn run_init(args: InitArgs, config: Config, genesis: Option<Genesis>) {
+    let Some(genesis) = genesis else {
+        error!("init needs the genesis document of the cluster, pass --genesis");
+        std::process::exit(1);
+    };
+
+    let Some(id) = args.id.or(config.id) else {
+        error!("the node id must be passed with --id, ID or the config file");
+        std::process::exit(1);
+    };
+
+    let data_dir = args
+        .data_dir
+        .or(config.data_dir)
+        .unwrap_or_else(|| PathBuf::from("."));
+
+    if let Err(err) = genesis::init(&data_dir, &genesis, id).await {
+        error!("initializing {}: {err:#}", data_dir.display());
+        std::process::exit(1);
+    }
+
+    info!(id, cluster_id = %genesis.cluster_id, data_dir = %data_dir.display(), "initialized data dir");
+}
 
-    match paxos.propose(value.into_bytes()).await {
+fn run_migrate(args: MigrateArgs) {
+    match single_decree_paxos::format::migrate_dir(&args.data_dir) {
+        Err(err) => {
+            error!("{err:#}");
+            std::process::exit(1);
+        }
+        Ok(migrated) => info!(
+            files = migrated.len(),
+            data_dir = %args.data_dir.display(),
+            "migrated state files"
+        ),
+    }
+}
+
+async fn run_reset_state(args: ResetStateArgs, config: Config, genesis: Option<Genesis>) {
+    let tls = TlsConfig::from_env().expect("reading tls config");
+    let (acceptor, resolver) = resolve_one(&args.acceptor).await;
+    let connector = connector_from_env(tls.as_ref()).with_resolver(resolver);
+    let instance = args.instance.or(config.instance).unwrap_or_default();
+
+    let client = match paxos::connect(&connector, acceptor).await {
+        Err(err) => {
+            error!("connecting to {}: {err:#}", args.acceptor);
+            std::process::exit(1);
+        }
+        Ok(v) => v,
+    };
+
+    let signed = credentials(genesis.as_ref()).sign("reset_state", &(&instance, &args.confirm));
+    match client
+        .reset_state(context::current(), signed, instance, args.confirm)
+        .await
+    {
+        Err(err) => {
+            error!("rpc error: {err:?}");
+            std::process::exit(1);
+        }
+        Ok(Err(err)) => {
+            error!("{err}");
+            std::process::exit(1);
+        }
+        Ok(Ok(())) => println!("{}: reset", args.acceptor),
+    }
+}
+
+async fn run_local_cluster(args: LocalClusterArgs) {
+    if args.nodes == 0 {
+        error!("--nodes must be at least 1");
+        std::process::exit(1);
+    }
+
+    let binary = std::env::current_exe().expect("finding the path of this binary");
+    let dir = args
+        .dir
+        .unwrap_or_else(|| std::env::temp_dir().join("paxos-local-cluster"));
+
+    let port = |first: u16, i: u16| -> SocketAddr {
+        let port = first.checked_add(i).expect("port out of range");
+        SocketAddr::from(([127, 0, 0, 1], port))
+    };
+
+    let acceptors: Vec<String> = (0..args.nodes)
+        .map(|i| port(args.rpc_port, i).to_string())
+        .collect();
+
+    let mut children = Vec::with_capacity(args.nodes as usize);
+    for i in 0..args.nodes {
+        let id = u32::from(i) + 1;
+        let node_dir = dir.join(format!("node-{id}"));
+        tokio::fs::create_dir_all(&node_dir)
+            .await
+            .expect("creating node dir");
+
+        let log_path = node_dir.join("node.log");
+        let log = std::fs::File::create(&log_path).expect("creating node log");
+
+        // The nodes stay in this process group, so Ctrl-C in the terminal
+        // reaches them as well and each shuts down on its own.
+        let child = tokio::process::Command::new(&binary)
+            .arg("node")
+            .args(["--id", &id.to_string()])
+            .args(["--listen", &port(args.rpc_port, i).to_string()])
+            .args(["--http-listen", &port(args.http_port, i).to_string()])
+            .args(["--client-listen", &port(args.client_port, i).to_string()])
+            .args(["--data-dir", &node_dir.display().to_string()])
+            .env("ACCEPTORS", acceptors.join(","))
+            .stdout(log.try_clone().expect("opening node log"))
+            .stderr(log)
+            .kill_on_drop(true)
+            .spawn()
+            .expect("spawning node");
+
+        println!(
+            "node {id}: rpc={} http={} client={} log={}",
+            port(args.rpc_port, i),
+            port(args.http_port, i),
+            port(args.client_port, i),
+            log_path.display()
+        );
+        children.push((id, child));
+    }
+    println!("ACCEPTORS={}", acceptors.join(","));
+
+    let exited = futures::future::select_all(
+        children
+            .iter_mut()
+            .map(|(id, child)| Box::pin(async move { (*id, child.wait().await) })),
+    );
+
+    let grace = select! {
+      _ = shutdown_signal() => {
+        info!("stopping the cluster");
+        Duration::from_secs(5)
+      }
+      ((id, status), _, _) = exited => {
+        error!(id, ?status, "node exited, stopping the cluster");
+        Duration::ZERO
+      }
+    };
+
+    // Nodes that got the signal get a moment to shut down cleanly before they
+    // are killed.
+    for (id, mut child) in children {
+        if tokio::time::timeout(grace, child.wait()).await.is_err() {
+            warn!(id, "node did not stop, killing it");
+            let _ = child.kill().await;
+        }
+    }
+}
+
+#[cfg(feature = "mmap")]
+fn run_inspect(args: InspectArgs) {
+    // SAFETY: inspect is run against the data dir of a stopped acceptor, see
+    // InspectArgs.
+    let view = match unsafe {
+        single_decree_paxos::mmap::StateView::open(&args.data_dir, args.id, &args.instance)
+    } {
+        Err(err) => {
+            error!("{err:#}");
+            std::process::exit(1);
+        }
+        Ok(v) => v,
+    };
+
+    let values = match StateKey::from_env().expect("reading state key") {
+        None => Ok((
+            view.proposal_value().map(<[u8]>::to_vec),
+            view.decided_value().map(<[u8]>::to_vec),
+        )),
+        Some(key) => view
+            .open_proposal_value(&key)
+            .and_then(|proposal| Ok((proposal, view.open_decided_value(&key)?))),
+    };
+    let (proposal_value, decided_value) = match values {
+        Err(err) => {
+            error!("{err:#}");
+            std::process::exit(1);
+        }
+        Ok(values) => values,
+    };
+
+    println!(
+        "proposal_id={} accepted_id={:?} proposal_value={:?} decided_value={:?}",
+        view.proposal_id(),
+        view.accepted_id().map(|id| id.get()),
+        proposal_value.as_deref().map(String::from_utf8_lossy),
+        decided_value.as_deref().map(String::from_utf8_lossy)
+    );
+}
+
+async fn propose(Extension(queue): Extension<ProposalQueue>, value: String) -> impl IntoResponse {
+    match queue.propose(value.into_bytes(), Priority::Normal).await {
         Err(err) => err.to_string(),
-        Ok(()) => "value accepted".to_owned(),
+        Ok(Decided::Ours(_)) => "value accepted".to_owned(),
+        Ok(Decided::Other(value)) => format!(
+            "a value has already been accepted: {}",
+            String::from_utf8_lossy(&value)
+        ),
+    }
+}
+
+#[derive(Deserialize)]
+struct ProposeParams {
+    #[serde(default)]
+    priority: Priority,
+    client_id: Option<u64>,
+    request_id: Option<u64>,
+}
+
+/// Proposes the request body and responds with the value the cluster decided on.
+/// `?priority=high` schedules the proposal ahead of normal ones. Requests
+/// retried with the same `?client_id=&request_id=` are only proposed once.
+async fn propose_value(
+    Extension(queue): Extension<ProposalQueue>,
+    Extension(dedup): Extension<Deduplicator>,
+    Query(params): Query<ProposeParams>,
+    value: Bytes,
+) -> (StatusCode, Vec<u8>) {
+    let value = value.to_vec();
+    let outcome = match (params.client_id, params.request_id) {
+        (Some(client_id), Some(request_id)) => {
+            let request_id = RequestId {
+                client_id,
+                request_id,
+            };
+            dedup
+                .run(request_id, async move {
+                    propose_decided(&queue, value, params.priority).await
+                })
+                .await
+        }
+        (None, None) => propose_decided(&queue, value, params.priority).await,
+        _ => {
+            return (
+                StatusCode::BAD_REQUEST,
+                b"client_id and request_id must be set together".to_vec(),
+            )
+        }
+    };
+
+    match outcome {
+        Ok(decided) => (StatusCode::OK, decided),
+        Err(err) => (StatusCode::SERVICE_UNAVAILABLE, err.into_bytes()),
+    }
+}
+
+/// Responds with the value the cluster decided on, read from a quorum.
+async fn decided_value(Extension(paxos): Extension<Arc<Mutex<Paxos>>>) -> (StatusCode, Vec<u8>) {
+    match paxos.lock().await.read().await {
+        Ok(Some(value)) => (StatusCode::OK, value),
+        Ok(None) => (StatusCode::NOT_FOUND, b"no value has been decided".to_vec()),
+        Err(err) => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            format!("{err:#}").into_bytes(),
+        ),
+    }
+}
+
+/// Sets the key to the request body unless it was set already and responds
+/// with the value the key holds.
+async fn put_key(
+    Extension(map): Extension<WriteOnceMap>,
+    Path(key): Path<String>,
+    value: Bytes,
+) -> (StatusCode, Vec<u8>) {
+    match map.put_once(&key, value.to_vec()).await {
+        Ok(decided) => (StatusCode::OK, decided.into_value()),
+        Err(err) => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            format!("{err:#}").into_bytes(),
+        ),
+    }
+}
+
+/// Responds with the value of the key, read from a quorum.
+async fn get_key(
+    Extension(map): Extension<WriteOnceMap>,
+    Path(key): Path<String>,
+) -> (StatusCode, Vec<u8>) {
+    match map.get(&key).await {
+        Ok(Some(value)) => (StatusCode::OK, value),
+        Ok(None) => (StatusCode::NOT_FOUND, b"the key was never set".to_vec()),
+        Err(err) => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            format!("{err:#}").into_bytes(),
+        ),
+    }
+}
+
+/// Renders the node's metrics in the Prometheus text format.
+async fn metrics(Extension(prometheus): Extension<Arc<PrometheusRecorder>>) -> String {
+    prometheus.render()
+}
+
+/// Returns the rpc latencies observed between every pair of nodes.
+async fn latency_matrix(
+    Extension(paxos): Extension<Arc<Mutex<Paxos>>>,
+) -> Json<HashMap<SocketAddr, HashMap<SocketAddr, LatencySummary>>> {
+    let query = paxos.lock().await.peer_query();
+    Json(query.latency_matrix().await)
+}
+
+/// Returns whether each acceptor is up, and how it is doing if it is.
+async fn cluster_health(
+    Extension(paxos): Extension<Arc<Mutex<Paxos>>>,
+) -> Json<HashMap<SocketAddr, AcceptorStatus>> {
+    let query = paxos.lock().await.peer_query();
+    Json(query.cluster_health().await)
+}
+
+/// What failure detection believes about every member, empty when it is disabled.
+async fn members(Extension(membership): Extension<Option<Membership>>) -> Json<Vec<MemberUpdate>> {
+    Json(
+        membership
+            .map(|membership| membership.members())
+            .unwrap_or_default(),
+    )
+}
+
+/// Reloads the config file of a node on SIGHUP or when the file changes.
+struct Reload {
+    path: PathBuf,
+    /// The config as last loaded.
+    config: Config,
+    log_filter: LogFilter,
+}
+
+impl Reload {
+    async fn run(mut self, instances: Instances) {
+        #[cfg(unix)]
+        let mut hangup = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())
+            .expect("listening for SIGHUP");
+
+        let mut modified = self.modified().await;
+        let mut interval = tokio::time::interval(CONFIG_POLL_INTERVAL);
+
+        loop {
+            #[cfg(unix)]
+            let hangup = hangup.recv();
+            #[cfg(not(unix))]
+            let hangup = std::future::pending::<Option<()>>();
+
+            select! {
+                _ = hangup => info!(path = %self.path.display(), "received SIGHUP, reloading config"),
+                _ = interval.tick() => {
+                    let now = self.modified().await;
+                    if now == modified {
+                        continue;
+                    }
+                    modified = now;
+                    info!(path = %self.path.display(), "config file changed, reloading it");
+                }
+            }
+
+            if let Err(err) = self.reload(&instances).await {
+                error!("not reloading config, keeping the one in use: {err:#}");
+            }
+        }
+    }
+
+    async fn modified(&self) -> Option<std::time::SystemTime> {
+        tokio::fs::metadata(&self.path)
+            .await
+            .and_then(|metadata| metadata.modified())
+            .ok()
+    }
+
+    async fn reload(&mut self, instances: &Instances) -> anyhow::Result<()> {
+        let config = Config::load(&self.path).await?;
+        let diff = self.config.diff(&config)?;
+        if diff.is_empty() {
+            info!("config unchanged");
+            return Ok(());
+        }
+
+        // Env variables still take precedence over the file.
+        let timeouts = config.timeouts().with_env()?;
+        if config.log.filter != self.config.log.filter {
+            if std::env::var_os("RUST_LOG").is_some() {
+                warn!("RUST_LOG is set, it takes precedence over log.filter");
+            }
+            self.log_filter
+                .reload(log_filter(config.log.filter.as_deref()))?;
+        }
+        instances.set_timeouts(timeouts).await;
+
+        for change in &diff {
+            info!(%change, "applied config change");
+        }
+        self.config = config;
+        Ok(())
+    }
+}
+
+/// Periodically checks whether the cluster decided on a value this node missed.
+/// The other acceptors are asked without holding the node, it is only locked
+/// to apply what they answered.
+async fn heal(paxos: Arc<Mutex<Paxos>>) {
+    let mut interval = tokio::time::interval(Duration::from_secs(5));
+
+    loop {
+        interval.tick().await;
+
+        let Some(fetch) = paxos.lock().await.begin_heal() else {
+            continue;
+        };
+        let healed = fetch.run().await;
+
+        if let Err(err) = paxos.lock().await.finish_heal(healed).await {
+            warn!(?err, "healing acceptor state");
+        }
+    }
+}
+
+/// Keeps the lease while this node holds it. Getting the lease in the first
+/// place is left to the proposals the node receives.
+async fn renew_lease(paxos: Arc<Mutex<Paxos>>, heartbeat_interval: Duration) {
+    let mut interval = tokio::time::interval(heartbeat_interval);
+
+    loop {
+        interval.tick().await;
+
+        let mut paxos = paxos.lock().await;
+        if !paxos.holds_lease() {
+            continue;
+        }
+
+        if let Err(err) = paxos.heartbeat().await {
+            warn!(?err, "renewing lease");
+        }
     }
 }
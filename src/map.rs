@@ -0,0 +1,51 @@
+//! A write-once map. Every key is decided in an instance of its own, nested
+//! under the map's namespace, so an acceptor keeps the files of key `k` as
+//! `acceptor_{id}.keys.k.state` and so on, and writes to different keys don't
+//! wait on each other.
+
+use anyhow::{anyhow, Context, Result};
+
+use crate::{instance::InstanceId, instances::Instances, paxos::Decided};
+
+/// The namespace the keys served by the nodes live under.
+pub const DEFAULT_NAMESPACE: &str = "keys";
+
+#[derive(Debug, Clone)]
+pub struct WriteOnceMap {
+    instances: Instances,
+    namespace: InstanceId,
+}
+
+impl WriteOnceMap {
+    pub fn new(instances: Instances, namespace: InstanceId) -> Self {
+        Self {
+            instances,
+            namespace,
+        }
+    }
+
+    /// Sets `key` to `value` unless it was set already. Returns the value the
+    /// key holds, which is a different one when another write got there first.
+    pub async fn put_once(&self, key: &str, value: Vec<u8>) -> Result<Decided> {
+        self.instances.propose(&self.instance(key)?, value).await
+    }
+
+    /// Returns the value of `key`, None when it was never set.
+    pub async fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        let node = self.instances.create(&self.instance(key)?).await?;
+        let value = node.lock().await.read().await?;
+        Ok(value)
+    }
+
+    /// The instance `key` is decided in. Keys follow the rules of an instance
+    /// id segment.
+    fn instance(&self, key: &str) -> Result<InstanceId> {
+        if key.contains(InstanceId::SEPARATOR) {
+            return Err(anyhow!("invalid key {key:?}, keys can't contain '/'"));
+        }
+
+        self.namespace
+            .child(key)
+            .with_context(|| format!("invalid key {key:?}"))
+    }
+}
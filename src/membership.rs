@@ -0,0 +1,459 @@
+//! SWIM style failure detection. Every probe interval a node pings one peer,
+//! taking turns through all of them. When the peer doesn't answer, a few other
+//! peers are asked to ping it on the node's behalf, and only when none of them
+//! reaches it either is it suspected. A suspect that doesn't show it is alive
+//! within the suspicion timeout is considered dead.
+//!
+//! Every ping and answer carries the sender's view of the members, so nodes
+//! learn about members they never probed, members that aren't in their config
+//! included, and a member that hears it is suspected refutes it by announcing
+//! itself alive with a higher incarnation.
+//!
+//! Proposers skip the acceptors this considers dead instead of waiting for
+//! them to time out in every round. It never affects what acceptors promise
+//! or accept, a wrong verdict only costs progress.
+
+use anyhow::{anyhow, Context, Result};
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex as StdMutex},
+    time::Duration,
+};
+use tokio::{sync::Mutex, time::Instant};
+use tracing::{debug, info, warn};
+
+use crate::{
+    auth::Credentials,
+    paxos::{self, AcceptorServiceClient},
+    timeout,
+    transport::Connector,
+};
+
+#[derive(Debug, Clone, Copy)]
+pub struct MembershipConfig {
+    /// How often a peer is probed.
+    pub probe_interval: Duration,
+
+    /// How long to wait for a peer to answer a probe.
+    pub probe_timeout: Duration,
+
+    /// How long a suspect has to show it is alive before it is considered dead.
+    pub suspicion_timeout: Duration,
+
+    /// How many other peers are asked to probe a peer that didn't answer.
+    pub indirect_probes: usize,
+}
+
+impl Default for MembershipConfig {
+    fn default() -> Self {
+        Self {
+            probe_interval: Duration::from_secs(1),
+            probe_timeout: Duration::from_millis(300),
+            suspicion_timeout: Duration::from_secs(5),
+            indirect_probes: 2,
+        }
+    }
+}
+
+impl MembershipConfig {
+    /// Reads GOSSIP_INTERVAL_MS and GOSSIP_SUSPICION_MS. Returns None when
+    /// failure detection isn't enabled, that is when GOSSIP_INTERVAL_MS isn't set.
+    pub fn from_env() -> Result<Option<Self>> {
+        let Ok(value) = std::env::var("GOSSIP_INTERVAL_MS") else {
+            return Ok(None);
+        };
+
+        let mut config = Self::default();
+
+        let millis: u64 = value
+            .parse()
+            .context("GOSSIP_INTERVAL_MS must be an integer")?;
+        if millis == 0 {
+            return Err(anyhow!("GOSSIP_INTERVAL_MS must be greater than 0"));
+        }
+        config.probe_interval = Duration::from_millis(millis);
+        config.probe_timeout = std::cmp::min(config.probe_timeout, config.probe_interval);
+
+        if let Ok(value) = std::env::var("GOSSIP_SUSPICION_MS") {
+            let millis: u64 = value
+                .parse()
+                .context("GOSSIP_SUSPICION_MS must be an integer")?;
+            config.suspicion_timeout = Duration::from_millis(millis);
+        }
+
+        Ok(Some(config))
+    }
+}
+
+/// Ordered by precedence, a member's state only moves towards dead within an
+/// incarnation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum MemberState {
+    Alive,
+    Suspect,
+    Dead,
+}
+
+/// What a node believes about a member.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct MemberUpdate {
+    pub addr: SocketAddr,
+
+    /// Only the member itself increases it, to refute being suspected. A
+    /// higher incarnation overrides whatever was believed about a lower one.
+    pub incarnation: u64,
+
+    pub state: MemberState,
+}
+
+#[derive(Debug)]
+struct Member {
+    incarnation: u64,
+    state: MemberState,
+
+    /// When `state` was last changed, suspects become dead some time after it.
+    since: Instant,
+}
+
+#[derive(Debug)]
+struct Table {
+    /// The incarnation this node announces itself with.
+    incarnation: u64,
+
+    /// Every other member this node knows of.
+    members: HashMap<SocketAddr, Member>,
+
+    /// The members left to probe before every member was probed once.
+    probe_order: Vec<SocketAddr>,
+}
+
+/// A node's view of which members are alive. Cheap to clone, clones share it.
+#[derive(Debug, Clone)]
+pub struct Membership {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    /// The address this node is known by.
+    addr: SocketAddr,
+    config: MembershipConfig,
+    connector: Connector,
+    credentials: Credentials,
+    table: StdMutex<Table>,
+
+    /// Kept apart from the proposer's clients so probes don't wait on rounds.
+    clients: Mutex<HashMap<SocketAddr, AcceptorServiceClient>>,
+}
+
+impl Membership {
+    /// Starts out believing every one of `peers` is alive.
+    pub fn new(
+        addr: SocketAddr,
+        peers: &[SocketAddr],
+        connector: Connector,
+        credentials: Credentials,
+        config: MembershipConfig,
+    ) -> Self {
+        let now = Instant::now();
+        let members = peers
+            .iter()
+            .filter(|peer| **peer != addr)
+            .map(|peer| {
+                let member = Member {
+                    incarnation: 0,
+                    state: MemberState::Alive,
+                    since: now,
+                };
+                (*peer, member)
+            })
+            .collect();
+
+        Self {
+            inner: Arc::new(Inner {
+                addr,
+                config,
+                connector,
+                credentials,
+                table: StdMutex::new(Table {
+                    incarnation: 0,
+                    members,
+                    probe_order: Vec::new(),
+                }),
+                clients: Mutex::new(HashMap::new()),
+            }),
+        }
+    }
+
+    /// Whether `addr` is a member this node considers dead.
+    pub fn is_dead(&self, addr: SocketAddr) -> bool {
+        let table = self.inner.table.lock().unwrap();
+        matches!(
+            table.members.get(&addr),
+            Some(member) if member.state == MemberState::Dead
+        )
+    }
+
+    /// What this node believes about every member, itself included.
+    pub fn members(&self) -> Vec<MemberUpdate> {
+        let table = self.inner.table.lock().unwrap();
+
+        let mut members: Vec<MemberUpdate> = table
+            .members
+            .iter()
+            .map(|(addr, member)| MemberUpdate {
+                addr: *addr,
+                incarnation: member.incarnation,
+                state: member.state,
+            })
+            .collect();
+        members.push(MemberUpdate {
+            addr: self.inner.addr,
+            incarnation: table.incarnation,
+            state: MemberState::Alive,
+        });
+        members
+    }
+
+    /// Merges what another member believes into this node's view.
+    fn apply(&self, updates: Vec<MemberUpdate>) {
+        let mut table = self.inner.table.lock().unwrap();
+        let now = Instant::now();
+
+        for update in updates {
+            if update.addr == self.inner.addr {
+                if update.state != MemberState::Alive && update.incarnation >= table.incarnation {
+                    table.incarnation = update.incarnation + 1;
+                    info!(
+                        incarnation = table.incarnation,
+                        "refuting being {:?}", update.state
+                    );
+                }
+                continue;
+            }
+
+            match table.members.get_mut(&update.addr) {
+                None => {
+                    info!(member = %update.addr, state = ?update.state, "learned about a member");
+                    table.members.insert(
+                        update.addr,
+                        Member {
+                            incarnation: update.incarnation,
+                            state: update.state,
+                            since: now,
+                        },
+                    );
+                }
+                Some(member) => {
+                    let newer =
+                        (update.incarnation, update.state) > (member.incarnation, member.state);
+                    if !newer {
+                        continue;
+                    }
+
+                    if update.state != member.state {
+                        info!(member = %update.addr, from = ?member.state, to = ?update.state, "member changed state");
+                        member.since = now;
+                    }
+                    member.incarnation = update.incarnation;
+                    member.state = update.state;
+                }
+            }
+        }
+    }
+
+    /// Marks `addr` as suspect unless it already showed up with a newer
+    /// incarnation or was declared dead.
+    fn suspect(&self, addr: SocketAddr) {
+        let mut table = self.inner.table.lock().unwrap();
+        if let Some(member) = table.members.get_mut(&addr) {
+            if member.state == MemberState::Alive {
+                warn!(member = %addr, "suspecting member");
+                member.state = MemberState::Suspect;
+                member.since = Instant::now();
+            }
+        }
+    }
+
+    /// Declares the suspects whose suspicion timed out dead.
+    fn expire_suspects(&self) {
+        let mut table = self.inner.table.lock().unwrap();
+        let now = Instant::now();
+
+        for (addr, member) in table.members.iter_mut() {
+            if member.state == MemberState::Suspect
+                && now.duration_since(member.since) >= self.inner.config.suspicion_timeout
+            {
+                warn!(member = %addr, "member is dead");
+                member.state = MemberState::Dead;
+                member.since = now;
+            }
+        }
+    }
+
+    /// The next member to probe. Members are probed in a random order that is
+    /// shuffled again after every member was probed.
+    fn next_target(&self) -> Option<SocketAddr> {
+        let mut table = self.inner.table.lock().unwrap();
+
+        if table.probe_order.is_empty() {
+            let mut order: Vec<SocketAddr> = table.members.keys().copied().collect();
+            order.shuffle(&mut rand::thread_rng());
+            table.probe_order = order;
+        }
+
+        table.probe_order.pop()
+    }
+
+    /// Up to `count` members other than `target` that are believed alive.
+    fn helpers(&self, target: SocketAddr, count: usize) -> Vec<SocketAddr> {
+        let table = self.inner.table.lock().unwrap();
+        let alive: Vec<SocketAddr> = table
+            .members
+            .iter()
+            .filter(|(addr, member)| **addr != target && member.state == MemberState::Alive)
+            .map(|(addr, _)| *addr)
+            .collect();
+
+        alive
+            .choose_multiple(&mut rand::thread_rng(), count)
+            .copied()
+            .collect()
+    }
+
+    /// Answers a probe from another member.
+    pub fn on_ping(&self, updates: Vec<MemberUpdate>) -> Vec<MemberUpdate> {
+        self.apply(updates);
+        self.members()
+    }
+
+    /// Probes `target` on behalf of another member that couldn't reach it.
+    pub async fn on_ping_req(
+        &self,
+        target: SocketAddr,
+        updates: Vec<MemberUpdate>,
+    ) -> Result<Vec<MemberUpdate>> {
+        self.apply(updates);
+        self.ping(target).await?;
+        Ok(self.members())
+    }
+
+    async fn client(&self, addr: SocketAddr) -> Result<AcceptorServiceClient> {
+        let mut clients = self.inner.clients.lock().await;
+        if let Some(client) = clients.get(&addr) {
+            return Ok(client.clone());
+        }
+
+        let client = paxos::connect(&self.inner.connector, addr).await?;
+        clients.insert(addr, client.clone());
+        Ok(client)
+    }
+
+    /// Drops the connection to `addr` so the next probe opens a new one.
+    async fn disconnect(&self, addr: SocketAddr) {
+        self.inner.clients.lock().await.remove(&addr);
+    }
+
+    /// Pings `target` directly.
+    async fn ping(&self, target: SocketAddr) -> Result<()> {
+        let deadline = Instant::now() + self.inner.config.probe_timeout;
+        let result = tokio::time::timeout_at(deadline, async {
+            let client = self.client(target).await?;
+            let updates = self.members();
+            client
+                .ping(
+                    timeout::context_until(deadline),
+                    self.inner.credentials.sign("ping", &updates),
+                    updates,
+                )
+                .await
+                .map_err(anyhow::Error::from)?
+                .map_err(|err| anyhow!(err))
+        })
+        .await;
+
+        match result {
+            Ok(Ok(updates)) => {
+                self.apply(updates);
+                Ok(())
+            }
+            Ok(Err(err)) => {
+                self.disconnect(target).await;
+                Err(err.context(format!("pinging {target}")))
+            }
+            Err(_) => Err(anyhow!("{target} did not answer a ping in time")),
+        }
+    }
+
+    /// Asks `helper` to ping `target`.
+    async fn ping_req(&self, helper: SocketAddr, target: SocketAddr) -> Result<()> {
+        // The helper needs time for its own ping.
+        let deadline = Instant::now() + 2 * self.inner.config.probe_timeout;
+        let result = tokio::time::timeout_at(deadline, async {
+            let client = self.client(helper).await?;
+            let updates = self.members();
+            client
+                .ping_req(
+                    timeout::context_until(deadline),
+                    self.inner.credentials.sign("ping_req", &(target, &updates)),
+                    target,
+                    updates,
+                )
+                .await
+                .map_err(anyhow::Error::from)?
+                .map_err(|err| anyhow!(err))
+        })
+        .await;
+
+        match result {
+            Ok(Ok(updates)) => {
+                self.apply(updates);
+                Ok(())
+            }
+            Ok(Err(err)) => Err(err.context(format!("asking {helper} to ping {target}"))),
+            Err(_) => Err(anyhow!("{helper} did not reach {target} in time")),
+        }
+    }
+
+    /// Probes one member, suspecting it when neither this node nor any of
+    /// the helpers reaches it.
+    async fn probe(&self, target: SocketAddr) {
+        let err = match self.ping(target).await {
+            Ok(()) => return,
+            Err(err) => err,
+        };
+        debug!(member = %target, ?err, "direct probe failed");
+
+        let helpers = self.helpers(target, self.inner.config.indirect_probes);
+        let results =
+            futures::future::join_all(helpers.iter().map(|helper| self.ping_req(*helper, target)))
+                .await;
+
+        if results.iter().any(Result::is_ok) {
+            return;
+        }
+
+        self.suspect(target);
+    }
+
+    /// Probes members until the process exits.
+    pub async fn run(self) {
+        let mut interval = tokio::time::interval(self.inner.config.probe_interval);
+
+        loop {
+            interval.tick().await;
+
+            self.expire_suspects();
+
+            if let Some(target) = self.next_target() {
+                self.probe(target).await;
+            }
+        }
+    }
+}
@@ -0,0 +1,237 @@
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Write},
+    net::{SocketAddr, UdpSocket},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tracing::warn;
+
+use crate::timeout::Phase;
+
+/// The upper bounds of the buckets of latency histograms, in seconds.
+const BUCKETS: [f64; 12] = [
+    0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5,
+];
+
+/// Where a node reports its metrics. Embedders that already run a telemetry
+/// stack can implement this to forward metrics to it.
+pub trait Recorder: fmt::Debug + Send + Sync {
+    /// Adds `value` to the counter `name`.
+    fn increment(&self, name: &'static str, value: u64);
+
+    /// Records how long an operation named `name` took.
+    fn record_duration(&self, name: &'static str, duration: Duration);
+
+    /// Records the round trip of a `phase` request to `acceptor`, including
+    /// the ones that answered after the phase reached a quorum.
+    fn record_acceptor_rtt(&self, phase: Phase, acceptor: SocketAddr, rtt: Duration) {
+        let _ = (phase, acceptor, rtt);
+    }
+}
+
+/// Discards every metric. Used when no recorder is configured.
+#[derive(Debug, Default)]
+pub struct NoopRecorder;
+
+impl Recorder for NoopRecorder {
+    fn increment(&self, _: &'static str, _: u64) {}
+
+    fn record_duration(&self, _: &'static str, _: Duration) {}
+}
+
+/// Hands every metric to each of its recorders, e.g. to serve them to a
+/// scraper and send them to StatsD at once.
+#[derive(Debug)]
+pub struct FanoutRecorder {
+    recorders: Vec<Arc<dyn Recorder>>,
+}
+
+impl FanoutRecorder {
+    pub fn new(recorders: Vec<Arc<dyn Recorder>>) -> Self {
+        Self { recorders }
+    }
+}
+
+impl Recorder for FanoutRecorder {
+    fn increment(&self, name: &'static str, value: u64) {
+        for recorder in &self.recorders {
+            recorder.increment(name, value);
+        }
+    }
+
+    fn record_duration(&self, name: &'static str, duration: Duration) {
+        for recorder in &self.recorders {
+            recorder.record_duration(name, duration);
+        }
+    }
+
+    fn record_acceptor_rtt(&self, phase: Phase, acceptor: SocketAddr, rtt: Duration) {
+        for recorder in &self.recorders {
+            recorder.record_acceptor_rtt(phase, acceptor, rtt);
+        }
+    }
+}
+
+/// Keeps metrics in memory and renders them in the Prometheus text format
+/// so they can be served to a scraper.
+#[derive(Debug, Default)]
+pub struct PrometheusRecorder {
+    metrics: Mutex<Metrics>,
+}
+
+#[derive(Debug, Default)]
+struct Metrics {
+    counters: BTreeMap<&'static str, u64>,
+    /// The sum and count of the durations recorded for each operation.
+    durations: BTreeMap<&'static str, (Duration, u64)>,
+    acceptor_rtts: BTreeMap<(Phase, SocketAddr), Histogram>,
+}
+
+#[derive(Debug, Default)]
+struct Histogram {
+    /// How many observations fell in each of [BUCKETS], not cumulative.
+    buckets: [u64; BUCKETS.len()],
+    sum: Duration,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        if let Some(bucket) = BUCKETS.iter().position(|bound| seconds <= *bound) {
+            self.buckets[bucket] += 1;
+        }
+        self.sum += duration;
+        self.count += 1;
+    }
+}
+
+impl PrometheusRecorder {
+    pub fn render(&self) -> String {
+        let metrics = self.metrics.lock().unwrap();
+        let mut output = String::new();
+
+        for (name, value) in &metrics.counters {
+            let _ = writeln!(output, "# TYPE {name} counter\n{name} {value}");
+        }
+
+        for (name, (sum, count)) in &metrics.durations {
+            let _ = writeln!(
+                output,
+                "# TYPE {name}_seconds summary\n{name}_seconds_sum {}\n{name}_seconds_count {count}",
+                sum.as_secs_f64()
+            );
+        }
+
+        if !metrics.acceptor_rtts.is_empty() {
+            let name = "paxos_acceptor_rtt_seconds";
+            let _ = writeln!(output, "# TYPE {name} histogram");
+            for ((phase, acceptor), histogram) in &metrics.acceptor_rtts {
+                let labels = format!("phase=\"{phase}\",acceptor=\"{acceptor}\"");
+                let mut cumulative = 0;
+                for (bound, count) in BUCKETS.iter().zip(histogram.buckets) {
+                    cumulative += count;
+                    let _ = writeln!(
+                        output,
+                        "{name}_bucket{{{labels},le=\"{bound}\"}} {cumulative}"
+                    );
+                }
+                let _ = writeln!(
+                    output,
+                    "{name}_bucket{{{labels},le=\"+Inf\"}} {}\n{name}_sum{{{labels}}} {}\n{name}_count{{{labels}}} {}",
+                    histogram.count,
+                    histogram.sum.as_secs_f64(),
+                    histogram.count
+                );
+            }
+        }
+
+        output
+    }
+}
+
+impl Recorder for PrometheusRecorder {
+    fn increment(&self, name: &'static str, value: u64) {
+        *self
+            .metrics
+            .lock()
+            .unwrap()
+            .counters
+            .entry(name)
+            .or_default() += value;
+    }
+
+    fn record_duration(&self, name: &'static str, duration: Duration) {
+        let mut metrics = self.metrics.lock().unwrap();
+        let (sum, count) = metrics.durations.entry(name).or_default();
+        *sum += duration;
+        *count += 1;
+    }
+
+    fn record_acceptor_rtt(&self, phase: Phase, acceptor: SocketAddr, rtt: Duration) {
+        self.metrics
+            .lock()
+            .unwrap()
+            .acceptor_rtts
+            .entry((phase, acceptor))
+            .or_default()
+            .observe(rtt);
+    }
+}
+
+/// Sends every metric to a StatsD server over udp as soon as it is recorded.
+#[derive(Debug)]
+pub struct StatsdRecorder {
+    socket: UdpSocket,
+    prefix: String,
+}
+
+impl StatsdRecorder {
+    /// Metric names are prefixed with `prefix` followed by a `.`, unless it is empty.
+    pub fn new(addr: SocketAddr, prefix: impl Into<String>) -> std::io::Result<Self> {
+        let bind_addr: SocketAddr = if addr.is_ipv4() {
+            "0.0.0.0:0".parse().unwrap()
+        } else {
+            "[::]:0".parse().unwrap()
+        };
+
+        let socket = UdpSocket::bind(bind_addr)?;
+        socket.connect(addr)?;
+        socket.set_nonblocking(true)?;
+
+        let mut prefix = prefix.into();
+        if !prefix.is_empty() {
+            prefix.push('.');
+        }
+
+        Ok(Self { socket, prefix })
+    }
+
+    fn send(&self, metric: String) {
+        // Metrics are best effort, a lost packet must never affect consensus.
+        if let Err(err) = self.socket.send(metric.as_bytes()) {
+            warn!(?err, "sending metric to statsd");
+        }
+    }
+}
+
+impl Recorder for StatsdRecorder {
+    fn increment(&self, name: &'static str, value: u64) {
+        self.send(format!("{}{name}:{value}|c", self.prefix));
+    }
+
+    fn record_duration(&self, name: &'static str, duration: Duration) {
+        self.send(format!("{}{name}:{}|ms", self.prefix, duration.as_millis()));
+    }
+
+    /// Sent as `paxos_acceptor_rtt.{phase}.{ip}_{port}`, StatsD has no labels.
+    fn record_acceptor_rtt(&self, phase: Phase, acceptor: SocketAddr, rtt: Duration) {
+        let acceptor = format!("{}_{}", acceptor.ip(), acceptor.port()).replace([':', '.'], "_");
+        self.send(format!(
+            "{}paxos_acceptor_rtt.{phase}.{acceptor}:{}|ms",
+            self.prefix,
+            rtt.as_millis()
+        ));
+    }
+}
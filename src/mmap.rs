@@ -0,0 +1,120 @@
+use anyhow::{Context, Result};
+use memmap2::Mmap;
+use std::{fs::File, path::Path};
+
+use crate::{
+    encryption::{self, StateKey},
+    format,
+    instance::InstanceId,
+    paxos,
+    proposal::ProposalId,
+};
+
+/// A read-only view of the files an acceptor keeps, for inspecting a data dir
+/// offline. Live nodes answer status and decided value queries from memory,
+/// they never read through a view.
+///
+/// The state file is copied and decoded when the view is opened, the acceptor
+/// rewrites it in place. The decided file is memory mapped, it is only ever
+/// appended to except when the acceptor's state is reset.
+#[derive(Debug)]
+pub struct StateView {
+    file_prefix: String,
+    state: Option<format::State>,
+    /// The version the state file was written in.
+    version: u8,
+    decided: Option<Mmap>,
+}
+
+impl StateView {
+    /// Opens the view of the files acceptor `id` keeps for `instance`.
+    ///
+    /// # Safety
+    ///
+    /// Nothing may truncate the decided file while the view is alive, which
+    /// resetting the acceptor's state does. Only open views of acceptors that
+    /// aren't running.
+    pub unsafe fn open(data_dir: &Path, id: u32, instance: &InstanceId) -> Result<Self> {
+        let file_prefix = paxos::file_prefix(id, instance);
+
+        let state_path = data_dir.join(format!("{file_prefix}.state"));
+        let contents = match std::fs::read(&state_path) {
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            result => result.with_context(|| format!("reading {}", state_path.display()))?,
+        };
+        let (state, version) = format::decode(&contents).context("decoding state file")?;
+
+        Ok(Self {
+            state,
+            version,
+            decided: map(&data_dir.join(format!("{file_prefix}.decided")))
+                .context("mapping decided file")?,
+            file_prefix,
+        })
+    }
+
+    /// The highest proposal id the acceptor promised or accepted.
+    pub fn proposal_id(&self) -> ProposalId {
+        self.state
+            .as_ref()
+            .map_or(ProposalId::ZERO, |state| state.proposal_id)
+    }
+
+    /// The id of the proposal [StateView::proposal_value] was accepted in.
+    pub fn accepted_id(&self) -> Option<ProposalId> {
+        let state = self.state.as_ref()?;
+        state.proposal_value.as_ref().map(|_| state.accepted_id)
+    }
+
+    pub fn proposal_value(&self) -> Option<&[u8]> {
+        self.state.as_ref()?.proposal_value.as_deref()
+    }
+
+    pub fn decided_value(&self) -> Option<&[u8]> {
+        format::decode_decided(self.decided.as_deref()?)
+    }
+
+    /// [StateView::proposal_value] decrypted with the key the acceptor uses.
+    /// Version 1 files were written before values were encrypted, and
+    /// witnesses write no value to seal.
+    pub fn open_proposal_value(&self, key: &StateKey) -> Result<Option<Vec<u8>>> {
+        match (self.accepted_id(), self.proposal_value()) {
+            (Some(_), Some(value)) if self.version == 1 || value.is_empty() => {
+                Ok(Some(value.to_vec()))
+            }
+            (Some(accepted_id), Some(value)) => key
+                .open(
+                    &encryption::state_aad(&self.file_prefix, accepted_id),
+                    value,
+                )
+                .map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    /// [StateView::decided_value] decrypted with the key the acceptor uses.
+    pub fn open_decided_value(&self, key: &StateKey) -> Result<Option<Vec<u8>>> {
+        self.decided_value()
+            .map(|value| key.open(&encryption::decided_aad(&self.file_prefix), value))
+            .transpose()
+    }
+}
+
+/// Maps `path` or returns None when it is missing or empty, empty files can't be mapped.
+fn map(path: &Path) -> Result<Option<Mmap>> {
+    let file = match File::open(path) {
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        result => result.with_context(|| format!("opening {}", path.display()))?,
+    };
+
+    if file.metadata()?.len() == 0 {
+        return Ok(None);
+    }
+
+    // SAFETY: the callers of StateView::open promise nothing truncates the
+    // file while the map is alive.
+    let mmap =
+        unsafe { Mmap::map(&file) }.with_context(|| format!("mapping {}", path.display()))?;
+
+    Ok(Some(mmap))
+}
@@ -1,375 +1,5247 @@
 use anyhow::{anyhow, Context, Result};
-use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, io::Cursor, net::SocketAddr};
-use tarpc::{client::Config, context, tokio_serde::formats::Json};
+use futures::{stream::FuturesUnordered, Future, StreamExt};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex as StdMutex,
+    },
+    time::{Duration, SystemTime},
+};
+use tarpc::{
+    client::{Config, RpcError},
+    ClientMessage, Request, Response,
+};
 use tokio::{
     fs::{File, OpenOptions},
     io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
+    select,
+    sync::{broadcast, Mutex, MutexGuard},
+    time::Instant,
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn, Span};
+
+use crate::{
+    audit::Auditor,
+    auth::{Credentials, Signed},
+    backpressure::RpcPermits,
+    commit::{Durable, GroupCommit},
+    encryption::{self, StateKey},
+    events::{self, Event},
+    format,
+    instance::InstanceId,
+    keepalive::KeepaliveConfig,
+    latency::{LatencySummary, LatencyTracker},
+    lease::{Grant, HeartbeatRequest, HeartbeatResponse, LeaseConfig, LeaseHeld, NotLeader},
+    membership::{MemberUpdate, Membership},
+    metrics::{NoopRecorder, Recorder},
+    proposal::ProposalId,
+    protocol::{self, Persist, Promises},
+    rejoin::{Announced, Announcement, Rejoin, Status},
+    retry::{ContentionBackoff, RetryPolicy},
+    timeout::{self, Phase, Timeout, Timeouts},
+    transport::{self, Connector},
 };
 
+/// How many times connecting to an acceptor is attempted before giving up.
+const CONNECT_ATTEMPTS: u32 = 3;
+
+/// The wait between connection attempts, grows linearly with each attempt.
+const CONNECT_BACKOFF: Duration = Duration::from_millis(50);
+
+/// How long connecting and the handshake may take outside of a phase.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long a node waits for the digests and the decided value when it syncs
+/// or heals.
+const HEAL_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The largest value proposed or accepted unless configured otherwise.
+pub const DEFAULT_MAX_VALUE_SIZE: usize = 64 * 1024 * 1024;
+
+/// The version of the messages this build sends and understands. Bumped
+/// whenever a message changes in a way older nodes can't read. Version 2
+/// added [AcceptorService::accept_chunk], version 3 seals the answers to
+/// prepare, accept and fetch requests, see [Signed], version 4 added
+/// [AcceptorService::create_instance], version 5 added
+/// [AcceptorService::fetch_accepted].
+pub const PROTOCOL_VERSION: u32 = 5;
+
+/// The oldest version this build still talks to, so a cluster can be
+/// upgraded one node at a time. The handshake settles on the highest version
+/// both ends know, see [Hello], and each connection speaks it.
+pub const MIN_PROTOCOL_VERSION: u32 = 2;
+
+/// The first version whose acceptors seal their answers. Answers from older
+/// acceptors are taken unsealed, and older proposers get theirs unsealed.
+pub(crate) const SEALED_ANSWERS_VERSION: u32 = 3;
+
+/// The first version with [AcceptorService::create_instance]. Older
+/// proposers only reach instances that exist, and older acceptors, which
+/// create instances on first use, aren't sent the request.
+pub(crate) const CREATE_INSTANCE_VERSION: u32 = 4;
+
+/// The first version with [AcceptorService::fetch_accepted]. Nodes don't
+/// rejoin from older acceptors, which can't tell them what they accepted.
+pub(crate) const FETCH_ACCEPTED_VERSION: u32 = 5;
+
 #[tarpc::service]
 pub trait AcceptorService {
-    async fn prepare(message: PrepareRequest) -> Result<PrepareResponse, String>;
-    async fn accept(message: AcceptRequest) -> Result<AcceptResponse, String>;
+    /// Sent first on every connection so nodes that can't understand each
+    /// other find out before exchanging anything else.
+    async fn hello(message: Hello) -> Result<HelloResponse, AcceptorError>;
+    async fn prepare(
+        credentials: Credentials,
+        message: PrepareRequest,
+    ) -> Result<Signed<PrepareResponse>, AcceptorError>;
+    async fn accept(
+        credentials: Credentials,
+        message: AcceptRequest,
+    ) -> Result<Signed<AcceptResponse>, AcceptorError>;
+    /// Sends the value of an accept request one piece at a time. The acceptor
+    /// answers the chunk that completes the value like an accept request, and
+    /// the other chunks with None unless it already promised a higher id.
+    async fn accept_chunk(
+        credentials: Credentials,
+        chunk: AcceptChunk,
+    ) -> Result<Signed<Option<AcceptResponse>>, AcceptorError>;
+    async fn relay_accept(
+        credentials: Credentials,
+        message: RelayAcceptRequest,
+    ) -> Result<Vec<RelayedAcceptResponse>, AcceptorError>;
+    /// Hands a decided value down the relay tree accept requests took.
+    async fn relay_learn(
+        credentials: Credentials,
+        message: RelayLearnRequest,
+    ) -> Result<(), AcceptorError>;
+    async fn digest(
+        credentials: Credentials,
+        instance: InstanceId,
+    ) -> Result<Digest, AcceptorError>;
+    async fn fetch_decided(
+        credentials: Credentials,
+        instance: InstanceId,
+    ) -> Result<Signed<Option<Vec<u8>>>, AcceptorError>;
+    /// Hands what the acceptor accepted to a node that lost its data, see
+    /// [Paxos::rejoin].
+    async fn fetch_accepted(
+        credentials: Credentials,
+        instance: InstanceId,
+    ) -> Result<Signed<AcceptedProposal>, AcceptorError>;
+    /// Hands the values decided from `from` on to a node catching up, in
+    /// pages. Instances numbered like `log/7` are followed by their successors.
+    async fn fetch_state(
+        credentials: Credentials,
+        from: InstanceId,
+    ) -> Result<Signed<StateTransfer>, AcceptorError>;
+    async fn latencies(
+        credentials: Credentials,
+    ) -> Result<HashMap<SocketAddr, LatencySummary>, AcceptorError>;
+    async fn health(credentials: Credentials) -> Result<Health, AcceptorError>;
+    async fn dump_state(
+        credentials: Credentials,
+        instance: InstanceId,
+    ) -> Result<StateDump, AcceptorError>;
+    async fn reset_state(
+        credentials: Credentials,
+        instance: InstanceId,
+        confirmation: String,
+    ) -> Result<(), AcceptorError>;
+    async fn heartbeat(
+        credentials: Credentials,
+        message: HeartbeatRequest,
+    ) -> Result<HeartbeatResponse, AcceptorError>;
+    async fn ping(
+        credentials: Credentials,
+        updates: Vec<MemberUpdate>,
+    ) -> Result<Vec<MemberUpdate>, AcceptorError>;
+    async fn ping_req(
+        credentials: Credentials,
+        target: SocketAddr,
+        updates: Vec<MemberUpdate>,
+    ) -> Result<Vec<MemberUpdate>, AcceptorError>;
+    /// Creates the instance on the acceptor, which answers requests only for
+    /// instances that were created, see [crate::instances::Instances::get].
+    async fn create_instance(
+        credentials: Credentials,
+        instance: InstanceId,
+    ) -> Result<(), AcceptorError>;
+    /// Sent by a booting node to find out whether it ran before, see
+    /// [crate::rejoin].
+    async fn announce(
+        credentials: Credentials,
+        announcement: Announcement,
+    ) -> Result<Announced, AcceptorError>;
 }
 
 #[derive(Debug)]
 pub struct Paxos {
+    /// The id of this node.
+    id: u32,
+
+    /// The consensus instance this node takes part in.
+    instance: InstanceId,
+
     /// The address of this instance.
     address: SocketAddr,
 
     /// The next proposal id that will be sent to the acceptors.
-    current_proposal_id: u64,
+    current_proposal_id: ProposalId,
+
+    /// Keeps the highest proposal id this node sent so a restarted node
+    /// doesn't send the same ids again.
+    proposer_file: File,
 
     /// The address of each acceptor.
     acceptors: Vec<SocketAddr>,
 
-    /// Client used to communicate with acceptors.
-    acceptor_clients: HashMap<SocketAddr, AcceptorServiceClient>,
+    /// Client used to communicate with acceptors, shared with the other
+    /// instances built from the same builder.
+    acceptor_clients: ClientPool,
+
+    /// Used to open connections to acceptors.
+    connector: Connector,
+
+    /// Sent with every request so acceptors can authenticate this proposer.
+    credentials: Credentials,
+
+    /// How long to wait for acceptors in each phase.
+    timeouts: Timeouts,
+
+    /// How failed rounds are retried.
+    retry_policy: RetryPolicy,
+
+    /// How long to wait after another proposer preempted a round.
+    contention_backoff: ContentionBackoff,
+
+    /// Round trip times of the requests sent to each acceptor.
+    latencies: LatencyTracker,
+
+    /// Which acceptors rounds contact.
+    quorum_selection: QuorumSelection,
+
+    /// Set once a phase failed to reach a quorum of the acceptors
+    /// [QuorumSelection::Nearest] picked, until a value is decided.
+    widened: bool,
+
+    metrics: Arc<dyn Recorder>,
+
+    /// Overrides the majority quorum.
+    quorum: Option<usize>,
+
+    /// Decisions that take longer than this count against the slo.
+    decision_slo: Duration,
+
+    /// When the first proposal was submitted to this node.
+    first_submitted_at: Option<Instant>,
+
+    /// How long the prepare and accept phases of the last round took.
+    round_durations: (Duration, Duration),
+
+    /// Set once this node gets a value decided through its own proposals.
+    decision_timing: Option<DecisionTiming>,
+
+    /// False until the node has heard from a quorum after starting. Until then
+    /// it doesn't know how high the proposal ids in the cluster go, so it
+    /// doesn't propose to avoid disrupting rounds with low proposal ids.
+    synced: bool,
 
-    /// The last proposal id this acceptor has seen.
-    proposal_id: u64,
+    /// When set, accept requests are disseminated through a tree of relays where
+    /// each node contacts at most this many acceptors. Meant for large clusters.
+    relay_fanout: Option<usize>,
 
-    /// The last proposal value this acceptor has received.
-    proposal_value: Option<Vec<u8>>,
+    /// When set, values longer than this are sent to the acceptors in chunks
+    /// of this many bytes.
+    chunk_size: Option<usize>,
+
+    /// Values above this many bytes are neither proposed nor accepted.
+    max_value_size: usize,
+
+    /// The value being received in chunks, see [AcceptorService::accept_chunk].
+    upload: Option<Upload>,
+
+    /// When set, the node only proposes while it holds the lease.
+    lease: Option<LeaseConfig>,
+
+    /// When the last heartbeat a quorum granted was sent. The lease is held
+    /// until `lease.duration` after it, acceptors start counting later.
+    lease_granted_at: Option<Instant>,
+
+    /// The lease this acceptor granted.
+    lease_grant: Grant,
+
+    /// The file the granted lease is persisted to, see [Grant::encode].
+    lease_file: File,
+
+    /// Where this node serves clients. Set when proposals are forwarded to
+    /// the leader, see [PaxosBuilder::forward_to_leader].
+    client_addr: Option<SocketAddr>,
+
+    /// Whether reads are served locally while the node holds the lease.
+    lease_reads: bool,
+
+    /// Counts the times this node got the lease after not holding it. Nodes
+    /// may have proposed in between, while it stays the same nobody else did.
+    lease_term: u64,
+
+    /// The lease term in which a read round found no value accepted by a
+    /// quorum. The register stays empty until the term ends or this node
+    /// sends accept requests.
+    empty_in_term: Option<u64>,
+
+    /// Encrypts the values written to the state and decided files.
+    state_key: Option<StateKey>,
+
+    /// What this acceptor promised and accepted, and how it answers requests.
+    acceptor: protocol::Acceptor,
 
     /// The file that contains the acceptor state.
     state_file: File,
+    state_path: PathBuf,
+
+    /// When the state file was last written, from its modification time
+    /// until the node writes it.
+    persisted_at: Option<SystemTime>,
+
+    /// The value the cluster has decided on, once this node learns it.
+    decided_value: Option<Vec<u8>>,
+
+    /// The file the decided value is persisted to.
+    decided_file: File,
+
+    /// Why the last write to the state file failed, cleared by the next write that succeeds.
+    storage_error: Option<String>,
+
+    /// Set once the files were flushed before the process exits, the node
+    /// refuses to write to them afterwards.
+    shut_down: bool,
+
+    /// Checks every acceptor transition when audit mode is on.
+    auditor: Option<Auditor>,
+
+    /// Syncs the state file on behalf of concurrent writes when configured.
+    group_commit: Option<GroupCommit>,
+
+    /// Called once the node learns the decided value.
+    on_decided: Option<OnDecided>,
+
+    /// Where [Event]s go, see [Paxos::subscribe].
+    events: broadcast::Sender<Event>,
+
+    /// Which acceptors are believed dead, rounds skip them when set.
+    membership: Option<Membership>,
+
+    /// Caps the prepare and accept rpcs in flight, shared by every instance.
+    rpc_permits: RpcPermits,
+
+    /// The acceptors rounds only ask once the others failed to form a quorum.
+    auxiliary: HashSet<SocketAddr>,
+
+    /// Set once a phase failed without the auxiliary acceptors, the rounds
+    /// after it ask them too until the main acceptors form a quorum on their
+    /// own again or a value is decided.
+    auxiliary_needed: bool,
+
+    /// Whether the node may vote yet, see [PaxosBuilder::rejoin].
+    rejoin: Option<Rejoin>,
+
+    /// Set once the instance caught up after a data loss, or when it had state
+    /// to begin with. A recovering node only votes in these.
+    rejoined: bool,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// A callback the embedding application registers to hear about decisions.
+/// Every instance built from the same builder shares it.
+#[derive(Clone)]
+pub struct OnDecided(Arc<StdMutex<dyn FnMut(&InstanceId, &[u8]) + Send>>);
+
+impl OnDecided {
+    pub fn new(callback: impl FnMut(&InstanceId, &[u8]) + Send + 'static) -> Self {
+        Self(Arc::new(StdMutex::new(callback)))
+    }
+
+    fn call(&self, instance: &InstanceId, value: &[u8]) {
+        // A callback that panicked before can still be told about other instances.
+        let mut callback = self.0.lock().unwrap_or_else(|err| err.into_inner());
+        callback(instance, value);
+    }
+}
+
+impl fmt::Debug for OnDecided {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("OnDecided")
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct PrepareRequest {
-    pub proposal_id: u64,
+    pub instance: InstanceId,
+    pub proposal_id: ProposalId,
+
+    /// The id of the node proposing. Acceptors refuse the request while
+    /// another node holds the lease, anonymous proposers always wait.
+    #[serde(default)]
+    pub proposer: Option<u32>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct PrepareResponse {
-    pub proposal_id: u64,
+    pub proposal_id: ProposalId,
+    /// The id of the proposal `proposal_value` was accepted in. Proposers adopt
+    /// the value accepted in the highest proposal among the promises they get.
+    #[serde(default)]
+    pub accepted_id: ProposalId,
     pub proposal_value: Option<Vec<u8>>,
+    /// Whether the acceptor promised not to accept proposals lower than the requested id.
+    pub promised: bool,
+    /// Set by witnesses, which report `accepted_id` without the value.
+    #[serde(default)]
+    pub witness: bool,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct AcceptRequest {
-    pub proposal_id: u64,
-    pub proposal_value: Vec<u8>,
+/// How an acceptor answers a prepare request whose proposal id equals the one it
+/// already promised.
+///
+/// Proposal ids are not unique per node: every proposer counts up from the
+/// highest id it saw, so two proposers routinely pick the same id.
+/// Re-promising an equal id lets both of them believe they own the round and
+/// get different values chosen under the same id, so `StrictlyGreater` is the
+/// safe default. Set with [PaxosBuilder::promise_policy].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PromisePolicy {
+    #[default]
+    StrictlyGreater,
+    /// Makes retransmitted prepare requests idempotent. Only safe when the ids
+    /// are partitioned per proposer, e.g. a single proposer or proposers
+    /// that only pick ids congruent to their node id, which the proposers in
+    /// this crate don't do. Otherwise two values can be chosen.
+    GreaterOrEqual,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct AcceptResponse {
-    pub proposal_id: u64,
-    pub proposal_value: Option<Vec<u8>>,
+/// Which acceptors the rounds of a proposer contact.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum QuorumSelection {
+    /// Every acceptor. The round goes on with the first quorum to answer.
+    #[default]
+    All,
+    /// The acceptors with the lowest round trip times, as many as a quorum
+    /// needs and `spare` more. Meant for clusters spanning regions, where the
+    /// far acceptors are only asked when the near ones can't decide: after a
+    /// phase fails to reach a quorum, rounds contact every acceptor until a
+    /// value is decided.
+    Nearest { spare: usize },
 }
 
-#[derive(Debug)]
-struct State {
-    proposal_id: u64,
-    proposal_value: Option<Vec<u8>>,
+impl std::str::FromStr for QuorumSelection {
+    type Err = anyhow::Error;
+
+    /// Parses `all`, `nearest` or `nearest+<spare>`.
+    fn from_str(s: &str) -> Result<Self> {
+        match s.split_once('+') {
+            None if s == "all" => Ok(Self::All),
+            None if s == "nearest" => Ok(Self::Nearest { spare: 0 }),
+            Some(("nearest", spare)) => Ok(Self::Nearest {
+                spare: spare
+                    .parse()
+                    .with_context(|| format!("invalid spare acceptor count: {spare}"))?,
+            }),
+            _ => Err(anyhow!(
+                "unknown quorum selection, expected all, nearest or nearest+<spare>: {s}"
+            )),
+        }
+    }
 }
 
-async fn read_state(file: &mut File) -> Result<Option<State>> {
-    let mut buffer = Vec::new();
-    file.read_to_end(&mut buffer)
-        .await
-        .context("reading file contents to buffer")?;
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct AcceptRequest {
+    pub instance: InstanceId,
+    pub proposal_id: ProposalId,
+    pub proposal_value: Vec<u8>,
+}
 
-    if buffer.is_empty() {
-        return Ok(None);
-    }
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct AcceptChunk {
+    pub instance: InstanceId,
+    pub proposal_id: ProposalId,
 
-    let mut cursor = Cursor::new(buffer);
+    /// Where `data` starts in the value. Chunks are sent in order, one at
+    /// offset 0 starts a new value.
+    pub offset: u64,
 
-    // TODO: does not need to be async.
-    let proposal_id = cursor
-        .read_u64_le()
-        .await
-        .context("reading proposal id from buffer")?;
+    /// The length of the whole value.
+    pub value_len: u64,
+    pub data: Vec<u8>,
+}
 
-    let mut proposal_value = Vec::new();
-    cursor.read_to_end(&mut proposal_value).await?;
+/// A value an acceptor is receiving in chunks.
+#[derive(Debug)]
+struct Upload {
+    proposal_id: ProposalId,
+    value_len: u64,
+    data: Vec<u8>,
+}
 
-    Ok(Some(State {
-        proposal_id,
-        proposal_value: if proposal_value.is_empty() {
-            None
-        } else {
-            Some(proposal_value)
-        },
-    }))
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct AcceptResponse {
+    /// The id the acceptor promised. Above the requested id when the request
+    /// was rejected, so the proposer knows where its next round must start.
+    pub proposal_id: ProposalId,
+    pub proposal_value: Option<Vec<u8>>,
 }
 
-impl Paxos {
-    pub async fn new(id: u32, address: SocketAddr, acceptors: Vec<SocketAddr>) -> Result<Self> {
-        let mut state_file = OpenOptions::new()
-            .create(true)
-            .read(true)
-            .write(true)
-            .open(format!("acceptor_{id}.state"))
-            .await
-            .context("opening acceptor state file")?;
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct RelayAcceptRequest {
+    pub accept: AcceptRequest,
+    /// The acceptors the relay is responsible for forwarding the request to.
+    pub targets: Vec<SocketAddr>,
+    pub fanout: usize,
+}
 
-        let state = read_state(&mut state_file)
-            .await
-            .context("reading state from file")?;
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct RelayedAcceptResponse {
+    /// The acceptor that produced the response.
+    pub acceptor: SocketAddr,
+    pub response: Result<AcceptResponse, AcceptorError>,
+    /// The acceptor's HMAC of the request and its answer, see
+    /// [Credentials::sign_response]. Relays forward it untouched, the
+    /// proposer only counts the answers relays forward when it is valid.
+    #[serde(default)]
+    pub mac: Option<String>,
+}
 
-        let (proposal_id, proposal_value) = match state {
-            None => (0, None),
-            Some(state) => (state.proposal_id, state.proposal_value),
-        };
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct RelayLearnRequest {
+    pub decided: DecidedInstance,
+    /// The proposer's HMAC of `decided`. Relays forward it untouched,
+    /// acceptors only learn values the proposer signed.
+    pub mac: Option<String>,
+    /// The acceptors the relay is responsible for forwarding the value to.
+    pub targets: Vec<SocketAddr>,
+    pub fanout: usize,
+}
 
-        Ok(Self {
-            address,
-            current_proposal_id: 0,
-            acceptors,
-            acceptor_clients: HashMap::new(),
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Hello {
+    pub protocol_version: u32,
+}
 
-            proposal_id,
-            proposal_value,
-            state_file,
-        })
-    }
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct HelloResponse {
+    pub protocol_version: u32,
+    pub min_protocol_version: u32,
+}
 
-    fn majority(&self) -> usize {
-        self.acceptors.len() / 2 + 1
+impl Hello {
+    pub fn current() -> Self {
+        Hello {
+            protocol_version: PROTOCOL_VERSION,
+        }
     }
 
-    async fn get_or_init_client(&mut self, acceptor: SocketAddr) -> Result<AcceptorServiceClient> {
-        if let Some(client) = self.acceptor_clients.get(&acceptor) {
-            return Ok(client.clone());
+    /// How an acceptor running this build answers the hello.
+    pub fn answer(&self) -> Result<HelloResponse, AcceptorError> {
+        if !(MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&self.protocol_version) {
+            return Err(AcceptorError::UnsupportedVersion {
+                version: self.protocol_version,
+                min_supported: MIN_PROTOCOL_VERSION,
+                max_supported: PROTOCOL_VERSION,
+            });
         }
 
-        let mut transport = tarpc::serde_transport::tcp::connect(acceptor, Json::default);
-        transport.config_mut().max_frame_length(usize::MAX);
-        let transport = transport.await.context("initializing transport")?;
-
-        let client = AcceptorServiceClient::new(Config::default(), transport).spawn();
+        Ok(HelloResponse {
+            protocol_version: PROTOCOL_VERSION,
+            min_protocol_version: MIN_PROTOCOL_VERSION,
+        })
+    }
+}
 
-        self.acceptor_clients.insert(acceptor, client.clone());
+/// Why an acceptor could not handle a request. A proposal the acceptor
+/// refuses to promise or accept is not an error, it is answered normally.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum AcceptorError {
+    /// The acceptor could not persist its state. It did not promise or
+    /// accept anything, but may have applied the request in memory.
+    Storage {
+        message: String,
+    },
 
-        Ok(client)
-    }
+    /// The acceptor refused to handle the request, because the caller did not
+    /// authenticate or asked for something that is disabled.
+    Rejected {
+        reason: String,
+    },
 
-    pub async fn propose(&mut self, value: Vec<u8>) -> Result<()> {
-        self.current_proposal_id += 1;
+    /// The request makes no sense to the acceptor, such as an instance it
+    /// doesn't serve.
+    InvalidRequest {
+        reason: String,
+    },
 
-        let mut futures = Vec::with_capacity(self.acceptors.len());
+    ShuttingDown,
 
-        for i in 0..self.acceptors.len() {
-            let acceptor_addr = self.acceptors[i];
-            if acceptor_addr == self.address {
-                continue;
-            }
+    /// The acceptor doesn't vote yet: it is booting, or lost its data and
+    /// didn't catch the instance up yet. The caller should ask other acceptors.
+    Recovering,
 
-            let client = match self.get_or_init_client(acceptor_addr).await {
-                Err(err) => {
-                    eprintln!("getting rpc client: acceptor={acceptor_addr} {err:?}");
-                    continue;
-                }
-                Ok(v) => v,
-            };
+    /// The caller speaks a protocol version the acceptor can't handle.
+    UnsupportedVersion {
+        version: u32,
+        min_supported: u32,
+        max_supported: u32,
+    },
 
-            let request = PrepareRequest {
-                proposal_id: self.current_proposal_id,
-            };
-            futures.push(async move { client.prepare(context::current(), request).await });
-        }
+    /// A relay did not hear back from the acceptor in time, or the caller's
+    /// deadline passed before the acceptor got to the request.
+    TimedOut,
 
-        let results = futures::future::join_all(futures).await;
+    /// A relay could not get the request to the acceptor.
+    Unreachable {
+        reason: String,
+    },
 
-        let _ = self
-            .on_prepare(PrepareRequest {
-                proposal_id: self.proposal_id,
-            })
-            .await;
+    /// The acceptor is handling more requests than its rate limit allows.
+    /// Nothing was applied, the caller should try again after `retry_after_ms`.
+    Throttled {
+        retry_after_ms: u64,
+    },
 
-        let mut response_count = 0;
-        let mut highest_proposal_id = 0;
-        let mut accepted_value = None;
+    /// Another node holds the lease this acceptor granted, the acceptor
+    /// promises nothing to anyone else until it expires.
+    LeaseHeld {
+        holder: u32,
+        remaining_ms: u64,
+    },
 
-        for result in results {
-            let response = match result {
-                Err(err) => {
-                    eprintln!("rpc error {err:?}");
-                    continue;
-                }
-                Ok(v) => v,
-            };
+    /// Anything else that went wrong handling the request.
+    Internal {
+        message: String,
+    },
+}
 
-            match response {
-                Err(err) => {
-                    eprintln!("error response to prepare request: {err:?}");
-                    continue;
-                }
-                Ok(response) => {
-                    response_count += 1;
-                    highest_proposal_id = std::cmp::max(highest_proposal_id, response.proposal_id);
-                    if response.proposal_value.is_some() {
-                        accepted_value = response.proposal_value;
-                    }
-                }
-            }
+impl AcceptorError {
+    pub fn rejected(reason: impl fmt::Display) -> Self {
+        AcceptorError::Rejected {
+            reason: reason.to_string(),
         }
+    }
 
-        // -1 To take this proposer into account.
-        if response_count < self.majority() - 1 {
-            return Err(anyhow!(
-                "unable to get response to prepare request from majority of acceptors"
-            ));
+    pub fn storage(err: &anyhow::Error) -> Self {
+        AcceptorError::Storage {
+            message: format!("{err:#}"),
         }
+    }
 
-        self.current_proposal_id = std::cmp::max(self.current_proposal_id, highest_proposal_id);
+    pub fn throttled(retry_after: Duration) -> Self {
+        AcceptorError::Throttled {
+            // Rounded up so the caller doesn't retry before a token is available.
+            retry_after_ms: retry_after.as_millis() as u64 + 1,
+        }
+    }
 
-        match accepted_value {
-            None => self
-                .accept(value)
-                .await
-                .context("sending accept requests with proposed value"),
-            Some(accepted_value) => {
-                self.accept(accepted_value.clone())
-                    .await
-                    .context("sending accept requests with already accepted value")?;
+    pub fn lease_held(holder: u32, remaining: Duration) -> Self {
+        AcceptorError::LeaseHeld {
+            holder,
+            // Rounded up so the caller doesn't retry before the lease expires.
+            remaining_ms: remaining.as_millis() as u64 + 1,
+        }
+    }
 
-                Err(anyhow!(
-                    "a value has already been accepted: {}",
-                    String::from_utf8_lossy(&accepted_value)
-                ))
+    /// How long to wait before retrying when the acceptor throttled the request
+    /// or another node holds its lease.
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            AcceptorError::Throttled { retry_after_ms } => {
+                Some(Duration::from_millis(*retry_after_ms))
             }
+            AcceptorError::LeaseHeld { remaining_ms, .. } => {
+                Some(Duration::from_millis(*remaining_ms))
+            }
+            _ => None,
         }
     }
+}
 
-    async fn accept(&mut self, value: Vec<u8>) -> Result<()> {
-        let mut futures = Vec::with_capacity(self.acceptors.len());
-
-        for i in 0..self.acceptors.len() {
-            let acceptor_addr = self.acceptors[i];
-            if acceptor_addr == self.address {
-                continue;
+impl fmt::Display for AcceptorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AcceptorError::Storage { message } => write!(f, "storage failure: {message}"),
+            AcceptorError::Rejected { reason } => write!(f, "rejected: {reason}"),
+            AcceptorError::InvalidRequest { reason } => write!(f, "invalid request: {reason}"),
+            AcceptorError::ShuttingDown => write!(f, "acceptor is shutting down"),
+            AcceptorError::Recovering => {
+                write!(f, "acceptor lost its data and is catching up before voting")
             }
+            AcceptorError::UnsupportedVersion {
+                version,
+                min_supported,
+                max_supported,
+            } => write!(
+                f,
+                "unsupported protocol version {version}, expected {min_supported} to {max_supported}"
+            ),
+            AcceptorError::TimedOut => write!(f, "timed out"),
+            AcceptorError::Unreachable { reason } => write!(f, "unreachable: {reason}"),
+            AcceptorError::Throttled { retry_after_ms } => {
+                write!(f, "throttled, retry after {retry_after_ms}ms")
+            }
+            AcceptorError::LeaseHeld {
+                holder,
+                remaining_ms,
+            } => write!(f, "node {holder} holds the lease for another {remaining_ms}ms"),
+            AcceptorError::Internal { message } => write!(f, "{message}"),
+        }
+    }
+}
 
-            let client = match self.get_or_init_client(acceptor_addr).await {
-                Err(err) => {
-                    eprintln!("getting rpc client: {err:?}");
-                    continue;
-                }
-                Ok(v) => v,
-            };
+impl std::error::Error for AcceptorError {}
 
-            let request = AcceptRequest {
-                proposal_id: self.current_proposal_id,
-                proposal_value: value.clone(),
-            };
-            futures.push(async move { client.accept(context::current(), request).await });
+/// Keeps the kind of the errors the acceptor raised as an [AcceptorError],
+/// every other error is internal.
+impl From<anyhow::Error> for AcceptorError {
+    fn from(err: anyhow::Error) -> Self {
+        match err.downcast_ref::<AcceptorError>() {
+            Some(err) => err.clone(),
+            None => AcceptorError::Internal {
+                message: format!("{err:#}"),
+            },
         }
+    }
+}
 
-        let _ = self
-            .on_accept(AcceptRequest {
-                proposal_id: self.proposal_id,
-                proposal_value: value.clone(),
-            })
-            .await;
+/// Why a node can't be built with the acceptors it was given.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TopologyError {
+    NoAcceptors,
 
-        let results = futures::future::join_all(futures).await;
+    /// An acceptor listed more than once would count more than once towards
+    /// a quorum.
+    DuplicateAcceptor(SocketAddr),
 
-        let mut response_count = 0;
-        for result in results {
-            let response = match result {
-                Err(err) => {
-                    eprintln!("rpc error {err:?}");
-                    continue;
-                }
-                Ok(v) => v,
-            };
+    /// Any two quorums must intersect or two values could be chosen.
+    InvalidQuorum {
+        quorum: usize,
+        acceptors: usize,
+    },
 
-            match response {
-                Err(err) => {
-                    eprintln!("error response to accept request: {err:?}");
-                    continue;
-                }
-                Ok(response) => {
-                    if self.current_proposal_id < response.proposal_id {
-                        return Err(anyhow!(
-                            "acceptor has seen a proposal id greater than our own"
-                        ));
-                    }
+    /// A node that serves as an acceptor isn't in the acceptor list, so it
+    /// wouldn't count itself towards a quorum.
+    NotAnAcceptor(SocketAddr),
 
-                    response_count += 1;
-                }
+    /// Auxiliary acceptors must be in the acceptor list.
+    UnknownAuxiliary(SocketAddr),
+
+    /// Without the auxiliary acceptors the rest can't decide anything, so
+    /// every round would need them.
+    TooFewMainAcceptors {
+        main: usize,
+        quorum: usize,
+    },
+
+    /// Witnesses must be in the acceptor list.
+    UnknownWitness(SocketAddr),
+
+    /// A quorum made of witnesses alone could choose a value no acceptor
+    /// stores.
+    TooManyWitnesses {
+        witnesses: usize,
+        quorum: usize,
+    },
+}
+
+impl fmt::Display for TopologyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TopologyError::NoAcceptors => write!(f, "the acceptor list is empty"),
+            TopologyError::DuplicateAcceptor(acceptor) => {
+                write!(f, "acceptor {acceptor} is listed more than once")
+            }
+            TopologyError::InvalidQuorum { quorum, acceptors } => write!(
+                f,
+                "quorum must be more than half of the {acceptors} acceptors and at most all of them: quorum={quorum}"
+            ),
+            TopologyError::NotAnAcceptor(address) => write!(
+                f,
+                "{address} is not in the acceptor list, the address the node listens on must be listed"
+            ),
+            TopologyError::UnknownAuxiliary(acceptor) => {
+                write!(f, "auxiliary acceptor {acceptor} is not in the acceptor list")
+            }
+            TopologyError::TooFewMainAcceptors { main, quorum } => write!(
+                f,
+                "the {main} main acceptors can't form a quorum of {quorum} without the auxiliary ones"
+            ),
+            TopologyError::UnknownWitness(acceptor) => {
+                write!(f, "witness {acceptor} is not in the acceptor list")
             }
+            TopologyError::TooManyWitnesses { witnesses, quorum } => write!(
+                f,
+                "the {witnesses} witnesses could form a quorum of {quorum} on their own"
+            ),
         }
+    }
+}
 
-        if response_count < self.majority() - 1 {
-            return Err(anyhow!(
-                "unable to get response to accept request from majority of acceptors"
-            ));
-        }
+impl std::error::Error for TopologyError {}
 
-        Ok(())
+/// Checks that the acceptors not in `auxiliary` form a quorum of `acceptors`
+/// on their own, the majority when `quorum` is None.
+pub fn check_auxiliary(
+    acceptors: &[SocketAddr],
+    auxiliary: &[SocketAddr],
+    quorum: Option<usize>,
+) -> Result<(), TopologyError> {
+    if auxiliary.is_empty() {
+        return Ok(());
     }
 
-    pub async fn on_prepare(&mut self, message: PrepareRequest) -> Result<PrepareResponse> {
-        if message.proposal_id > self.proposal_id {
-            self.proposal_id = message.proposal_id;
+    if let Some(unknown) = auxiliary
+        .iter()
+        .find(|acceptor| !acceptors.contains(acceptor))
+    {
+        return Err(TopologyError::UnknownAuxiliary(*unknown));
+    }
 
-            self.state_file
-                .seek(std::io::SeekFrom::Start(0))
-                .await
-                .context("seeking to beginning of state file")?;
+    let main = acceptors
+        .iter()
+        .filter(|acceptor| !auxiliary.contains(acceptor))
+        .count();
+    let quorum = quorum.unwrap_or(acceptors.len() / 2 + 1);
+    if main < quorum {
+        return Err(TopologyError::TooFewMainAcceptors { main, quorum });
+    }
 
-            self.state_file
-                .write_u64_le(message.proposal_id)
-                .await
-                .context("writing proposal id to disk")?;
+    Ok(())
+}
 
-            self.state_file
-                .sync_all()
-                .await
-                .context("syncing state file")?;
+/// Checks that `witnesses` are acceptors and that every quorum of `acceptors`
+/// holds at least one acceptor that stores values, the majority when `quorum`
+/// is None.
+pub fn check_witnesses(
+    acceptors: &[SocketAddr],
+    witnesses: &[SocketAddr],
+    quorum: Option<usize>,
+) -> Result<(), TopologyError> {
+    if let Some(unknown) = witnesses
+        .iter()
+        .find(|witness| !acceptors.contains(witness))
+    {
+        return Err(TopologyError::UnknownWitness(*unknown));
+    }
+
+    let quorum = quorum.unwrap_or(acceptors.len() / 2 + 1);
+    if witnesses.len() >= quorum {
+        return Err(TopologyError::TooManyWitnesses {
+            witnesses: witnesses.len(),
+            quorum,
+        });
+    }
+
+    Ok(())
+}
+
+/// Checks that `acceptors` can form a cluster with `quorum`, the majority when
+/// None, and that `acceptor`, the address of the node when it serves as an
+/// acceptor, is one of them. Warns about sizes that work but waste an acceptor
+/// or can't lose one.
+pub fn check_topology(
+    acceptors: &[SocketAddr],
+    quorum: Option<usize>,
+    acceptor: Option<SocketAddr>,
+) -> Result<(), TopologyError> {
+    if acceptors.is_empty() {
+        return Err(TopologyError::NoAcceptors);
+    }
+
+    if let Some(acceptor) = acceptor {
+        if !acceptors.contains(&acceptor) {
+            return Err(TopologyError::NotAnAcceptor(acceptor));
         }
+    }
 
-        Ok(PrepareResponse {
-            proposal_id: self.proposal_id,
-            proposal_value: self.proposal_value.clone(),
-        })
+    let mut seen = HashSet::with_capacity(acceptors.len());
+    for acceptor in acceptors {
+        if !seen.insert(acceptor) {
+            return Err(TopologyError::DuplicateAcceptor(*acceptor));
+        }
     }
 
-    pub async fn on_accept(&mut self, message: AcceptRequest) -> Result<AcceptResponse> {
-        if message.proposal_id < self.proposal_id {
-            return Ok(AcceptResponse {
-                proposal_id: self.proposal_id,
-                proposal_value: self.proposal_value.clone(),
+    if let Some(quorum) = quorum {
+        if quorum <= acceptors.len() / 2 || quorum > acceptors.len() {
+            return Err(TopologyError::InvalidQuorum {
+                quorum,
+                acceptors: acceptors.len(),
             });
         }
+    }
 
-        self.proposal_id = message.proposal_id;
-        self.proposal_value = Some(message.proposal_value);
+    if acceptors.len() < 3 {
+        warn!(
+            acceptors = acceptors.len(),
+            "the cluster can't make progress when an acceptor is down"
+        );
+    } else if acceptors.len() % 2 == 0 {
+        warn!(
+            acceptors = acceptors.len(),
+            "an even number of acceptors tolerates as many failures as one acceptor less"
+        );
+    }
 
-        let mut buffer = Vec::new();
-        buffer
-            .write_u64_le(self.proposal_id)
-            .await
-            .context("writing proposal id to buffer")?;
-        buffer
-            .write_all(self.proposal_value.as_ref().unwrap())
-            .await
-            .context("writing proposal value to buffer")?;
+    Ok(())
+}
 
-        self.state_file
-            .seek(std::io::SeekFrom::Start(0))
+/// Returned when acceptors rejected a round because they promised a proposal
+/// id at or above the proposer's. The proposer's next round starts above
+/// `promised`.
+#[derive(Debug)]
+pub struct Preempted {
+    pub phase: Phase,
+
+    /// The highest proposal id the acceptors that rejected the round reported.
+    pub promised: ProposalId,
+}
+
+impl fmt::Display for Preempted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} phase preempted by proposal id {}",
+            self.phase, self.promised
+        )
+    }
+}
+
+impl std::error::Error for Preempted {}
+
+/// Returned when a proposal was cancelled through its [CancellationToken].
+/// Requests still in flight were dropped. Cancelled in the accept phase, the
+/// value may still get decided.
+#[derive(Debug)]
+pub struct Cancelled {
+    /// The phase the proposal was in, None between rounds.
+    pub phase: Option<Phase>,
+}
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.phase {
+            None => write!(f, "proposal cancelled"),
+            Some(phase) => write!(f, "proposal cancelled in the {phase} phase"),
+        }
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+/// A proposal between its rounds, see [Paxos::begin_propose].
+#[derive(Debug)]
+pub struct Proposal {
+    value: Vec<u8>,
+    started_at: Instant,
+    attempts: u32,
+    /// Rounds preempted in a row.
+    preemptions: u32,
+    metrics: Arc<dyn Recorder>,
+}
+
+/// How a round run with [Paxos::propose_round] ended.
+#[derive(Debug)]
+pub enum Round {
+    Decided(Decided),
+    /// The round failed, the next one starts after `backoff`.
+    Retry {
+        backoff: Duration,
+    },
+}
+
+/// Returned when a phase failed to reach a majority because acceptors
+/// throttled the proposer or granted the lease to another node. The next
+/// round waits at least `retry_after`.
+#[derive(Debug)]
+pub struct Throttled {
+    pub phase: Phase,
+
+    /// The longest wait the acceptors asked for.
+    pub retry_after: Duration,
+}
+
+impl fmt::Display for Throttled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} phase throttled by acceptors, retry after {:?}",
+            self.phase, self.retry_after
+        )
+    }
+}
+
+impl std::error::Error for Throttled {}
+
+/// Returned when a value is above the size limit of the node.
+#[derive(Debug)]
+pub struct ValueTooLarge {
+    pub size: usize,
+    pub limit: usize,
+}
+
+impl fmt::Display for ValueTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "value of {} bytes is above the limit of {} bytes",
+            self.size, self.limit
+        )
+    }
+}
+
+impl std::error::Error for ValueTooLarge {}
+
+/// The value a proposal got decided. Values are compared by their bytes, a
+/// decided value equal to the proposed one is the proposer's own.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Decided {
+    /// The proposed value was chosen.
+    Ours(Vec<u8>),
+
+    /// Another value had already been accepted and was chosen instead.
+    /// Retrying does not change the outcome.
+    Other(Vec<u8>),
+}
+
+impl Decided {
+    fn new(proposed: &[u8], decided: Vec<u8>) -> Self {
+        if decided == proposed {
+            Decided::Ours(decided)
+        } else {
+            Decided::Other(decided)
+        }
+    }
+
+    pub fn is_ours(&self) -> bool {
+        matches!(self, Decided::Ours(_))
+    }
+
+    pub fn value(&self) -> &[u8] {
+        match self {
+            Decided::Ours(value) | Decided::Other(value) => value,
+        }
+    }
+
+    pub fn into_value(self) -> Vec<u8> {
+        match self {
+            Decided::Ours(value) | Decided::Other(value) => value,
+        }
+    }
+}
+
+/// A summary of an acceptor state used to find out whether a node is behind.
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Digest {
+    pub proposal_id: ProposalId,
+    /// Whether the acceptor knows the decided value.
+    pub decided: bool,
+}
+
+/// The proposal an acceptor accepted last, handed to a node rejoining after
+/// it lost its data.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct AcceptedProposal {
+    /// The proposal `value` was accepted in, zero when nothing was accepted.
+    pub accepted_id: ProposalId,
+    pub value: Option<Vec<u8>>,
+    /// Set by witnesses, which report `accepted_id` without the value.
+    #[serde(default)]
+    pub witness: bool,
+}
+
+/// A value decided in one instance, handed to a node catching up.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct DecidedInstance {
+    pub instance: InstanceId,
+    /// The highest proposal id the acceptor has seen in the instance.
+    pub proposal_id: ProposalId,
+    pub value: Vec<u8>,
+}
+
+/// A page of the decisions an acceptor knows, see [AcceptorService::fetch_state].
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct StateTransfer {
+    /// The instance asked for and its successors, in order, up to the first
+    /// one the acceptor knows no decision for.
+    pub decided: Vec<DecidedInstance>,
+    /// Set when the page is full, the rest is fetched from the successor of
+    /// the last instance.
+    pub more: bool,
+}
+
+/// What an acceptor reports about itself when asked whether it is healthy.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Health {
+    pub id: u32,
+
+    /// The highest proposal id the acceptor has promised or accepted.
+    pub proposal_id: ProposalId,
+
+    /// Whether the acceptor has accepted a value.
+    pub accepted: bool,
+
+    pub decided: bool,
+
+    /// Why the last write to the state file failed, if it did.
+    pub storage_error: Option<String>,
+}
+
+/// What an acceptor has persisted, for operators looking into a node that
+/// disagrees with the others.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct StateDump {
+    pub id: u32,
+    pub instance: InstanceId,
+
+    /// The highest proposal id the acceptor has promised.
+    pub proposal_id: ProposalId,
+
+    /// The proposal the value was accepted in, zero when there is no value.
+    pub accepted_id: ProposalId,
+    pub accepted_value: Option<DumpedValue>,
+
+    pub state_file: PathBuf,
+
+    /// When the acceptor last wrote the state file, if it ever did.
+    pub persisted_at: Option<SystemTime>,
+}
+
+/// A value in a readable form, as text when it is valid UTF-8 and as hex
+/// otherwise.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "encoding", content = "value", rename_all = "lowercase")]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum DumpedValue {
+    Utf8(String),
+    Hex(String),
+}
+
+impl DumpedValue {
+    pub fn new(value: &[u8]) -> Self {
+        match std::str::from_utf8(value) {
+            Ok(text) => DumpedValue::Utf8(text.to_owned()),
+            Err(_) => DumpedValue::Hex(value.iter().map(|byte| format!("{byte:02x}")).collect()),
+        }
+    }
+}
+
+/// Whether an acceptor answered a health check. A slow acceptor is up with a
+/// high round trip time, a down one did not answer within the rpc timeout.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "status", rename_all = "lowercase")]
+pub enum AcceptorStatus {
+    Up { health: Health, rtt: Duration },
+    Down { error: String },
+}
+
+/// An acceptor as seen by a node, see [Paxos::peers].
+#[derive(Debug, Clone)]
+pub struct PeerState {
+    pub acceptor: SocketAddr,
+
+    /// Whether keepalives or the membership protocol found the acceptor dead.
+    pub dead: bool,
+
+    /// Whether the acceptor is only asked once the others failed to form a quorum.
+    pub auxiliary: bool,
+
+    /// Whether rounds currently leave the acceptor out.
+    pub skipped: bool,
+
+    /// The round trips of the requests this node sent to the acceptor, None
+    /// until one was answered.
+    pub latency: Option<LatencySummary>,
+}
+
+/// How long it took a node to get the value of its instance decided.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct DecisionTiming {
+    /// From the first proposal submitted to the node until the decision.
+    pub total: Duration,
+
+    /// The rounds attempted by the proposal that got the value decided.
+    pub attempts: u32,
+
+    /// Time spent in each phase of the round that succeeded.
+    pub prepare: Duration,
+    pub accept: Duration,
+
+    /// Whether `total` was within the node's decision slo.
+    pub within_slo: bool,
+}
+
+/// The prefix of the names of the files acceptor `id` keeps for `instance`.
+pub(crate) fn file_prefix(id: u32, instance: &InstanceId) -> String {
+    // The default instance keeps the file names used before instances existed.
+    if *instance == InstanceId::default() {
+        format!("acceptor_{id}")
+    } else {
+        format!("acceptor_{id}.{}", instance.storage_key())
+    }
+}
+
+/// Reads the state and the version of the file it was read from.
+async fn read_state(
+    file: &mut File,
+    key: Option<&StateKey>,
+    file_prefix: &str,
+) -> Result<(Option<format::State>, u8)> {
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer)
+        .await
+        .context("reading file contents to buffer")?;
+
+    let (state, version) = format::decode(&buffer)?;
+    let Some(mut state) = state else {
+        return Ok((None, version));
+    };
+
+    // Only witnesses write no value, sealed values are never empty. Version 1
+    // files predate encryption.
+    if let (Some(key), Some(value)) = (key, &mut state.proposal_value) {
+        if !value.is_empty() && version > 1 {
+            *value = key
+                .open(
+                    &encryption::state_aad(file_prefix, state.accepted_id),
+                    value,
+                )
+                .context("decrypting accepted value")?;
+        }
+    }
+
+    Ok((Some(state), version))
+}
+
+/// Reads the highest proposal id the node sent before it restarted, zero when
+/// it never sent one.
+async fn read_proposer_id(file: &mut File) -> Result<ProposalId> {
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer)
+        .await
+        .context("reading file contents to buffer")?;
+
+    if buffer.is_empty() {
+        return Ok(ProposalId::ZERO);
+    }
+
+    let bytes = buffer
+        .try_into()
+        .map_err(|buffer: Vec<u8>| anyhow!("expected 8 bytes, found {}", buffer.len()))?;
+
+    Ok(ProposalId::from_bytes(bytes))
+}
+
+/// Configures a [Paxos] node. Every option has a default so new options can be
+/// added without breaking existing callers.
+#[derive(Debug, Clone)]
+pub struct PaxosBuilder {
+    id: u32,
+    address: SocketAddr,
+    acceptors: Vec<SocketAddr>,
+    instance: InstanceId,
+    connector: Connector,
+    credentials: Credentials,
+    data_dir: PathBuf,
+    timeouts: Timeouts,
+    promise_policy: PromisePolicy,
+    retry_policy: RetryPolicy,
+    contention_backoff: ContentionBackoff,
+    relay_fanout: Option<usize>,
+    quorum_selection: QuorumSelection,
+    chunk_size: Option<usize>,
+    max_value_size: usize,
+    metrics: Arc<dyn Recorder>,
+    quorum: Option<usize>,
+    decision_slo: Duration,
+    audit: bool,
+    group_commit: Option<Duration>,
+    lease: Option<LeaseConfig>,
+    client_addr: Option<SocketAddr>,
+    lease_reads: bool,
+    state_key: Option<StateKey>,
+    on_decided: Option<OnDecided>,
+    membership: Option<Membership>,
+    rpc_permits: RpcPermits,
+    acceptor_clients: ClientPool,
+    auxiliary: Vec<SocketAddr>,
+    witnesses: Vec<SocketAddr>,
+    rejoin: Option<Rejoin>,
+    keepalive: Option<KeepaliveConfig>,
+}
+
+impl PaxosBuilder {
+    /// The consensus instance the node takes part in. Defaults to [InstanceId::default].
+    pub fn instance(mut self, instance: InstanceId) -> Self {
+        self.instance = instance;
+        self
+    }
+
+    /// How the node connects to the other acceptors. Defaults to plain tcp.
+    pub fn connector(mut self, connector: Connector) -> Self {
+        self.connector = connector;
+        // Connections opened with another connector can't be reused.
+        self.acceptor_clients = ClientPool::default();
+        self
+    }
+
+    /// Sent with every request to the other acceptors.
+    pub fn credentials(mut self, credentials: Credentials) -> Self {
+        self.credentials = credentials;
+        self
+    }
+
+    /// The directory the acceptor state files are kept in. Defaults to the working directory.
+    pub fn data_dir(mut self, data_dir: impl Into<PathBuf>) -> Self {
+        self.data_dir = data_dir.into();
+        self
+    }
+
+    pub fn timeouts(mut self, timeouts: Timeouts) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    /// How acceptors answer a prepare request with the id they already
+    /// promised, see [PromisePolicy]. Defaults to `StrictlyGreater`, never
+    /// pass `GreaterOrEqual` unless the ids are partitioned per proposer.
+    pub fn promise_policy(mut self, promise_policy: PromisePolicy) -> Self {
+        self.promise_policy = promise_policy;
+        self
+    }
+
+    pub fn retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// How long to wait before retrying a round another proposer preempted.
+    /// Defaults to [ContentionBackoff::default].
+    pub fn contention_backoff(mut self, contention_backoff: ContentionBackoff) -> Self {
+        self.contention_backoff = contention_backoff;
+        self
+    }
+
+    /// Disseminate accept requests through a tree where each node forwards to at most
+    /// `fanout` acceptors instead of sending every request directly. Decided
+    /// values are handed down the same tree. Needs a message key: relays
+    /// forward the answers of their targets, and the proposer only counts the
+    /// ones the acceptors signed.
+    pub fn relay_fanout(mut self, fanout: usize) -> Self {
+        self.relay_fanout = Some(fanout.max(1));
+        self
+    }
+
+    /// Which acceptors rounds contact. Defaults to [QuorumSelection::All].
+    pub fn quorum_selection(mut self, quorum_selection: QuorumSelection) -> Self {
+        self.quorum_selection = quorum_selection;
+        self
+    }
+
+    /// Sends values longer than `chunk_size` bytes to the acceptors in chunks
+    /// of that size, so no single message holds a whole large value. Chunked
+    /// values are sent to every acceptor directly, without relays.
+    pub fn chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = Some(chunk_size.max(1));
+        self
+    }
+
+    /// The largest value the node proposes or, as an acceptor, accepts.
+    /// Defaults to [DEFAULT_MAX_VALUE_SIZE].
+    pub fn max_value_size(mut self, max_value_size: usize) -> Self {
+        self.max_value_size = max_value_size;
+        self
+    }
+
+    /// Where the node reports its metrics. Defaults to discarding them.
+    pub fn metrics(mut self, metrics: Arc<dyn Recorder>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// How many acceptors must respond for a phase to succeed. Defaults to a majority.
+    pub fn quorum(mut self, quorum: usize) -> Self {
+        self.quorum = Some(quorum);
+        self
+    }
+
+    /// Decisions that take longer than this, measured from the first proposal the
+    /// node receives, count against the slo. Defaults to 50ms.
+    pub fn decision_slo(mut self, slo: Duration) -> Self {
+        self.decision_slo = slo;
+        self
+    }
+
+    /// Checks every acceptor transition against a separate implementation of
+    /// the acceptance rules and aborts the process if they disagree. Meant for
+    /// staging, off by default.
+    pub fn audit(mut self, audit: bool) -> Self {
+        self.audit = audit;
+        self
+    }
+
+    /// Lets writes to the state file share one fsync. The first write of a
+    /// group waits up to `interval` for others before the file is synced.
+    /// Only pays off for callers that use [Paxos::begin_prepare] and
+    /// [Paxos::begin_accept] and let go of the node while they wait.
+    pub fn group_commit(mut self, interval: Duration) -> Self {
+        self.group_commit = Some(interval);
+        self
+    }
+
+    /// Makes the node get a lease from a quorum before proposing and refrain
+    /// from proposing while another node holds it. Disabled by default.
+    pub fn lease(mut self, lease: LeaseConfig) -> Self {
+        self.lease = Some(lease);
+        self
+    }
+
+    /// Tells the other nodes that this one serves clients at `client_addr`
+    /// when it gets the lease, and makes proposals fail with [NotLeader]
+    /// instead of waiting while another node that said so holds the lease, so
+    /// the caller can forward them. Only has an effect with a lease.
+    pub fn forward_to_leader(mut self, client_addr: SocketAddr) -> Self {
+        self.client_addr = Some(client_addr);
+        self
+    }
+
+    /// Lets [Paxos::read] answer from local state while the node holds the
+    /// lease and a read round during the lease found the register empty,
+    /// instead of running a round every time. Acceptors refuse prepare
+    /// requests from other nodes while the lease is held, so nothing can be
+    /// chosen behind the holder's back. Only safe when clocks run at about the
+    /// same rate. Off by default.
+    pub fn lease_reads(mut self, lease_reads: bool) -> Self {
+        self.lease_reads = lease_reads;
+        self
+    }
+
+    /// Encrypts the accepted and decided values the acceptor writes to disk
+    /// with `key`. The key can't be added to or removed from an acceptor
+    /// that already has files, it reads them with the key it is given.
+    pub fn state_key(mut self, key: StateKey) -> Self {
+        self.state_key = Some(key);
+        self
+    }
+
+    /// Calls `callback` with the instance and the decided value once the node
+    /// learns the value, whether its own proposal got it decided, another
+    /// proposer's did or it fetched it from the other acceptors. A node that
+    /// starts with a decided value on disk calls it on [PaxosBuilder::build],
+    /// so a decision isn't lost when the process crashes before the callback
+    /// runs.
+    pub fn on_decided(mut self, callback: impl FnMut(&InstanceId, &[u8]) + Send + 'static) -> Self {
+        self.on_decided = Some(OnDecided::new(callback));
+        self
+    }
+
+    /// Skips the acceptors `membership` considers dead in every round instead
+    /// of waiting for them to time out. The caller runs [Membership::run].
+    pub fn membership(mut self, membership: Membership) -> Self {
+        self.membership = Some(membership);
+        self
+    }
+
+    /// Sends at most `max` prepare and accept rpcs at once, counting the rpcs
+    /// of every instance built from this builder. Rpcs over the cap wait for
+    /// one in flight to finish, within the deadline of their phase. Not capped
+    /// by default.
+    pub fn max_outstanding_rpcs(mut self, max: usize) -> Self {
+        self.rpc_permits = RpcPermits::new(max);
+        self
+    }
+
+    /// Runs Cheap Paxos: rounds only ask the acceptors not in `auxiliary`
+    /// while those respond, saving the auxiliary ones the writes and the
+    /// network the requests. Once a phase fails without them, or failure
+    /// detection considers a main acceptor dead, the auxiliary acceptors are
+    /// asked as well. The main acceptors must form a quorum on their own, for
+    /// example F+1 main and F auxiliary acceptors out of 2F+1.
+    pub fn auxiliary_acceptors(mut self, auxiliary: Vec<SocketAddr>) -> Self {
+        self.auxiliary = auxiliary;
+        self
+    }
+
+    /// Makes the acceptors in `witnesses` vote without storing values: they
+    /// persist the proposal ids they promised and accepted only, which is
+    /// enough for a tie-breaker, say the third node of two full acceptors and
+    /// a witness. A witness can't tell proposers the value it accepted, so
+    /// rounds wait for an acceptor that stores it, and there must be fewer
+    /// witnesses than the quorum. A witness node doesn't propose or read.
+    pub fn witnesses(mut self, witnesses: Vec<SocketAddr>) -> Self {
+        self.witnesses = witnesses;
+        self
+    }
+
+    /// Keeps the acceptor from voting until `rejoin` found out whether it lost
+    /// its data, and after a data loss from voting in an instance it has no
+    /// state for until [Paxos::rejoin] caught it up. Without it the acceptor
+    /// trusts its disk.
+    pub fn rejoin(mut self, rejoin: Rejoin) -> Self {
+        self.rejoin = Some(rejoin);
+        self
+    }
+
+    /// Pings the connections to the other acceptors between rounds. Rounds
+    /// skip an acceptor that stopped answering until it answers again, see
+    /// [crate::keepalive].
+    pub fn keepalive(mut self, keepalive: KeepaliveConfig) -> Self {
+        self.keepalive = Some(keepalive);
+        self
+    }
+
+    /// The file the acceptor state of `instance` is kept in, there is one for
+    /// every instance this node created.
+    pub(crate) fn state_path(&self, instance: &InstanceId) -> PathBuf {
+        self.data_dir
+            .join(format!("{}.state", file_prefix(self.id, instance)))
+    }
+
+    /// The file the value decided in `instance` is kept in.
+    pub(crate) fn decided_path(&self, instance: &InstanceId) -> PathBuf {
+        self.data_dir
+            .join(format!("{}.decided", file_prefix(self.id, instance)))
+    }
+
+    /// Opens the acceptor state files and creates the node.
+    pub async fn build(self) -> Result<Paxos> {
+        let PaxosBuilder {
+            id,
+            address,
+            acceptors,
+            instance,
+            connector,
+            credentials,
+            data_dir,
+            timeouts,
+            promise_policy,
+            retry_policy,
+            contention_backoff,
+            relay_fanout,
+            quorum_selection,
+            chunk_size,
+            max_value_size,
+            metrics,
+            quorum,
+            decision_slo,
+            audit,
+            group_commit,
+            lease,
+            client_addr,
+            lease_reads,
+            state_key,
+            on_decided,
+            membership,
+            rpc_permits,
+            acceptor_clients,
+            auxiliary,
+            witnesses,
+            rejoin,
+            keepalive,
+        } = self;
+
+        // Proposers build nodes too, only the caller knows whether this one
+        // serves as an acceptor.
+        check_topology(&acceptors, quorum, None)?;
+        check_auxiliary(&acceptors, &auxiliary, quorum)?;
+        check_witnesses(&acceptors, &witnesses, quorum)?;
+        if relay_fanout.is_some() && credentials.message_key.is_none() {
+            return Err(anyhow!(
+                "relaying accept requests needs a message key to check the acks relays forward"
+            ));
+        }
+        let witness = witnesses.contains(&address);
+
+        if let Some(keepalive) = keepalive {
+            let peers = acceptors
+                .iter()
+                .copied()
+                .filter(|acceptor| *acceptor != address)
+                .collect();
+            acceptor_clients.keepalive(keepalive, connector.clone(), peers, Arc::clone(&metrics));
+        }
+
+        let file_prefix = file_prefix(id, &instance);
+
+        let state_path = data_dir.join(format!("{file_prefix}.state"));
+        let mut state_file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .write(true)
+            .open(&state_path)
             .await
-            .context("seeking to beginning of state file")?;
+            .context("opening acceptor state file")?;
 
-        self.state_file
-            .write_all(&buffer)
+        let persisted_at = match state_file.metadata().await {
+            Ok(metadata) if metadata.len() > 0 => metadata.modified().ok(),
+            _ => None,
+        };
+
+        let (state, version) = read_state(&mut state_file, state_key.as_ref(), &file_prefix)
             .await
-            .context("writing buffer to state file")?;
+            .context("reading state from file")?;
+        let migrate = state.is_some() && version < format::CURRENT_VERSION;
 
-        self.state_file
-            .sync_all()
+        let rejoined = state.is_some();
+        let acceptor = protocol::Acceptor::restore(promise_policy, witness, state);
+
+        let mut proposer_file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .write(true)
+            .open(data_dir.join(format!("{file_prefix}.proposer")))
             .await
-            .context("syncing state file")?;
+            .context("opening proposer file")?;
 
-        Ok(AcceptResponse {
-            proposal_id: self.proposal_id,
-            proposal_value: None,
-        })
+        let current_proposal_id = read_proposer_id(&mut proposer_file)
+            .await
+            .context("reading proposal id from proposer file")?;
+
+        let mut decided_file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .write(true)
+            .open(data_dir.join(format!("{file_prefix}.decided")))
+            .await
+            .context("opening decided file")?;
+
+        let mut contents = Vec::new();
+        decided_file
+            .read_to_end(&mut contents)
+            .await
+            .context("reading decided value from file")?;
+
+        let decided_value = match (format::decode_decided(&contents), &state_key) {
+            (None, _) => None,
+            (Some(value), None) => Some(value.to_vec()),
+            (Some(sealed), Some(key)) => Some(
+                key.open(&encryption::decided_aad(&file_prefix), sealed)
+                    .context("decrypting decided value")?,
+            ),
+        };
+
+        let mut lease_file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .write(true)
+            .open(data_dir.join(format!("{file_prefix}.lease")))
+            .await
+            .context("opening lease file")?;
+
+        let mut contents = Vec::new();
+        lease_file
+            .read_to_end(&mut contents)
+            .await
+            .context("reading lease from file")?;
+        let lease_grant = Grant::decode(&contents).context("decoding lease file")?;
+
+        let group_commit = match group_commit {
+            None => None,
+            Some(interval) => {
+                let file = state_file
+                    .try_clone()
+                    .await
+                    .context("cloning state file handle")?
+                    .into_std()
+                    .await;
+                Some(GroupCommit::spawn(file, interval, Arc::clone(&metrics)))
+            }
+        };
+
+        if audit && witness {
+            warn!("witnesses don't hold the values the auditor checks, not auditing");
+        }
+        let auditor = (audit && !witness).then(|| {
+            Auditor::new(
+                acceptor.policy,
+                acceptor.promised,
+                acceptor.accepted_value.clone(),
+                decided_value.clone(),
+            )
+        });
+
+        if let (Some(on_decided), Some(value)) = (&on_decided, &decided_value) {
+            on_decided.call(&instance, value);
+        }
+
+        let mut paxos = Self {
+            id,
+            instance,
+            address,
+            current_proposal_id,
+            proposer_file,
+            acceptors,
+            acceptor_clients,
+            connector,
+            credentials,
+            timeouts,
+            retry_policy,
+            contention_backoff,
+            latencies: LatencyTracker::default(),
+            quorum_selection,
+            widened: false,
+            metrics,
+            quorum,
+            decision_slo,
+            first_submitted_at: None,
+            round_durations: (Duration::ZERO, Duration::ZERO),
+            decision_timing: None,
+            synced: false,
+            relay_fanout,
+            chunk_size,
+            max_value_size,
+            upload: None,
+            lease,
+            lease_granted_at: None,
+            lease_grant,
+            lease_file,
+            client_addr,
+            lease_reads,
+            lease_term: 0,
+            state_key,
+            empty_in_term: None,
+
+            acceptor,
+            state_file,
+            state_path,
+            persisted_at,
+
+            decided_value,
+            decided_file,
+            storage_error: None,
+            shut_down: false,
+            auditor,
+            group_commit,
+            on_decided,
+            events: broadcast::channel(events::CAPACITY).0,
+            membership,
+            rpc_permits,
+            auxiliary: auxiliary.into_iter().collect(),
+            auxiliary_needed: false,
+            rejoin,
+            rejoined,
+        };
+
+        if migrate {
+            paxos
+                .write_state()
+                .await
+                .context("migrating state file")?
+                .wait()
+                .await?;
+            info!(
+                from = version,
+                to = format::CURRENT_VERSION,
+                "migrated state file"
+            );
+        }
+
+        Ok(paxos)
     }
 }
+
+impl Paxos {
+    /// Starts building a node that takes part in the cluster formed by `acceptors`.
+    /// `address` is the address of the node's own acceptor, if it has one.
+    pub fn builder(id: u32, address: SocketAddr, acceptors: Vec<SocketAddr>) -> PaxosBuilder {
+        PaxosBuilder {
+            id,
+            address,
+            acceptors,
+            instance: InstanceId::default(),
+            connector: Connector::default(),
+            credentials: Credentials::default(),
+            data_dir: PathBuf::from("."),
+            timeouts: Timeouts::default(),
+            promise_policy: PromisePolicy::default(),
+            retry_policy: RetryPolicy::default(),
+            contention_backoff: ContentionBackoff::default(),
+            relay_fanout: None,
+            quorum_selection: QuorumSelection::default(),
+            chunk_size: None,
+            max_value_size: DEFAULT_MAX_VALUE_SIZE,
+            metrics: Arc::new(NoopRecorder),
+            quorum: None,
+            decision_slo: Duration::from_millis(50),
+            audit: false,
+            group_commit: None,
+            lease: None,
+            client_addr: None,
+            lease_reads: false,
+            state_key: None,
+            on_decided: None,
+            membership: None,
+            rpc_permits: RpcPermits::default(),
+            acceptor_clients: ClientPool::default(),
+            auxiliary: Vec::new(),
+            witnesses: Vec::new(),
+            rejoin: None,
+            keepalive: None,
+        }
+    }
+
+    /// How many acceptors must respond for a phase to succeed.
+    fn quorum(&self) -> usize {
+        self.quorum.unwrap_or(self.acceptors.len() / 2 + 1)
+    }
+
+    /// Whether failure detection considers `acceptor` dead, or it stopped
+    /// answering keepalives.
+    fn is_dead(&self, acceptor: SocketAddr) -> bool {
+        let dead = self.detected_dead(acceptor);
+        if dead {
+            debug!(%acceptor, "skipping dead acceptor");
+            self.metrics
+                .increment("paxos_dead_acceptors_skipped_total", 1);
+        }
+        dead
+    }
+
+    fn detected_dead(&self, acceptor: SocketAddr) -> bool {
+        self.acceptor_clients.is_down(acceptor)
+            || matches!(&self.membership, Some(membership) if membership.is_dead(acceptor))
+    }
+
+    /// Whether rounds leave `acceptor` out, because it is dead or because it
+    /// is auxiliary and the main acceptors are expected to form a quorum.
+    fn skips(&self, acceptor: SocketAddr) -> bool {
+        if self.auxiliary.contains(&acceptor) && !self.needs_auxiliary() {
+            return true;
+        }
+        self.is_dead(acceptor)
+    }
+
+    fn needs_auxiliary(&self) -> bool {
+        if self.auxiliary_needed {
+            return true;
+        }
+
+        self.acceptors
+            .iter()
+            .filter(|acceptor| !self.auxiliary.contains(acceptor))
+            .any(|acceptor| self.detected_dead(*acceptor))
+    }
+
+    /// The acceptors other than this node a phase sends requests to, when
+    /// `needed` of them have to answer.
+    fn targets(&self, needed: usize) -> Vec<SocketAddr> {
+        let targets: Vec<SocketAddr> = self
+            .acceptors
+            .iter()
+            .copied()
+            .filter(|acceptor| *acceptor != self.address && !self.skips(*acceptor))
+            .collect();
+
+        match self.quorum_selection {
+            QuorumSelection::Nearest { spare } if !self.widened => {
+                self.latencies.nearest(&targets, needed + spare)
+            }
+            _ => targets,
+        }
+    }
+
+    /// Called when a phase failed to reach a quorum without being preempted.
+    /// Every acceptor is asked from the next round on, until a value is decided.
+    fn widen_quorum(&mut self) {
+        if self.quorum_selection == QuorumSelection::All || self.widened {
+            return;
+        }
+
+        warn!("the nearest acceptors did not form a quorum, asking every acceptor");
+        self.metrics.increment("paxos_quorum_widened_total", 1);
+        self.widened = true;
+    }
+
+    /// Called when a phase failed to reach a quorum without being preempted.
+    /// The auxiliary acceptors are asked from the next round on.
+    fn fall_back_to_auxiliary(&mut self) {
+        if self.auxiliary.is_empty() || self.auxiliary_needed {
+            return;
+        }
+
+        warn!("the main acceptors did not form a quorum, asking the auxiliary acceptors");
+        self.metrics.increment("paxos_auxiliary_fallbacks_total", 1);
+        self.auxiliary_needed = true;
+    }
+
+    /// Called when a phase reached a quorum. Once `main` of the acceptors
+    /// that answered are main acceptors and form a quorum on their own, the
+    /// auxiliary acceptors are left out again.
+    fn recover_main_quorum(&mut self, main: usize) {
+        if !self.auxiliary_needed || main < self.quorum() {
+            return;
+        }
+
+        info!("the main acceptors formed a quorum again, leaving the auxiliary acceptors out");
+        self.auxiliary_needed = false;
+    }
+
+    /// The acceptors of `acceptors` that aren't auxiliary.
+    fn main_acceptors<'a>(&self, acceptors: impl IntoIterator<Item = &'a SocketAddr>) -> usize {
+        acceptors
+            .into_iter()
+            .filter(|acceptor| !self.auxiliary.contains(acceptor))
+            .count()
+    }
+
+    /// Whether this node is one of the acceptors. A node that only proposes
+    /// can't count itself towards a quorum.
+    fn is_acceptor(&self) -> bool {
+        self.acceptors.contains(&self.address)
+    }
+
+    fn check_not_witness(&self) -> Result<()> {
+        if self.acceptor.witness {
+            return Err(anyhow!(
+                "{} is a witness, witnesses don't learn values so they can't propose or read",
+                self.address
+            ));
+        }
+        Ok(())
+    }
+
+    /// Refuses votes from an acceptor that may have forgotten ones it cast,
+    /// see [PaxosBuilder::rejoin].
+    fn check_voting(&self) -> Result<()> {
+        let Some(rejoin) = &self.rejoin else {
+            return Ok(());
+        };
+
+        match rejoin.status() {
+            Status::Voting => Ok(()),
+            Status::Recovering { .. } if self.rejoined => Ok(()),
+            Status::Recovering { .. } => Err(AcceptorError::Recovering.into()),
+            Status::Booting => Err(AcceptorError::Recovering.into()),
+        }
+    }
+
+    async fn get_or_init_client(&self, acceptor: SocketAddr) -> Result<AcceptorServiceClient> {
+        let deadline = Instant::now() + CONNECT_TIMEOUT;
+        tokio::time::timeout_at(deadline, self.connections().client(acceptor, deadline))
+            .await
+            .map_err(|_| anyhow!("connecting to acceptor {acceptor} timed out"))?
+    }
+
+    fn connections(&self) -> Connections {
+        Connections {
+            pool: self.acceptor_clients.clone(),
+            connector: self.connector.clone(),
+        }
+    }
+
+    /// Called for every failed rpc. Drops the cached client when the error means
+    /// its connection is gone so the next request to the acceptor opens a new one.
+    fn evict_if_disconnected(&mut self, acceptor: SocketAddr, err: &RpcError) {
+        self.connections()
+            .evict_if_disconnected(acceptor, err, self.metrics.as_ref());
+    }
+
+    /// Proposes `value`, retrying failed rounds according to the retry policy,
+    /// and returns the value that got decided. Errors mean nothing is known to
+    /// be decided yet.
+    pub async fn propose(&mut self, value: Vec<u8>) -> Result<Decided> {
+        self.propose_with_cancel(value, &CancellationToken::new())
+            .await
+    }
+
+    /// Like [Paxos::propose], but gives up with [Cancelled] once `cancel` is
+    /// cancelled. Proposals are only cancelled while waiting for acceptors or
+    /// between rounds, never halfway through a write to disk, so the node can
+    /// propose again right away. Nodes shared behind a mutex propose with
+    /// [propose_shared] instead, which doesn't hold them between rounds.
+    pub async fn propose_with_cancel(
+        &mut self,
+        value: Vec<u8>,
+        cancel: &CancellationToken,
+    ) -> Result<Decided> {
+        let mut proposal = self.begin_propose(value).await?;
+
+        loop {
+            let backoff = match self.propose_round(&mut proposal, cancel).await? {
+                Round::Decided(decided) => return Ok(decided),
+                Round::Retry { backoff } => backoff,
+            };
+
+            select! {
+                _ = cancel.cancelled() => return Err(self.cancelled(None)),
+                _ = tokio::time::sleep(backoff) => {}
+            }
+        }
+    }
+
+    /// Starts proposing `value`, once the node heard from a quorum. The
+    /// rounds are run with [Paxos::propose_round].
+    pub async fn begin_propose(&mut self, value: Vec<u8>) -> Result<Proposal> {
+        let started_at = Instant::now();
+
+        self.metrics.increment("paxos_proposals_total", 1);
+
+        self.check_not_witness()?;
+
+        if value.len() > self.max_value_size {
+            return Err(ValueTooLarge {
+                size: value.len(),
+                limit: self.max_value_size,
+            }
+            .into());
+        }
+
+        self.first_submitted_at.get_or_insert(started_at);
+
+        self.sync()
+            .await
+            .context("node is read-only until it hears from a quorum")?;
+
+        Ok(Proposal {
+            value,
+            started_at,
+            attempts: 0,
+            preemptions: 0,
+            metrics: Arc::clone(&self.metrics),
+        })
+    }
+
+    /// Runs the next round of `proposal`. A round that failed in a way worth
+    /// retrying returns how long to back off before the next one, which the
+    /// caller waits out without holding the node.
+    #[tracing::instrument(skip_all, fields(instance = %self.instance, value_len = proposal.value.len()))]
+    pub async fn propose_round(
+        &mut self,
+        proposal: &mut Proposal,
+        cancel: &CancellationToken,
+    ) -> Result<Round> {
+        if cancel.is_cancelled() {
+            return Err(self.cancelled(None));
+        }
+
+        proposal.attempts += 1;
+        let attempts = proposal.attempts;
+        self.metrics.increment("paxos_propose_attempts_total", 1);
+
+        let result = match self.acquire_lease().await {
+            Ok(()) => self.propose_once(proposal.value.clone(), cancel).await,
+            Err(err) => Err(err),
+        };
+
+        let err = match result {
+            Ok(Decided::Ours(value)) => {
+                self.metrics.increment("paxos_proposals_decided_total", 1);
+                self.metrics
+                    .record_duration("paxos_propose", proposal.started_at.elapsed());
+                self.record_decision(attempts);
+                info!(attempts, "proposed value decided");
+                return Ok(Round::Decided(Decided::Ours(value)));
+            }
+            Ok(decided) => {
+                self.record_decision(attempts);
+                info!(attempts, "another value was already decided");
+                return Ok(Round::Decided(decided));
+            }
+            Err(err) => err,
+        };
+
+        if err.is::<Cancelled>() {
+            return Err(err);
+        }
+
+        if let (Some(held), Some(_)) = (err.downcast_ref::<LeaseHeld>(), self.client_addr) {
+            if let Some(client_addr) = held.client_addr {
+                debug!(leader = held.holder, %client_addr, "not the leader");
+                return Err(NotLeader {
+                    leader: held.holder,
+                    client_addr,
+                }
+                .into());
+            }
+        }
+
+        let elapsed = proposal.started_at.elapsed();
+        let mut backoff = match self.retry_policy.backoff(attempts, elapsed) {
+            None => {
+                warn!(attempts, ?err, "giving up on proposal");
+                return Err(err.context(format!("giving up after {attempts} attempts")));
+            }
+            Some(v) => v,
+        };
+
+        if err.is::<Preempted>() {
+            proposal.preemptions += 1;
+            backoff = self.contention_backoff.delay(proposal.preemptions);
+        } else {
+            proposal.preemptions = 0;
+        }
+
+        if let Some(throttled) = err.downcast_ref::<Throttled>() {
+            backoff = backoff.max(throttled.retry_after);
+        }
+
+        // Proposing before the lease expires would only preempt the holder.
+        // The random part keeps nodes waiting on the same lease from
+        // asking for it at once.
+        if let Some(held) = err.downcast_ref::<LeaseHeld>() {
+            backoff = held.remaining + self.contention_backoff.delay(1);
+        }
+
+        warn!(
+            attempt = attempts,
+            ?backoff,
+            ?err,
+            "propose attempt failed, retrying"
+        );
+
+        Ok(Round::Retry { backoff })
+    }
+
+    fn cancelled(&self, phase: Option<Phase>) -> anyhow::Error {
+        cancelled(self.metrics.as_ref(), phase)
+    }
+
+    /// Records how long the decision took the first time this node sees its
+    /// instance decided after proposing.
+    fn record_decision(&mut self, attempts: u32) {
+        if self.decision_timing.is_some() || self.decided_value.is_none() {
+            return;
+        }
+
+        let Some(first_submitted_at) = self.first_submitted_at else {
+            return;
+        };
+
+        let total = first_submitted_at.elapsed();
+        let within_slo = total <= self.decision_slo;
+        let (prepare, accept) = self.round_durations;
+
+        self.metrics.increment("paxos_decisions_total", 1);
+        if within_slo {
+            self.metrics
+                .increment("paxos_decisions_within_slo_total", 1);
+        }
+        self.metrics.record_duration("paxos_decision", total);
+
+        self.decision_timing = Some(DecisionTiming {
+            total,
+            attempts,
+            prepare,
+            accept,
+            within_slo,
+        });
+    }
+
+    /// How long this node took to get its instance decided, once it has.
+    pub fn decision_timing(&self) -> Option<DecisionTiming> {
+        self.decision_timing.clone()
+    }
+
+    /// Proposes a structured value and returns the value the cluster decided on,
+    /// which is a different one when another proposal won. Values are encoded as
+    /// json, acceptors only ever see the encoded bytes.
+    pub async fn propose_value<T: Serialize + DeserializeOwned>(&mut self, value: T) -> Result<T> {
+        let bytes = serde_json::to_vec(&value).context("encoding proposed value")?;
+
+        match self.propose(bytes).await? {
+            Decided::Ours(_) => Ok(value),
+            Decided::Other(decided) => {
+                serde_json::from_slice(&decided).context("decoding decided value")
+            }
+        }
+    }
+
+    /// Returns the value this node learned was decided, decoded from json.
+    pub fn learn<T: DeserializeOwned>(&self) -> Result<Option<T>> {
+        self.decided_value
+            .as_deref()
+            .map(|value| serde_json::from_slice(value).context("decoding decided value"))
+            .transpose()
+    }
+
+    async fn propose_once(
+        &mut self,
+        value: Vec<u8>,
+        cancel: &CancellationToken,
+    ) -> Result<Decided> {
+        if let Some(decided_value) = &self.decided_value {
+            return Ok(Decided::new(&value, decided_value.clone()));
+        }
+
+        let round_started_at = Instant::now();
+
+        let accepted_value = self.prepare(cancel).await?;
+
+        let prepare_took = round_started_at.elapsed();
+        let accept_started_at = Instant::now();
+
+        let result = match accepted_value {
+            None => self
+                .accept(value.clone(), cancel)
+                .await
+                .context("sending accept requests with proposed value")
+                .map(|()| Decided::Ours(value)),
+            Some(accepted_value) => {
+                self.accept(accepted_value.clone(), cancel)
+                    .await
+                    .context("sending accept requests with already accepted value")?;
+
+                Ok(Decided::new(&value, accepted_value))
+            }
+        };
+
+        self.round_durations = (prepare_took, accept_started_at.elapsed());
+
+        result
+    }
+
+    /// Returns the value the cluster decided on, or None when no value has
+    /// been chosen yet.
+    ///
+    /// Acceptors only persist the highest proposal id they have seen, promised
+    /// or accepted, so equal values at a quorum don't prove a value was chosen.
+    /// Unless an acceptor already knows the decision, a phase 1 is run with a
+    /// new proposal id and any value it finds is accepted again before being
+    /// returned, which makes sure it is chosen.
+    pub async fn read(&mut self) -> Result<Option<Vec<u8>>> {
+        self.check_not_witness()?;
+
+        if self.lease_reads
+            && self.decided_value.is_none()
+            && self.holds_lease()
+            && self.empty_in_term == Some(self.lease_term)
+        {
+            self.metrics.increment("paxos_lease_reads_total", 1);
+            return Ok(None);
+        }
+
+        // Learns the decision from the other acceptors if one of them knows it.
+        self.heal().await.context("fetching decided value")?;
+
+        if let Some(decided_value) = &self.decided_value {
+            return Ok(Some(decided_value.clone()));
+        }
+
+        self.sync()
+            .await
+            .context("node is read-only until it hears from a quorum")?;
+
+        // Once a quorum promised, rounds started before can't get a value
+        // chosen and nobody starts one while the lease is held.
+        let term = self.holds_lease().then_some(self.lease_term);
+        // Reads aren't cancelled.
+        let cancel = CancellationToken::new();
+
+        match self.prepare(&cancel).await? {
+            None => {
+                if term.is_some() && term == self.holds_lease().then_some(self.lease_term) {
+                    self.empty_in_term = term;
+                }
+                Ok(None)
+            }
+            Some(accepted_value) => {
+                self.accept(accepted_value.clone(), &cancel)
+                    .await
+                    .context("sending accept requests with already accepted value")?;
+                Ok(Some(accepted_value))
+            }
+        }
+    }
+
+    /// Runs phase 1 with a new proposal id and returns the value one of the
+    /// acceptors that promised had accepted, if any.
+    #[tracing::instrument(skip_all, fields(proposal_id))]
+    async fn prepare(&mut self, cancel: &CancellationToken) -> Result<Option<Vec<u8>>> {
+        self.current_proposal_id = self.current_proposal_id.next();
+        Span::current().record("proposal_id", self.current_proposal_id.get());
+
+        self.write_proposer_id()
+            .await
+            .context("persisting proposal id")?;
+
+        let phase_started_at = Instant::now();
+        let phase_deadline = phase_started_at + self.timeouts.prepare_phase;
+
+        let mut futures = FuturesUnordered::new();
+
+        // The local acceptor answers without a request.
+        let needed = self
+            .quorum()
+            .saturating_sub(usize::from(self.is_acceptor()));
+        for acceptor_addr in self.targets(needed) {
+            let request = PrepareRequest {
+                instance: self.instance.clone(),
+                proposal_id: self.current_proposal_id,
+                proposer: Some(self.id),
+            };
+            let credentials = self.credentials.sign("prepare", &request);
+            let deadline = timeout::rpc_deadline(self.timeouts.prepare_rpc, phase_deadline);
+            let connections = self.connections();
+            let metrics = Arc::clone(&self.metrics);
+            let rpc_permits = self.rpc_permits.clone();
+            // Connecting is part of the request, an acceptor that doesn't
+            // answer holds up neither the others nor the phase.
+            futures.push(async move {
+                let result = tokio::time::timeout_at(deadline, async {
+                    let (client, version) = match connections.peer(acceptor_addr, deadline).await {
+                        Err(err) => return (Duration::ZERO, Err(connect_error(err))),
+                        Ok(peer) => peer,
+                    };
+                    let _permit = rpc_permits.acquire().await;
+                    let started_at = Instant::now();
+                    let result = client
+                        .prepare(
+                            timeout::context_until(deadline),
+                            credentials.clone(),
+                            request.clone(),
+                        )
+                        .await
+                        .map(|answer| open(&credentials, version, "prepare", &request, answer));
+                    (started_at.elapsed(), result)
+                })
+                .await;
+                let (rtt, result) = match result {
+                    Err(elapsed) => (Duration::ZERO, Err(elapsed)),
+                    Ok((rtt, result)) => (rtt, Ok(result)),
+                };
+                if let Ok(Ok(_)) = &result {
+                    metrics.record_acceptor_rtt(Phase::Prepare, acceptor_addr, rtt);
+                }
+                (acceptor_addr, rtt, result)
+            });
+        }
+
+        let mut timed_out = Vec::new();
+        let mut promises = Promises::default();
+        let mut retry_after = None;
+        let mut promised = Vec::new();
+
+        // The local acceptor answers in process but its response is counted
+        // exactly like the ones sent over the network.
+        if self.is_acceptor() {
+            let response = self
+                .on_prepare(PrepareRequest {
+                    instance: self.instance.clone(),
+                    proposal_id: self.current_proposal_id,
+                    proposer: Some(self.id),
+                })
+                .await
+                .map_err(AcceptorError::from);
+            if count_promise(
+                &mut promises,
+                &mut retry_after,
+                self.address,
+                response,
+                self.metrics.as_ref(),
+            ) {
+                promised.push(self.address);
+            }
+        }
+
+        // Responses are counted as they arrive and the phase ends as soon as a
+        // quorum promised, a slow acceptor doesn't hold up the round. When a
+        // witness accepted a value the promises don't carry, the phase waits
+        // for an acceptor that stores it.
+        while !promises.complete(self.quorum()) {
+            // Returning drops the requests still in flight.
+            let next = select! {
+                biased;
+                _ = cancel.cancelled() => return Err(self.cancelled(Some(Phase::Prepare))),
+                next = futures.next() => next,
+            };
+            let Some((acceptor_addr, rtt, result)) = next else {
+                break;
+            };
+
+            let result = match result {
+                Err(_) => {
+                    warn!(acceptor = %acceptor_addr, "prepare request timed out");
+                    self.metrics.increment("paxos_rpc_timeouts_total", 1);
+                    timed_out.push(acceptor_addr);
+                    continue;
+                }
+                Ok(v) => v,
+            };
+
+            if result.is_ok() {
+                self.latencies.record(acceptor_addr, rtt);
+            }
+
+            match result {
+                Err(err) => {
+                    warn!(acceptor = %acceptor_addr, ?err, "rpc error");
+                    self.evict_if_disconnected(acceptor_addr, &err);
+                }
+                Ok(response) => {
+                    if count_promise(
+                        &mut promises,
+                        &mut retry_after,
+                        acceptor_addr,
+                        Ok(response),
+                        self.metrics.as_ref(),
+                    ) {
+                        promised.push(acceptor_addr);
+                    }
+                }
+            }
+        }
+
+        finish_in_background(futures);
+
+        if promises.count >= self.quorum() {
+            self.metrics
+                .record_duration("paxos_prepare_quorum", phase_started_at.elapsed());
+            self.recover_main_quorum(self.main_acceptors(&promised));
+        }
+
+        if promises.count < self.quorum() {
+            self.metrics
+                .increment("paxos_prepare_quorum_failures_total", 1);
+
+            // The next round starts above every proposal id the acceptors have seen.
+            self.current_proposal_id =
+                std::cmp::max(self.current_proposal_id, promises.highest_proposal_id);
+
+            if let Some(promised) = promises.highest_rejection {
+                return Err(Preempted {
+                    phase: Phase::Prepare,
+                    promised,
+                }
+                .into());
+            }
+
+            self.fall_back_to_auxiliary();
+            self.widen_quorum();
+
+            if let Some(retry_after) = retry_after {
+                return Err(Throttled {
+                    phase: Phase::Prepare,
+                    retry_after,
+                }
+                .into());
+            }
+
+            if !timed_out.is_empty() {
+                return Err(Timeout {
+                    phase: Phase::Prepare,
+                    acceptors: timed_out,
+                }
+                .into());
+            }
+
+            return Err(anyhow!(
+                "unable to get response to prepare request from majority of acceptors"
+            ));
+        }
+
+        if promises.missing_value() {
+            self.metrics
+                .increment("paxos_witness_recovery_failures_total", 1);
+            // The acceptors that store the value may be the far ones.
+            self.widen_quorum();
+            return Err(anyhow!(
+                "a witness accepted proposal {} but no acceptor that stores values answered with it",
+                promises.witness_accepted_id
+            ));
+        }
+
+        Ok(promises.value())
+    }
+
+    #[tracing::instrument(skip_all, fields(proposal_id = %self.current_proposal_id))]
+    async fn accept(&mut self, value: Vec<u8>, cancel: &CancellationToken) -> Result<()> {
+        // A value may get chosen even if the round seems to fail.
+        self.empty_in_term = None;
+
+        let request = AcceptRequest {
+            instance: self.instance.clone(),
+            proposal_id: self.current_proposal_id,
+            proposal_value: value.clone(),
+        };
+
+        let mut responses = Vec::with_capacity(self.acceptors.len());
+        let phase_started_at = Instant::now();
+
+        if self.is_acceptor() {
+            let response = self
+                .on_accept(request.clone())
+                .await
+                .map_err(AcceptorError::from);
+            responses.push(RelayedAcceptResponse {
+                acceptor: self.address,
+                response,
+                mac: None,
+            });
+        }
+
+        // The local acceptor already promised a higher proposal, asking the
+        // others can't save the round.
+        if let Some(promised) = self.highest_rejection(&responses) {
+            return Err(self.accept_preempted(promised));
+        }
+
+        let phase_deadline = Instant::now() + self.timeouts.accept_phase;
+
+        let acked_locally = responses
+            .iter()
+            .filter(|relayed| self.acks(relayed))
+            .count();
+        let needed = self.quorum().saturating_sub(acked_locally);
+        let targets = self.targets(needed);
+
+        // Without relays every acceptor is contacted directly, and so are they
+        // when the value is sent in chunks.
+        let fanout = match self.relay_fanout {
+            Some(fanout) if self.chunks(&value).is_none() => fanout,
+            _ => targets.len(),
+        };
+
+        let (remote_responses, timed_out) = self
+            .send_accept_requests(&request, &targets, fanout, phase_deadline, needed, cancel)
+            .await;
+        responses.extend(remote_responses);
+
+        if let Some(promised) = self.highest_rejection(&responses) {
+            return Err(self.accept_preempted(promised));
+        }
+
+        // Relays only forward responses, each acceptor is counted at most once
+        // and only if it is part of the cluster.
+        let mut acked = HashSet::with_capacity(responses.len());
+        let mut retry_after = None;
+        for RelayedAcceptResponse {
+            acceptor, response, ..
+        } in responses
+        {
+            if !self.acceptors.contains(&acceptor) {
+                warn!(%acceptor, "ignoring accept response from unknown acceptor");
+                continue;
+            }
+
+            match response {
+                Err(err) => {
+                    warn!(%acceptor, %err, "error response to accept request");
+                    retry_after = retry_after.max(err.retry_after());
+                    continue;
+                }
+                Ok(_) => {
+                    acked.insert(acceptor);
+                }
+            }
+        }
+
+        if acked.len() < self.quorum() {
+            if cancel.is_cancelled() {
+                return Err(self.cancelled(Some(Phase::Accept)));
+            }
+
+            self.metrics
+                .increment("paxos_accept_quorum_failures_total", 1);
+            self.fall_back_to_auxiliary();
+            self.widen_quorum();
+
+            if let Some(retry_after) = retry_after {
+                return Err(Throttled {
+                    phase: Phase::Accept,
+                    retry_after,
+                }
+                .into());
+            }
+
+            if !timed_out.is_empty() {
+                return Err(Timeout {
+                    phase: Phase::Accept,
+                    acceptors: timed_out,
+                }
+                .into());
+            }
+
+            return Err(anyhow!(
+                "unable to get response to accept request from majority of acceptors"
+            ));
+        }
+
+        self.metrics
+            .record_duration("paxos_accept_quorum", phase_started_at.elapsed());
+        self.widened = false;
+        // Nothing is proposed in the instance once a value is decided.
+        self.auxiliary_needed = false;
+
+        self.mark_decided(value.clone())
+            .await
+            .context("persisting decided value")?;
+
+        if fanout < targets.len() {
+            self.relay_decided(&targets, fanout, value);
+        }
+
+        Ok(())
+    }
+
+    /// The highest proposal id among the acceptors of the cluster that rejected
+    /// the current proposal, if any did.
+    fn highest_rejection(&self, responses: &[RelayedAcceptResponse]) -> Option<ProposalId> {
+        highest_rejection(&self.acceptors, self.current_proposal_id, responses)
+    }
+
+    fn accept_preempted(&mut self, promised: ProposalId) -> anyhow::Error {
+        self.metrics.increment("paxos_accept_nacks_total", 1);
+
+        // The next round starts above the id the acceptors promised.
+        self.current_proposal_id = promised;
+
+        Preempted {
+            phase: Phase::Accept,
+            promised,
+        }
+        .into()
+    }
+
+    /// The size of the chunks `value` is sent in, None when it is sent whole.
+    fn chunks(&self, value: &[u8]) -> Option<usize> {
+        self.chunk_size
+            .filter(|chunk_size| value.len() > *chunk_size)
+    }
+
+    /// Whether `relayed` is an acceptor of the cluster accepting the current proposal.
+    fn acks(&self, relayed: &RelayedAcceptResponse) -> bool {
+        acks(&self.acceptors, self.current_proposal_id, relayed)
+    }
+
+    /// Sends the accept request to `targets`, see [AcceptSender::send], and
+    /// records the round trips of the direct requests.
+    async fn send_accept_requests(
+        &mut self,
+        request: &AcceptRequest,
+        targets: &[SocketAddr],
+        fanout: usize,
+        phase_deadline: Instant,
+        needed: usize,
+        cancel: &CancellationToken,
+    ) -> (Vec<RelayedAcceptResponse>, Vec<SocketAddr>) {
+        let sent = self
+            .accept_sender(request.clone(), false)
+            .send(targets, fanout, phase_deadline, needed, cancel)
+            .await;
+
+        for (acceptor, rtt) in sent.rtts {
+            self.latencies.record(acceptor, rtt);
+        }
+
+        (sent.responses, sent.timed_out)
+    }
+
+    fn accept_sender(&self, request: AcceptRequest, relay: bool) -> AcceptSender {
+        // Relays send values whole, the proposer only relays values it doesn't chunk.
+        let chunks = if relay {
+            None
+        } else {
+            self.chunks(&request.proposal_value)
+        };
+
+        AcceptSender {
+            request,
+            acceptors: self.acceptors.clone(),
+            connections: self.connections(),
+            credentials: self.credentials.clone(),
+            metrics: Arc::clone(&self.metrics),
+            rpc_permits: self.rpc_permits.clone(),
+            accept_rpc: self.timeouts.accept_rpc,
+            chunks,
+            relay,
+        }
+    }
+
+    /// Handles the accept request locally and returns what is left to do to
+    /// forward it to the targets this node is relaying to. The forward doesn't
+    /// need the node, callers let go of it before running the forward.
+    pub async fn begin_relay_accept(&mut self, message: RelayAcceptRequest) -> RelayForward {
+        let response = self
+            .on_accept(message.accept.clone())
+            .await
+            .map_err(AcceptorError::from);
+
+        // The proposer only counts the acks relays forward when the acceptor
+        // signed them, this node relays its own too.
+        let mac = response.as_ref().ok().and_then(|response| {
+            self.credentials
+                .sign_response("accept", self.address, &message.accept, response)
+        });
+
+        RelayForward {
+            own: RelayedAcceptResponse {
+                acceptor: self.address,
+                response,
+                mac,
+            },
+            deadline: Instant::now() + self.timeouts.accept_phase,
+            sender: self.accept_sender(message.accept, true),
+            targets: message.targets,
+            fanout: message.fanout,
+        }
+    }
+
+    /// Learns the decided value a proposer handed down the relay tree and
+    /// returns what is left to do to hand it on, see [Paxos::begin_relay_accept].
+    pub async fn begin_relay_learn(&mut self, message: RelayLearnRequest) -> Result<LearnForward> {
+        self.credentials
+            .verify("learn", &message.decided, message.mac.as_deref())
+            .map_err(|err| AcceptorError::rejected(format!("unverified decided value: {err}")))?;
+
+        self.learn_decided(message.decided.clone()).await?;
+
+        Ok(LearnForward {
+            decided: message.decided,
+            mac: message.mac,
+            targets: message.targets,
+            fanout: message.fanout,
+            connections: self.connections(),
+            credentials: self.credentials.clone(),
+            deadline: Instant::now() + self.timeouts.accept_phase,
+        })
+    }
+
+    /// Hands the value this node got decided down the tree the accept requests
+    /// took, so the acceptors learn it without asking. Runs in the background,
+    /// acceptors that miss it learn the value when they heal.
+    fn relay_decided(&self, targets: &[SocketAddr], fanout: usize, value: Vec<u8>) {
+        let decided = DecidedInstance {
+            instance: self.instance.clone(),
+            proposal_id: self.current_proposal_id,
+            value,
+        };
+
+        let forward = LearnForward {
+            mac: self.credentials.sign("learn", &decided).mac,
+            decided,
+            targets: targets.to_vec(),
+            fanout,
+            connections: self.connections(),
+            credentials: self.credentials.clone(),
+            deadline: Instant::now() + self.timeouts.accept_phase,
+        };
+
+        tokio::spawn(forward.run());
+    }
+
+    pub async fn on_prepare(&mut self, message: PrepareRequest) -> Result<PrepareResponse> {
+        let (response, durable) = self.begin_prepare(message).await?;
+        durable.wait().await?;
+        Ok(response)
+    }
+
+    /// Like [Paxos::on_prepare] but returns before the promise is on disk. The
+    /// response must not be sent before the [Durable] resolves. Callers can let
+    /// go of the node while they wait so concurrent writes share a sync.
+    #[tracing::instrument(skip_all, fields(instance = %message.instance, proposal_id = %message.proposal_id))]
+    pub async fn begin_prepare(
+        &mut self,
+        message: PrepareRequest,
+    ) -> Result<(PrepareResponse, Durable)> {
+        self.check_instance(&message.instance)?;
+        self.check_voting()?;
+
+        if let Some((holder, remaining)) = self.lease_grant.held_by_other(message.proposer) {
+            self.metrics
+                .increment("paxos_lease_refused_prepares_total", 1);
+            return Err(AcceptorError::lease_held(holder, remaining).into());
+        }
+
+        let proposal_id = message.proposal_id;
+        let response = self.handle_prepare(message).await;
+
+        if let Some(auditor) = &mut self.auditor {
+            auditor.prepare(
+                proposal_id,
+                response.as_ref().ok().map(|(response, _)| response),
+                (
+                    self.acceptor.promised,
+                    self.acceptor.accepted_value.as_deref(),
+                ),
+            );
+        }
+
+        response
+    }
+
+    async fn handle_prepare(
+        &mut self,
+        message: PrepareRequest,
+    ) -> Result<(PrepareResponse, Durable)> {
+        let step = self.acceptor.on_prepare(message.proposal_id);
+        let durable = self.persist(step.persist).await?;
+
+        if step.persist != Persist::Nothing {
+            self.emit(|node| Event::Promised {
+                instance: node.instance.clone(),
+                proposal_id: node.acceptor.promised,
+            });
+        }
+
+        debug!(
+            promised = step.response.promised,
+            highest_proposal_id = %self.acceptor.promised,
+            "handled prepare"
+        );
+
+        Ok((step.response, durable))
+    }
+
+    pub async fn on_accept(&mut self, message: AcceptRequest) -> Result<AcceptResponse> {
+        let (response, durable) = self.begin_accept(message).await?;
+        durable.wait().await?;
+        Ok(response)
+    }
+
+    /// Like [Paxos::on_accept] but returns before the accepted value is on
+    /// disk, see [Paxos::begin_prepare].
+    #[tracing::instrument(skip_all, fields(instance = %message.instance, proposal_id = %message.proposal_id))]
+    pub async fn begin_accept(
+        &mut self,
+        message: AcceptRequest,
+    ) -> Result<(AcceptResponse, Durable)> {
+        self.check_instance(&message.instance)?;
+        self.check_voting()?;
+
+        // Only cloned when auditing, values can be large.
+        let audited = self
+            .auditor
+            .is_some()
+            .then(|| (message.proposal_id, message.proposal_value.clone()));
+        let response = self.handle_accept(message).await;
+
+        if let (Some(auditor), Some((proposal_id, value))) = (&mut self.auditor, audited) {
+            auditor.accept(
+                proposal_id,
+                &value,
+                response.as_ref().ok().map(|(response, _)| response),
+                (
+                    self.acceptor.promised,
+                    self.acceptor.accepted_value.as_deref(),
+                ),
+            );
+        }
+
+        response
+    }
+
+    async fn handle_accept(&mut self, message: AcceptRequest) -> Result<(AcceptResponse, Durable)> {
+        self.check_value_size(message.proposal_value.len() as u64)?;
+
+        let step = self
+            .acceptor
+            .on_accept(message.proposal_id, message.proposal_value);
+        if step.persist == Persist::Nothing {
+            debug!(
+                highest_proposal_id = %self.acceptor.promised,
+                "rejected accept for a lower proposal id"
+            );
+            return Ok((step.response, self.pending_sync()));
+        }
+
+        let durable = self.persist(step.persist).await?;
+
+        debug!("accepted value");
+
+        self.emit(|node| Event::Accepted {
+            instance: node.instance.clone(),
+            proposal_id: node.acceptor.accepted_id,
+            value: node.acceptor.accepted_value.clone().unwrap_or_default(),
+        });
+
+        Ok((step.response, durable))
+    }
+
+    /// Writes what the acceptor needs on disk before it answers.
+    async fn persist(&mut self, persist: Persist) -> Result<Durable> {
+        let result = match persist {
+            Persist::Nothing => return Ok(self.pending_sync()),
+            Persist::Promise => self.write_promise().await,
+            Persist::State => self.write_state().await,
+        };
+        self.record_storage_result(result)
+    }
+
+    /// Adds `chunk` to the value being received and accepts the value once it
+    /// is complete. A chunk for a proposal the acceptor won't accept is
+    /// answered with the rejection right away.
+    pub async fn on_accept_chunk(&mut self, chunk: AcceptChunk) -> Result<Option<AcceptResponse>> {
+        self.check_instance(&chunk.instance)?;
+        self.check_voting()?;
+        self.check_value_size(chunk.value_len)?;
+
+        if let Some(rejection) = self.acceptor.rejects(chunk.proposal_id) {
+            self.upload = None;
+            return Ok(Some(rejection));
+        }
+
+        if chunk.offset == 0 {
+            self.upload = Some(Upload {
+                proposal_id: chunk.proposal_id,
+                value_len: chunk.value_len,
+                data: Vec::with_capacity(chunk.value_len as usize),
+            });
+        }
+
+        let upload = match &mut self.upload {
+            Some(upload)
+                if upload.proposal_id == chunk.proposal_id
+                    && upload.value_len == chunk.value_len
+                    && upload.data.len() as u64 == chunk.offset =>
+            {
+                upload
+            }
+            _ => {
+                return Err(AcceptorError::InvalidRequest {
+                    reason: format!(
+                        "unexpected chunk at offset {} of proposal {}",
+                        chunk.offset, chunk.proposal_id
+                    ),
+                }
+                .into())
+            }
+        };
+
+        if upload.data.len() + chunk.data.len() > upload.value_len as usize {
+            self.upload = None;
+            return Err(AcceptorError::InvalidRequest {
+                reason: "chunks are longer than the value".to_owned(),
+            }
+            .into());
+        }
+        upload.data.extend_from_slice(&chunk.data);
+
+        if (upload.data.len() as u64) < upload.value_len {
+            return Ok(None);
+        }
+
+        let Some(upload) = self.upload.take() else {
+            return Ok(None);
+        };
+        let response = self
+            .on_accept(AcceptRequest {
+                instance: chunk.instance,
+                proposal_id: upload.proposal_id,
+                proposal_value: upload.data,
+            })
+            .await?;
+
+        Ok(Some(response))
+    }
+
+    fn check_value_size(&self, size: u64) -> Result<()> {
+        if size > self.max_value_size as u64 {
+            return Err(AcceptorError::InvalidRequest {
+                reason: ValueTooLarge {
+                    size: size as usize,
+                    limit: self.max_value_size,
+                }
+                .to_string(),
+            }
+            .into());
+        }
+
+        Ok(())
+    }
+
+    /// Receives the [Event]s the node sends from now on. A receiver that falls
+    /// too far behind gets [broadcast::error::RecvError::Lagged] and skips the
+    /// events it missed.
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.events.subscribe()
+    }
+
+    /// Sends the event `event` builds, only building it when someone listens
+    /// since events can carry values.
+    fn emit(&self, event: impl FnOnce(&Self) -> Event) {
+        if self.events.receiver_count() > 0 {
+            // Receivers can go away between the check and the send.
+            let _ = self.events.send(event(self));
+        }
+    }
+
+    fn check_instance(&self, instance: &InstanceId) -> Result<()> {
+        if *instance != self.instance {
+            return Err(AcceptorError::InvalidRequest {
+                reason: format!(
+                    "unknown instance: expected={} got={instance}",
+                    self.instance
+                ),
+            }
+            .into());
+        }
+
+        Ok(())
+    }
+
+    /// Persists the promised proposal id without rewriting the accepted value.
+    #[tracing::instrument(skip_all)]
+    async fn write_promise(&mut self) -> Result<Durable> {
+        self.check_open()?;
+
+        self.state_file
+            .seek(std::io::SeekFrom::Start(0))
+            .await
+            .context("seeking to beginning of state file")?;
+
+        self.state_file
+            .write_all(&format::encode_promise(self.acceptor.promised))
+            .await
+            .context("writing proposal id to disk")?;
+
+        self.sync_state_file().await
+    }
+
+    /// Persists the proposal id of the round about to start, before any
+    /// acceptor sees it.
+    async fn write_proposer_id(&mut self) -> Result<()> {
+        self.check_open()?;
+
+        self.proposer_file
+            .seek(std::io::SeekFrom::Start(0))
+            .await
+            .context("seeking to beginning of proposer file")?;
+
+        self.proposer_file
+            .write_all(&self.current_proposal_id.to_bytes())
+            .await
+            .context("writing proposal id to disk")?;
+
+        self.proposer_file
+            .sync_data()
+            .await
+            .context("syncing proposer file")?;
+        self.metrics.increment("paxos_fsyncs_total", 1);
+
+        Ok(())
+    }
+
+    /// For answers that wrote nothing. They report the in-memory state, which
+    /// with group commit can hold writes of concurrent requests that aren't
+    /// synced yet, so they wait for those.
+    fn pending_sync(&self) -> Durable {
+        match &self.group_commit {
+            Some(group_commit) => group_commit.pending(),
+            None => Durable::done(),
+        }
+    }
+
+    /// Syncs the state file now, or hands the sync to the group commit when
+    /// one is configured.
+    async fn sync_state_file(&mut self) -> Result<Durable> {
+        if let Some(group_commit) = &self.group_commit {
+            // Makes sure the write reached the file before the sync is requested.
+            self.state_file
+                .flush()
+                .await
+                .context("flushing state file")?;
+            return Ok(group_commit.sync());
+        }
+
+        self.state_file
+            .sync_all()
+            .await
+            .context("syncing state file")?;
+        self.metrics.increment("paxos_fsyncs_total", 1);
+
+        Ok(Durable::done())
+    }
+
+    /// Remembers whether the last write to the state file failed so it can be
+    /// reported by [Paxos::on_health].
+    fn record_storage_result<T>(&mut self, result: Result<T>) -> Result<T> {
+        self.storage_error = result.as_ref().err().map(|err| format!("{err:#}"));
+        match result {
+            Ok(v) => {
+                self.persisted_at = Some(SystemTime::now());
+                Ok(v)
+            }
+            Err(err) if err.is::<AcceptorError>() => Err(err),
+            Err(err) => Err(AcceptorError::storage(&err).into()),
+        }
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn write_state(&mut self) -> Result<Durable> {
+        self.check_open()?;
+
+        let mut state = self.acceptor.state();
+        if let (Some(key), Some(value)) = (&self.state_key, &mut state.proposal_value) {
+            // A witness writes the id it accepted with no value to seal.
+            if !(self.acceptor.witness && value.is_empty()) {
+                let aad =
+                    encryption::state_aad(&file_prefix(self.id, &self.instance), state.accepted_id);
+                *value = key.seal(&aad, value)?;
+            }
+        }
+        let buffer = format::encode(&state);
+
+        self.state_file
+            .seek(std::io::SeekFrom::Start(0))
+            .await
+            .context("seeking to beginning of state file")?;
+
+        self.state_file
+            .write_all(&buffer)
+            .await
+            .context("writing buffer to state file")?;
+
+        // A shorter value must not leave bytes of the previous one behind.
+        self.state_file
+            .set_len(buffer.len() as u64)
+            .await
+            .context("truncating state file")?;
+
+        self.sync_state_file().await
+    }
+
+    /// The highest proposal id this acceptor has promised or accepted.
+    pub fn proposal_id(&self) -> ProposalId {
+        self.acceptor.promised
+    }
+
+    /// The value of the last proposal this acceptor accepted.
+    pub fn accepted_value(&self) -> Option<&[u8]> {
+        self.acceptor.accepted_value.as_deref()
+    }
+
+    /// The id of the proposal [Paxos::accepted_value] was accepted in.
+    pub fn accepted_id(&self) -> ProposalId {
+        self.acceptor.accepted_id
+    }
+
+    /// The proposal id of this node's last round as a proposer, its next
+    /// round uses a higher one.
+    pub fn current_proposal_id(&self) -> ProposalId {
+        self.current_proposal_id
+    }
+
+    /// The value this node learned was decided.
+    pub fn decided_value(&self) -> Option<&[u8]> {
+        self.decided_value.as_deref()
+    }
+
+    /// Whether this node heard from a quorum since it started, it doesn't
+    /// propose until it has.
+    pub fn is_synced(&self) -> bool {
+        self.synced
+    }
+
+    /// How this node sees each acceptor of the cluster.
+    pub fn peers(&self) -> Vec<PeerState> {
+        let latencies = self.latencies.summary();
+        let needs_auxiliary = self.needs_auxiliary();
+
+        self.acceptors
+            .iter()
+            .map(|acceptor| {
+                let dead = self.detected_dead(*acceptor);
+                let auxiliary = self.auxiliary.contains(acceptor);
+                PeerState {
+                    acceptor: *acceptor,
+                    dead,
+                    auxiliary,
+                    skipped: dead || (auxiliary && !needs_auxiliary),
+                    latency: latencies.get(acceptor).cloned(),
+                }
+            })
+            .collect()
+    }
+
+    pub fn on_digest(&self) -> Digest {
+        Digest {
+            proposal_id: self.acceptor.promised,
+            decided: self.decided_value.is_some(),
+        }
+    }
+
+    pub fn on_health(&self) -> Health {
+        Health {
+            id: self.id,
+            proposal_id: self.acceptor.promised,
+            accepted: self.acceptor.accepted_value.is_some(),
+            decided: self.decided_value.is_some(),
+            storage_error: self.storage_error.clone(),
+        }
+    }
+
+    pub fn on_dump_state(&self) -> StateDump {
+        StateDump {
+            id: self.id,
+            instance: self.instance.clone(),
+            proposal_id: self.acceptor.promised,
+            accepted_id: self.acceptor.accepted_id,
+            accepted_value: self
+                .acceptor
+                .accepted_value
+                .as_deref()
+                .map(DumpedValue::new),
+            state_file: self.state_path.clone(),
+            persisted_at: self.persisted_at,
+        }
+    }
+
+    /// Grants or renews the lease. A grant is on disk before it is answered.
+    pub async fn on_heartbeat(&mut self, message: HeartbeatRequest) -> Result<HeartbeatResponse> {
+        self.check_instance(&message.instance)?;
+        self.grant_lease(&message).await
+    }
+
+    async fn grant_lease(&mut self, message: &HeartbeatRequest) -> Result<HeartbeatResponse> {
+        let response = self.lease_grant.on_heartbeat(message);
+        if response.granted {
+            self.write_lease().await?;
+        }
+
+        Ok(response)
+    }
+
+    async fn write_lease(&mut self) -> Result<()> {
+        self.check_open()?;
+
+        self.lease_file
+            .seek(std::io::SeekFrom::Start(0))
+            .await
+            .context("seeking to beginning of lease file")?;
+
+        self.lease_file
+            .write_all(&self.lease_grant.encode())
+            .await
+            .context("writing lease to disk")?;
+
+        self.lease_file
+            .sync_data()
+            .await
+            .context("syncing lease file")?;
+        self.metrics.increment("paxos_fsyncs_total", 1);
+
+        Ok(())
+    }
+
+    pub fn on_fetch_decided(&self) -> Option<Vec<u8>> {
+        self.decided_value.clone()
+    }
+
+    /// What the acceptor accepted, refused while it may have forgotten it.
+    pub fn on_fetch_accepted(&self) -> Result<AcceptedProposal> {
+        self.check_voting()?;
+
+        Ok(AcceptedProposal {
+            accepted_id: self.acceptor.accepted_id,
+            value: self.acceptor.accepted_value.clone(),
+            witness: self.acceptor.witness,
+        })
+    }
+
+    /// The decided value with the instance it was decided in, None while
+    /// nothing is decided.
+    pub fn decided_instance(&self) -> Option<DecidedInstance> {
+        Some(DecidedInstance {
+            instance: self.instance.clone(),
+            proposal_id: self.acceptor.promised,
+            value: self.decided_value.clone()?,
+        })
+    }
+
+    /// Answers a fetch state request for a server that only holds this
+    /// instance, there are no successors to hand out.
+    pub fn on_fetch_state(&self, from: &InstanceId) -> StateTransfer {
+        StateTransfer {
+            decided: self
+                .decided_instance()
+                .filter(|decided| decided.instance == *from)
+                .into_iter()
+                .collect(),
+            more: false,
+        }
+    }
+
+    pub fn on_latencies(&self) -> HashMap<SocketAddr, LatencySummary> {
+        self.latencies.summary()
+    }
+
+    /// Collects the latencies every reachable acceptor observed to its peers,
+    /// see [PeerQuery::latency_matrix].
+    pub async fn latency_matrix(&self) -> HashMap<SocketAddr, HashMap<SocketAddr, LatencySummary>> {
+        self.peer_query().latency_matrix().await
+    }
+
+    /// Sends a health check to every acceptor, see [PeerQuery::cluster_health].
+    pub async fn cluster_health(&self) -> HashMap<SocketAddr, AcceptorStatus> {
+        self.peer_query().cluster_health().await
+    }
+
+    /// What the admin queries to the other acceptors need from the node, so
+    /// they run without holding it.
+    pub fn peer_query(&self) -> PeerQuery {
+        PeerQuery {
+            address: self.address,
+            acceptors: self.acceptors.clone(),
+            latencies: self.latencies.summary(),
+            health: self.on_health(),
+            rpc_timeout: self.timeouts.prepare_rpc,
+            connections: self.connections(),
+            credentials: self.credentials.clone(),
+            metrics: Arc::clone(&self.metrics),
+        }
+    }
+
+    /// Replaces the timeouts, from the next phase on.
+    pub fn set_timeouts(&mut self, timeouts: Timeouts) {
+        self.timeouts = timeouts;
+    }
+
+    pub fn lease_config(&self) -> Option<LeaseConfig> {
+        self.lease
+    }
+
+    /// Whether this node holds the lease, as far as it can tell. Always false
+    /// when leases are disabled.
+    pub fn holds_lease(&self) -> bool {
+        match (self.lease, self.lease_granted_at) {
+            (Some(lease), Some(granted_at)) => granted_at + lease.duration > Instant::now(),
+            _ => false,
+        }
+    }
+
+    /// Gets or renews the lease unless leases are disabled or the last
+    /// heartbeat is recent enough.
+    async fn acquire_lease(&mut self) -> Result<()> {
+        let Some(lease) = self.lease else {
+            return Ok(());
+        };
+
+        match self.lease_granted_at {
+            Some(granted_at) if granted_at + lease.heartbeat_interval > Instant::now() => Ok(()),
+            _ => self.heartbeat().await,
+        }
+    }
+
+    /// Sends a heartbeat to every acceptor, getting the lease or renewing it
+    /// when a quorum grants it. Returns [LeaseHeld] when an acceptor reports
+    /// another holder and not enough acceptors granted the lease.
+    pub async fn heartbeat(&mut self) -> Result<()> {
+        let Some(lease) = self.lease else {
+            return Err(anyhow!("leases are disabled"));
+        };
+
+        let sent_at = Instant::now();
+        let (instance, id) = (self.instance.clone(), self.id);
+        let client_addr = self.client_addr;
+        let request = || HeartbeatRequest {
+            instance: instance.clone(),
+            proposer: id,
+            duration: lease.duration,
+            client_addr,
+        };
+
+        let mut responses = Vec::with_capacity(self.acceptors.len());
+        if self.is_acceptor() {
+            responses.push(self.grant_lease(&request()).await?);
+        }
+
+        let mut futures = Vec::with_capacity(self.acceptors.len());
+        for i in 0..self.acceptors.len() {
+            let acceptor_addr = self.acceptors[i];
+            if acceptor_addr == self.address {
+                continue;
+            }
+            if self.skips(acceptor_addr) {
+                continue;
+            }
+
+            let client = match self.get_or_init_client(acceptor_addr).await {
+                Err(err) => {
+                    debug!(acceptor = %acceptor_addr, ?err, "getting rpc client");
+                    continue;
+                }
+                Ok(v) => v,
+            };
+
+            let request = request();
+            let credentials = self.credentials.sign("heartbeat", &request);
+            let deadline = sent_at + self.timeouts.prepare_rpc;
+            futures.push(async move {
+                let result = tokio::time::timeout_at(
+                    deadline,
+                    client.heartbeat(timeout::context_until(deadline), credentials, request),
+                )
+                .await;
+                (acceptor_addr, result)
+            });
+        }
+
+        for (acceptor_addr, result) in futures::future::join_all(futures).await {
+            match result {
+                Ok(Ok(Ok(response))) => responses.push(response),
+                Ok(Ok(Err(err))) => {
+                    debug!(acceptor = %acceptor_addr, %err, "error response to heartbeat");
+                }
+                Ok(Err(err)) => {
+                    debug!(acceptor = %acceptor_addr, ?err, "rpc error");
+                    self.evict_if_disconnected(acceptor_addr, &err);
+                }
+                Err(_) => debug!(acceptor = %acceptor_addr, "heartbeat timed out"),
+            }
+        }
+
+        let granted = responses.iter().filter(|response| response.granted).count();
+        if granted >= self.quorum() {
+            if !self.holds_lease() {
+                self.lease_term += 1;
+            }
+            if self.lease_granted_at.is_none() {
+                info!(duration = ?lease.duration, "got the lease");
+                self.emit(|node| Event::LeaseAcquired {
+                    instance: node.instance.clone(),
+                });
+            }
+            self.lease_granted_at = Some(sent_at);
+            return Ok(());
+        }
+
+        if self.lease_granted_at.take().is_some() {
+            warn!(granted, "lost the lease");
+            self.emit(|node| Event::LeaseLost {
+                instance: node.instance.clone(),
+            });
+        }
+
+        let held = responses
+            .iter()
+            .filter(|response| !response.granted)
+            .max_by_key(|response| response.remaining);
+
+        match held {
+            Some(held) => Err(LeaseHeld {
+                holder: held.holder,
+                remaining: held.remaining,
+                client_addr: held.holder_client_addr,
+            }
+            .into()),
+            None => Err(anyhow!(
+                "{granted} acceptors granted the lease, a quorum is {}",
+                self.quorum()
+            )),
+        }
+    }
+
+    /// Asks every acceptor for its digest and, once a quorum answered, moves
+    /// the next proposal id above every id they have seen and learns the
+    /// decided value if one of them knows it. Only does work until it first
+    /// succeeds after the node starts.
+    pub async fn sync(&mut self) -> Result<()> {
+        if self.synced {
+            return Ok(());
+        }
+
+        self.create_on_acceptors().await;
+        let healed = self.heal_fetch(Instant::now() + HEAL_TIMEOUT).run().await;
+        self.finish_heal(healed).await
+    }
+
+    /// Asks the other acceptors to create the instance, they only answer
+    /// requests for instances that were created. Acceptors that didn't create
+    /// it are left to the quorum checks of the requests that follow.
+    async fn create_on_acceptors(&mut self) {
+        let deadline = Instant::now() + self.timeouts.prepare_rpc;
+        let acceptors: HashSet<SocketAddr> = self
+            .acceptors
+            .iter()
+            .chain(&self.auxiliary)
+            .copied()
+            .filter(|acceptor| *acceptor != self.address)
+            .collect();
+
+        let mut futures = FuturesUnordered::new();
+        for acceptor in acceptors {
+            let instance = self.instance.clone();
+            let connections = self.connections();
+            let credentials = self.credentials.sign("create_instance", &instance);
+
+            futures.push(async move {
+                let result = tokio::time::timeout_at(deadline, async {
+                    let (client, version) = connections
+                        .peer(acceptor, deadline)
+                        .await
+                        .map_err(connect_error)?;
+                    // Older acceptors can't decode the request, they create
+                    // the instances they are asked about.
+                    if version < CREATE_INSTANCE_VERSION {
+                        return Ok(Ok(()));
+                    }
+                    let created = client
+                        .create_instance(timeout::context_until(deadline), credentials, instance)
+                        .await?;
+                    Ok::<_, RpcError>(created)
+                })
+                .await;
+                (acceptor, result)
+            });
+        }
+
+        while let Some((acceptor, result)) = futures.next().await {
+            match result {
+                Err(_) => warn!(%acceptor, "create instance request timed out"),
+                Ok(Err(err)) => {
+                    warn!(%acceptor, ?err, "rpc error");
+                    self.evict_if_disconnected(acceptor, &err);
+                }
+                Ok(Ok(Err(err))) => {
+                    warn!(%acceptor, %err, "error response to create instance request");
+                }
+                Ok(Ok(Ok(()))) => {}
+            }
+        }
+    }
+
+    /// Asks the other acceptors whether a value has been decided and, if this
+    /// node has not learned it yet, fetches and persists it.
+    pub async fn heal(&mut self) -> Result<()> {
+        // Witnesses don't keep the values they could learn.
+        if self.decided_value.is_some() || self.acceptor.witness {
+            return Ok(());
+        }
+
+        let healed = self.heal_fetch(Instant::now() + HEAL_TIMEOUT).run().await;
+        self.learn_healed(healed.decided).await
+    }
+
+    /// What [Paxos::sync] and [Paxos::heal] ask the other acceptors, to be
+    /// run without holding the node. [Paxos::finish_heal] applies the
+    /// answers. None when the node is synced and has nothing to learn.
+    pub fn begin_heal(&self) -> Option<HealFetch> {
+        let learn = self.decided_value.is_none() && !self.acceptor.witness;
+        if self.synced && !learn {
+            return None;
+        }
+
+        Some(self.heal_fetch(Instant::now() + HEAL_TIMEOUT))
+    }
+
+    fn heal_fetch(&self, deadline: Instant) -> HealFetch {
+        HealFetch {
+            instance: self.instance.clone(),
+            acceptors: self
+                .acceptors
+                .iter()
+                .copied()
+                .filter(|acceptor| *acceptor != self.address)
+                .collect(),
+            learn: self.decided_value.is_none() && !self.acceptor.witness,
+            accepted: false,
+            connections: self.connections(),
+            credentials: self.credentials.clone(),
+            metrics: Arc::clone(&self.metrics),
+            deadline,
+        }
+    }
+
+    /// Learns the decided value [HealFetch::run] found and, unless the node
+    /// is synced already, syncs it with the digests the acceptors sent.
+    pub async fn finish_heal(&mut self, healed: Healed) -> Result<()> {
+        self.learn_healed(healed.decided).await?;
+
+        if self.synced {
+            return Ok(());
+        }
+
+        let responses = healed.responses + usize::from(self.is_acceptor());
+        if responses < self.quorum() {
+            return Err(anyhow!(
+                "heard from {responses} acceptors, a quorum is {}",
+                self.quorum()
+            ));
+        }
+
+        self.current_proposal_id = self
+            .current_proposal_id
+            .max(self.acceptor.promised)
+            .max(healed.highest_proposal_id);
+
+        self.synced = true;
+        info!(
+            proposal_id = %self.current_proposal_id,
+            decided = self.decided_value.is_some(),
+            "synced with a quorum"
+        );
+
+        Ok(())
+    }
+
+    async fn learn_healed(
+        &mut self,
+        decided: Option<(SocketAddr, ProposalId, Vec<u8>)>,
+    ) -> Result<()> {
+        let Some((acceptor, proposal_id, value)) = decided else {
+            return Ok(());
+        };
+        if self.decided_value.is_some() || self.acceptor.witness {
+            return Ok(());
+        }
+
+        info!(%acceptor, "learned decided value");
+        self.adopt_decided(proposal_id, value).await
+    }
+
+    /// One anti-entropy pass over the instance: compares digests with the
+    /// other acceptors and learns the decided value if one of them knows it.
+    /// Returns whether the pass repaired anything.
+    pub async fn repair(&mut self) -> Result<bool> {
+        if self.decided_value.is_some() || self.acceptor.witness {
+            return Ok(false);
+        }
+
+        self.metrics.increment("paxos_anti_entropy_checks_total", 1);
+        self.heal().await?;
+
+        let repaired = self.decided_value.is_some();
+        if repaired {
+            self.metrics
+                .increment("paxos_anti_entropy_repairs_total", 1);
+        }
+        Ok(repaired)
+    }
+
+    /// Catches the instance up after the node lost its data, so it can vote
+    /// in it again. Proposers may have counted on promises and accepts the
+    /// node forgot, so it first waits out the rounds that could have been in
+    /// flight. Then, like the prepare phase of a round, it asks a quorum of
+    /// the other acceptors what they promised and accepted: it promises the
+    /// highest id and takes on the value accepted in the highest proposal,
+    /// which is the chosen one if a value was chosen, and learns the decided
+    /// value if there is one. Returns whether the instance rejoined, false
+    /// when it didn't need to or it is too early.
+    pub async fn rejoin(&mut self) -> Result<bool> {
+        let Some(fetch) = self.begin_rejoin() else {
+            return Ok(false);
+        };
+        let healed = fetch.run().await;
+        self.finish_rejoin(healed).await
+    }
+
+    /// What [Paxos::rejoin] asks the other acceptors, to be run without
+    /// holding the node. [Paxos::finish_rejoin] applies the answers. None when
+    /// the instance doesn't need to rejoin or it is too early.
+    pub fn begin_rejoin(&self) -> Option<HealFetch> {
+        if !self.rejoin_due() {
+            return None;
+        }
+
+        let mut fetch = self.heal_fetch(Instant::now() + self.timeouts.prepare_rpc);
+        fetch.accepted = true;
+        Some(fetch)
+    }
+
+    fn rejoin_due(&self) -> bool {
+        match self.rejoin.as_ref().map(Rejoin::status) {
+            Some(Status::Recovering { since }) if !self.rejoined => {
+                since.elapsed() >= self.timeouts.prepare_phase + self.timeouts.accept_phase
+            }
+            _ => false,
+        }
+    }
+
+    /// Rejoins the instance with what the [HealFetch] of
+    /// [Paxos::begin_rejoin] found, once a quorum of the other acceptors
+    /// answered. Returns whether the instance rejoined.
+    pub async fn finish_rejoin(&mut self, healed: Healed) -> Result<bool> {
+        if !self.rejoin_due() {
+            return Ok(false);
+        }
+
+        let needed = self
+            .quorum()
+            .min(self.acceptors.len() - usize::from(self.is_acceptor()));
+        if healed.responses < needed {
+            return Err(anyhow!(
+                "only {} of the {needed} acceptors needed to rejoin answered",
+                healed.responses
+            ));
+        }
+
+        // A value may have been chosen by a quorum that counted an accept
+        // this node forgot. Any quorum of the others includes another member
+        // of that quorum, so the value they report accepted in the highest
+        // proposal is the chosen one.
+        let (accepted_id, value) = healed.accepted.unwrap_or_default();
+        if !self.acceptor.witness && healed.witness_accepted_id > accepted_id {
+            return Err(anyhow!(
+                "a witness accepted proposal {} but no acceptor that stores values answered with it",
+                healed.witness_accepted_id
+            ));
+        }
+        let accepted_id = accepted_id.max(healed.witness_accepted_id);
+        if accepted_id > self.acceptor.accepted_id {
+            self.acceptor.accepted_id = accepted_id;
+            self.acceptor.accepted_value = (!self.acceptor.witness).then_some(value);
+        }
+
+        self.acceptor.promised = self
+            .acceptor
+            .promised
+            .max(healed.highest_proposal_id)
+            .max(self.acceptor.accepted_id);
+        self.persist(Persist::State).await?.wait().await?;
+
+        let decided = healed.decided.is_some();
+        self.learn_healed(healed.decided)
+            .await
+            .context("learning decided value")?;
+
+        self.rejoined = true;
+        self.metrics.increment("paxos_rejoined_instances_total", 1);
+        info!(proposal_id = %self.acceptor.promised, decided, "rejoined instance");
+
+        Ok(true)
+    }
+
+    /// Asks the acceptors for the values decided from `from` on, for a node
+    /// that missed them while it was down. The result goes as far as the
+    /// acceptor that knows the most. See [Paxos::learn_decided].
+    pub async fn fetch_state(&self, from: &InstanceId) -> Result<Vec<DecidedInstance>> {
+        Ok(self.begin_fetch_state(from).run().await)
+    }
+
+    /// What [Paxos::fetch_state] asks the other acceptors, to be run without
+    /// holding the node.
+    pub fn begin_fetch_state(&self, from: &InstanceId) -> StateFetch {
+        StateFetch {
+            from: from.clone(),
+            acceptors: self
+                .acceptors
+                .iter()
+                .copied()
+                .filter(|acceptor| *acceptor != self.address)
+                .collect(),
+            rpc_timeout: self.timeouts.prepare_rpc,
+            connections: self.connections(),
+            credentials: self.credentials.clone(),
+            metrics: Arc::clone(&self.metrics),
+        }
+    }
+
+    /// Adopts a value another acceptor reported decided in this instance, as
+    /// returned by [Paxos::fetch_state].
+    pub async fn learn_decided(&mut self, decided: DecidedInstance) -> Result<()> {
+        self.check_instance(&decided.instance)?;
+
+        // Witnesses don't keep the values they could learn.
+        if self.decided_value.is_some() || self.acceptor.witness {
+            return Ok(());
+        }
+
+        self.adopt_decided(decided.proposal_id, decided.value).await
+    }
+
+    async fn adopt_decided(&mut self, proposal_id: ProposalId, value: Vec<u8>) -> Result<()> {
+        let persist = self.acceptor.learn(proposal_id, value.clone());
+
+        if let Some(auditor) = &mut self.auditor {
+            auditor.learn(
+                proposal_id,
+                &value,
+                (
+                    self.acceptor.promised,
+                    self.acceptor.accepted_value.as_deref(),
+                ),
+            );
+        }
+
+        self.persist(persist).await?.wait().await?;
+
+        self.mark_decided(value).await
+    }
+
+    /// Flushes the state and decided files to disk. Called before the process
+    /// exits, every write the node would make afterwards fails.
+    pub async fn shutdown(&mut self) -> Result<()> {
+        self.shut_down = true;
+
+        self.state_file
+            .sync_all()
+            .await
+            .context("syncing state file")?;
+        self.decided_file
+            .sync_all()
+            .await
+            .context("syncing decided file")?;
+        self.metrics.increment("paxos_fsyncs_total", 2);
+
+        Ok(())
+    }
+
+    /// Seals this acceptor's answer to `request` with its message key, see [Signed].
+    pub fn seal<Req: Serialize, T: Serialize>(
+        &self,
+        kind: &str,
+        request: &Req,
+        value: T,
+    ) -> Signed<T> {
+        self.credentials.seal(kind, request, value)
+    }
+
+    /// What a caller has to send to confirm it means to reset this acceptor.
+    pub fn reset_confirmation(&self) -> String {
+        format!("reset acceptor {} {}", self.id, self.instance)
+    }
+
+    /// Forgets everything the acceptor promised, accepted and learned, as if
+    /// it never ran. Proposals it answered before can be chosen again with a
+    /// different value, so this is only for test clusters.
+    pub async fn reset_state(&mut self) -> Result<()> {
+        self.check_open()?;
+
+        warn!("resetting acceptor state");
+
+        for (file, name) in [
+            (&mut self.state_file, "state"),
+            (&mut self.decided_file, "decided"),
+            (&mut self.lease_file, "lease"),
+        ] {
+            file.set_len(0)
+                .await
+                .with_context(|| format!("truncating {name} file"))?;
+            file.seek(std::io::SeekFrom::Start(0))
+                .await
+                .with_context(|| format!("seeking to beginning of {name} file"))?;
+            file.sync_all()
+                .await
+                .with_context(|| format!("syncing {name} file"))?;
+        }
+
+        self.acceptor.reset();
+        self.decided_value = None;
+        self.persisted_at = None;
+        self.storage_error = None;
+        self.lease_grant = Grant::default();
+
+        if let Some(auditor) = &mut self.auditor {
+            *auditor = Auditor::new(self.acceptor.policy, ProposalId::ZERO, None, None);
+        }
+
+        Ok(())
+    }
+
+    fn check_open(&self) -> Result<()> {
+        if self.shut_down {
+            return Err(AcceptorError::ShuttingDown.into());
+        }
+
+        Ok(())
+    }
+
+    /// Closes the instance locally, the decided value can never change afterwards.
+    #[tracing::instrument(skip_all)]
+    async fn mark_decided(&mut self, value: Vec<u8>) -> Result<()> {
+        if let Some(auditor) = &mut self.auditor {
+            auditor.decide(&value);
+        }
+
+        if self.decided_value.is_some() {
+            return Ok(());
+        }
+
+        self.check_open()?;
+
+        let sealed = match &self.state_key {
+            None => None,
+            Some(key) => Some(key.seal(
+                &encryption::decided_aad(&file_prefix(self.id, &self.instance)),
+                &value,
+            )?),
+        };
+
+        self.decided_file
+            .write_all(&format::encode_decided(sealed.as_deref().unwrap_or(&value)))
+            .await
+            .context("writing decided value to file")?;
+
+        self.decided_file
+            .sync_all()
+            .await
+            .context("syncing decided file")?;
+        self.metrics.increment("paxos_fsyncs_total", 1);
+
+        if let Some(on_decided) = &self.on_decided {
+            on_decided.call(&self.instance, &value);
+        }
+
+        self.emit(|node| Event::Chosen {
+            instance: node.instance.clone(),
+            value: value.clone(),
+        });
+
+        self.decided_value = Some(value);
+
+        Ok(())
+    }
+}
+
+/// Counts an acceptor's answer to a prepare request, returns whether it
+/// promised. Keeps the longest wait asked for by the acceptors that throttled
+/// it in `retry_after`.
+/// Proposes `value` with the node behind `node`, like
+/// [Paxos::propose_with_cancel]. The node is only locked while a round runs:
+/// the backoff between rounds is waited out without it, so the node's
+/// acceptor keeps answering other proposers and its lease keeps being renewed.
+pub async fn propose_shared(
+    node: &Mutex<Paxos>,
+    value: Vec<u8>,
+    cancel: &CancellationToken,
+) -> Result<Decided> {
+    let mut proposal = lock_unless_cancelled(node, cancel)
+        .await?
+        .begin_propose(value)
+        .await?;
+
+    loop {
+        let round = lock_unless_cancelled(node, cancel)
+            .await?
+            .propose_round(&mut proposal, cancel)
+            .await?;
+        let backoff = match round {
+            Round::Decided(decided) => return Ok(decided),
+            Round::Retry { backoff } => backoff,
+        };
+
+        select! {
+            _ = cancel.cancelled() => return Err(cancelled(proposal.metrics.as_ref(), None)),
+            _ = tokio::time::sleep(backoff) => {}
+        }
+    }
+}
+
+async fn lock_unless_cancelled<'a>(
+    node: &'a Mutex<Paxos>,
+    cancel: &CancellationToken,
+) -> Result<MutexGuard<'a, Paxos>> {
+    select! {
+        _ = cancel.cancelled() => Err(Cancelled { phase: None }.into()),
+        node = node.lock() => Ok(node),
+    }
+}
+
+fn cancelled(metrics: &dyn Recorder, phase: Option<Phase>) -> anyhow::Error {
+    metrics.increment("paxos_proposals_cancelled_total", 1);
+    info!(?phase, "proposal cancelled");
+    Cancelled { phase }.into()
+}
+
+fn count_promise(
+    promises: &mut Promises,
+    retry_after: &mut Option<Duration>,
+    acceptor: SocketAddr,
+    response: Result<PrepareResponse, AcceptorError>,
+    metrics: &dyn Recorder,
+) -> bool {
+    let response = match response {
+        Err(err) => {
+            warn!(%acceptor, %err, "error response to prepare request");
+            *retry_after = (*retry_after).max(err.retry_after());
+            return false;
+        }
+        Ok(v) => v,
+    };
+
+    let proposal_id = response.proposal_id;
+    if !promises.count(response) {
+        metrics.increment("paxos_prepare_nacks_total", 1);
+        debug!(%acceptor, %proposal_id, "prepare request rejected");
+        return false;
+    }
+
+    true
+}
+
+/// Lets the requests of a phase that ended early run to completion instead of
+/// cancelling them, so slow acceptors still see them.
+fn finish_in_background<F>(futures: FuturesUnordered<F>)
+where
+    F: Future + Send + 'static,
+    F::Output: Send,
+{
+    if futures.is_empty() {
+        return;
+    }
+
+    tokio::spawn(futures.for_each(|_| async {}));
+}
+
+/// Sends the value of `request` in chunks of `chunk_size` bytes and returns
+/// the acceptor's answer to the chunk that completed it, or to the first chunk
+/// it rejected.
+async fn accept_in_chunks(
+    client: &AcceptorServiceClient,
+    version: u32,
+    credentials: Credentials,
+    request: AcceptRequest,
+    chunk_size: usize,
+    deadline: Instant,
+) -> Result<Result<AcceptResponse, AcceptorError>, RpcError> {
+    let value_len = request.proposal_value.len() as u64;
+    let mut offset = 0;
+
+    for data in request.proposal_value.chunks(chunk_size) {
+        let chunk = AcceptChunk {
+            instance: request.instance.clone(),
+            proposal_id: request.proposal_id,
+            offset,
+            value_len,
+            data: data.to_vec(),
+        };
+        offset += data.len() as u64;
+
+        let answer = client
+            .accept_chunk(
+                timeout::context_until(deadline),
+                credentials.sign("accept_chunk", &chunk),
+                chunk.clone(),
+            )
+            .await?;
+        match open(&credentials, version, "accept_chunk", &chunk, answer) {
+            Ok(None) => continue,
+            Ok(Some(response)) => return Ok(Ok(response)),
+            Err(err) => return Ok(Err(err)),
+        }
+    }
+
+    Ok(Err(AcceptorError::Internal {
+        message: "the acceptor did not answer the last chunk".to_owned(),
+    }))
+}
+
+/// Connects to the acceptors through the client pool of a node. Cloned into
+/// the requests of a phase, so connecting to one acceptor doesn't hold up the
+/// requests to the others.
+#[derive(Clone)]
+struct Connections {
+    pool: ClientPool,
+    connector: Connector,
+}
+
+impl Connections {
+    /// The client of `acceptor`, connecting when the pool has none. Attempts
+    /// stop at `deadline`, callers bound the wait with it too.
+    async fn client(
+        &self,
+        acceptor: SocketAddr,
+        deadline: Instant,
+    ) -> Result<AcceptorServiceClient> {
+        let (client, _) = self.peer(acceptor, deadline).await?;
+        Ok(client)
+    }
+
+    /// Like [Connections::client], also returning the protocol version the
+    /// connection speaks.
+    async fn peer(
+        &self,
+        acceptor: SocketAddr,
+        deadline: Instant,
+    ) -> Result<(AcceptorServiceClient, u32)> {
+        if let Some(peer) = self.pool.get(acceptor) {
+            return Ok(peer);
+        }
+
+        let mut attempts = 0;
+        let (client, version) = loop {
+            attempts += 1;
+
+            match connect_until(&self.connector, acceptor, deadline).await {
+                Ok(peer) => break peer,
+                Err(err) if attempts >= CONNECT_ATTEMPTS => return Err(err),
+                Err(err) => {
+                    warn!(%acceptor, attempt = attempts, ?err, "connecting to acceptor failed, retrying");
+                    tokio::time::sleep(CONNECT_BACKOFF * attempts).await;
+                }
+            }
+        };
+
+        // Another instance may have connected meanwhile, its client is kept.
+        Ok(self.pool.insert(acceptor, client, version))
+    }
+
+    /// Called for every failed rpc. Drops the cached client when the error means
+    /// its connection is gone so the next request to the acceptor opens a new one.
+    fn evict_if_disconnected(&self, acceptor: SocketAddr, err: &RpcError, metrics: &dyn Recorder) {
+        metrics.increment("paxos_rpc_errors_total", 1);
+
+        // The connection is still usable after these.
+        if matches!(err, RpcError::DeadlineExceeded | RpcError::Server(_)) {
+            return;
+        }
+
+        if self.pool.remove(acceptor) {
+            warn!(%acceptor, ?err, "dropping broken connection");
+        }
+    }
+}
+
+/// Sends one accept request to the acceptors, directly or through relays.
+/// Holds nothing of the node, so relays forward requests without holding it.
+#[derive(Clone)]
+struct AcceptSender {
+    request: AcceptRequest,
+    acceptors: Vec<SocketAddr>,
+    connections: Connections,
+    credentials: Credentials,
+    metrics: Arc<dyn Recorder>,
+    rpc_permits: RpcPermits,
+    accept_rpc: Duration,
+    /// The size of the chunks the value is sent in, None when it is sent whole.
+    chunks: Option<usize>,
+    /// Set on relays. Relays ask every target through
+    /// [AcceptorService::relay_accept] so the acks they forward come signed,
+    /// and leave checking them to the proposer.
+    relay: bool,
+}
+
+/// What an [AcceptSender] heard back.
+struct Sent {
+    responses: Vec<RelayedAcceptResponse>,
+    /// The acceptors that did not respond in time.
+    timed_out: Vec<SocketAddr>,
+    /// The round trips of the direct requests that succeeded.
+    rtts: Vec<(SocketAddr, Duration)>,
+}
+
+impl AcceptSender {
+    /// Sends the request to `targets`, contacting at most `fanout` of them
+    /// directly. Each contacted acceptor relays the request to its share of the
+    /// remaining targets and returns their responses along with its own.
+    /// Returns as soon as `needed` acceptors accepted the request, letting the
+    /// requests still in flight finish in the background, or as soon as one
+    /// rejected it or `cancel` was cancelled, dropping the requests still in
+    /// flight.
+    async fn send(
+        &self,
+        targets: &[SocketAddr],
+        fanout: usize,
+        phase_deadline: Instant,
+        needed: usize,
+        cancel: &CancellationToken,
+    ) -> Sent {
+        let mut futures = FuturesUnordered::new();
+
+        for (relay, subtree) in partition(targets, fanout) {
+            let sender = self.clone();
+            let subtree = subtree.to_vec();
+
+            // Relays need time to hear back from their own targets and large
+            // values time to be sent, so they are given the rest of the phase.
+            let deadline = if subtree.is_empty() && self.chunks.is_none() {
+                timeout::rpc_deadline(self.accept_rpc, phase_deadline)
+            } else {
+                phase_deadline
+            };
+
+            // Only requests to a single peer measure the round trip to it.
+            let leaf = subtree.is_empty();
+            // Requests answered without a signature.
+            let direct = leaf && !self.relay;
+
+            futures.push(async move {
+                let result = tokio::time::timeout_at(deadline, async {
+                    let (client, version) = match sender.connections.peer(relay, deadline).await {
+                        Err(err) => return (Duration::ZERO, Err(connect_error(err))),
+                        Ok(peer) => peer,
+                    };
+                    let _permit = sender.rpc_permits.acquire().await;
+                    let started_at = Instant::now();
+                    let credentials = sender.credentials.clone();
+                    let request = sender.request.clone();
+                    let result = async {
+                        if let Some(chunk_size) = sender.chunks {
+                            accept_in_chunks(
+                                &client,
+                                version,
+                                credentials,
+                                request,
+                                chunk_size,
+                                deadline,
+                            )
+                            .await
+                            .map(|response| {
+                                vec![RelayedAcceptResponse {
+                                    acceptor: relay,
+                                    response,
+                                    mac: None,
+                                }]
+                            })
+                            .map(Ok)
+                        } else if direct {
+                            let signed = credentials.sign("accept", &request);
+                            client
+                                .accept(timeout::context_until(deadline), signed, request.clone())
+                                .await
+                                .map(|answer| {
+                                    open(&credentials, version, "accept", &request, answer).map(
+                                        |response| {
+                                            vec![RelayedAcceptResponse {
+                                                acceptor: relay,
+                                                response: Ok(response),
+                                                mac: None,
+                                            }]
+                                        },
+                                    )
+                                })
+                        } else {
+                            let request = RelayAcceptRequest {
+                                accept: request,
+                                targets: subtree,
+                                fanout,
+                            };
+                            let credentials = credentials.sign("relay_accept", &request);
+                            client
+                                .relay_accept(
+                                    timeout::context_until(deadline),
+                                    credentials,
+                                    request,
+                                )
+                                .await
+                        }
+                    }
+                    .await;
+                    (started_at.elapsed(), result)
+                })
+                .await;
+                let (rtt, result) = match result {
+                    Err(elapsed) => (Duration::ZERO, Err(elapsed)),
+                    Ok((rtt, result)) => (rtt, Ok(result)),
+                };
+                if leaf && matches!(result, Ok(Ok(_))) {
+                    sender
+                        .metrics
+                        .record_acceptor_rtt(Phase::Accept, relay, rtt);
+                }
+                (relay, leaf, direct, rtt, result)
+            });
+        }
+
+        let mut sent = Sent {
+            responses: Vec::with_capacity(targets.len()),
+            timed_out: Vec::new(),
+            rtts: Vec::new(),
+        };
+        let mut acked = HashSet::new();
+        let proposal_id = self.request.proposal_id;
+
+        while acked.len() < needed {
+            let next = select! {
+                biased;
+                _ = cancel.cancelled() => return sent,
+                next = futures.next() => next,
+            };
+            let Some((relay, leaf, direct, rtt, result)) = next else {
+                break;
+            };
+
+            if leaf && matches!(result, Ok(Ok(_))) {
+                sent.rtts.push((relay, rtt));
+            }
+
+            match result {
+                Err(_) => {
+                    warn!(acceptor = %relay, "accept request timed out");
+                    self.metrics.increment("paxos_rpc_timeouts_total", 1);
+                    sent.timed_out.push(relay);
+                }
+                Ok(Err(err)) => {
+                    self.connections
+                        .evict_if_disconnected(relay, &err, self.metrics.as_ref());
+                    sent.responses.push(RelayedAcceptResponse {
+                        acceptor: relay,
+                        response: Err(AcceptorError::Unreachable {
+                            reason: format!("rpc error {err:?}"),
+                        }),
+                        mac: None,
+                    });
+                }
+                Ok(Ok(Err(err))) => sent.responses.push(RelayedAcceptResponse {
+                    acceptor: relay,
+                    response: Err(err),
+                    mac: None,
+                }),
+                Ok(Ok(Ok(responses))) if direct => sent.responses.extend(responses),
+                Ok(Ok(Ok(responses))) => sent.responses.extend(
+                    responses
+                        .into_iter()
+                        .map(|relayed| self.checked(relay, relayed)),
+                ),
+            }
+
+            // The round is lost, the other acceptors can't change that.
+            if highest_rejection(&self.acceptors, proposal_id, &sent.responses).is_some() {
+                return sent;
+            }
+
+            for relayed in &sent.responses {
+                if acks(&self.acceptors, proposal_id, relayed) {
+                    acked.insert(relayed.acceptor);
+                }
+            }
+        }
+
+        finish_in_background(futures);
+
+        sent
+    }
+
+    /// Turns the answers `relay` forwards that the acceptor didn't sign into
+    /// errors, a relay can't make up acks or rejections for the others.
+    /// Relays forward answers as they are, only the proposer checks them.
+    fn checked(&self, relay: SocketAddr, relayed: RelayedAcceptResponse) -> RelayedAcceptResponse {
+        let Ok(response) = &relayed.response else {
+            return relayed;
+        };
+        if self.relay {
+            return relayed;
+        }
+
+        match self.credentials.verify_response(
+            "accept",
+            relayed.acceptor,
+            &self.request,
+            response,
+            relayed.mac.as_deref(),
+        ) {
+            Ok(()) => relayed,
+            Err(err) => {
+                warn!(%relay, acceptor = %relayed.acceptor, ?err, "ignoring unverified relayed accept response");
+                self.metrics
+                    .increment("paxos_unverified_relayed_responses_total", 1);
+                RelayedAcceptResponse {
+                    acceptor: relayed.acceptor,
+                    response: Err(AcceptorError::Unreachable {
+                        reason: format!("unverified response relayed by {relay}"),
+                    }),
+                    mac: None,
+                }
+            }
+        }
+    }
+}
+
+/// The highest proposal id above `proposal_id` among the acceptors of the
+/// cluster that rejected it, if any did.
+fn highest_rejection(
+    acceptors: &[SocketAddr],
+    proposal_id: ProposalId,
+    responses: &[RelayedAcceptResponse],
+) -> Option<ProposalId> {
+    responses
+        .iter()
+        .filter(|relayed| acceptors.contains(&relayed.acceptor))
+        .filter_map(|relayed| relayed.response.as_ref().ok())
+        .map(|response| response.proposal_id)
+        .filter(|promised| *promised > proposal_id)
+        .max()
+}
+
+/// Whether `relayed` is an acceptor of the cluster accepting `proposal_id`.
+fn acks(
+    acceptors: &[SocketAddr],
+    proposal_id: ProposalId,
+    relayed: &RelayedAcceptResponse,
+) -> bool {
+    acceptors.contains(&relayed.acceptor)
+        && matches!(&relayed.response, Ok(response) if response.proposal_id <= proposal_id)
+}
+
+/// The part of a relayed accept request that is left once the relay accepted
+/// it, see [Paxos::begin_relay_accept].
+pub struct RelayForward {
+    own: RelayedAcceptResponse,
+    sender: AcceptSender,
+    targets: Vec<SocketAddr>,
+    fanout: usize,
+    deadline: Instant,
+}
+
+impl RelayForward {
+    /// Forwards the request and returns the responses of the targets along
+    /// with the relay's own.
+    pub async fn run(self) -> Vec<RelayedAcceptResponse> {
+        // The proposer counts the acceptors, a relay waits for all of its
+        // targets. It isn't cancelled, the proposer drops the request instead.
+        let sent = self
+            .sender
+            .send(
+                &self.targets,
+                self.fanout,
+                self.deadline,
+                self.targets.len(),
+                &CancellationToken::new(),
+            )
+            .await;
+
+        let mut responses = vec![self.own];
+        responses.extend(sent.responses);
+        responses.extend(
+            sent.timed_out
+                .into_iter()
+                .map(|acceptor| RelayedAcceptResponse {
+                    acceptor,
+                    response: Err(AcceptorError::TimedOut),
+                    mac: None,
+                }),
+        );
+        responses
+    }
+}
+
+/// A decided value being handed down the relay tree, see
+/// [Paxos::begin_relay_learn].
+pub struct LearnForward {
+    decided: DecidedInstance,
+    mac: Option<String>,
+    targets: Vec<SocketAddr>,
+    fanout: usize,
+    connections: Connections,
+    credentials: Credentials,
+    deadline: Instant,
+}
+
+impl LearnForward {
+    /// Hands the value to the first acceptor of each partition of the
+    /// targets, which hands it on to the rest. Failures are only logged, the
+    /// acceptors that miss the value learn it when they heal.
+    pub async fn run(self) {
+        let mut futures = FuturesUnordered::new();
+
+        for (relay, subtree) in partition(&self.targets, self.fanout) {
+            let message = RelayLearnRequest {
+                decided: self.decided.clone(),
+                mac: self.mac.clone(),
+                targets: subtree.to_vec(),
+                fanout: self.fanout,
+            };
+            let credentials = self.credentials.sign("relay_learn", &message);
+            let connections = self.connections.clone();
+            let deadline = self.deadline;
+
+            futures.push(async move {
+                let result = tokio::time::timeout_at(deadline, async {
+                    let client = connections.client(relay, deadline).await?;
+                    client
+                        .relay_learn(timeout::context_until(deadline), credentials, message)
+                        .await??;
+                    anyhow::Ok(())
+                })
+                .await;
+                (relay, result)
+            });
+        }
+
+        while let Some((relay, result)) = futures.next().await {
+            match result {
+                Err(_) => warn!(acceptor = %relay, "relaying decided value timed out"),
+                Ok(Err(err)) => warn!(acceptor = %relay, ?err, "relaying decided value failed"),
+                Ok(Ok(())) => {}
+            }
+        }
+    }
+}
+
+/// Asks the other acceptors how they are doing, for the admin endpoints,
+/// without holding the node. Created by [Paxos::peer_query].
+pub struct PeerQuery {
+    address: SocketAddr,
+    acceptors: Vec<SocketAddr>,
+    /// What this node observed, it answers for itself without a request.
+    latencies: HashMap<SocketAddr, LatencySummary>,
+    health: Health,
+    rpc_timeout: Duration,
+    connections: Connections,
+    credentials: Credentials,
+    metrics: Arc<dyn Recorder>,
+}
+
+impl PeerQuery {
+    /// Collects the latencies every reachable acceptor observed to its peers,
+    /// indexed by the node that observed them. Acceptors are asked
+    /// concurrently, those that don't answer within the prepare rpc timeout
+    /// are left out so a slow peer doesn't hold up the others.
+    pub async fn latency_matrix(self) -> HashMap<SocketAddr, HashMap<SocketAddr, LatencySummary>> {
+        let mut matrix = HashMap::with_capacity(self.acceptors.len());
+        matrix.insert(self.address, self.latencies.clone());
+
+        let mut futures = FuturesUnordered::new();
+        for acceptor_addr in self.acceptors.iter().copied() {
+            if acceptor_addr == self.address {
+                continue;
+            }
+
+            let credentials = self.credentials.sign("latencies", &());
+            let deadline = Instant::now() + self.rpc_timeout;
+            let connections = self.connections.clone();
+            futures.push(async move {
+                let result = tokio::time::timeout_at(deadline, async {
+                    let client = connections
+                        .client(acceptor_addr, deadline)
+                        .await
+                        .map_err(connect_error)?;
+                    client
+                        .latencies(timeout::context_until(deadline), credentials)
+                        .await
+                })
+                .await;
+                (acceptor_addr, result)
+            });
+        }
+
+        while let Some((acceptor_addr, result)) = futures.next().await {
+            match result {
+                Ok(Ok(Ok(row))) => {
+                    matrix.insert(acceptor_addr, row);
+                }
+                Ok(Ok(Err(err))) => {
+                    warn!(acceptor = %acceptor_addr, %err, "error response to latencies request");
+                }
+                Ok(Err(err)) => {
+                    warn!(acceptor = %acceptor_addr, ?err, "rpc error");
+                    self.connections.evict_if_disconnected(
+                        acceptor_addr,
+                        &err,
+                        self.metrics.as_ref(),
+                    );
+                }
+                Err(_) => warn!(acceptor = %acceptor_addr, "latencies request timed out"),
+            }
+        }
+
+        matrix
+    }
+
+    /// Sends a health check to every acceptor, concurrently so a slow acceptor
+    /// doesn't delay the others. Acceptors that don't answer within the prepare
+    /// rpc timeout are reported as down.
+    pub async fn cluster_health(self) -> HashMap<SocketAddr, AcceptorStatus> {
+        let mut statuses = HashMap::with_capacity(self.acceptors.len());
+        let mut futures = Vec::with_capacity(self.acceptors.len());
+
+        for acceptor_addr in self.acceptors.iter().copied() {
+            if acceptor_addr == self.address {
+                statuses.insert(
+                    acceptor_addr,
+                    AcceptorStatus::Up {
+                        health: self.health.clone(),
+                        rtt: Duration::ZERO,
+                    },
+                );
+                continue;
+            }
+
+            let credentials = self.credentials.sign("health", &());
+            let deadline = Instant::now() + self.rpc_timeout;
+            let connections = self.connections.clone();
+            futures.push(async move {
+                let started_at = Instant::now();
+                let result = tokio::time::timeout_at(deadline, async {
+                    let client = connections
+                        .client(acceptor_addr, deadline)
+                        .await
+                        .map_err(connect_error)?;
+                    client
+                        .health(timeout::context_until(deadline), credentials)
+                        .await
+                })
+                .await;
+                (acceptor_addr, started_at.elapsed(), result)
+            });
+        }
+
+        for (acceptor_addr, rtt, result) in futures::future::join_all(futures).await {
+            let status = match result {
+                Ok(Ok(Ok(health))) => AcceptorStatus::Up { health, rtt },
+                Ok(Ok(Err(err))) => AcceptorStatus::Down {
+                    error: err.to_string(),
+                },
+                Ok(Err(err)) => {
+                    self.connections.evict_if_disconnected(
+                        acceptor_addr,
+                        &err,
+                        self.metrics.as_ref(),
+                    );
+                    AcceptorStatus::Down {
+                        error: err.to_string(),
+                    }
+                }
+                Err(_) => AcceptorStatus::Down {
+                    error: format!("no response within {:?}", self.rpc_timeout),
+                },
+            };
+            statuses.insert(acceptor_addr, status);
+        }
+
+        statuses
+    }
+}
+
+/// The digests and decided value [Paxos::sync], [Paxos::heal] and
+/// [Paxos::rejoin] ask the other acceptors for, gathered without holding the
+/// node.
+pub struct HealFetch {
+    instance: InstanceId,
+    acceptors: Vec<SocketAddr>,
+    /// Whether to fetch the decided value from an acceptor that knows it.
+    learn: bool,
+    /// Whether to ask every acceptor what it accepted, which only acceptors
+    /// that answer count then.
+    accepted: bool,
+    connections: Connections,
+    credentials: Credentials,
+    metrics: Arc<dyn Recorder>,
+    deadline: Instant,
+}
+
+/// What a [HealFetch] found, applied with [Paxos::finish_heal] or
+/// [Paxos::finish_rejoin].
+#[derive(Debug, Default)]
+pub struct Healed {
+    /// How many of the other acceptors sent their digest.
+    responses: usize,
+    highest_proposal_id: ProposalId,
+    /// The acceptor the decided value came from, the highest id it has seen
+    /// and the value.
+    decided: Option<(SocketAddr, ProposalId, Vec<u8>)>,
+    /// The value accepted in the highest proposal and its id, when asked for.
+    accepted: Option<(ProposalId, Vec<u8>)>,
+    /// The highest proposal id a witness reported accepting.
+    witness_accepted_id: ProposalId,
+}
+
+impl HealFetch {
+    /// Asks every acceptor for its digest at once and fetches the decided
+    /// value from the ones that know it, until one hands it over.
+    pub async fn run(self) -> Healed {
+        let mut futures = FuturesUnordered::new();
+
+        for acceptor in self.acceptors.iter().copied() {
+            let instance = self.instance.clone();
+            let connections = self.connections.clone();
+            let credentials = self.credentials.clone();
+            let deadline = self.deadline;
+            let fetch_accepted = self.accepted;
+
+            futures.push(async move {
+                let result = tokio::time::timeout_at(deadline, async {
+                    let (client, version) = connections
+                        .peer(acceptor, deadline)
+                        .await
+                        .map_err(connect_error)?;
+                    let digest = client
+                        .digest(
+                            timeout::context_until(deadline),
+                            credentials.sign("digest", &instance),
+                            instance.clone(),
+                        )
+                        .await?;
+                    let accepted = match &digest {
+                        Ok(_) if fetch_accepted => Some(
+                            fetch_accepted_from(
+                                &client,
+                                version,
+                                &credentials,
+                                &instance,
+                                deadline,
+                            )
+                            .await?,
+                        ),
+                        _ => None,
+                    };
+                    Ok::<_, RpcError>((client, version, digest, accepted))
+                })
+                .await;
+                (acceptor, result)
+            });
+        }
+
+        let mut healed = Healed::default();
+        let mut knowing = Vec::new();
+
+        while let Some((acceptor, result)) = futures.next().await {
+            match result {
+                Err(_) => warn!(%acceptor, "digest request timed out"),
+                Ok(Err(err)) => {
+                    warn!(%acceptor, ?err, "rpc error");
+                    self.connections
+                        .evict_if_disconnected(acceptor, &err, self.metrics.as_ref());
+                }
+                Ok(Ok((_, _, Err(err), _))) => {
+                    warn!(%acceptor, %err, "error response to digest request");
+                }
+                Ok(Ok((_, _, Ok(_), Some(Err(err))))) => {
+                    warn!(%acceptor, %err, "error response to fetch accepted request");
+                }
+                Ok(Ok((client, version, Ok(digest), accepted))) => {
+                    healed.responses += 1;
+                    healed.highest_proposal_id = healed.highest_proposal_id.max(digest.proposal_id);
+                    if let Some(Ok(accepted)) = accepted {
+                        healed.count_accepted(accepted);
+                    }
+                    if digest.decided {
+                        knowing.push((acceptor, client, version, digest.proposal_id));
+                    }
+                }
+            }
+        }
+
+        if !self.learn {
+            return healed;
+        }
+
+        for (acceptor, client, version, proposal_id) in knowing {
+            let answer = tokio::time::timeout_at(
+                self.deadline,
+                client.fetch_decided(
+                    timeout::context_until(self.deadline),
+                    self.credentials.sign("fetch_decided", &self.instance),
+                    self.instance.clone(),
+                ),
+            )
+            .await;
+
+            match answer.map(|answer| {
+                answer.map(|answer| {
+                    open(
+                        &self.credentials,
+                        version,
+                        "fetch_decided",
+                        &self.instance,
+                        answer,
+                    )
+                })
+            }) {
+                Err(_) => warn!(%acceptor, "fetch decided request timed out"),
+                Ok(Ok(Ok(Some(value)))) => {
+                    healed.decided = Some((acceptor, proposal_id, value));
+                    break;
+                }
+                Ok(Ok(Ok(None))) => {}
+                Ok(Ok(Err(err))) => {
+                    warn!(%acceptor, %err, "error response to fetch decided request");
+                }
+                Ok(Err(err)) => {
+                    warn!(%acceptor, ?err, "rpc error");
+                    self.connections
+                        .evict_if_disconnected(acceptor, &err, self.metrics.as_ref());
+                }
+            }
+        }
+
+        healed
+    }
+}
+
+impl Healed {
+    /// Keeps what an acceptor accepted if it was accepted in the highest
+    /// proposal reported so far.
+    fn count_accepted(&mut self, accepted: AcceptedProposal) {
+        if accepted.witness {
+            self.witness_accepted_id = self.witness_accepted_id.max(accepted.accepted_id);
+            return;
+        }
+
+        let Some(value) = accepted.value else {
+            return;
+        };
+        if self
+            .accepted
+            .as_ref()
+            .map_or(true, |(accepted_id, _)| accepted.accepted_id > *accepted_id)
+        {
+            self.accepted = Some((accepted.accepted_id, value));
+        }
+    }
+}
+
+/// Asks `client` what it accepted in `instance`, for [HealFetch::run]. Acceptors
+/// that speak a version without [AcceptorService::fetch_accepted] aren't asked.
+async fn fetch_accepted_from(
+    client: &AcceptorServiceClient,
+    version: u32,
+    credentials: &Credentials,
+    instance: &InstanceId,
+    deadline: Instant,
+) -> Result<Result<AcceptedProposal, AcceptorError>, RpcError> {
+    if version < FETCH_ACCEPTED_VERSION {
+        return Ok(Err(AcceptorError::UnsupportedVersion {
+            version,
+            min_supported: FETCH_ACCEPTED_VERSION,
+            max_supported: PROTOCOL_VERSION,
+        }));
+    }
+
+    let answer = client
+        .fetch_accepted(
+            timeout::context_until(deadline),
+            credentials.sign("fetch_accepted", instance),
+            instance.clone(),
+        )
+        .await?;
+    Ok(open(
+        credentials,
+        version,
+        "fetch_accepted",
+        instance,
+        answer,
+    ))
+}
+
+/// The values decided from an instance on that [Paxos::fetch_state] asks the
+/// other acceptors for, gathered without holding the node.
+pub struct StateFetch {
+    from: InstanceId,
+    acceptors: Vec<SocketAddr>,
+    /// How long each page may take, connecting included.
+    rpc_timeout: Duration,
+    connections: Connections,
+    credentials: Credentials,
+    metrics: Arc<dyn Recorder>,
+}
+
+impl StateFetch {
+    /// Pages through every acceptor at once, each from `from` on, and returns
+    /// the values of the acceptor that knows the most, in order.
+    pub async fn run(self) -> Vec<DecidedInstance> {
+        let mut futures: FuturesUnordered<_> = self
+            .acceptors
+            .iter()
+            .map(|acceptor| self.transfer(*acceptor))
+            .collect();
+
+        let mut decided: Vec<DecidedInstance> = Vec::new();
+        while let Some(transferred) = futures.next().await {
+            if transferred.len() > decided.len() {
+                decided = transferred;
+            }
+        }
+
+        self.metrics
+            .increment("paxos_transferred_instances_total", decided.len() as u64);
+
+        decided
+    }
+
+    /// The values `acceptor` knows decided from `from` on, up to the first
+    /// page that fails.
+    async fn transfer(&self, acceptor: SocketAddr) -> Vec<DecidedInstance> {
+        let mut decided = Vec::new();
+        let mut from = self.from.clone();
+        let mut next = Some(from.clone());
+
+        loop {
+            let deadline = Instant::now() + self.rpc_timeout;
+            let answer = tokio::time::timeout_at(deadline, async {
+                let (client, version) = self
+                    .connections
+                    .peer(acceptor, deadline)
+                    .await
+                    .map_err(connect_error)?;
+                client
+                    .fetch_state(
+                        timeout::context_until(deadline),
+                        self.credentials.sign("fetch_state", &from),
+                        from.clone(),
+                    )
+                    .await
+                    .map(|answer| open(&self.credentials, version, "fetch_state", &from, answer))
+            })
+            .await;
+
+            let transfer = match answer {
+                Ok(Ok(Ok(transfer))) => transfer,
+                Err(_) => {
+                    warn!(%acceptor, "fetch state request timed out");
+                    break;
+                }
+                Ok(Ok(Err(err))) => {
+                    warn!(%acceptor, %err, "error response to fetch state request");
+                    break;
+                }
+                Ok(Err(err)) => {
+                    warn!(%acceptor, ?err, "rpc error");
+                    self.connections
+                        .evict_if_disconnected(acceptor, &err, self.metrics.as_ref());
+                    break;
+                }
+            };
+
+            let more = transfer.more && !transfer.decided.is_empty();
+            for value in transfer.decided {
+                if next.as_ref() != Some(&value.instance) {
+                    warn!(%acceptor, instance = %value.instance, "acceptor transferred an instance out of order");
+                    break;
+                }
+                next = value.instance.successor();
+                decided.push(value);
+            }
+
+            match &next {
+                Some(instance) if more && *instance != from => from = instance.clone(),
+                _ => break,
+            }
+        }
+
+        decided
+    }
+}
+
+/// The value of an acceptor's sealed answer to `request`, see [Signed]. An
+/// answer that doesn't check out counts as no answer. Acceptors that speak a
+/// `version` before [SEALED_ANSWERS_VERSION] answer unsealed, their answers
+/// are taken as they are unless a message key is configured. The handshake
+/// isn't authenticated, so anyone on the path could have settled on such a
+/// version to forge answers.
+pub(crate) fn open<Req: Serialize, T: Serialize>(
+    credentials: &Credentials,
+    version: u32,
+    kind: &str,
+    request: &Req,
+    answer: Result<Signed<T>, AcceptorError>,
+) -> Result<T, AcceptorError> {
+    let answer = answer?;
+    if version < SEALED_ANSWERS_VERSION {
+        if credentials.message_key.is_some() {
+            return Err(AcceptorError::Unreachable {
+                reason: format!(
+                    "the connection speaks protocol version {version}, which doesn't seal \
+                     answers, and a message key is configured"
+                ),
+            });
+        }
+        return Ok(answer.value);
+    }
+
+    credentials
+        .open(kind, request, answer)
+        .map_err(|err| AcceptorError::Unreachable {
+            reason: format!("{err:#}"),
+        })
+}
+
+/// Reports a failed connection like a request that could not be sent.
+fn connect_error(err: anyhow::Error) -> RpcError {
+    RpcError::Send(err.into())
+}
+
+/// The clients of every instance built from one [PaxosBuilder]. A node
+/// keeps one connection per acceptor however many instances it runs, and
+/// acceptors limit the connections they accept from one peer.
+#[derive(Debug, Clone, Default)]
+struct ClientPool {
+    inner: Arc<Pool>,
+}
+
+#[derive(Debug, Default)]
+struct Pool {
+    /// The client of each acceptor and the protocol version its connection speaks.
+    clients: StdMutex<HashMap<SocketAddr, (AcceptorServiceClient, u32)>>,
+
+    /// The acceptors keepalives found gone, until they answer again.
+    down: StdMutex<HashSet<SocketAddr>>,
+
+    /// Set once the keepalives of the pool run.
+    keepalive: AtomicBool,
+}
+
+impl ClientPool {
+    fn get(&self, acceptor: SocketAddr) -> Option<(AcceptorServiceClient, u32)> {
+        self.inner.clients.lock().unwrap().get(&acceptor).cloned()
+    }
+
+    /// Adds `client`, whose connection speaks `version`, unless the pool has
+    /// one for `acceptor` already and returns the one kept.
+    fn insert(
+        &self,
+        acceptor: SocketAddr,
+        client: AcceptorServiceClient,
+        version: u32,
+    ) -> (AcceptorServiceClient, u32) {
+        self.inner.down.lock().unwrap().remove(&acceptor);
+        self.inner
+            .clients
+            .lock()
+            .unwrap()
+            .entry(acceptor)
+            .or_insert((client, version))
+            .clone()
+    }
+
+    /// Drops the client of `acceptor`, returning whether there was one.
+    fn remove(&self, acceptor: SocketAddr) -> bool {
+        self.inner
+            .clients
+            .lock()
+            .unwrap()
+            .remove(&acceptor)
+            .is_some()
+    }
+
+    /// Whether keepalives found `acceptor` gone.
+    fn is_down(&self, acceptor: SocketAddr) -> bool {
+        self.inner.down.lock().unwrap().contains(&acceptor)
+    }
+
+    /// Starts pinging the connections of the pool, unless it already does.
+    /// The pings stop once no node uses the pool anymore.
+    fn keepalive(
+        &self,
+        config: KeepaliveConfig,
+        connector: Connector,
+        acceptors: Vec<SocketAddr>,
+        metrics: Arc<dyn Recorder>,
+    ) {
+        if self.inner.keepalive.swap(true, Ordering::SeqCst) {
+            return;
+        }
+
+        let pool = Arc::downgrade(&self.inner);
+        tokio::spawn(async move {
+            let mut misses: HashMap<SocketAddr, u32> = HashMap::new();
+            let mut interval = tokio::time::interval(config.interval);
+
+            loop {
+                interval.tick().await;
+                let Some(inner) = pool.upgrade() else {
+                    return;
+                };
+                let pool = ClientPool { inner };
+
+                let pings = acceptors.iter().map(|acceptor| {
+                    let (pool, connector) = (pool.clone(), connector.clone());
+                    async move { (*acceptor, pool.ping(*acceptor, &connector, config).await) }
+                });
+
+                for (acceptor, alive) in futures::future::join_all(pings).await {
+                    match alive {
+                        None => {}
+                        Some(true) => {
+                            let missed = misses.remove(&acceptor).unwrap_or_default();
+                            if missed >= config.max_misses {
+                                metrics.increment("paxos_keepalive_reconnects_total", 1);
+                            }
+                        }
+                        Some(false) => {
+                            let missed = misses.entry(acceptor).or_default();
+                            *missed += 1;
+                            metrics.increment("paxos_keepalive_misses_total", 1);
+                            if *missed == config.max_misses {
+                                warn!(%acceptor, missed, "acceptor stopped answering keepalives, skipping it");
+                                metrics.increment("paxos_keepalive_dead_acceptors_total", 1);
+                                pool.remove(acceptor);
+                                pool.inner.down.lock().unwrap().insert(acceptor);
+                            }
+                        }
+                    }
+                }
+            }
+        });
+    }
+
+    /// Pings the connection to `acceptor`, or reconnects to it when
+    /// keepalives found it gone. Returns whether it answered, None when
+    /// there is no connection to ping.
+    async fn ping(
+        &self,
+        acceptor: SocketAddr,
+        connector: &Connector,
+        config: KeepaliveConfig,
+    ) -> Option<bool> {
+        let deadline = Instant::now() + config.timeout;
+
+        if self.is_down(acceptor) {
+            let client =
+                tokio::time::timeout_at(deadline, connect_until(connector, acceptor, deadline))
+                    .await;
+            return match client {
+                Ok(Ok((client, version))) => {
+                    info!(%acceptor, "acceptor answers again, reconnected");
+                    self.insert(acceptor, client, version);
+                    Some(true)
+                }
+                _ => None,
+            };
+        }
+
+        let (client, version) = self.get(acceptor)?;
+        let hello = Hello {
+            protocol_version: version,
+        };
+        let response = tokio::time::timeout_at(
+            deadline,
+            client.hello(timeout::context_until(deadline), hello),
+        )
+        .await;
+        match response {
+            Ok(Ok(_)) => Some(true),
+            Ok(Err(err)) => {
+                debug!(%acceptor, ?err, "keepalive failed");
+                Some(false)
+            }
+            Err(_) => {
+                debug!(%acceptor, "keepalive timed out");
+                Some(false)
+            }
+        }
+    }
+}
+
+/// Opens an rpc client to the acceptor at `addr`, giving up after [CONNECT_TIMEOUT].
+pub async fn connect(connector: &Connector, addr: SocketAddr) -> Result<AcceptorServiceClient> {
+    let (client, _) = connect_negotiated(connector, addr).await?;
+    Ok(client)
+}
+
+/// Like [connect], also returning the protocol version the connection speaks.
+pub(crate) async fn connect_negotiated(
+    connector: &Connector,
+    addr: SocketAddr,
+) -> Result<(AcceptorServiceClient, u32)> {
+    let deadline = Instant::now() + CONNECT_TIMEOUT;
+    tokio::time::timeout_at(deadline, connect_until(connector, addr, deadline))
+        .await
+        .map_err(|_| anyhow!("connecting to acceptor {addr} timed out"))?
+}
+
+/// Like [connect_negotiated], with the handshake answered by `deadline`.
+/// Callers bound the connection itself.
+async fn connect_until(
+    connector: &Connector,
+    addr: SocketAddr,
+    deadline: Instant,
+) -> Result<(AcceptorServiceClient, u32)> {
+    let client = if let Some(network) = connector.channel_network() {
+        let transport = network.connect(addr)?;
+        spawn_client(connector, addr, transport)
+    } else {
+        let connection = connector
+            .connect(addr)
+            .await
+            .context("initializing transport")?;
+        spawn_client(connector, addr, transport::framed(connection))
+    };
+
+    let version = handshake(&client, addr, deadline).await?;
+
+    Ok((client, version))
+}
+
+/// Agrees with the acceptor at `addr` on a protocol version both understand
+/// and returns it: this build's, or the acceptor's when it runs an older
+/// build this one still talks to.
+async fn handshake(
+    client: &AcceptorServiceClient,
+    addr: SocketAddr,
+    deadline: Instant,
+) -> Result<u32> {
+    let mut hello = Hello::current();
+
+    loop {
+        // Acceptors from before the handshake existed can't decode the request
+        // and drop the connection.
+        let response = client
+            .hello(timeout::context_until(deadline), hello.clone())
+            .await
+            .with_context(|| {
+                format!(
+                    "acceptor {addr} did not answer the handshake, it may run a build from \
+                     before protocol versions were introduced"
+                )
+            })?;
+
+        match response {
+            Ok(response) => {
+                let version = hello.protocol_version.min(response.protocol_version);
+                if version < MIN_PROTOCOL_VERSION {
+                    return Err(anyhow!(
+                        "acceptor {addr} speaks protocol version {version}, expected at least \
+                         {MIN_PROTOCOL_VERSION}"
+                    ));
+                }
+                return Ok(version);
+            }
+            // Older builds refuse versions above their own, the hello is sent
+            // again in the newest version the acceptor knows.
+            Err(AcceptorError::UnsupportedVersion { max_supported, .. })
+                if (MIN_PROTOCOL_VERSION..hello.protocol_version).contains(&max_supported) =>
+            {
+                debug!(%addr, version = max_supported, "acceptor runs an older build, downgrading");
+                hello.protocol_version = max_supported;
+            }
+            Err(err) => return Err(anyhow!("acceptor {addr} refused the handshake: {err}")),
+        }
+    }
+}
+
+fn spawn_client<T, E>(
+    connector: &Connector,
+    addr: SocketAddr,
+    transport: T,
+) -> AcceptorServiceClient
+where
+    T: futures::Stream<Item = Result<Response<AcceptorServiceResponse>, E>>
+        + futures::Sink<ClientMessage<AcceptorServiceRequest>, Error = E>
+        + Send
+        + 'static,
+    E: std::error::Error + Send + Sync + 'static,
+{
+    match connector.faults() {
+        None => AcceptorServiceClient::new(Config::default(), transport).spawn(),
+        Some(faults) => {
+            let transport = faults.wrap(addr, transport, changes_acceptor_state);
+            AcceptorServiceClient::new(Config::default(), transport).spawn()
+        }
+    }
+}
+
+/// Whether a request can change the state of the acceptor it is sent to.
+fn changes_acceptor_state(message: &ClientMessage<AcceptorServiceRequest>) -> bool {
+    matches!(
+        message,
+        ClientMessage::Request(Request {
+            message: AcceptorServiceRequest::Prepare { .. }
+                | AcceptorServiceRequest::Accept { .. }
+                | AcceptorServiceRequest::AcceptChunk { .. }
+                | AcceptorServiceRequest::RelayAccept { .. }
+                | AcceptorServiceRequest::RelayLearn { .. },
+            ..
+        })
+    )
+}
+
+/// Splits `targets` into at most `fanout` groups. The first acceptor of each group
+/// is contacted directly and relays to the rest of the group.
+fn partition(
+    targets: &[SocketAddr],
+    fanout: usize,
+) -> impl Iterator<Item = (SocketAddr, &[SocketAddr])> {
+    let chunk_size = std::cmp::max(1, targets.len().div_ceil(std::cmp::max(1, fanout)));
+
+    targets
+        .chunks(chunk_size)
+        .map(|chunk| (chunk[0], &chunk[1..]))
+}
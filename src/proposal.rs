@@ -0,0 +1,55 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Identifies a proposal. Acceptors order proposals by their ids, a proposal
+/// with a higher id preempts one with a lower id.
+///
+/// Serialized as a plain integer on the wire and as 8 little endian bytes in
+/// the state file.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(transparent)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct ProposalId(u64);
+
+impl ProposalId {
+    /// Lower than every id a proposer sends, what acceptors start from.
+    pub const ZERO: Self = Self(0);
+
+    /// The size of [ProposalId::to_bytes].
+    pub const ENCODED_LEN: usize = 8;
+
+    pub const fn new(id: u64) -> Self {
+        Self(id)
+    }
+
+    pub const fn get(self) -> u64 {
+        self.0
+    }
+
+    /// The lowest id above this one.
+    pub fn next(self) -> Self {
+        Self(self.0 + 1)
+    }
+
+    pub fn to_bytes(self) -> [u8; Self::ENCODED_LEN] {
+        self.0.to_le_bytes()
+    }
+
+    pub fn from_bytes(bytes: [u8; Self::ENCODED_LEN]) -> Self {
+        Self(u64::from_le_bytes(bytes))
+    }
+}
+
+impl From<u64> for ProposalId {
+    fn from(id: u64) -> Self {
+        Self(id)
+    }
+}
+
+impl fmt::Display for ProposalId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
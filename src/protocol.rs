@@ -0,0 +1,249 @@
+//! The decisions proposers and acceptors make, without any io, so they can be
+//! model checked on their own. See `tests/model.rs`.
+//!
+//! [Acceptor] answers requests and says what has to be written before an
+//! answer may be sent, [Promises] counts the answers to a prepare request.
+//! [crate::paxos::Paxos] does the writing and the sending.
+
+use crate::{
+    format::State,
+    paxos::{AcceptResponse, PrepareResponse, PromisePolicy},
+    proposal::ProposalId,
+};
+
+/// Whether an acceptor that has seen `highest` promises a prepare request for `proposal_id`.
+pub fn promises(policy: PromisePolicy, highest: ProposalId, proposal_id: ProposalId) -> bool {
+    match policy {
+        PromisePolicy::StrictlyGreater => proposal_id > highest,
+        PromisePolicy::GreaterOrEqual => proposal_id >= highest,
+    }
+}
+
+/// Whether an acceptor that has seen `highest` accepts an accept request for
+/// `proposal_id`. Only a promise for a higher proposal id makes it refuse.
+pub fn accepts(highest: ProposalId, proposal_id: ProposalId) -> bool {
+    proposal_id >= highest
+}
+
+/// The value a proposer must send in its accept requests given the proposal
+/// id and value each acceptor that promised had accepted: the one accepted in
+/// the highest proposal. None means the proposer is free to propose its own value.
+pub fn choose_value<V>(promises: impl IntoIterator<Item = Option<(ProposalId, V)>>) -> Option<V> {
+    promises
+        .into_iter()
+        .flatten()
+        .max_by_key(|(accepted_id, _)| *accepted_id)
+        .map(|(_, value)| value)
+}
+
+/// What an acceptor has to write before the answer to a request may be sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Persist {
+    /// The answer only repeats what is already on disk.
+    Nothing,
+    /// The promised proposal id.
+    Promise,
+    /// The promised proposal id and the accepted proposal.
+    State,
+}
+
+/// The answer to a request and what has to be on disk before it is sent.
+#[derive(Debug)]
+pub struct Step<R> {
+    pub response: R,
+    pub persist: Persist,
+}
+
+/// What an acceptor promised and accepted.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Acceptor {
+    pub policy: PromisePolicy,
+
+    /// Witnesses keep the ids of the proposals they accept, not the values.
+    pub witness: bool,
+
+    /// The highest proposal id the acceptor promised or accepted.
+    pub promised: ProposalId,
+
+    /// The id of the proposal `accepted_value` was accepted in.
+    pub accepted_id: ProposalId,
+
+    /// The value of the last proposal the acceptor accepted.
+    pub accepted_value: Option<Vec<u8>>,
+}
+
+impl Acceptor {
+    /// An acceptor that restarted from `state`, None when it never wrote one.
+    pub fn restore(policy: PromisePolicy, witness: bool, state: Option<State>) -> Self {
+        let Some(state) = state else {
+            return Self {
+                policy,
+                witness,
+                ..Default::default()
+            };
+        };
+
+        Self {
+            policy,
+            witness,
+            promised: state.proposal_id,
+            accepted_id: state.accepted_id,
+            // A witness keeps nothing of a value it stored before it was one.
+            accepted_value: if witness { None } else { state.proposal_value },
+        }
+    }
+
+    /// What [Persist::State] writes, with the value unsealed. A witness
+    /// writes the id of the proposal it accepted alone.
+    pub fn state(&self) -> State {
+        let proposal_value = if self.witness && self.accepted_id > ProposalId::ZERO {
+            Some(Vec::new())
+        } else {
+            self.accepted_value.clone()
+        };
+
+        State {
+            proposal_id: self.promised,
+            accepted_id: self.accepted_id,
+            proposal_value,
+        }
+    }
+
+    pub fn on_prepare(&mut self, proposal_id: ProposalId) -> Step<PrepareResponse> {
+        let promised = promises(self.policy, self.promised, proposal_id);
+
+        // Re-promising an equal id does not need to touch the disk, the promise is already durable.
+        let persist = if proposal_id > self.promised {
+            self.promised = proposal_id;
+            Persist::Promise
+        } else {
+            Persist::Nothing
+        };
+
+        Step {
+            response: PrepareResponse {
+                proposal_id: self.promised,
+                accepted_id: self.accepted_id,
+                proposal_value: self.accepted_value.clone(),
+                promised,
+                witness: self.witness,
+            },
+            persist,
+        }
+    }
+
+    /// The answer to an accept request for `proposal_id`, or to any chunk of
+    /// its value, when the acceptor refuses it.
+    pub fn rejects(&self, proposal_id: ProposalId) -> Option<AcceptResponse> {
+        (!accepts(self.promised, proposal_id)).then(|| AcceptResponse {
+            proposal_id: self.promised,
+            proposal_value: self.accepted_value.clone(),
+        })
+    }
+
+    pub fn on_accept(&mut self, proposal_id: ProposalId, value: Vec<u8>) -> Step<AcceptResponse> {
+        if let Some(response) = self.rejects(proposal_id) {
+            return Step {
+                response,
+                persist: Persist::Nothing,
+            };
+        }
+
+        self.promised = proposal_id;
+        self.accepted_id = proposal_id;
+        self.accepted_value = (!self.witness).then_some(value);
+
+        Step {
+            response: AcceptResponse {
+                proposal_id: self.promised,
+                proposal_value: None,
+            },
+            persist: Persist::State,
+        }
+    }
+
+    /// Takes on a value decided in `proposal_id`. It is reported as accepted
+    /// in the highest proposal seen so proposers adopt it over anything else
+    /// they are told about.
+    pub fn learn(&mut self, proposal_id: ProposalId, value: Vec<u8>) -> Persist {
+        self.promised = std::cmp::max(self.promised, proposal_id);
+        self.accepted_id = self.promised;
+        self.accepted_value = Some(value);
+        Persist::State
+    }
+
+    /// Forgets everything promised and accepted.
+    pub fn reset(&mut self) {
+        self.promised = ProposalId::ZERO;
+        self.accepted_id = ProposalId::ZERO;
+        self.accepted_value = None;
+    }
+}
+
+/// The answers to a prepare request counted so far.
+#[derive(Debug, Default)]
+pub struct Promises {
+    /// How many acceptors promised.
+    pub count: usize,
+
+    /// The highest proposal id any acceptor reported.
+    pub highest_proposal_id: ProposalId,
+
+    /// The highest proposal id reported by an acceptor that didn't promise.
+    pub highest_rejection: Option<ProposalId>,
+
+    /// The proposal id and value each acceptor that promised had accepted.
+    pub accepted_values: Vec<Option<(ProposalId, Vec<u8>)>>,
+
+    /// The highest proposal id a witness that promised had accepted.
+    pub witness_accepted_id: ProposalId,
+}
+
+impl Promises {
+    /// Counts an acceptor's answer, returns whether it promised.
+    pub fn count(&mut self, response: PrepareResponse) -> bool {
+        self.highest_proposal_id = std::cmp::max(self.highest_proposal_id, response.proposal_id);
+
+        if !response.promised {
+            self.highest_rejection = self.highest_rejection.max(Some(response.proposal_id));
+            return false;
+        }
+
+        self.count += 1;
+        if response.witness {
+            self.witness_accepted_id =
+                std::cmp::max(self.witness_accepted_id, response.accepted_id);
+        }
+        self.accepted_values.push(
+            response
+                .proposal_value
+                .map(|value| (response.accepted_id, value)),
+        );
+        true
+    }
+
+    /// Whether a witness accepted a proposal newer than any value reported.
+    /// Choosing among the reported values would then ignore one that may
+    /// have been chosen.
+    pub fn missing_value(&self) -> bool {
+        let highest = self
+            .accepted_values
+            .iter()
+            .flatten()
+            .map(|(accepted_id, _)| *accepted_id)
+            .max()
+            .unwrap_or(ProposalId::ZERO);
+        self.witness_accepted_id > highest
+    }
+
+    /// Whether `quorum` acceptors promised and their answers are enough to
+    /// pick the value to send, the phase is over then.
+    pub fn complete(&self, quorum: usize) -> bool {
+        self.count >= quorum && !self.missing_value()
+    }
+
+    /// The value to send in the accept requests, see [choose_value].
+    pub fn value(self) -> Option<Vec<u8>> {
+        choose_value(self.accepted_values)
+    }
+}
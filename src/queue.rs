@@ -0,0 +1,663 @@
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeSet, VecDeque},
+    net::SocketAddr,
+    sync::{Arc, Mutex as StdMutex},
+    time::Duration,
+};
+use tokio::{
+    sync::{mpsc, oneshot, Mutex, OwnedSemaphorePermit, Semaphore},
+    time::{Instant, MissedTickBehavior},
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, info_span, warn, Instrument, Span};
+
+use crate::{
+    backpressure::Overloaded,
+    instance::InstanceId,
+    instances::Instances,
+    learner::Learner,
+    paxos::{propose_shared, Decided, Paxos},
+};
+
+/// How many high priority proposals run in a row while normal ones are waiting.
+const MAX_HIGH_PRIORITY_STREAK: usize = 8;
+
+/// The batch decided in a slot its owner had nothing to propose in.
+const SKIP: &[u8] = b"[]";
+
+/// How often a node with rotating slots looks for batches the others decided.
+const FOLLOW_INTERVAL: Duration = Duration::from_millis(20);
+
+/// How many turns of every node past the learner are looked at.
+const FOLLOW_TURNS: u64 = 4;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum Priority {
+    #[default]
+    Normal,
+    High,
+}
+
+impl std::str::FromStr for Priority {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "normal" => Ok(Priority::Normal),
+            "high" => Ok(Priority::High),
+            _ => Err(anyhow!("unknown priority {s:?}, expected normal or high")),
+        }
+    }
+}
+
+/// How the queue groups values into batches. Each batch is decided as one
+/// json encoded list of values in an instance of its own.
+#[derive(Debug, Clone, Copy)]
+pub struct BatchConfig {
+    /// The most values decided in one round.
+    pub max_size: usize,
+
+    /// How long the first value of a batch waits for others to join it.
+    pub linger: Duration,
+
+    /// How many batches can be in flight at once, each in its own instance.
+    pub pipeline: usize,
+
+    /// The slots this node proposes in when nodes take turns. Every node
+    /// proposes in any slot when None.
+    pub rotation: Option<Rotation>,
+}
+
+impl Default for BatchConfig {
+    fn default() -> Self {
+        Self {
+            max_size: 64,
+            linger: Duration::from_millis(5),
+            pipeline: 1,
+            rotation: None,
+        }
+    }
+}
+
+impl BatchConfig {
+    /// Reads BATCH_MAX_SIZE, BATCH_LINGER_MS and PIPELINE_WINDOW. Returns None
+    /// when batching isn't enabled, that is when none of them is set.
+    pub fn from_env() -> Result<Option<Self>> {
+        let max_size = std::env::var("BATCH_MAX_SIZE").ok();
+        let linger = std::env::var("BATCH_LINGER_MS").ok();
+        let pipeline = std::env::var("PIPELINE_WINDOW").ok();
+        if max_size.is_none() && linger.is_none() && pipeline.is_none() {
+            return Ok(None);
+        }
+
+        let mut config = Self::default();
+
+        if let Some(value) = max_size {
+            config.max_size = value.parse().context("BATCH_MAX_SIZE must be an integer")?;
+            if config.max_size == 0 {
+                return Err(anyhow!("BATCH_MAX_SIZE must be at least 1"));
+            }
+        }
+
+        if let Some(value) = linger {
+            let millis: u64 = value
+                .parse()
+                .context("BATCH_LINGER_MS must be an integer")?;
+            config.linger = Duration::from_millis(millis);
+        }
+
+        if let Some(value) = pipeline {
+            config.pipeline = value
+                .parse()
+                .context("PIPELINE_WINDOW must be an integer")?;
+            if config.pipeline == 0 {
+                return Err(anyhow!("PIPELINE_WINDOW must be at least 1"));
+            }
+        }
+
+        Ok(Some(config))
+    }
+}
+
+/// Slots owned round-robin by the nodes, as in Mencius: slot `s` belongs to
+/// the node at position `s % nodes`. Every node proposes its clients' batches
+/// in its own slots, so clients far from the other nodes don't pay for a trip
+/// to a single leader. A node decides skips in its slots below any slot it
+/// sees decided, and slots of a node that stays silent are skipped for it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rotation {
+    /// The position of this node.
+    pub index: u64,
+
+    /// How many nodes take turns.
+    pub nodes: u64,
+
+    /// How long a slot holding up batches decided after it waits for its
+    /// owner before another node decides a skip in it.
+    pub revoke_after: Duration,
+}
+
+impl Rotation {
+    pub fn new(index: u64, nodes: u64) -> Self {
+        assert!(
+            index < nodes,
+            "node {index} is not one of the {nodes} nodes"
+        );
+        Self {
+            index,
+            nodes,
+            revoke_after: Duration::from_secs(1),
+        }
+    }
+
+    pub fn revoke_after(mut self, revoke_after: Duration) -> Self {
+        self.revoke_after = revoke_after;
+        self
+    }
+
+    /// Whether this node proposes in `slot`.
+    pub fn owns(&self, slot: u64) -> bool {
+        slot % self.nodes == self.index
+    }
+
+    /// Reads ROTATE_SLOTS and SLOT_REVOKE_AFTER_MS. Returns None unless
+    /// ROTATE_SLOTS is true. `address` takes the turn of its position in
+    /// `acceptors`, so every node must list the acceptors in the same order.
+    pub fn from_env(address: SocketAddr, acceptors: &[SocketAddr]) -> Result<Option<Self>> {
+        let Ok(rotate) = std::env::var("ROTATE_SLOTS") else {
+            return Ok(None);
+        };
+        let rotate: bool = rotate
+            .parse()
+            .context("ROTATE_SLOTS must be true or false")?;
+        if !rotate {
+            return Ok(None);
+        }
+
+        let index = acceptors
+            .iter()
+            .position(|acceptor| *acceptor == address)
+            .ok_or_else(|| anyhow!("{address} is not one of the acceptors"))?;
+        let mut rotation = Self::new(index as u64, acceptors.len() as u64);
+
+        if let Ok(value) = std::env::var("SLOT_REVOKE_AFTER_MS") {
+            let millis: u64 = value
+                .parse()
+                .context("SLOT_REVOKE_AFTER_MS must be an integer")?;
+            rotation.revoke_after = Duration::from_millis(millis);
+        }
+
+        Ok(Some(rotation))
+    }
+}
+
+#[derive(Debug)]
+struct Job {
+    value: Vec<u8>,
+    respond: oneshot::Sender<Result<Decided>>,
+
+    /// The span the proposal was submitted in, so the round shows up in the
+    /// trace of the request that asked for it.
+    span: Span,
+
+    /// Held until the proposal is done when pending proposals are capped.
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+/// Runs the proposals a node receives one at a time, high priority ones first.
+/// Normal proposals still get a turn after a bounded number of high priority
+/// ones so a steady stream of control commands can't starve bulk traffic.
+#[derive(Debug, Clone)]
+pub struct ProposalQueue {
+    sender: mpsc::UnboundedSender<(Priority, Job)>,
+
+    /// A permit per proposal allowed to be pending, with their number.
+    pending: Option<(Arc<Semaphore>, usize)>,
+}
+
+impl ProposalQueue {
+    /// Proposes every value on its own to `paxos`.
+    pub fn spawn(paxos: Arc<Mutex<Paxos>>) -> Self {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        tokio::spawn(run(paxos, receiver));
+        Self {
+            sender,
+            pending: None,
+        }
+    }
+
+    /// Proposes values in batches, one per child of `instance`: `instance/0`,
+    /// `instance/1` and so on. A batch that loses its instance to another
+    /// node's batch is proposed again in the next one. Up to `config.pipeline`
+    /// batches are in flight at once, `learner` applies them in slot order.
+    /// With `config.rotation` batches only go in this node's slots.
+    pub fn spawn_batched(
+        instances: Instances,
+        instance: InstanceId,
+        config: BatchConfig,
+        learner: Learner,
+    ) -> Self {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let batcher = Batcher {
+            instances,
+            instance,
+            slots: Arc::new(StdMutex::new(Slots::new(config.rotation))),
+            learner: Arc::new(StdMutex::new(learner)),
+        };
+        tokio::spawn(run_batched(batcher, config, receiver));
+        Self {
+            sender,
+            pending: None,
+        }
+    }
+
+    /// Turns proposals away with [Overloaded] while `max` are waiting or
+    /// being proposed, instead of queueing them without bound.
+    pub fn max_pending(mut self, max: usize) -> Self {
+        self.pending = Some((Arc::new(Semaphore::new(max)), max));
+        self
+    }
+
+    /// Queues `value` and waits for the outcome of its proposal.
+    pub async fn propose(&self, value: Vec<u8>, priority: Priority) -> Result<Decided> {
+        let permit = match &self.pending {
+            None => None,
+            Some((semaphore, limit)) => match Arc::clone(semaphore).try_acquire_owned() {
+                Ok(permit) => Some(permit),
+                Err(_) => return Err(Overloaded { limit: *limit }.into()),
+            },
+        };
+
+        let (respond, response) = oneshot::channel();
+
+        self.sender
+            .send((
+                priority,
+                Job {
+                    value,
+                    respond,
+                    span: Span::current(),
+                    _permit: permit,
+                },
+            ))
+            .map_err(|_| anyhow!("proposal queue is closed"))?;
+
+        response
+            .await
+            .map_err(|_| anyhow!("proposal queue dropped the proposal"))?
+    }
+}
+
+/// The jobs waiting to be proposed, by priority.
+#[derive(Default)]
+struct Pending {
+    high: VecDeque<Job>,
+    normal: VecDeque<Job>,
+    high_streak: usize,
+}
+
+impl Pending {
+    fn is_empty(&self) -> bool {
+        self.high.is_empty() && self.normal.is_empty()
+    }
+
+    fn push(&mut self, priority: Priority, job: Job) {
+        match priority {
+            Priority::High => self.high.push_back(job),
+            Priority::Normal => self.normal.push_back(job),
+        }
+    }
+
+    /// Moves the jobs already sent into the queue. Returns false once every
+    /// sender is gone and nothing is pending.
+    async fn fill(&mut self, receiver: &mut mpsc::UnboundedReceiver<(Priority, Job)>) -> bool {
+        if self.is_empty() {
+            match receiver.recv().await {
+                None => return false,
+                Some((priority, job)) => self.push(priority, job),
+            }
+        }
+
+        while let Ok((priority, job)) = receiver.try_recv() {
+            self.push(priority, job);
+        }
+
+        true
+    }
+
+    fn pop(&mut self) -> Option<Job> {
+        if !self.high.is_empty()
+            && (self.normal.is_empty() || self.high_streak < MAX_HIGH_PRIORITY_STREAK)
+        {
+            self.high_streak += 1;
+            self.high.pop_front()
+        } else {
+            self.high_streak = 0;
+            self.normal.pop_front()
+        }
+    }
+}
+
+async fn run(paxos: Arc<Mutex<Paxos>>, mut receiver: mpsc::UnboundedReceiver<(Priority, Job)>) {
+    let mut pending = Pending::default();
+
+    while pending.fill(&mut receiver).await {
+        let Some(job) = pending.pop() else {
+            continue;
+        };
+
+        let result = propose_shared(&paxos, job.value, &CancellationToken::new())
+            .instrument(job.span)
+            .await;
+
+        // The caller may have given up waiting.
+        let _ = job.respond.send(result);
+    }
+}
+
+/// Hands out the instances batches are proposed in.
+#[derive(Debug)]
+struct Slots {
+    /// The first slot no batch was proposed in yet.
+    next: u64,
+
+    /// How far apart the slots of this node are.
+    step: u64,
+
+    /// Slots whose batch failed without anything being decided in them. They
+    /// are handed out again first so the learner isn't left waiting on them.
+    holes: BTreeSet<u64>,
+}
+
+impl Slots {
+    fn new(rotation: Option<Rotation>) -> Self {
+        let (next, step) = match rotation {
+            None => (0, 1),
+            Some(rotation) => (rotation.index, rotation.nodes),
+        };
+        Self {
+            next,
+            step,
+            holes: BTreeSet::new(),
+        }
+    }
+
+    fn claim(&mut self) -> u64 {
+        self.holes.pop_first().unwrap_or_else(|| {
+            let slot = self.next;
+            self.next += self.step;
+            slot
+        })
+    }
+
+    /// Claims every slot below `limit` no batch is being proposed in.
+    fn claim_below(&mut self, limit: u64) -> Vec<u64> {
+        let above = self.holes.split_off(&limit);
+        let mut slots: Vec<u64> = std::mem::replace(&mut self.holes, above)
+            .into_iter()
+            .collect();
+
+        while self.next < limit {
+            slots.push(self.next);
+            self.next += self.step;
+        }
+
+        slots
+    }
+
+    fn release(&mut self, slot: u64) {
+        self.holes.insert(slot);
+    }
+
+    /// Moves past the slots below `slot`, they were decided already.
+    fn advance(&mut self, slot: u64) {
+        self.holes = self.holes.split_off(&slot);
+        while self.next < slot {
+            self.next += self.step;
+        }
+    }
+}
+
+/// What every batch being proposed shares.
+#[derive(Clone)]
+struct Batcher {
+    instances: Instances,
+    instance: InstanceId,
+    slots: Arc<StdMutex<Slots>>,
+    learner: Arc<StdMutex<Learner>>,
+}
+
+async fn run_batched(
+    batcher: Batcher,
+    config: BatchConfig,
+    mut receiver: mpsc::UnboundedReceiver<(Priority, Job)>,
+) {
+    let mut pending = Pending::default();
+    let window = Arc::new(Semaphore::new(config.pipeline));
+    // Batches decided while the node was down are applied before new ones.
+    batcher.recover().await;
+
+    let follower = config
+        .rotation
+        .map(|rotation| tokio::spawn(batcher.clone().follow(rotation)));
+
+    while pending.fill(&mut receiver).await {
+        // Waits while the window is full, values keep queueing meanwhile.
+        let Ok(permit) = Arc::clone(&window).acquire_owned().await else {
+            break;
+        };
+
+        let mut batch: Vec<Job> = Vec::with_capacity(config.max_size);
+        let deadline = Instant::now() + config.linger;
+
+        while batch.len() < config.max_size {
+            if let Some(job) = pending.pop() {
+                batch.push(job);
+                continue;
+            }
+
+            match tokio::time::timeout_at(deadline, receiver.recv()).await {
+                Ok(Some((priority, job))) => pending.push(priority, job),
+                Ok(None) | Err(_) => break,
+            }
+        }
+
+        if batch.is_empty() {
+            continue;
+        }
+
+        let span = info_span!("batch", size = batch.len());
+        for job in &batch {
+            span.follows_from(&job.span);
+        }
+
+        let batcher = batcher.clone();
+        tokio::spawn(async move {
+            let values: Vec<&[u8]> = batch.iter().map(|job| job.value.as_slice()).collect();
+            let result = match serde_json::to_vec(&values) {
+                Err(err) => Err(anyhow::Error::from(err).context("encoding batch")),
+                Ok(encoded) => batcher.propose(encoded).instrument(span).await,
+            };
+            drop(permit);
+
+            for job in batch {
+                // Batches are proposed again until they are decided.
+                let result = match &result {
+                    Ok(()) => Ok(Decided::Ours(job.value)),
+                    Err(err) => Err(anyhow!("{err:#}")),
+                };
+
+                // The caller may have given up waiting.
+                let _ = job.respond.send(result);
+            }
+        });
+    }
+
+    if let Some(follower) = follower {
+        follower.abort();
+    }
+}
+
+impl Batcher {
+    /// Proposes `batch` in the lowest slot it can be decided in.
+    async fn propose(&self, batch: Vec<u8>) -> Result<()> {
+        loop {
+            let slot = self.slots.lock().unwrap().claim();
+            let instance = self.instance.child(&slot.to_string())?;
+
+            let decided = match self.instances.propose(&instance, batch.clone()).await {
+                Ok(decided) => decided,
+                Err(err) => {
+                    self.slots.lock().unwrap().release(slot);
+                    return Err(err);
+                }
+            };
+
+            let ours = decided.is_ours();
+            let decided = decided.into_value();
+            self.learner.lock().unwrap().learn(slot, decided)?;
+
+            if ours {
+                info!(%instance, "batch decided");
+                return Ok(());
+            }
+
+            warn!(%instance, "instance decided another batch, trying the next one");
+        }
+    }
+
+    /// Learns the batches decided while the node was down from the acceptors.
+    async fn recover(&self) {
+        let next = self.learner.lock().unwrap().next();
+        let result = async {
+            let from = self.instance.child(&next.to_string())?;
+            let transferred = self.instances.catch_up(&from).await?;
+
+            let mut learner = self.learner.lock().unwrap();
+            for (slot, decided) in (next..).zip(transferred) {
+                learner.learn(slot, decided.value)?;
+            }
+            self.slots.lock().unwrap().advance(learner.next());
+            anyhow::Ok(learner.next() - next)
+        };
+
+        match result.await {
+            Ok(0) => {}
+            Ok(batches) => info!(
+                batches,
+                "learned the batches decided while the node was down"
+            ),
+            Err(err) => warn!(?err, "learning the batches decided while the node was down"),
+        }
+    }
+
+    /// Decides an empty batch in `slot`, unless a batch was decided in it
+    /// already, and learns whichever was decided.
+    async fn skip(&self, slot: u64) -> Result<()> {
+        let instance = self.instance.child(&slot.to_string())?;
+        let decided = self.instances.propose(&instance, SKIP.to_vec()).await?;
+        if !decided.is_ours() {
+            info!(%instance, "slot was decided before it could be skipped");
+        }
+        self.learner
+            .lock()
+            .unwrap()
+            .learn(slot, decided.into_value())
+    }
+
+    /// Learns the batch decided in `slot` if an acceptor knows it. Unlike a
+    /// read this never runs a round, which would preempt the slot's owner.
+    async fn probe(&self, slot: u64) -> Result<bool> {
+        let instance = self.instance.child(&slot.to_string())?;
+        let node = self.instances.create(&instance).await?;
+        let decided = {
+            let mut node = node.lock().await;
+            node.heal().await?;
+            node.on_fetch_decided()
+        };
+
+        match decided {
+            None => Ok(false),
+            Some(batch) => {
+                self.learner.lock().unwrap().learn(slot, batch)?;
+                Ok(true)
+            }
+        }
+    }
+
+    /// Keeps up with the batches the other nodes decide in their slots and
+    /// skips the slots that would otherwise hold the learner up.
+    async fn follow(self, rotation: Rotation) {
+        let mut interval = tokio::time::interval(FOLLOW_INTERVAL);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        // The slot holding the learner up and since when.
+        let mut stalled = None;
+
+        loop {
+            interval.tick().await;
+            if let Err(err) = self.catch_up(&rotation, &mut stalled).await {
+                warn!(?err, "catching up with the other nodes' slots");
+            }
+        }
+    }
+
+    async fn catch_up(
+        &self,
+        rotation: &Rotation,
+        stalled: &mut Option<(u64, Instant)>,
+    ) -> Result<()> {
+        let next = self.learner.lock().unwrap().next();
+        let end = next + rotation.nodes * FOLLOW_TURNS;
+
+        // Nodes fill their slots in order, so the first slot of a node that
+        // isn't decided yet is as far as it got.
+        for owner in (0..rotation.nodes).filter(|owner| *owner != rotation.index) {
+            let first = next + (owner + rotation.nodes - next % rotation.nodes) % rotation.nodes;
+            for slot in (first..end).step_by(rotation.nodes as usize) {
+                let known = self.learner.lock().unwrap().is_decided(slot);
+                if !known && !self.probe(slot).await? {
+                    break;
+                }
+            }
+        }
+
+        // Batches decided after our unused slots would wait on them forever.
+        let frontier = self.learner.lock().unwrap().frontier();
+        let mut skips = self.slots.lock().unwrap().claim_below(frontier).into_iter();
+        while let Some(slot) = skips.next() {
+            if let Err(err) = self.skip(slot).await {
+                let mut slots = self.slots.lock().unwrap();
+                slots.release(slot);
+                skips.for_each(|slot| slots.release(slot));
+                return Err(err);
+            }
+        }
+
+        let (next, waiting) = {
+            let learner = self.learner.lock().unwrap();
+            (learner.next(), learner.is_waiting())
+        };
+        if rotation.owns(next) || !waiting {
+            *stalled = None;
+            return Ok(());
+        }
+
+        match *stalled {
+            Some((slot, since)) if slot == next => {
+                if since.elapsed() >= rotation.revoke_after {
+                    warn!(slot, "slot's owner is silent, skipping it");
+                    *stalled = None;
+                    self.skip(slot).await?;
+                }
+            }
+            _ => *stalled = Some((next, Instant::now())),
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,231 @@
+//! Nodes talking over QUIC instead of TCP. Every rpc connection is a stream
+//! of one QUIC connection per peer, so reconnecting after a stream breaks
+//! takes no handshake, and a connection survives the peer's address changing
+//! underneath it. QUIC always encrypts, so it needs the node's TLS config.
+
+use anyhow::{Context, Result};
+use futures::{future, StreamExt};
+use std::{
+    collections::HashMap,
+    io,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{self, Poll},
+};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio_rustls::rustls::Certificate;
+use tracing::{debug, warn};
+
+use crate::{
+    tls::TlsConfig,
+    transport::{BoxedConnection, Connection},
+};
+
+/// Peers refuse connections that don't ask for this protocol.
+const ALPN: &[u8] = b"paxos";
+
+/// One bidirectional stream of a QUIC connection.
+pub struct QuicStream {
+    connection: quinn::Connection,
+    send: quinn::SendStream,
+    recv: quinn::RecvStream,
+}
+
+impl Connection for QuicStream {
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        Ok(self.connection.remote_address())
+    }
+
+    fn peer_certificate(&self) -> Option<Certificate> {
+        self.connection
+            .peer_identity()
+            .and_then(|identity| identity.downcast::<Vec<Certificate>>().ok())
+            .and_then(|certs| certs.first().cloned())
+    }
+}
+
+impl AsyncRead for QuicStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.recv).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for QuicStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.send)
+            .poll_write(cx, buf)
+            .map_err(io::Error::from)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.send).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.send).poll_shutdown(cx)
+    }
+}
+
+/// Opens streams to other nodes, keeping one connection per peer open.
+#[derive(Clone)]
+pub struct QuicConnector {
+    config: quinn::ClientConfig,
+
+    /// The local endpoints, one per address family, created on first use.
+    endpoints: Arc<Mutex<HashMap<bool, quinn::Endpoint>>>,
+
+    connections: Arc<Mutex<HashMap<SocketAddr, quinn::Connection>>>,
+}
+
+impl std::fmt::Debug for QuicConnector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("QuicConnector")
+            .field("connections", &self.connections.lock().unwrap().len())
+            .finish()
+    }
+}
+
+impl QuicConnector {
+    pub fn new(tls: &TlsConfig) -> Result<Self> {
+        let mut crypto = tls.client_config()?;
+        crypto.alpn_protocols = vec![ALPN.to_vec()];
+
+        Ok(Self {
+            config: quinn::ClientConfig::new(Arc::new(crypto)),
+            endpoints: Arc::new(Mutex::new(HashMap::new())),
+            connections: Arc::new(Mutex::new(HashMap::new())),
+        })
+    }
+
+    /// Opens a stream to the node at `target`, which must present a
+    /// certificate for `server_name`.
+    pub async fn connect(&self, target: SocketAddr, server_name: &str) -> Result<BoxedConnection> {
+        let connection = self.connection(target, server_name).await?;
+
+        let (send, recv) = match connection.open_bi().await {
+            Ok(streams) => streams,
+            Err(err) => {
+                // The connection is gone, the next attempt opens a new one.
+                self.forget(target, &connection);
+                return Err(err).with_context(|| format!("opening stream to {target}"));
+            }
+        };
+
+        Ok(Box::new(QuicStream {
+            connection,
+            send,
+            recv,
+        }))
+    }
+
+    async fn connection(&self, target: SocketAddr, server_name: &str) -> Result<quinn::Connection> {
+        let open = self.connections.lock().unwrap().get(&target).cloned();
+        if let Some(connection) = open {
+            if connection.close_reason().is_none() {
+                return Ok(connection);
+            }
+            self.forget(target, &connection);
+        }
+
+        let connection = self
+            .endpoint(target)?
+            .connect(target, server_name)
+            .with_context(|| format!("connecting to {target}"))?
+            .await
+            .with_context(|| format!("connecting to {target}"))?;
+        debug!(%target, "opened quic connection");
+
+        self.connections
+            .lock()
+            .unwrap()
+            .insert(target, connection.clone());
+        Ok(connection)
+    }
+
+    fn forget(&self, target: SocketAddr, connection: &quinn::Connection) {
+        let mut connections = self.connections.lock().unwrap();
+        if matches!(connections.get(&target), Some(open) if open.stable_id() == connection.stable_id())
+        {
+            connections.remove(&target);
+        }
+    }
+
+    fn endpoint(&self, target: SocketAddr) -> Result<quinn::Endpoint> {
+        let mut endpoints = self.endpoints.lock().unwrap();
+        if let Some(endpoint) = endpoints.get(&target.is_ipv6()) {
+            return Ok(endpoint.clone());
+        }
+
+        let local = if target.is_ipv6() {
+            SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0))
+        } else {
+            SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0))
+        };
+        let mut endpoint = quinn::Endpoint::client(local).context("binding quic endpoint")?;
+        endpoint.set_default_client_config(self.config.clone());
+
+        endpoints.insert(target.is_ipv6(), endpoint.clone());
+        Ok(endpoint)
+    }
+}
+
+/// Accepts the streams peers open on `addr`. Client certificates are checked
+/// the same way as over TCP.
+pub async fn listen(
+    addr: SocketAddr,
+    tls: &TlsConfig,
+) -> Result<impl futures::Stream<Item = BoxedConnection>> {
+    let mut crypto = tls.server_config()?;
+    crypto.alpn_protocols = vec![ALPN.to_vec()];
+
+    let endpoint =
+        quinn::Endpoint::server(quinn::ServerConfig::with_crypto(Arc::new(crypto)), addr)
+            .with_context(|| format!("binding quic listener to {addr}"))?;
+
+    let connections = futures::stream::unfold(endpoint, |endpoint| async move {
+        let connecting = endpoint.accept().await?;
+        Some((connecting, endpoint))
+    })
+    .map(|connecting| async move {
+        let peer = connecting.remote_address();
+        match connecting.await {
+            Ok(connection) => Some(connection),
+            Err(err) => {
+                warn!(%peer, ?err, "quic handshake failed");
+                None
+            }
+        }
+    })
+    // Handshakes run concurrently so a slow peer does not block the others.
+    .buffer_unordered(16)
+    .filter_map(future::ready);
+
+    Ok(connections.flat_map_unordered(None, |connection| {
+        futures::stream::unfold(connection, |connection| async move {
+            match connection.accept_bi().await {
+                Ok((send, recv)) => {
+                    let stream: BoxedConnection = Box::new(QuicStream {
+                        connection: connection.clone(),
+                        send,
+                        recv,
+                    });
+                    Some((stream, connection))
+                }
+                Err(err) => {
+                    debug!(peer = %connection.remote_address(), ?err, "quic connection closed");
+                    None
+                }
+            }
+        })
+        .boxed()
+    }))
+}
@@ -0,0 +1,161 @@
+//! Token bucket limits on the protocol requests an acceptor handles. Every
+//! prepare and accept is synced to disk, a proposer that floods an acceptor
+//! with them would keep it busy syncing. Requests over the limit are answered
+//! with [crate::paxos::AcceptorError::Throttled] and the proposer backs off.
+
+use anyhow::{anyhow, Context, Result};
+use std::{
+    sync::{Arc, Mutex as StdMutex},
+    time::Duration,
+};
+use tokio::time::Instant;
+
+/// Up to `burst` requests at once, refilled at `per_second` requests a second.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    pub per_second: f64,
+    pub burst: u32,
+}
+
+impl RateLimit {
+    /// A limit of `per_second` requests a second that allows as many at once.
+    pub fn new(per_second: f64) -> Self {
+        Self {
+            per_second,
+            burst: per_second.ceil() as u32,
+        }
+    }
+
+    pub fn with_burst(mut self, burst: u32) -> Self {
+        self.burst = burst;
+        self
+    }
+
+    /// Reads the limit from `{prefix}_RATE_LIMIT` and `{prefix}_RATE_BURST`,
+    /// None when the first one is not set.
+    fn from_env(prefix: &str) -> Result<Option<Self>> {
+        let Ok(value) = std::env::var(format!("{prefix}_RATE_LIMIT")) else {
+            return Ok(None);
+        };
+
+        let per_second: f64 = value
+            .parse()
+            .with_context(|| format!("{prefix}_RATE_LIMIT must be a number"))?;
+        if !per_second.is_finite() || per_second <= 0.0 {
+            return Err(anyhow!("{prefix}_RATE_LIMIT must be positive"));
+        }
+
+        let mut limit = Self::new(per_second);
+
+        if let Ok(value) = std::env::var(format!("{prefix}_RATE_BURST")) {
+            limit.burst = value
+                .parse()
+                .with_context(|| format!("{prefix}_RATE_BURST must be an integer"))?;
+        }
+
+        Ok(Some(limit))
+    }
+}
+
+/// The limits an acceptor enforces. Requests are unlimited by default.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct RateLimits {
+    /// Shared by every connection.
+    pub global: Option<RateLimit>,
+
+    /// Applied to each connection on its own.
+    pub per_connection: Option<RateLimit>,
+}
+
+impl RateLimits {
+    /// Reads the global limit from ACCEPTOR_RATE_LIMIT and ACCEPTOR_RATE_BURST
+    /// and the limit of each connection from CONNECTION_RATE_LIMIT and
+    /// CONNECTION_RATE_BURST.
+    pub fn from_env() -> Result<Self> {
+        Ok(Self {
+            global: RateLimit::from_env("ACCEPTOR")?,
+            per_connection: RateLimit::from_env("CONNECTION")?,
+        })
+    }
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    limit: RateLimit,
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl TokenBucket {
+    fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            tokens: f64::from(limit.burst),
+            refilled_at: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.refilled_at).as_secs_f64();
+        self.tokens =
+            (self.tokens + elapsed * self.limit.per_second).min(f64::from(self.limit.burst));
+        self.refilled_at = now;
+    }
+
+    /// Takes a token, or returns how long until one is available.
+    fn take(&mut self) -> Result<(), Duration> {
+        self.refill();
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            return Ok(());
+        }
+
+        Err(Duration::from_secs_f64(
+            (1.0 - self.tokens) / self.limit.per_second,
+        ))
+    }
+}
+
+/// Checks requests against the global limit and the limit of the connection
+/// they came in on. Clones share their buckets.
+#[derive(Debug, Clone, Default)]
+pub struct RateLimiter {
+    per_connection: Option<RateLimit>,
+    global: Option<Arc<StdMutex<TokenBucket>>>,
+    connection: Option<Arc<StdMutex<TokenBucket>>>,
+}
+
+impl RateLimiter {
+    pub fn new(limits: RateLimits) -> Self {
+        let bucket = |limit| Arc::new(StdMutex::new(TokenBucket::new(limit)));
+        Self {
+            per_connection: limits.per_connection,
+            global: limits.global.map(bucket),
+            connection: limits.per_connection.map(bucket),
+        }
+    }
+
+    /// A limiter for a new connection. It shares the global bucket and starts
+    /// a bucket of its own.
+    pub fn for_connection(&self) -> Self {
+        Self {
+            per_connection: self.per_connection,
+            global: self.global.clone(),
+            connection: self
+                .per_connection
+                .map(|limit| Arc::new(StdMutex::new(TokenBucket::new(limit)))),
+        }
+    }
+
+    /// Lets a request through, or returns how long the caller should wait
+    /// before trying again. A request the connection's limit throttles does
+    /// not count against the global limit.
+    pub fn check(&self) -> Result<(), Duration> {
+        for bucket in [&self.connection, &self.global].into_iter().flatten() {
+            bucket.lock().unwrap().take()?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,102 @@
+//! A register that can be written more than once. Every write is decided in
+//! an instance of its own, `instance/0`, `instance/1` and so on, and the
+//! register holds the value of the last instance decided. A write only goes
+//! in the instance after the value it expects, so of two writers expecting
+//! the same value exactly one wins.
+
+use anyhow::Result;
+use tracing::debug;
+
+use crate::{instance::InstanceId, instances::Instances, paxos::Decided};
+
+/// How a compare-and-set ended.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CasOutcome {
+    /// The new value was chosen.
+    Swapped,
+
+    /// The register did not hold the expected value, or another writer got
+    /// its value chosen first. `current` is the value that was there instead.
+    Mismatch { current: Option<Vec<u8>> },
+}
+
+impl CasOutcome {
+    pub fn swapped(&self) -> bool {
+        matches!(self, CasOutcome::Swapped)
+    }
+}
+
+/// A compare-and-set register over the children of one instance. Nothing
+/// else should propose in them.
+#[derive(Debug)]
+pub struct Register {
+    instances: Instances,
+    instance: InstanceId,
+
+    /// The first slot not known to be decided.
+    next: u64,
+
+    /// The value decided in the slot before `next`.
+    current: Option<Vec<u8>>,
+}
+
+impl Register {
+    pub fn new(instances: Instances, instance: InstanceId) -> Self {
+        Self {
+            instances,
+            instance,
+            next: 0,
+            current: None,
+        }
+    }
+
+    /// Returns the value the register holds, None when it was never written.
+    pub async fn read(&mut self) -> Result<Option<Vec<u8>>> {
+        loop {
+            let node = self.instances.create(&self.slot(self.next)?).await?;
+            let decided = node.lock().await.read().await?;
+            match decided {
+                None => return Ok(self.current.clone()),
+                Some(value) => self.advance(value),
+            }
+        }
+    }
+
+    /// Writes `value` unless the register was already written.
+    pub async fn propose_if_unset(&mut self, value: Vec<u8>) -> Result<CasOutcome> {
+        self.cas(None, value).await
+    }
+
+    /// Writes `new` if the register holds `expected`, None meaning it was never
+    /// written. Values are compared by their bytes, so a writer that loses to
+    /// another one writing the same value is told it swapped.
+    pub async fn cas(&mut self, expected: Option<&[u8]>, new: Vec<u8>) -> Result<CasOutcome> {
+        let current = self.read().await?;
+        if current.as_deref() != expected {
+            return Ok(CasOutcome::Mismatch { current });
+        }
+
+        let slot = self.slot(self.next)?;
+        let decided = self.instances.propose(&slot, new).await?;
+        debug!(%slot, ours = decided.is_ours(), "register write decided");
+
+        let outcome = match &decided {
+            Decided::Ours(_) => CasOutcome::Swapped,
+            Decided::Other(value) => CasOutcome::Mismatch {
+                current: Some(value.clone()),
+            },
+        };
+        self.advance(decided.into_value());
+
+        Ok(outcome)
+    }
+
+    fn slot(&self, slot: u64) -> Result<InstanceId> {
+        self.instance.child(&slot.to_string())
+    }
+
+    fn advance(&mut self, value: Vec<u8>) {
+        self.next += 1;
+        self.current = Some(value);
+    }
+}
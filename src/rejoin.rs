@@ -0,0 +1,408 @@
+//! Keeps an acceptor that lost its disk from voting before it caught up. An
+//! acceptor that comes back with empty state would promise proposal ids it
+//! already promised before, or accept values below a promise it forgot,
+//! which can get two values chosen.
+//!
+//! Every node keeps an identity in its data dir: the cluster it belongs to and
+//! a generation, bumped every time the node comes back from a data loss. Nodes
+//! announce their generation to their peers, which remember it. A node that
+//! boots without an identity asks a quorum of its peers whether they remember
+//! it: if none does this is its first boot, otherwise it lost its data and
+//! rejoins as recovering. A recovering node only votes in an instance once it
+//! caught it up from its peers, see [crate::paxos::Paxos::rejoin].
+
+use anyhow::{anyhow, Context, Result};
+use ring::digest;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex as StdMutex},
+    time::Duration,
+};
+use tarpc::context;
+use tokio::{sync::Mutex, time::Instant};
+use tracing::{info, warn};
+
+use crate::{
+    auth::Credentials,
+    genesis::hex_encode,
+    instances::Instances,
+    paxos::{self, AcceptorError},
+    transport::Connector,
+};
+
+/// How long a booting node waits between two rounds of announcements.
+const ANNOUNCE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often a recovering node tries to rejoin the instances it has open.
+const REJOIN_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Who a node is, kept in `identity.toml` in its data dir.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Identity {
+    pub cluster: String,
+
+    /// Starts at 1 and goes up every time the node comes back from a data loss.
+    pub generation: u64,
+
+    /// Set when the node came back from a data loss. Instances it has no
+    /// state for may be ones it voted in before, it catches them up first.
+    #[serde(default)]
+    pub recovering: bool,
+}
+
+/// Sent by a node to its peers when it boots.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Announcement {
+    pub id: u32,
+    pub cluster: String,
+    /// None while the node doesn't know its generation yet, the peer only
+    /// answers then.
+    pub generation: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Announced {
+    /// The highest generation the peer had recorded for the node.
+    pub generation: Option<u64>,
+}
+
+/// Whether the node votes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    /// The node doesn't know yet whether it lost its data, it votes in nothing.
+    Booting,
+    /// The node lost its data, it only votes in instances it caught up.
+    Recovering {
+        since: Instant,
+    },
+    Voting,
+}
+
+/// The identity of a node and the generations of its peers. Clones share them.
+#[derive(Debug, Clone)]
+pub struct Rejoin {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    data_dir: PathBuf,
+    cluster: String,
+    status: StdMutex<Status>,
+
+    /// The highest generation each peer announced, kept in `peers.toml`.
+    peers: Mutex<BTreeMap<u32, u64>>,
+}
+
+impl Rejoin {
+    /// Opens the records in `data_dir` of a node of `cluster`. The node is
+    /// booting until [Rejoin::boot] returns.
+    pub async fn open(data_dir: &Path, cluster: String) -> Result<Self> {
+        let peers: BTreeMap<String, u64> =
+            read_toml(&peers_path(data_dir)).await?.unwrap_or_default();
+        let peers = peers
+            .into_iter()
+            .map(|(id, generation)| Ok((id.parse().context("parsing peer id")?, generation)))
+            .collect::<Result<_>>()?;
+
+        Ok(Self {
+            inner: Arc::new(Inner {
+                data_dir: data_dir.to_owned(),
+                cluster,
+                status: StdMutex::new(Status::Booting),
+                peers: Mutex::new(peers),
+            }),
+        })
+    }
+
+    pub fn status(&self) -> Status {
+        *self.inner.status.lock().unwrap()
+    }
+
+    fn set_status(&self, status: Status) {
+        *self.inner.status.lock().unwrap() = status;
+    }
+
+    /// Answers a peer's announcement with the generation recorded for it and
+    /// records the one it announced.
+    pub async fn on_announce(
+        &self,
+        announcement: Announcement,
+    ) -> Result<Announced, AcceptorError> {
+        if announcement.cluster != self.inner.cluster {
+            return Err(AcceptorError::InvalidRequest {
+                reason: format!(
+                    "node {} belongs to cluster {}, this node to {}",
+                    announcement.id, announcement.cluster, self.inner.cluster
+                ),
+            });
+        }
+
+        let mut peers = self.inner.peers.lock().await;
+        let recorded = peers.get(&announcement.id).copied();
+
+        if let Some(generation) = announcement.generation {
+            if recorded.map_or(true, |recorded| generation > recorded) {
+                peers.insert(announcement.id, generation);
+                let encoded: BTreeMap<String, u64> = peers
+                    .iter()
+                    .map(|(id, generation)| (id.to_string(), *generation))
+                    .collect();
+                write_toml(&peers_path(&self.inner.data_dir), &encoded)
+                    .await
+                    .map_err(|err| AcceptorError::storage(&err))?;
+            }
+        }
+
+        Ok(Announced {
+            generation: recorded,
+        })
+    }
+
+    /// Finds out whether node `id` lost its data and records its generation
+    /// with a majority of `peers`, then lets it vote. Any two majorities
+    /// share a peer, so the peers asked include one that recorded the node
+    /// if it ran before. A node with an identity already knows, it only lets
+    /// its peers know it is back.
+    pub async fn boot(
+        &self,
+        id: u32,
+        peers: &[SocketAddr],
+        connector: &Connector,
+        credentials: &Credentials,
+    ) -> Result<Identity> {
+        let path = identity_path(&self.inner.data_dir);
+        let needed = match peers.len() {
+            0 => 0,
+            n => n / 2 + 1,
+        };
+
+        if let Some(identity) = read_toml::<Identity>(&path).await? {
+            if identity.cluster != self.inner.cluster {
+                return Err(anyhow!(
+                    "the data dir belongs to cluster {}, not {}",
+                    identity.cluster,
+                    self.inner.cluster
+                ));
+            }
+
+            self.set_status(match identity.recovering {
+                true => Status::Recovering {
+                    since: Instant::now(),
+                },
+                false => Status::Voting,
+            });
+
+            let announcement = self.announcement(id, Some(identity.generation));
+            let (peers, connector, credentials) =
+                (peers.to_vec(), connector.clone(), credentials.clone());
+            tokio::spawn(async move {
+                announce(&announcement, &peers, peers.len(), &connector, &credentials).await
+            });
+
+            return Ok(identity);
+        }
+
+        // Nodes that ran before identities existed have state but no identity.
+        let has_state = has_state(&self.inner.data_dir).await?;
+
+        let answers = announce(
+            &self.announcement(id, None),
+            peers,
+            needed,
+            connector,
+            credentials,
+        )
+        .await;
+        let previous = answers.iter().filter_map(|answer| answer.generation).max();
+
+        let identity = Identity {
+            cluster: self.inner.cluster.clone(),
+            generation: previous.map_or(1, |generation| generation + 1),
+            recovering: previous.is_some() && !has_state,
+        };
+
+        // Recorded by the peers first: a node that crashes before writing its
+        // identity finds out it ran before on its next boot.
+        announce(
+            &self.announcement(id, Some(identity.generation)),
+            peers,
+            needed,
+            connector,
+            credentials,
+        )
+        .await;
+        write_toml(&path, &identity).await?;
+
+        if identity.recovering {
+            warn!(
+                generation = identity.generation,
+                "the node ran before but lost its data, catching instances up before voting in them"
+            );
+            self.set_status(Status::Recovering {
+                since: Instant::now(),
+            });
+        } else {
+            info!(generation = identity.generation, "first boot of the node");
+            self.set_status(Status::Voting);
+        }
+
+        Ok(identity)
+    }
+
+    fn announcement(&self, id: u32, generation: Option<u64>) -> Announcement {
+        Announcement {
+            id,
+            cluster: self.inner.cluster.clone(),
+            generation,
+        }
+    }
+}
+
+/// Rejoins the instances open in `instances` while the node is recovering,
+/// see [crate::paxos::Paxos::rejoin]. Instances opened later are picked up on
+/// the next pass, they refuse votes until then.
+pub async fn run(instances: Instances, rejoin: Rejoin) {
+    loop {
+        tokio::time::sleep(REJOIN_INTERVAL).await;
+
+        if rejoin.status() == Status::Voting {
+            continue;
+        }
+
+        for instance in instances.ids().await {
+            let node = match instances.get(&instance).await {
+                Err(err) => {
+                    warn!(%instance, ?err, "opening instance to rejoin");
+                    continue;
+                }
+                Ok(node) => node,
+            };
+
+            let Some(fetch) = node.lock().await.begin_rejoin() else {
+                continue;
+            };
+            let healed = fetch.run().await;
+
+            let result = node.lock().await.finish_rejoin(healed).await;
+            if let Err(err) = result {
+                warn!(%instance, ?err, "rejoining instance");
+            }
+        }
+    }
+}
+
+/// Announces to `peers` until `needed` of them answered and returns their answers.
+async fn announce(
+    announcement: &Announcement,
+    peers: &[SocketAddr],
+    needed: usize,
+    connector: &Connector,
+    credentials: &Credentials,
+) -> Vec<Announced> {
+    let mut answers = BTreeMap::new();
+
+    loop {
+        for peer in peers {
+            if answers.contains_key(peer) {
+                continue;
+            }
+
+            let answer = async {
+                let client = paxos::connect(connector, *peer).await?;
+                let answer = client
+                    .announce(
+                        context::current(),
+                        credentials.sign("announce", announcement),
+                        announcement.clone(),
+                    )
+                    .await??;
+                anyhow::Ok(answer)
+            };
+
+            match answer.await {
+                Ok(answer) => {
+                    answers.insert(*peer, answer);
+                }
+                Err(err) => warn!(%peer, ?err, "announcing to peer"),
+            }
+        }
+
+        if answers.len() >= needed {
+            return answers.into_values().collect();
+        }
+
+        info!(
+            answered = answers.len(),
+            needed, "waiting for peers to answer the announcement"
+        );
+        tokio::time::sleep(ANNOUNCE_INTERVAL).await;
+    }
+}
+
+/// The cluster id of a cluster without a genesis document, derived from its
+/// acceptors so every node agrees on it.
+pub fn cluster_from_acceptors(acceptors: &[SocketAddr]) -> String {
+    let mut acceptors: Vec<String> = acceptors.iter().map(ToString::to_string).collect();
+    acceptors.sort();
+    let digest = digest::digest(&digest::SHA256, acceptors.join(",").as_bytes());
+    hex_encode(&digest.as_ref()[..16])
+}
+
+fn identity_path(data_dir: &Path) -> PathBuf {
+    data_dir.join("identity.toml")
+}
+
+fn peers_path(data_dir: &Path) -> PathBuf {
+    data_dir.join("peers.toml")
+}
+
+/// Whether the data dir holds acceptor state.
+async fn has_state(data_dir: &Path) -> Result<bool> {
+    let mut entries = tokio::fs::read_dir(data_dir)
+        .await
+        .with_context(|| format!("listing {}", data_dir.display()))?;
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        if path
+            .extension()
+            .is_some_and(|extension| extension == "state")
+            && entry.metadata().await?.len() > 0
+        {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+async fn read_toml<T: serde::de::DeserializeOwned>(path: &Path) -> Result<Option<T>> {
+    match tokio::fs::read_to_string(path).await {
+        Ok(contents) => toml::from_str(&contents)
+            .with_context(|| format!("parsing {}", path.display()))
+            .map(Some),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err).with_context(|| format!("reading {}", path.display())),
+    }
+}
+
+/// Writes `value` to a temporary file and renames it over `path`, so a crash
+/// leaves either the old or the new contents.
+async fn write_toml<T: Serialize>(path: &Path, value: &T) -> Result<()> {
+    let contents = toml::to_string(value).context("encoding toml")?;
+    let tmp = path.with_extension("toml.tmp");
+
+    let mut file = tokio::fs::File::create(&tmp)
+        .await
+        .with_context(|| format!("creating {}", tmp.display()))?;
+    tokio::io::AsyncWriteExt::write_all(&mut file, contents.as_bytes()).await?;
+    file.sync_all().await?;
+
+    tokio::fs::rename(&tmp, path)
+        .await
+        .with_context(|| format!("writing {}", path.display()))
+}
@@ -0,0 +1,131 @@
+use anyhow::{Context, Result};
+use rand::Rng;
+use std::time::Duration;
+
+/// How the proposer retries rounds that failed because of transient errors.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    /// The maximum number of rounds attempted for a single proposal, including the first one.
+    pub max_attempts: u32,
+
+    /// The backoff before the first retry. Doubles after every attempt.
+    pub initial_backoff: Duration,
+
+    /// The backoff never grows past this.
+    pub max_backoff: Duration,
+
+    /// Stop retrying once this much time has passed since the first attempt.
+    pub deadline: Option<Duration>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(50),
+            max_backoff: Duration::from_secs(1),
+            deadline: None,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Overrides the defaults with the PROPOSE_MAX_ATTEMPTS and PROPOSE_DEADLINE_MS env variables.
+    pub fn from_env() -> Result<Self> {
+        let mut policy = Self::default();
+
+        if let Ok(value) = std::env::var("PROPOSE_MAX_ATTEMPTS") {
+            policy.max_attempts = value
+                .parse()
+                .context("PROPOSE_MAX_ATTEMPTS must be an integer")?;
+        }
+
+        if let Ok(value) = std::env::var("PROPOSE_DEADLINE_MS") {
+            let millis: u64 = value
+                .parse()
+                .context("PROPOSE_DEADLINE_MS must be an integer")?;
+            policy.deadline = Some(Duration::from_millis(millis));
+        }
+
+        Ok(policy)
+    }
+
+    /// Returns how long to wait before the next attempt or None when the
+    /// proposer should give up. `attempts` is the number of attempts made so far.
+    pub fn backoff(&self, attempts: u32, elapsed: Duration) -> Option<Duration> {
+        if attempts >= self.max_attempts {
+            return None;
+        }
+
+        let exponential = self
+            .initial_backoff
+            .saturating_mul(2_u32.saturating_pow(attempts.saturating_sub(1)));
+        let ceiling = std::cmp::min(exponential, self.max_backoff);
+
+        // Full jitter so proposers that failed together don't retry together.
+        let backoff =
+            Duration::from_millis(rand::thread_rng().gen_range(0..=ceiling.as_millis() as u64));
+
+        match self.deadline {
+            Some(deadline) if elapsed + backoff >= deadline => None,
+            _ => Some(backoff),
+        }
+    }
+}
+
+/// How long a proposer waits before its next round after another proposer's
+/// round preempted it. Two proposers that keep preempting each other never
+/// decide anything, waiting a random time lets one of them finish its round
+/// before the other starts the next one.
+#[derive(Debug, Clone, Copy)]
+pub struct ContentionBackoff {
+    /// The upper bound of the wait after the first preemption. Doubles after
+    /// every preemption in a row.
+    pub initial: Duration,
+
+    /// The wait never grows past this.
+    pub max: Duration,
+}
+
+impl Default for ContentionBackoff {
+    fn default() -> Self {
+        Self {
+            initial: Duration::from_millis(20),
+            max: Duration::from_millis(500),
+        }
+    }
+}
+
+impl ContentionBackoff {
+    /// Overrides the defaults with the CONTENTION_BACKOFF_MS and
+    /// CONTENTION_BACKOFF_MAX_MS env variables.
+    pub fn from_env() -> Result<Self> {
+        let mut backoff = Self::default();
+
+        if let Ok(value) = std::env::var("CONTENTION_BACKOFF_MS") {
+            let millis: u64 = value
+                .parse()
+                .context("CONTENTION_BACKOFF_MS must be an integer")?;
+            backoff.initial = Duration::from_millis(millis);
+        }
+
+        if let Ok(value) = std::env::var("CONTENTION_BACKOFF_MAX_MS") {
+            let millis: u64 = value
+                .parse()
+                .context("CONTENTION_BACKOFF_MAX_MS must be an integer")?;
+            backoff.max = Duration::from_millis(millis);
+        }
+
+        Ok(backoff)
+    }
+
+    /// How long to wait after `preemptions` rounds in a row were preempted.
+    pub fn delay(&self, preemptions: u32) -> Duration {
+        let exponential = self
+            .initial
+            .saturating_mul(2_u32.saturating_pow(preemptions.saturating_sub(1)));
+        let ceiling = std::cmp::min(exponential, self.max);
+
+        Duration::from_millis(rand::thread_rng().gen_range(0..=ceiling.as_millis() as u64))
+    }
+}
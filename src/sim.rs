@@ -0,0 +1,575 @@
+//! Runs whole clusters in one process over a [MemoryNetwork] to check the
+//! protocol keeps its guarantees while messages are delayed, reordered and
+//! lost and acceptors crash. How long each message takes, which messages are
+//! lost and which acceptors crash and when all follow from a seed, so a run
+//! that breaks an invariant can be replayed.
+
+use anyhow::{anyhow, Context, Result};
+use futures::StreamExt;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex as StdMutex},
+    time::Duration,
+};
+use tarpc::{
+    context,
+    server::{self, Channel},
+};
+use tokio::{sync::Mutex, task::JoinHandle};
+
+use crate::{
+    auth::{Credentials, Signed},
+    fault::{FaultInjector, LinkFaults},
+    instance::InstanceId,
+    latency::LatencySummary,
+    lease::{HeartbeatRequest, HeartbeatResponse, LeaseConfig},
+    membership::MemberUpdate,
+    paxos::{
+        AcceptChunk, AcceptRequest, AcceptResponse, AcceptedProposal, AcceptorError,
+        AcceptorService, Decided, Digest, Health, Hello, HelloResponse, Paxos, PrepareRequest,
+        PrepareResponse, RelayAcceptRequest, RelayLearnRequest, RelayedAcceptResponse, StateDump,
+        StateTransfer,
+    },
+    rejoin::{Announced, Announcement},
+    retry::{ContentionBackoff, RetryPolicy},
+    timeout::Timeouts,
+    transport::{self, Connector, MemoryNetwork},
+};
+
+#[derive(Debug, Clone)]
+pub struct SimConfig {
+    pub acceptors: usize,
+
+    /// Proposers are separate from the acceptors and each proposes its own value.
+    pub proposers: usize,
+
+    /// Acceptors wait up to this long before handling each request, which
+    /// decides the order requests from different proposers are handled in.
+    pub max_delay: Duration,
+
+    /// The probability an acceptor never answers a request.
+    pub drop_probability: f64,
+
+    /// How many times an acceptor crashes and restarts during a run.
+    pub crashes: usize,
+
+    /// Faults injected into the prepare and accept requests proposers send.
+    pub faults: LinkFaults,
+
+    /// How long proposers wait after being preempted.
+    pub contention_backoff: ContentionBackoff,
+
+    /// Makes proposers take turns through leases when set.
+    pub lease: Option<LeaseConfig>,
+}
+
+impl Default for SimConfig {
+    fn default() -> Self {
+        Self {
+            acceptors: 3,
+            proposers: 2,
+            max_delay: Duration::from_millis(10),
+            drop_probability: 0.05,
+            crashes: 2,
+            faults: LinkFaults::default(),
+            contention_backoff: ContentionBackoff::default(),
+            lease: None,
+        }
+    }
+}
+
+/// What every node ended up believing after a run.
+#[derive(Debug)]
+pub struct Report {
+    pub seed: u64,
+
+    /// The value each proposer proposed.
+    pub proposed: Vec<Vec<u8>>,
+
+    /// The value each proposer was told was decided, if its proposal finished.
+    pub outcomes: Vec<Option<Vec<u8>>>,
+
+    /// The value each acceptor learned was decided, if it did.
+    pub learned: Vec<Option<Vec<u8>>>,
+}
+
+impl Report {
+    /// The value decided in the run, if any node found out about one.
+    pub fn decided(&self) -> Option<&[u8]> {
+        self.outcomes
+            .iter()
+            .chain(self.learned.iter())
+            .flatten()
+            .map(Vec::as_slice)
+            .next()
+    }
+
+    /// Checks agreement, every node that found out about a decision found out
+    /// about the same value, and validity, the value was proposed.
+    pub fn check(&self) -> Result<()> {
+        let Some(decided) = self.decided() else {
+            return Ok(());
+        };
+
+        for value in self.outcomes.iter().chain(self.learned.iter()).flatten() {
+            if value != decided {
+                return Err(anyhow!(
+                    "agreement violated: seed={} decided={:?} and {:?}",
+                    self.seed,
+                    String::from_utf8_lossy(decided),
+                    String::from_utf8_lossy(value)
+                ));
+            }
+        }
+
+        if !self.proposed.iter().any(|value| value == decided) {
+            return Err(anyhow!(
+                "validity violated: seed={} decided={:?} was never proposed",
+                self.seed,
+                String::from_utf8_lossy(decided)
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// Delays and drops the requests an acceptor receives.
+#[derive(Debug, Clone)]
+struct Link {
+    rng: Arc<StdMutex<StdRng>>,
+    max_delay: Duration,
+    drop_probability: f64,
+}
+
+impl Link {
+    /// Waits until the request is delivered. Never returns for requests that are lost.
+    async fn deliver(&self) {
+        let (delay, dropped) = {
+            let mut rng = self.rng.lock().unwrap();
+            (
+                rng.gen_range(Duration::ZERO..=self.max_delay),
+                rng.gen_bool(self.drop_probability),
+            )
+        };
+
+        tokio::time::sleep(delay).await;
+
+        if dropped {
+            std::future::pending::<()>().await;
+        }
+    }
+}
+
+/// Serves an acceptor of the simulated cluster.
+#[derive(Clone)]
+struct SimServer {
+    paxos: Arc<Mutex<Paxos>>,
+    link: Link,
+}
+
+#[tarpc::server]
+impl AcceptorService for SimServer {
+    async fn hello(
+        self,
+        _: context::Context,
+        request: Hello,
+    ) -> Result<HelloResponse, AcceptorError> {
+        request.answer()
+    }
+
+    async fn prepare(
+        self,
+        _: context::Context,
+        _: Credentials,
+        request: PrepareRequest,
+    ) -> Result<Signed<PrepareResponse>, AcceptorError> {
+        self.link.deliver().await;
+        let sealed = request.clone();
+        let mut paxos = self.paxos.lock().await;
+        let response = paxos.on_prepare(request).await?;
+        Ok(paxos.seal("prepare", &sealed, response))
+    }
+
+    async fn accept(
+        self,
+        _: context::Context,
+        _: Credentials,
+        request: AcceptRequest,
+    ) -> Result<Signed<AcceptResponse>, AcceptorError> {
+        self.link.deliver().await;
+        let sealed = request.clone();
+        let mut paxos = self.paxos.lock().await;
+        let response = paxos.on_accept(request).await?;
+        Ok(paxos.seal("accept", &sealed, response))
+    }
+
+    async fn accept_chunk(
+        self,
+        _: context::Context,
+        _: Credentials,
+        chunk: AcceptChunk,
+    ) -> Result<Signed<Option<AcceptResponse>>, AcceptorError> {
+        self.link.deliver().await;
+        let sealed = chunk.clone();
+        let mut paxos = self.paxos.lock().await;
+        let response = paxos.on_accept_chunk(chunk).await?;
+        Ok(paxos.seal("accept_chunk", &sealed, response))
+    }
+
+    async fn relay_accept(
+        self,
+        _: context::Context,
+        _: Credentials,
+        request: RelayAcceptRequest,
+    ) -> Result<Vec<RelayedAcceptResponse>, AcceptorError> {
+        self.link.deliver().await;
+        let forward = self.paxos.lock().await.begin_relay_accept(request).await;
+        Ok(forward.run().await)
+    }
+
+    async fn relay_learn(
+        self,
+        _: context::Context,
+        _: Credentials,
+        request: RelayLearnRequest,
+    ) -> Result<(), AcceptorError> {
+        self.link.deliver().await;
+        let forward = self.paxos.lock().await.begin_relay_learn(request).await?;
+        tokio::spawn(forward.run());
+        Ok(())
+    }
+
+    async fn digest(
+        self,
+        _: context::Context,
+        _: Credentials,
+        _: InstanceId,
+    ) -> Result<Digest, AcceptorError> {
+        self.link.deliver().await;
+        let digest = self.paxos.lock().await.on_digest();
+        Ok(digest)
+    }
+
+    async fn fetch_decided(
+        self,
+        _: context::Context,
+        _: Credentials,
+        instance: InstanceId,
+    ) -> Result<Signed<Option<Vec<u8>>>, AcceptorError> {
+        self.link.deliver().await;
+        let paxos = self.paxos.lock().await;
+        let value = paxos.on_fetch_decided();
+        Ok(paxos.seal("fetch_decided", &instance, value))
+    }
+
+    async fn fetch_accepted(
+        self,
+        _: context::Context,
+        _: Credentials,
+        instance: InstanceId,
+    ) -> Result<Signed<AcceptedProposal>, AcceptorError> {
+        self.link.deliver().await;
+        let paxos = self.paxos.lock().await;
+        let accepted = paxos.on_fetch_accepted()?;
+        Ok(paxos.seal("fetch_accepted", &instance, accepted))
+    }
+
+    async fn fetch_state(
+        self,
+        _: context::Context,
+        _: Credentials,
+        from: InstanceId,
+    ) -> Result<Signed<StateTransfer>, AcceptorError> {
+        self.link.deliver().await;
+        let paxos = self.paxos.lock().await;
+        let transfer = paxos.on_fetch_state(&from);
+        Ok(paxos.seal("fetch_state", &from, transfer))
+    }
+
+    async fn latencies(
+        self,
+        _: context::Context,
+        _: Credentials,
+    ) -> Result<HashMap<SocketAddr, LatencySummary>, AcceptorError> {
+        let latencies = self.paxos.lock().await.on_latencies();
+        Ok(latencies)
+    }
+
+    async fn health(self, _: context::Context, _: Credentials) -> Result<Health, AcceptorError> {
+        let health = self.paxos.lock().await.on_health();
+        Ok(health)
+    }
+
+    async fn dump_state(
+        self,
+        _: context::Context,
+        _: Credentials,
+        _: InstanceId,
+    ) -> Result<StateDump, AcceptorError> {
+        let dump = self.paxos.lock().await.on_dump_state();
+        Ok(dump)
+    }
+
+    async fn reset_state(
+        self,
+        _: context::Context,
+        _: Credentials,
+        _: InstanceId,
+        _: String,
+    ) -> Result<(), AcceptorError> {
+        Err(AcceptorError::rejected("resetting state is disabled"))
+    }
+
+    async fn heartbeat(
+        self,
+        _: context::Context,
+        _: Credentials,
+        request: HeartbeatRequest,
+    ) -> Result<HeartbeatResponse, AcceptorError> {
+        self.link.deliver().await;
+        let mut paxos = self.paxos.lock().await;
+        paxos
+            .on_heartbeat(request)
+            .await
+            .map_err(AcceptorError::from)
+    }
+
+    async fn ping(
+        self,
+        _: context::Context,
+        _: Credentials,
+        _: Vec<MemberUpdate>,
+    ) -> Result<Vec<MemberUpdate>, AcceptorError> {
+        Err(AcceptorError::rejected("failure detection is disabled"))
+    }
+
+    async fn ping_req(
+        self,
+        _: context::Context,
+        _: Credentials,
+        _: SocketAddr,
+        _: Vec<MemberUpdate>,
+    ) -> Result<Vec<MemberUpdate>, AcceptorError> {
+        Err(AcceptorError::rejected("failure detection is disabled"))
+    }
+
+    async fn create_instance(
+        self,
+        _: context::Context,
+        _: Credentials,
+        _: InstanceId,
+    ) -> Result<(), AcceptorError> {
+        // Serves the one node whatever the instance.
+        Ok(())
+    }
+
+    async fn announce(
+        self,
+        _: context::Context,
+        _: Credentials,
+        _: Announcement,
+    ) -> Result<Announced, AcceptorError> {
+        Err(AcceptorError::rejected("rejoin is disabled"))
+    }
+}
+
+/// A running acceptor.
+struct Node {
+    paxos: Arc<Mutex<Paxos>>,
+    server: JoinHandle<()>,
+}
+
+struct Cluster {
+    network: MemoryNetwork,
+    link: Link,
+    data_dir: PathBuf,
+    acceptors: Vec<SocketAddr>,
+    /// None while the acceptor is down.
+    nodes: Vec<Option<Node>>,
+}
+
+impl Cluster {
+    fn timeouts() -> Timeouts {
+        Timeouts {
+            prepare_rpc: Duration::from_millis(100),
+            prepare_phase: Duration::from_millis(200),
+            accept_rpc: Duration::from_millis(100),
+            accept_phase: Duration::from_millis(200),
+        }
+    }
+
+    /// Starts acceptor `i` from the state it left on disk, if any.
+    async fn start(&mut self, i: usize) -> Result<()> {
+        let addr = self.acceptors[i];
+
+        // Every transition is checked against the audit rules, a violation
+        // aborts the run right where it happened.
+        let paxos = Paxos::builder(i as u32 + 1, addr, self.acceptors.clone())
+            .connector(Connector::memory(self.network.clone()))
+            .data_dir(&self.data_dir)
+            .timeouts(Self::timeouts())
+            .audit(true)
+            .build()
+            .await
+            .with_context(|| format!("starting acceptor {addr}"))?;
+        let paxos = Arc::new(Mutex::new(paxos));
+
+        let server = {
+            let paxos = Arc::clone(&paxos);
+            let link = self.link.clone();
+
+            tokio::spawn(
+                self.network
+                    .listen(addr)
+                    .map(|connection| {
+                        server::BaseChannel::with_defaults(transport::framed(connection))
+                    })
+                    .map(move |channel| {
+                        let server = SimServer {
+                            paxos: Arc::clone(&paxos),
+                            link: link.clone(),
+                        };
+                        channel.execute(server.serve())
+                    })
+                    .buffer_unordered(16)
+                    .for_each(|_| async {}),
+            )
+        };
+
+        self.nodes[i] = Some(Node { paxos, server });
+
+        Ok(())
+    }
+
+    /// Takes acceptor `i` down. Requests it was about to handle are lost and
+    /// it never writes to its files again, only what it had persisted survives.
+    async fn crash(&mut self, i: usize) -> Result<()> {
+        let Some(node) = self.nodes[i].take() else {
+            return Ok(());
+        };
+
+        self.network.close(self.acceptors[i]);
+        node.server.abort();
+        node.paxos.lock().await.shutdown().await
+    }
+}
+
+/// Runs one simulation with `seed`. Every proposer proposes its own value
+/// while acceptors crash and restart, then every acceptor is brought back up
+/// and asked what it learned.
+pub async fn run(seed: u64, config: &SimConfig) -> Result<Report> {
+    let data_dir = std::env::temp_dir().join(format!("paxos-sim-{}-{seed}", std::process::id()));
+    let _ = tokio::fs::remove_dir_all(&data_dir).await;
+    tokio::fs::create_dir_all(&data_dir)
+        .await
+        .context("creating data dir")?;
+
+    let result = run_in(seed, config, &data_dir).await;
+
+    let _ = tokio::fs::remove_dir_all(&data_dir).await;
+
+    result
+}
+
+async fn run_in(seed: u64, config: &SimConfig, data_dir: &Path) -> Result<Report> {
+    let rng = Arc::new(StdMutex::new(StdRng::seed_from_u64(seed)));
+
+    let acceptors: Vec<SocketAddr> = (0..config.acceptors)
+        .map(|i| SocketAddr::from(([10, 0, 0, i as u8 + 1], 8000)))
+        .collect();
+
+    let mut cluster = Cluster {
+        network: MemoryNetwork::default(),
+        link: Link {
+            rng: Arc::clone(&rng),
+            max_delay: config.max_delay,
+            drop_probability: config.drop_probability,
+        },
+        data_dir: data_dir.to_path_buf(),
+        acceptors: acceptors.clone(),
+        nodes: (0..config.acceptors).map(|_| None).collect(),
+    };
+
+    for i in 0..config.acceptors {
+        cluster.start(i).await?;
+    }
+
+    let faults = FaultInjector::new(seed);
+    faults.set_default(config.faults);
+
+    let proposed: Vec<Vec<u8>> = (0..config.proposers)
+        .map(|j| format!("value-{j}").into_bytes())
+        .collect();
+
+    let mut proposals = Vec::with_capacity(config.proposers);
+    for (j, value) in proposed.iter().enumerate() {
+        let mut proposer = Paxos::builder(
+            1000 + j as u32,
+            SocketAddr::from(([10, 0, 1, j as u8 + 1], 8000)),
+            acceptors.clone(),
+        )
+        .connector(Connector::memory(cluster.network.clone()).with_faults(faults.clone()))
+        .data_dir(data_dir)
+        .timeouts(Cluster::timeouts())
+        .retry_policy(RetryPolicy {
+            max_attempts: 10,
+            ..RetryPolicy::default()
+        })
+        .contention_backoff(config.contention_backoff);
+
+        if let Some(lease) = config.lease {
+            proposer = proposer.lease(lease);
+        }
+
+        let mut proposer = proposer.build().await.context("creating proposer")?;
+
+        let value = value.clone();
+        proposals.push(tokio::spawn(async move {
+            proposer.propose(value).await.ok().map(Decided::into_value)
+        }));
+    }
+
+    for _ in 0..config.crashes {
+        let (i, down_after, down_for) = {
+            let mut rng = rng.lock().unwrap();
+            (
+                rng.gen_range(0..config.acceptors),
+                Duration::from_millis(rng.gen_range(0..=200)),
+                Duration::from_millis(rng.gen_range(0..=200)),
+            )
+        };
+
+        tokio::time::sleep(down_after).await;
+        cluster.crash(i).await?;
+        tokio::time::sleep(down_for).await;
+        cluster.start(i).await?;
+    }
+
+    let mut outcomes = Vec::with_capacity(proposals.len());
+    for proposal in proposals {
+        outcomes.push(proposal.await.context("proposer panicked")?);
+    }
+
+    let mut learned = Vec::with_capacity(config.acceptors);
+    for node in cluster.nodes.iter().flatten() {
+        let mut paxos = node.paxos.lock().await;
+        // Acceptors that missed the decision learn it from their peers.
+        let _ = paxos.heal().await;
+        learned.push(paxos.on_fetch_decided());
+    }
+
+    for i in 0..config.acceptors {
+        cluster.crash(i).await?;
+    }
+
+    Ok(Report {
+        seed,
+        proposed,
+        outcomes,
+        learned,
+    })
+}
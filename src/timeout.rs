@@ -0,0 +1,117 @@
+use anyhow::{Context, Result};
+use std::{
+    fmt,
+    net::SocketAddr,
+    time::{Duration, SystemTime},
+};
+use tarpc::context;
+use tokio::time::Instant;
+
+/// How long the proposer waits for acceptors in each phase.
+#[derive(Debug, Clone, Copy)]
+pub struct Timeouts {
+    /// How long to wait for a single acceptor to respond to a prepare request.
+    pub prepare_rpc: Duration,
+
+    /// How long to wait for the whole prepare phase.
+    pub prepare_phase: Duration,
+
+    /// How long to wait for a single acceptor to respond to an accept request.
+    pub accept_rpc: Duration,
+
+    /// How long to wait for the whole accept phase.
+    pub accept_phase: Duration,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            prepare_rpc: Duration::from_secs(2),
+            prepare_phase: Duration::from_secs(5),
+            accept_rpc: Duration::from_secs(2),
+            accept_phase: Duration::from_secs(5),
+        }
+    }
+}
+
+impl Timeouts {
+    /// Overrides `self` with the PREPARE_RPC_TIMEOUT_MS, PREPARE_PHASE_TIMEOUT_MS,
+    /// ACCEPT_RPC_TIMEOUT_MS and ACCEPT_PHASE_TIMEOUT_MS env variables.
+    pub fn with_env(self) -> Result<Self> {
+        let mut timeouts = self;
+
+        for (var, timeout) in [
+            ("PREPARE_RPC_TIMEOUT_MS", &mut timeouts.prepare_rpc),
+            ("PREPARE_PHASE_TIMEOUT_MS", &mut timeouts.prepare_phase),
+            ("ACCEPT_RPC_TIMEOUT_MS", &mut timeouts.accept_rpc),
+            ("ACCEPT_PHASE_TIMEOUT_MS", &mut timeouts.accept_phase),
+        ] {
+            if let Ok(value) = std::env::var(var) {
+                let millis: u64 = value
+                    .parse()
+                    .with_context(|| format!("{var} must be an integer"))?;
+                *timeout = Duration::from_millis(millis);
+            }
+        }
+
+        Ok(timeouts)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Phase {
+    Prepare,
+    Accept,
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Phase::Prepare => write!(f, "prepare"),
+            Phase::Accept => write!(f, "accept"),
+        }
+    }
+}
+
+/// Returned when a phase fails to reach a majority because acceptors did not respond in time.
+#[derive(Debug)]
+pub struct Timeout {
+    pub phase: Phase,
+
+    /// The acceptors that did not respond before the deadline.
+    pub acceptors: Vec<SocketAddr>,
+}
+
+impl fmt::Display for Timeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} phase timed out waiting for acceptors: {:?}",
+            self.phase, self.acceptors
+        )
+    }
+}
+
+impl std::error::Error for Timeout {}
+
+/// Returns the instant an rpc must complete by, which is never after the end of its phase.
+pub fn rpc_deadline(rpc_timeout: Duration, phase_deadline: Instant) -> Instant {
+    std::cmp::min(Instant::now() + rpc_timeout, phase_deadline)
+}
+
+/// Creates a context whose deadline is propagated to the acceptor.
+pub fn context_until(deadline: Instant) -> context::Context {
+    let mut ctx = context::current();
+    ctx.deadline = SystemTime::now() + deadline.saturating_duration_since(Instant::now());
+    ctx
+}
+
+/// The instant the caller that sent a request with `ctx` stops waiting for
+/// the response, the inverse of [context_until].
+pub fn deadline_of(ctx: &context::Context) -> Instant {
+    Instant::now()
+        + ctx
+            .deadline
+            .duration_since(SystemTime::now())
+            .unwrap_or(Duration::ZERO)
+}
@@ -0,0 +1,118 @@
+use anyhow::{anyhow, Context, Result};
+use std::{
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tokio_rustls::{
+    rustls::{
+        self, server::AllowAnyAnonymousOrAuthenticatedClient, Certificate, PrivateKey,
+        RootCertStore,
+    },
+    TlsAcceptor, TlsConnector,
+};
+
+/// Paths to the PEM encoded files used to secure the connections between nodes.
+#[derive(Debug, Clone)]
+pub struct TlsConfig {
+    /// The certificate chain presented by this node.
+    pub cert_path: PathBuf,
+
+    /// The private key that matches the certificate.
+    pub key_path: PathBuf,
+
+    /// The certificate authority used to verify the other nodes.
+    pub ca_path: PathBuf,
+}
+
+impl TlsConfig {
+    /// Reads the config from the TLS_CERT, TLS_KEY and TLS_CA env variables.
+    /// Returns None when none of them are set.
+    pub fn from_env() -> Result<Option<Self>> {
+        let cert = std::env::var("TLS_CERT").ok();
+        let key = std::env::var("TLS_KEY").ok();
+        let ca = std::env::var("TLS_CA").ok();
+
+        match (cert, key, ca) {
+            (None, None, None) => Ok(None),
+            (Some(cert), Some(key), Some(ca)) => Ok(Some(Self {
+                cert_path: cert.into(),
+                key_path: key.into(),
+                ca_path: ca.into(),
+            })),
+            _ => Err(anyhow!("TLS_CERT, TLS_KEY and TLS_CA must be set together")),
+        }
+    }
+
+    /// Client certificates are optional but, when presented, must be signed by the
+    /// certificate authority so they can be used to authenticate proposers.
+    pub fn acceptor(&self) -> Result<TlsAcceptor> {
+        Ok(TlsAcceptor::from(Arc::new(self.server_config()?)))
+    }
+
+    /// The node presents its own certificate so acceptors can identify it.
+    pub fn connector(&self) -> Result<TlsConnector> {
+        Ok(TlsConnector::from(Arc::new(self.client_config()?)))
+    }
+
+    pub(crate) fn server_config(&self) -> Result<rustls::ServerConfig> {
+        rustls::ServerConfig::builder()
+            .with_safe_defaults()
+            .with_client_cert_verifier(
+                AllowAnyAnonymousOrAuthenticatedClient::new(self.root_cert_store()?).boxed(),
+            )
+            .with_single_cert(
+                load_certs(&self.cert_path)?,
+                load_private_key(&self.key_path)?,
+            )
+            .context("building tls server config")
+    }
+
+    pub(crate) fn client_config(&self) -> Result<rustls::ClientConfig> {
+        rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(self.root_cert_store()?)
+            .with_client_auth_cert(
+                load_certs(&self.cert_path)?,
+                load_private_key(&self.key_path)?,
+            )
+            .context("building tls client config")
+    }
+
+    fn root_cert_store(&self) -> Result<RootCertStore> {
+        let mut roots = RootCertStore::empty();
+        for cert in load_certs(&self.ca_path)? {
+            roots
+                .add(&cert)
+                .map_err(|err| anyhow!("adding ca certificate to root store: {err:?}"))?;
+        }
+        Ok(roots)
+    }
+}
+
+pub(crate) fn load_certs(path: &Path) -> Result<Vec<Certificate>> {
+    let file = File::open(path)
+        .with_context(|| format!("opening certificate file: {}", path.display()))?;
+
+    let certs = rustls_pemfile::certs(&mut BufReader::new(file))
+        .with_context(|| format!("parsing certificates: {}", path.display()))?;
+
+    if certs.is_empty() {
+        return Err(anyhow!("no certificates found in {}", path.display()));
+    }
+
+    Ok(certs.into_iter().map(Certificate).collect())
+}
+
+fn load_private_key(path: &Path) -> Result<PrivateKey> {
+    let file = File::open(path)
+        .with_context(|| format!("opening private key file: {}", path.display()))?;
+
+    let mut keys = rustls_pemfile::pkcs8_private_keys(&mut BufReader::new(file))
+        .with_context(|| format!("parsing private key: {}", path.display()))?;
+
+    keys.pop()
+        .map(PrivateKey)
+        .ok_or_else(|| anyhow!("no pkcs8 private key found in {}", path.display()))
+}
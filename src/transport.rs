@@ -0,0 +1,636 @@
+use anyhow::{anyhow, Context, Result};
+use futures::{future, stream::BoxStream, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fmt, io,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tarpc::{
+    serde_transport::Transport,
+    tokio_serde::formats::Json,
+    tokio_util::codec::{Framed, LengthDelimitedCodec},
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, DuplexStream},
+    net::{lookup_host, TcpListener, TcpStream, UnixListener, UnixStream},
+    sync::mpsc,
+};
+use tokio_rustls::{
+    rustls::{Certificate, ServerName},
+    TlsAcceptor, TlsConnector,
+};
+use tracing::{info, warn};
+
+#[cfg(feature = "quic")]
+use crate::quic::QuicConnector;
+use crate::{channel::ChannelNetwork, fault::FaultInjector, tls::TlsConfig};
+
+/// How long a peer has to finish the TLS handshake after connecting. Peers
+/// that stall it would otherwise hold one of the handshake slots forever.
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A bidirectional byte stream that rpc messages are framed over.
+pub trait Connection: AsyncRead + AsyncWrite + Unpin + Send + 'static {
+    fn peer_addr(&self) -> io::Result<SocketAddr>;
+
+    /// The certificate the peer authenticated with, if any.
+    fn peer_certificate(&self) -> Option<Certificate> {
+        None
+    }
+
+    /// The id of the process on the other end, for connections between
+    /// processes on the same machine.
+    fn peer_pid(&self) -> Option<i32> {
+        None
+    }
+}
+
+impl Connection for TcpStream {
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        TcpStream::peer_addr(self)
+    }
+}
+
+impl Connection for tokio_rustls::server::TlsStream<TcpStream> {
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.get_ref().0.peer_addr()
+    }
+
+    fn peer_certificate(&self) -> Option<Certificate> {
+        self.get_ref()
+            .1
+            .peer_certificates()
+            .and_then(|certs| certs.first().cloned())
+    }
+}
+
+impl Connection for tokio_rustls::client::TlsStream<TcpStream> {
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.get_ref().0.peer_addr()
+    }
+}
+
+impl Connection for UnixStream {
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "unix socket connections have no peer address",
+        ))
+    }
+
+    fn peer_pid(&self) -> Option<i32> {
+        self.peer_cred().ok().and_then(|cred| cred.pid())
+    }
+}
+
+impl Connection for DuplexStream {
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "in memory connections have no peer address",
+        ))
+    }
+}
+
+pub type BoxedConnection = Box<dyn Connection>;
+
+/// Opens connections to other nodes, wrapping them in TLS when configured.
+#[derive(Clone, Default)]
+pub struct Connector {
+    tls: Option<TlsConnector>,
+
+    /// Connects through this instead of the network when set.
+    memory: Option<MemoryNetwork>,
+
+    /// Injects faults into the requests sent over the connections when set.
+    faults: Option<FaultInjector>,
+
+    /// Finds where acceptors configured by host name are now.
+    resolver: Resolver,
+
+    /// Sends requests to the acceptors in this process instead of opening
+    /// connections when set.
+    channels: Option<ChannelNetwork>,
+
+    /// Opens QUIC streams instead of tcp connections when set.
+    #[cfg(feature = "quic")]
+    quic: Option<QuicConnector>,
+}
+
+impl fmt::Debug for Connector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Connector")
+            .field("tls", &self.tls.is_some())
+            .field("memory", &self.memory.is_some())
+            .field("channels", &self.channels.is_some())
+            .field("faults", &self.faults)
+            .field("resolver", &self.resolver)
+            .finish()
+    }
+}
+
+/// Whether nodes talk over QUIC, set by TRANSPORT=quic. TCP is the default.
+pub fn quic_from_env() -> Result<bool> {
+    match std::env::var("TRANSPORT").as_deref() {
+        Err(_) | Ok("tcp") => Ok(false),
+        Ok("quic") => Ok(true),
+        Ok(other) => Err(anyhow!("unknown TRANSPORT {other:?}, expected tcp or quic")),
+    }
+}
+
+impl Connector {
+    pub fn new(tls: Option<TlsConnector>) -> Self {
+        Self {
+            tls,
+            memory: None,
+            channels: None,
+            faults: None,
+            resolver: Resolver::default(),
+            #[cfg(feature = "quic")]
+            quic: None,
+        }
+    }
+
+    /// Connects over the transport picked by TRANSPORT, with `tls` when set.
+    pub fn from_env(tls: Option<&TlsConfig>) -> Result<Self> {
+        let connector = Self::new(tls.map(|tls| tls.connector()).transpose()?);
+
+        if !quic_from_env()? {
+            return Ok(connector);
+        }
+
+        #[cfg(feature = "quic")]
+        return Ok(connector.with_quic(QuicConnector::new(
+            tls.context("TRANSPORT=quic needs TLS_CERT, TLS_KEY and TLS_CA")?,
+        )?));
+
+        #[cfg(not(feature = "quic"))]
+        Err(anyhow!(
+            "TRANSPORT=quic needs a build with the quic feature"
+        ))
+    }
+
+    /// Opens connections to the nodes listening on `network` instead of over tcp.
+    pub fn memory(network: MemoryNetwork) -> Self {
+        Self {
+            tls: None,
+            memory: Some(network),
+            channels: None,
+            faults: None,
+            resolver: Resolver::default(),
+            #[cfg(feature = "quic")]
+            quic: None,
+        }
+    }
+
+    /// Sends requests to the acceptors served on `network`, skipping
+    /// connections and encoding altogether.
+    pub fn channels(network: ChannelNetwork) -> Self {
+        Self {
+            channels: Some(network),
+            ..Self::default()
+        }
+    }
+
+    /// Injects faults into the requests sent over the connections this opens.
+    pub fn with_faults(mut self, faults: FaultInjector) -> Self {
+        self.faults = Some(faults);
+        self
+    }
+
+    /// Looks up the host names `resolver` knows every time a connection to
+    /// one of their acceptors is opened.
+    pub fn with_resolver(mut self, resolver: Resolver) -> Self {
+        self.resolver = resolver;
+        self
+    }
+
+    /// Connects over QUIC instead of tcp. The tls connector is not used then,
+    /// QUIC brings its own.
+    #[cfg(feature = "quic")]
+    pub fn with_quic(mut self, quic: QuicConnector) -> Self {
+        self.quic = Some(quic);
+        self
+    }
+
+    pub(crate) fn faults(&self) -> Option<&FaultInjector> {
+        self.faults.as_ref()
+    }
+
+    pub(crate) fn channel_network(&self) -> Option<&ChannelNetwork> {
+        self.channels.as_ref()
+    }
+
+    pub async fn connect(&self, addr: SocketAddr) -> Result<BoxedConnection> {
+        if let Some(network) = &self.memory {
+            return network.connect(addr);
+        }
+
+        let endpoint = self.resolver.endpoint(addr);
+
+        // Unix sockets only connect processes on the same machine, the file
+        // permissions protect them instead of tls.
+        if let Endpoint::Unix(path) = &endpoint {
+            let stream = UnixStream::connect(path)
+                .await
+                .with_context(|| format!("connecting to {endpoint}"))?;
+            return Ok(Box::new(stream));
+        }
+
+        let target = self.resolver.lookup(addr).await?;
+
+        #[cfg(feature = "quic")]
+        if let Some(quic) = &self.quic {
+            let server_name = match &endpoint {
+                Endpoint::Host { name, .. } => name.clone(),
+                _ => target.ip().to_string(),
+            };
+            return quic.connect(target, &server_name).await;
+        }
+
+        let stream = TcpStream::connect(target)
+            .await
+            .with_context(|| format!("connecting to {endpoint}"))?;
+
+        match &self.tls {
+            None => Ok(Box::new(stream)),
+            Some(tls) => {
+                // Nodes configured by name are expected to present a
+                // certificate for the name.
+                let server_name = match &endpoint {
+                    Endpoint::Host { name, .. } => ServerName::try_from(name.as_str())
+                        .with_context(|| format!("{name} is not a valid tls server name"))?,
+                    _ => ServerName::IpAddress(target.ip()),
+                };
+
+                let stream = tls
+                    .connect(server_name, stream)
+                    .await
+                    .with_context(|| format!("performing tls handshake with {endpoint}"))?;
+
+                Ok(Box::new(stream))
+            }
+        }
+    }
+}
+
+/// An acceptor as configured, an address, a host name and port or the path
+/// of a unix socket, written as `unix:<path>`. IPv6 addresses are written in
+/// brackets, as in `[::1]:8001`. Nodes know acceptors by
+/// address, a host name stands for the address it resolved to when the node
+/// started, and is looked up again every time a connection to it is opened so
+/// acceptors that moved can still be reached. A unix socket stands for an
+/// address derived from its path, see [Endpoint::unix_addr].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Endpoint {
+    Addr(SocketAddr),
+    Host { name: String, port: u16 },
+    Unix(PathBuf),
+}
+
+impl FromStr for Endpoint {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if let Some(path) = s.strip_prefix("unix:") {
+            if path.is_empty() {
+                return Err(anyhow!("expected unix:<path>, got {s:?}"));
+            }
+            return Ok(Endpoint::Unix(PathBuf::from(path)));
+        }
+
+        if let Ok(addr) = s.parse() {
+            return Ok(Endpoint::Addr(addr));
+        }
+
+        let (name, port) = s
+            .rsplit_once(':')
+            .ok_or_else(|| anyhow!("expected host:port, got {s:?}"))?;
+        if name.starts_with('[') {
+            return Err(anyhow!("invalid IPv6 address in {s:?}"));
+        }
+        if name.contains(':') {
+            return Err(anyhow!(
+                "IPv6 addresses must be written in brackets, as in [{name}]:{port}"
+            ));
+        }
+        let port = port
+            .parse()
+            .with_context(|| format!("invalid port in {s:?}"))?;
+        if name.is_empty() {
+            return Err(anyhow!("expected host:port, got {s:?}"));
+        }
+        if let Some(invalid) = name
+            .chars()
+            .find(|c| !(c.is_ascii_alphanumeric() || matches!(c, '-' | '.' | '_')))
+        {
+            return Err(anyhow!(
+                "invalid character {invalid:?} in host name {name:?}"
+            ));
+        }
+
+        Ok(Endpoint::Host {
+            name: name.to_owned(),
+            port,
+        })
+    }
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Endpoint::Addr(addr) => addr.fmt(f),
+            Endpoint::Host { name, port } => write!(f, "{name}:{port}"),
+            Endpoint::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Endpoint {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+impl Endpoint {
+    /// Parses a comma separated list of endpoints, as in AUXILIARY_ACCEPTORS.
+    pub fn parse_list(s: &str) -> Result<Vec<Self>> {
+        s.split(',')
+            .map(|endpoint| endpoint.trim().parse())
+            .collect()
+    }
+
+    /// The address nodes know the acceptor by.
+    pub async fn lookup(&self) -> Result<SocketAddr> {
+        match self {
+            Endpoint::Addr(addr) => Ok(*addr),
+            Endpoint::Unix(path) => Ok(Self::unix_addr(path)),
+            Endpoint::Host { name, port } => lookup_host((name.as_str(), *port))
+                .await
+                .with_context(|| format!("resolving {name}"))?
+                .next()
+                .ok_or_else(|| anyhow!("{name} did not resolve to any address")),
+        }
+    }
+
+    /// The address to bind a tcp listener to, a host name resolves to the
+    /// first address it has. Unix sockets have none.
+    pub async fn bind_addr(&self) -> Result<SocketAddr> {
+        match self {
+            Endpoint::Unix(path) => Err(anyhow!(
+                "expected an address or host name, unix:{} is a socket",
+                path.display()
+            )),
+            endpoint => endpoint.lookup().await,
+        }
+    }
+
+    /// The address a unix socket is known by. It is in 0.0.0.0/8, which no
+    /// node listens on, and only depends on the path so every node that lists
+    /// the socket the same way agrees on it.
+    pub fn unix_addr(path: &Path) -> SocketAddr {
+        let digest = ring::digest::digest(
+            &ring::digest::SHA256,
+            path.as_os_str().to_string_lossy().as_bytes(),
+        );
+        let bytes = digest.as_ref();
+        SocketAddr::from((
+            [0, bytes[0], bytes[1], bytes[2]],
+            u16::from_le_bytes([bytes[3], bytes[4]]),
+        ))
+    }
+}
+
+/// Maps the address each acceptor configured by host name or unix socket is
+/// known by to how it was configured.
+#[derive(Debug, Clone, Default)]
+pub struct Resolver {
+    hosts: Arc<HashMap<SocketAddr, Endpoint>>,
+}
+
+impl Resolver {
+    /// Resolves every endpoint once. Returns the addresses the acceptors are
+    /// known by, in the order of `endpoints`, and the resolver that finds
+    /// them again when they move.
+    pub async fn resolve(endpoints: &[Endpoint]) -> Result<(Vec<SocketAddr>, Self)> {
+        let mut addrs = Vec::with_capacity(endpoints.len());
+        let mut hosts = HashMap::new();
+
+        for endpoint in endpoints {
+            let addr = endpoint.lookup().await?;
+            if !matches!(endpoint, Endpoint::Addr(_)) {
+                info!(%endpoint, %addr, "resolved acceptor");
+                hosts.insert(addr, endpoint.clone());
+            }
+            addrs.push(addr);
+        }
+
+        Ok((
+            addrs,
+            Self {
+                hosts: Arc::new(hosts),
+            },
+        ))
+    }
+
+    /// The address the acceptor configured as `endpoint` is known by: the one
+    /// it resolved to with the others when it is one of them, looked up
+    /// otherwise.
+    pub async fn known_addr(&self, endpoint: &Endpoint) -> Result<SocketAddr> {
+        if let Some((addr, _)) = self.hosts.iter().find(|(_, known)| *known == endpoint) {
+            return Ok(*addr);
+        }
+
+        endpoint.lookup().await
+    }
+
+    fn endpoint(&self, addr: SocketAddr) -> Endpoint {
+        self.hosts
+            .get(&addr)
+            .cloned()
+            .unwrap_or(Endpoint::Addr(addr))
+    }
+
+    /// Where the acceptor known by `addr` can be reached now.
+    async fn lookup(&self, addr: SocketAddr) -> Result<SocketAddr> {
+        let Some(endpoint) = self.hosts.get(&addr) else {
+            return Ok(addr);
+        };
+
+        let current = endpoint.lookup().await?;
+        if current != addr {
+            info!(%endpoint, was = %addr, now = %current, "acceptor moved");
+        }
+        Ok(current)
+    }
+}
+
+/// Connects nodes running in the same process without going through the
+/// operating system, used to run whole clusters in tests and simulations.
+#[derive(Clone, Default)]
+pub struct MemoryNetwork {
+    listeners: Arc<Mutex<HashMap<SocketAddr, mpsc::UnboundedSender<BoxedConnection>>>>,
+}
+
+impl MemoryNetwork {
+    /// How many bytes a connection buffers in each direction.
+    const BUFFER_SIZE: usize = 64 * 1024;
+
+    /// Accepts the connections opened to `addr`, replacing any previous listener.
+    pub fn listen(&self, addr: SocketAddr) -> impl futures::Stream<Item = BoxedConnection> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        self.listeners.lock().unwrap().insert(addr, sender);
+
+        futures::stream::unfold(receiver, |mut receiver| async move {
+            receiver
+                .recv()
+                .await
+                .map(|connection| (connection, receiver))
+        })
+    }
+
+    /// Stops accepting connections to `addr`, as if the node went down.
+    /// Connections that are already open stay open until either end drops them.
+    pub fn close(&self, addr: SocketAddr) {
+        self.listeners.lock().unwrap().remove(&addr);
+    }
+
+    fn connect(&self, addr: SocketAddr) -> Result<BoxedConnection> {
+        let mut listeners = self.listeners.lock().unwrap();
+
+        let (client, server) = tokio::io::duplex(Self::BUFFER_SIZE);
+
+        let delivered = listeners
+            .get(&addr)
+            .map(|listener| listener.send(Box::new(server)).is_ok())
+            .unwrap_or(false);
+
+        if !delivered {
+            listeners.remove(&addr);
+            return Err(anyhow!("connecting to {addr}: connection refused"));
+        }
+
+        Ok(Box::new(client))
+    }
+}
+
+/// Accepts connections on the unix socket at `path`, replacing the socket a
+/// previous run left behind.
+pub async fn listen_unix(path: &Path) -> Result<impl futures::Stream<Item = BoxedConnection>> {
+    match tokio::fs::remove_file(path).await {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => {
+            return Err(err).with_context(|| format!("removing stale socket {}", path.display()));
+        }
+        _ => {}
+    }
+
+    let listener = UnixListener::bind(path)
+        .with_context(|| format!("binding rpc listener to {}", path.display()))?;
+
+    let incoming = futures::stream::unfold(listener, |listener| async move {
+        let result = listener.accept().await;
+        Some((result, listener))
+    });
+
+    Ok(incoming
+        // Ignore accept errors.
+        .filter_map(|r| future::ready(r.ok()))
+        .map(|(stream, _)| Box::new(stream) as BoxedConnection))
+}
+
+/// Frames a connection so tarpc clients and servers can send json messages over it.
+pub fn framed<Item, SinkItem>(
+    connection: BoxedConnection,
+) -> Transport<BoxedConnection, Item, SinkItem, Json<Item, SinkItem>>
+where
+    Item: for<'de> Deserialize<'de>,
+    SinkItem: Serialize,
+{
+    let codec = LengthDelimitedCodec::builder()
+        .max_frame_length(usize::MAX)
+        .new_codec();
+
+    tarpc::serde_transport::new(Framed::new(connection, codec), Json::default())
+}
+
+/// Accepts connections on `endpoint`, over the transport picked by TRANSPORT
+/// unless it is a unix socket.
+#[cfg_attr(not(feature = "quic"), allow(unused_variables))]
+pub async fn listen_endpoint(
+    endpoint: &Endpoint,
+    tls: Option<&TlsConfig>,
+    tls_acceptor: Option<TlsAcceptor>,
+) -> Result<BoxStream<'static, BoxedConnection>> {
+    if let Endpoint::Unix(path) = endpoint {
+        return Ok(listen_unix(path).await?.boxed());
+    }
+
+    let addr = endpoint.lookup().await?;
+    if !quic_from_env()? {
+        return Ok(listen(addr, tls_acceptor).await?.boxed());
+    }
+
+    #[cfg(feature = "quic")]
+    return Ok(crate::quic::listen(
+        addr,
+        tls.ok_or_else(|| anyhow!("TRANSPORT=quic needs TLS_CERT, TLS_KEY and TLS_CA"))?,
+    )
+    .await?
+    .boxed());
+
+    #[cfg(not(feature = "quic"))]
+    Err(anyhow!(
+        "TRANSPORT=quic needs a build with the quic feature"
+    ))
+}
+
+/// Accepts connections on `addr`, performing the TLS handshake when an acceptor is given.
+pub async fn listen(
+    addr: SocketAddr,
+    tls: Option<TlsAcceptor>,
+) -> Result<impl futures::Stream<Item = BoxedConnection>> {
+    let listener = TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("binding rpc listener to {addr}"))?;
+
+    let incoming = futures::stream::unfold(listener, |listener| async move {
+        let result = listener.accept().await;
+        Some((result, listener))
+    });
+
+    Ok(incoming
+        // Ignore accept errors.
+        .filter_map(|r| future::ready(r.ok()))
+        .map(move |(stream, peer)| {
+            let tls = tls.clone();
+            async move {
+                let connection: BoxedConnection = match tls {
+                    None => Box::new(stream),
+                    Some(tls) => {
+                        match tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, tls.accept(stream)).await
+                        {
+                            Err(_) => {
+                                warn!(%peer, "tls handshake timed out");
+                                return None;
+                            }
+                            Ok(Err(err)) => {
+                                warn!(%peer, ?err, "tls handshake failed");
+                                return None;
+                            }
+                            Ok(Ok(stream)) => Box::new(stream),
+                        }
+                    }
+                };
+                Some(connection)
+            }
+        })
+        // Handshakes run concurrently so a slow peer does not block the others.
+        .buffer_unordered(16)
+        .filter_map(future::ready))
+}
@@ -0,0 +1,617 @@
+//! Vertical Paxos. A configuration master hands out the ballots of an
+//! instance, each with the acceptors it runs on, so the acceptors can be
+//! replaced in the middle of an instance without a round of consensus among
+//! them, e.g. to swap a failed backup of a primary-backup pair.
+//!
+//! A proposer asks the master for a ballot, reads what the acceptors of the
+//! last complete ballot accepted and writes it, or its own value when they
+//! accepted nothing, to the acceptors of the new ballot. A ballot is complete
+//! once its acceptors hold the state of the ballots before it, only then may
+//! its proposer propose a value of its own. The master completes only the
+//! newest ballot it handed out, so a proposer of an older ballot can't choose
+//! a value the newer one didn't read.
+//!
+//! The acceptors are the usual ones. An instance must only be proposed to
+//! through the master, the ballots it hands out are the proposal ids.
+
+use anyhow::{anyhow, Context, Result};
+use futures::{stream::FuturesUnordered, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{Arc, Mutex as StdMutex},
+};
+use tarpc::context;
+use tokio::time::Instant;
+use tracing::{info, warn};
+
+use crate::{
+    auth::{Authenticator, Credentials},
+    instance::InstanceId,
+    paxos::{self, AcceptRequest, AcceptorServiceClient, Decided, Preempted, PrepareRequest},
+    proposal::ProposalId,
+    protocol,
+    retry::RetryPolicy,
+    timeout::{self, Phase, Timeouts},
+    transport::Connector,
+};
+
+/// The acceptors of a ballot.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Configuration {
+    pub ballot: ProposalId,
+    pub acceptors: Vec<SocketAddr>,
+}
+
+/// A ballot handed out by the master.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BallotAssignment {
+    pub configuration: Configuration,
+
+    /// The last complete ballot, whose acceptors hold what may have been
+    /// chosen. None when no ballot of the instance completed yet.
+    pub previous: Option<Configuration>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct InstanceState {
+    /// The newest ballot handed out.
+    latest: Option<Configuration>,
+    complete: Option<Configuration>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct MasterState {
+    /// The acceptors of the ballots handed out from now on.
+    acceptors: Vec<SocketAddr>,
+    instances: BTreeMap<InstanceId, InstanceState>,
+}
+
+/// Hands out ballots and tracks which one of each instance is complete.
+/// Clones share their state.
+#[derive(Debug, Clone)]
+pub struct ConfigurationMaster {
+    state: Arc<StdMutex<MasterState>>,
+
+    /// Where the state is kept. A master that forgets the ballots it handed
+    /// out could hand them out again, so only tests run without one.
+    path: Option<PathBuf>,
+}
+
+impl ConfigurationMaster {
+    /// A master that keeps its state in memory, handing out ballots on `acceptors`.
+    pub fn in_memory(acceptors: Vec<SocketAddr>) -> Self {
+        Self {
+            state: Arc::new(StdMutex::new(MasterState {
+                acceptors,
+                instances: BTreeMap::new(),
+            })),
+            path: None,
+        }
+    }
+
+    /// Loads the master from `path`, starting with `acceptors` when the file
+    /// doesn't exist yet.
+    pub fn open(path: impl Into<PathBuf>, acceptors: Vec<SocketAddr>) -> Result<Self> {
+        let path = path.into();
+        let state = match std::fs::read(&path) {
+            Ok(contents) => serde_json::from_slice(&contents)
+                .with_context(|| format!("parsing master state {}", path.display()))?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => MasterState {
+                acceptors,
+                instances: BTreeMap::new(),
+            },
+            Err(err) => {
+                return Err(err).with_context(|| format!("reading master state {}", path.display()))
+            }
+        };
+
+        Ok(Self {
+            state: Arc::new(StdMutex::new(state)),
+            path: Some(path),
+        })
+    }
+
+    /// Hands out the next ballot of `instance`, on the current acceptors.
+    pub fn new_ballot(&self, instance: &InstanceId) -> Result<BallotAssignment> {
+        self.update(|state| {
+            let acceptors = state.acceptors.clone();
+            let instance = state.instances.entry(instance.clone()).or_default();
+
+            let ballot = instance
+                .latest
+                .as_ref()
+                .map_or(ProposalId::ZERO, |latest| latest.ballot)
+                .next();
+            let configuration = Configuration { ballot, acceptors };
+            instance.latest = Some(configuration.clone());
+
+            BallotAssignment {
+                configuration,
+                previous: instance.complete.clone(),
+            }
+        })
+    }
+
+    /// Marks `ballot` of `instance` complete. Returns false when a newer
+    /// ballot was handed out meanwhile, its proposer must not propose a
+    /// value of its own.
+    pub fn complete(&self, instance: &InstanceId, ballot: ProposalId) -> Result<bool> {
+        self.update(|state| {
+            let Some(instance) = state.instances.get_mut(instance) else {
+                return false;
+            };
+
+            match &instance.latest {
+                Some(latest) if latest.ballot == ballot => {
+                    instance.complete = Some(latest.clone());
+                    true
+                }
+                _ => false,
+            }
+        })
+    }
+
+    /// Moves the ballots handed out from now on to `acceptors`. Instances in
+    /// progress move over with their next ballot.
+    pub fn reconfigure(&self, acceptors: Vec<SocketAddr>) -> Result<()> {
+        if acceptors.is_empty() {
+            return Err(anyhow!("a configuration needs at least one acceptor"));
+        }
+
+        self.update(|state| {
+            info!(from = ?state.acceptors, to = ?acceptors, "reconfiguring");
+            state.acceptors = acceptors;
+        })
+    }
+
+    /// The acceptors of the ballots handed out from now on.
+    pub fn acceptors(&self) -> Vec<SocketAddr> {
+        self.state.lock().unwrap().acceptors.clone()
+    }
+
+    /// Applies `change` and persists the state before anyone hears of it.
+    fn update<T>(&self, change: impl FnOnce(&mut MasterState) -> T) -> Result<T> {
+        let mut state = self.state.lock().unwrap();
+        let result = change(&mut state);
+
+        if let Some(path) = &self.path {
+            let tmp = path.with_extension("tmp");
+            std::fs::write(&tmp, serde_json::to_vec(&*state)?)
+                .with_context(|| format!("writing master state {}", tmp.display()))?;
+            std::fs::File::open(&tmp)
+                .and_then(|file| file.sync_all())
+                .context("syncing master state")?;
+            std::fs::rename(&tmp, path).context("replacing master state")?;
+        }
+
+        Ok(result)
+    }
+}
+
+/// Served by the configuration master to the proposers.
+#[tarpc::service]
+pub trait MasterService {
+    async fn new_ballot(
+        credentials: Credentials,
+        instance: InstanceId,
+    ) -> Result<BallotAssignment, String>;
+
+    async fn complete(
+        credentials: Credentials,
+        instance: InstanceId,
+        ballot: ProposalId,
+    ) -> Result<bool, String>;
+
+    async fn reconfigure(
+        credentials: Credentials,
+        acceptors: Vec<SocketAddr>,
+    ) -> Result<(), String>;
+}
+
+/// Serves a [ConfigurationMaster] to the proposers that authenticate.
+#[derive(Debug, Clone)]
+pub struct MasterServer {
+    master: ConfigurationMaster,
+    authenticator: Arc<Authenticator>,
+}
+
+impl MasterServer {
+    pub fn new(master: ConfigurationMaster, authenticator: Arc<Authenticator>) -> Self {
+        Self {
+            master,
+            authenticator,
+        }
+    }
+
+    fn authenticate(&self, credentials: &Credentials) -> Result<(), String> {
+        self.authenticator
+            .authenticate(credentials, None)
+            .map_err(|err| err.to_string())
+    }
+}
+
+#[tarpc::server]
+impl MasterService for MasterServer {
+    async fn new_ballot(
+        self,
+        _: context::Context,
+        credentials: Credentials,
+        instance: InstanceId,
+    ) -> Result<BallotAssignment, String> {
+        self.authenticate(&credentials)?;
+        self.master
+            .new_ballot(&instance)
+            .map_err(|err| format!("{err:#}"))
+    }
+
+    async fn complete(
+        self,
+        _: context::Context,
+        credentials: Credentials,
+        instance: InstanceId,
+        ballot: ProposalId,
+    ) -> Result<bool, String> {
+        self.authenticate(&credentials)?;
+        self.master
+            .complete(&instance, ballot)
+            .map_err(|err| format!("{err:#}"))
+    }
+
+    async fn reconfigure(
+        self,
+        _: context::Context,
+        credentials: Credentials,
+        acceptors: Vec<SocketAddr>,
+    ) -> Result<(), String> {
+        self.authenticate(&credentials)?;
+        self.master
+            .reconfigure(acceptors)
+            .map_err(|err| format!("{err:#}"))
+    }
+}
+
+/// Where a [VerticalProposer] gets its ballots from.
+#[derive(Debug, Clone)]
+pub enum Master {
+    /// A master in the same process.
+    Local(ConfigurationMaster),
+    Remote(MasterServiceClient),
+}
+
+impl Master {
+    async fn new_ballot(
+        &self,
+        credentials: &Credentials,
+        instance: &InstanceId,
+    ) -> Result<BallotAssignment> {
+        match self {
+            Master::Local(master) => master.new_ballot(instance),
+            Master::Remote(client) => client
+                .new_ballot(context::current(), credentials.clone(), instance.clone())
+                .await?
+                .map_err(|err| anyhow!("master refused a ballot: {err}")),
+        }
+    }
+
+    async fn complete(
+        &self,
+        credentials: &Credentials,
+        instance: &InstanceId,
+        ballot: ProposalId,
+    ) -> Result<bool> {
+        match self {
+            Master::Local(master) => master.complete(instance, ballot),
+            Master::Remote(client) => client
+                .complete(
+                    context::current(),
+                    credentials.clone(),
+                    instance.clone(),
+                    ballot,
+                )
+                .await?
+                .map_err(|err| anyhow!("master refused to complete the ballot: {err}")),
+        }
+    }
+}
+
+/// Proposes in an instance with ballots from a configuration master.
+#[derive(Debug)]
+pub struct VerticalProposer {
+    master: Master,
+    instance: InstanceId,
+    connector: Connector,
+    credentials: Credentials,
+    timeouts: Timeouts,
+    retry_policy: RetryPolicy,
+    /// The client of each acceptor and the protocol version its connection speaks.
+    clients: HashMap<SocketAddr, (AcceptorServiceClient, u32)>,
+}
+
+impl VerticalProposer {
+    pub fn new(master: Master, instance: InstanceId, connector: Connector) -> Self {
+        Self {
+            master,
+            instance,
+            connector,
+            credentials: Credentials::default(),
+            timeouts: Timeouts::default(),
+            retry_policy: RetryPolicy::default(),
+            clients: HashMap::new(),
+        }
+    }
+
+    /// Sent with every request to the master and the acceptors.
+    pub fn credentials(mut self, credentials: Credentials) -> Self {
+        self.credentials = credentials;
+        self
+    }
+
+    pub fn timeouts(mut self, timeouts: Timeouts) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    pub fn retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Proposes `value` and returns the value decided in the instance,
+    /// retrying with a new ballot when one fails.
+    pub async fn propose(&mut self, value: Vec<u8>) -> Result<Decided> {
+        let started_at = Instant::now();
+        let mut attempts = 0;
+
+        loop {
+            attempts += 1;
+
+            let err = match self.propose_once(&value).await {
+                Ok(decided) => return Ok(decided),
+                Err(err) => err,
+            };
+
+            let Some(backoff) = self.retry_policy.backoff(attempts, started_at.elapsed()) else {
+                return Err(err.context(format!("giving up after {attempts} attempts")));
+            };
+            warn!(
+                attempt = attempts,
+                ?backoff,
+                ?err,
+                "vertical ballot failed, retrying"
+            );
+            tokio::time::sleep(backoff).await;
+        }
+    }
+
+    async fn propose_once(&mut self, value: &[u8]) -> Result<Decided> {
+        let BallotAssignment {
+            configuration,
+            previous,
+        } = self
+            .master
+            .new_ballot(&self.credentials, &self.instance)
+            .await?;
+        let ballot = configuration.ballot;
+
+        // Whatever may have been chosen is held by the last complete ballot.
+        let inherited = match &previous {
+            None => None,
+            Some(previous) => self.prepare(ballot, &previous.acceptors).await?,
+        };
+
+        let proposed = match inherited {
+            // The new acceptors must hold the inherited value before the
+            // ballot is complete.
+            Some(inherited) => {
+                self.accept(ballot, &configuration.acceptors, inherited.clone())
+                    .await?;
+                self.complete(ballot).await?;
+                inherited
+            }
+            None => {
+                self.complete(ballot).await?;
+                self.accept(ballot, &configuration.acceptors, value.to_vec())
+                    .await?;
+                value.to_vec()
+            }
+        };
+
+        info!(instance = %self.instance, %ballot, "vertical ballot decided");
+        Ok(if proposed == value {
+            Decided::Ours(proposed)
+        } else {
+            Decided::Other(proposed)
+        })
+    }
+
+    async fn complete(&self, ballot: ProposalId) -> Result<()> {
+        if !self
+            .master
+            .complete(&self.credentials, &self.instance, ballot)
+            .await?
+        {
+            return Err(anyhow!(
+                "ballot {ballot} was superseded before it completed"
+            ));
+        }
+        Ok(())
+    }
+
+    /// Asks a majority of `acceptors` to promise `ballot` and returns the
+    /// value accepted in the highest ballot among them.
+    async fn prepare(
+        &mut self,
+        ballot: ProposalId,
+        acceptors: &[SocketAddr],
+    ) -> Result<Option<Vec<u8>>> {
+        let deadline = Instant::now() + self.timeouts.prepare_phase;
+        let mut futures = FuturesUnordered::new();
+
+        for &acceptor in acceptors {
+            let (client, version) = match self.client(acceptor).await {
+                Ok(peer) => peer,
+                Err(err) => {
+                    warn!(%acceptor, ?err, "getting rpc client");
+                    continue;
+                }
+            };
+            let request = PrepareRequest {
+                instance: self.instance.clone(),
+                proposal_id: ballot,
+                proposer: None,
+            };
+            let credentials = self.credentials.clone();
+            futures.push(async move {
+                let response = tokio::time::timeout_at(
+                    deadline,
+                    client.prepare(
+                        timeout::context_until(deadline),
+                        credentials.sign("prepare", &request),
+                        request.clone(),
+                    ),
+                )
+                .await
+                .map(|result| {
+                    result.map(|answer| {
+                        paxos::open(&credentials, version, "prepare", &request, answer)
+                    })
+                });
+                (acceptor, response)
+            });
+        }
+
+        let mut promised = 0;
+        let mut accepted = Vec::new();
+        while promised < majority(acceptors) {
+            let Some((acceptor, response)) = futures.next().await else {
+                break;
+            };
+
+            match response {
+                Ok(Ok(Ok(response))) if response.promised => {
+                    promised += 1;
+                    accepted.push(
+                        response
+                            .proposal_value
+                            .map(|value| (response.accepted_id, value)),
+                    );
+                }
+                Ok(Ok(Ok(response))) => {
+                    return Err(Preempted {
+                        phase: Phase::Prepare,
+                        promised: response.proposal_id,
+                    }
+                    .into());
+                }
+                Ok(Ok(Err(err))) => warn!(%acceptor, %err, "error response to prepare request"),
+                Ok(Err(err)) => {
+                    warn!(%acceptor, ?err, "rpc error");
+                    self.clients.remove(&acceptor);
+                }
+                Err(_) => warn!(%acceptor, "prepare request timed out"),
+            }
+        }
+
+        if promised < majority(acceptors) {
+            return Err(anyhow!(
+                "unable to get promises from a majority of the previous configuration"
+            ));
+        }
+
+        Ok(protocol::choose_value(accepted))
+    }
+
+    /// Asks `acceptors` to accept `value` in `ballot`, returning once a
+    /// majority did.
+    async fn accept(
+        &mut self,
+        ballot: ProposalId,
+        acceptors: &[SocketAddr],
+        value: Vec<u8>,
+    ) -> Result<()> {
+        let deadline = Instant::now() + self.timeouts.accept_phase;
+        let mut futures = FuturesUnordered::new();
+
+        for &acceptor in acceptors {
+            let (client, version) = match self.client(acceptor).await {
+                Ok(peer) => peer,
+                Err(err) => {
+                    warn!(%acceptor, ?err, "getting rpc client");
+                    continue;
+                }
+            };
+            let request = AcceptRequest {
+                instance: self.instance.clone(),
+                proposal_id: ballot,
+                proposal_value: value.clone(),
+            };
+            let credentials = self.credentials.clone();
+            futures.push(async move {
+                let response = tokio::time::timeout_at(
+                    deadline,
+                    client.accept(
+                        timeout::context_until(deadline),
+                        credentials.sign("accept", &request),
+                        request.clone(),
+                    ),
+                )
+                .await
+                .map(|result| {
+                    result.map(|answer| {
+                        paxos::open(&credentials, version, "accept", &request, answer)
+                    })
+                });
+                (acceptor, response)
+            });
+        }
+
+        let mut accepted = 0;
+        while accepted < majority(acceptors) {
+            let Some((acceptor, response)) = futures.next().await else {
+                break;
+            };
+
+            match response {
+                Ok(Ok(Ok(response))) if response.proposal_id <= ballot => accepted += 1,
+                Ok(Ok(Ok(response))) => {
+                    return Err(Preempted {
+                        phase: Phase::Accept,
+                        promised: response.proposal_id,
+                    }
+                    .into());
+                }
+                Ok(Ok(Err(err))) => warn!(%acceptor, %err, "error response to accept request"),
+                Ok(Err(err)) => {
+                    warn!(%acceptor, ?err, "rpc error");
+                    self.clients.remove(&acceptor);
+                }
+                Err(_) => warn!(%acceptor, "accept request timed out"),
+            }
+        }
+
+        if accepted < majority(acceptors) {
+            return Err(anyhow!(
+                "unable to get a majority of the configuration to accept"
+            ));
+        }
+
+        Ok(())
+    }
+
+    async fn client(&mut self, acceptor: SocketAddr) -> Result<(AcceptorServiceClient, u32)> {
+        if let Some(peer) = self.clients.get(&acceptor) {
+            return Ok(peer.clone());
+        }
+
+        let peer = paxos::connect_negotiated(&self.connector, acceptor).await?;
+        self.clients.insert(acceptor, peer.clone());
+        Ok(peer)
+    }
+}
+
+fn majority(acceptors: &[SocketAddr]) -> usize {
+    acceptors.len() / 2 + 1
+}
@@ -0,0 +1,24 @@
+//! Checks the schedule of anti-entropy passes.
+
+use single_decree_paxos::antientropy::AntiEntropyConfig;
+use std::time::Duration;
+
+#[test]
+fn passes_are_spread_within_the_jitter() {
+    let config = AntiEntropyConfig {
+        interval: Duration::from_secs(10),
+        jitter: 0.2,
+    };
+
+    for _ in 0..1000 {
+        let delay = config.next_delay();
+        assert!(delay >= Duration::from_secs(8), "{delay:?}");
+        assert!(delay <= Duration::from_secs(12), "{delay:?}");
+    }
+
+    let config = AntiEntropyConfig {
+        jitter: 0.0,
+        ..config
+    };
+    assert_eq!(config.next_delay(), Duration::from_secs(10));
+}
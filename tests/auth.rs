@@ -0,0 +1,108 @@
+//! Checks which protocol messages an acceptor configured with a message key lets through.
+
+use single_decree_paxos::{
+    auth::{Authenticator, Credentials, MessageKey, Signed},
+    instance::InstanceId,
+    paxos::{AcceptRequest, AcceptResponse, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION},
+    proposal::ProposalId,
+};
+
+fn request(value: &[u8]) -> AcceptRequest {
+    AcceptRequest {
+        instance: InstanceId::default(),
+        proposal_id: ProposalId::new(3),
+        proposal_value: value.to_vec(),
+    }
+}
+
+#[test]
+fn only_messages_signed_with_the_cluster_key_are_accepted() {
+    let mut authenticator = Authenticator::default();
+    authenticator.set_message_key(MessageKey::new(&[1; 32]));
+
+    let credentials = Credentials {
+        message_key: Some(MessageKey::new(&[1; 32])),
+        ..Credentials::default()
+    };
+    let foreign = Credentials {
+        message_key: Some(MessageKey::new(&[2; 32])),
+        ..Credentials::default()
+    };
+
+    let accept = |credentials: &Credentials, kind, message: &AcceptRequest| {
+        authenticator.authenticate_message(credentials, None, kind, message)
+    };
+
+    let signed = credentials.sign("accept", &request(b"value"));
+    assert!(accept(&signed, "accept", &request(b"value")).is_ok());
+
+    // Tampered with on the way.
+    assert!(accept(&signed, "accept", &request(b"other")).is_err());
+    // Replayed as another rpc.
+    assert!(accept(&signed, "accept_chunk", &request(b"value")).is_err());
+    // Unsigned, or signed by another cluster.
+    assert!(accept(&Credentials::default(), "accept", &request(b"value")).is_err());
+    let signed = foreign.sign("accept", &request(b"value"));
+    assert!(accept(&signed, "accept", &request(b"value")).is_err());
+}
+
+#[test]
+fn answers_only_open_for_the_request_they_were_sealed_for() {
+    let acceptor = Credentials {
+        message_key: Some(MessageKey::new(&[1; 32])),
+        ..Credentials::default()
+    };
+    let foreign = Credentials {
+        message_key: Some(MessageKey::new(&[2; 32])),
+        ..Credentials::default()
+    };
+    let response = || AcceptResponse {
+        proposal_id: ProposalId::new(3),
+        proposal_value: None,
+    };
+
+    let sealed = acceptor.seal("accept", &request(b"value"), response());
+    assert!(acceptor
+        .open("accept", &request(b"value"), sealed.clone())
+        .is_ok());
+
+    // Handed out as the answer to another request or rpc.
+    assert!(acceptor
+        .open("accept", &request(b"other"), sealed.clone())
+        .is_err());
+    assert!(acceptor
+        .open("accept_chunk", &request(b"value"), sealed.clone())
+        .is_err());
+    // Made up by someone without the key.
+    let forged = foreign.seal("accept", &request(b"value"), response());
+    assert!(acceptor.open("accept", &request(b"value"), forged).is_err());
+    let mut unsigned = sealed;
+    unsigned.mac = None;
+    assert!(acceptor
+        .open("accept", &request(b"value"), unsigned)
+        .is_err());
+}
+
+#[test]
+fn answers_for_older_peers_go_over_the_wire_bare() {
+    let acceptor = Credentials {
+        message_key: Some(MessageKey::new(&[1; 32])),
+        ..Credentials::default()
+    };
+    let response = AcceptResponse {
+        proposal_id: ProposalId::new(3),
+        proposal_value: None,
+    };
+    let sealed = acceptor.seal("accept", &request(b"value"), response);
+
+    let encoded = serde_json::to_string(&sealed.clone().for_version(MIN_PROTOCOL_VERSION)).unwrap();
+    assert_eq!(encoded, serde_json::to_string(&sealed.value).unwrap());
+    let decoded: Signed<AcceptResponse> = serde_json::from_str(&encoded).unwrap();
+    assert!(decoded.unsealed);
+    assert_eq!(decoded.mac, None);
+
+    let encoded = serde_json::to_string(&sealed.clone().for_version(PROTOCOL_VERSION)).unwrap();
+    let decoded: Signed<AcceptResponse> = serde_json::from_str(&encoded).unwrap();
+    assert!(!decoded.unsealed);
+    assert!(acceptor.open("accept", &request(b"value"), decoded).is_ok());
+}
@@ -0,0 +1,82 @@
+//! Checks that a node turns proposals away once too many are pending.
+
+mod common;
+
+use common::data_dir;
+use single_decree_paxos::{
+    backpressure::{InflightRequests, Overloaded},
+    paxos::Paxos,
+    queue::{Priority, ProposalQueue},
+};
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+use tokio::{sync::Mutex, time::Instant};
+
+#[tokio::test]
+async fn proposals_over_the_cap_are_turned_away() {
+    let addr = SocketAddr::from(([127, 0, 0, 1], 8000));
+    let node = Paxos::builder(1, addr, vec![addr])
+        .data_dir(data_dir("over-the-cap"))
+        .build()
+        .await
+        .unwrap();
+    let node = Arc::new(Mutex::new(node));
+    let queue = ProposalQueue::spawn(Arc::clone(&node)).max_pending(2);
+
+    // Holding the node keeps the proposals pending.
+    let held = node.lock().await;
+    let pending: Vec<_> = (0..2)
+        .map(|i| {
+            let queue = queue.clone();
+            tokio::spawn(async move { queue.propose(vec![i], Priority::Normal).await })
+        })
+        .collect();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    let err = queue
+        .propose(b"one too many".to_vec(), Priority::Normal)
+        .await
+        .unwrap_err();
+    assert_eq!(err.downcast_ref::<Overloaded>().unwrap().limit, 2);
+
+    drop(held);
+    for proposal in pending {
+        proposal.await.unwrap().unwrap();
+    }
+
+    // The permits come back once the proposals are done.
+    queue
+        .propose(b"after".to_vec(), Priority::Normal)
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn requests_wait_for_a_slot_until_their_deadline() {
+    let inflight = InflightRequests::new(1);
+
+    let held = inflight
+        .acquire_until(Instant::now() + Duration::from_millis(50))
+        .await
+        .unwrap();
+    assert!(held.is_some());
+
+    // The only slot is taken, the request gives up at its deadline.
+    assert!(inflight
+        .acquire_until(Instant::now() + Duration::from_millis(50))
+        .await
+        .is_err());
+
+    drop(held);
+    assert!(inflight
+        .acquire_until(Instant::now() + Duration::from_millis(50))
+        .await
+        .unwrap()
+        .is_some());
+
+    // Uncapped requests never wait.
+    assert!(InflightRequests::default()
+        .acquire_until(Instant::now())
+        .await
+        .unwrap()
+        .is_none());
+}
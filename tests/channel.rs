@@ -0,0 +1,1063 @@
+//! Drives a proposer against acceptors served over a [ChannelNetwork], which
+//! hands requests to the acceptors without sockets or encoding.
+
+mod common;
+
+use common::data_dir;
+use single_decree_paxos::{
+    auth::{Credentials, MessageKey},
+    channel::ChannelNetwork,
+    instance::InstanceId,
+    keepalive::KeepaliveConfig,
+    lease::{LeaseConfig, NotLeader},
+    metrics::{FanoutRecorder, PrometheusRecorder},
+    paxos::{
+        self, AcceptRequest, AcceptorError, Cancelled, Decided, Hello, Paxos, PaxosBuilder,
+        PrepareRequest, PromisePolicy, QuorumSelection, TopologyError, ValueTooLarge,
+        MIN_PROTOCOL_VERSION, PROTOCOL_VERSION,
+    },
+    proposal::ProposalId,
+    rejoin::Rejoin,
+    retry::RetryPolicy,
+    timeout::Timeouts,
+    transport::Connector,
+};
+use std::{net::SocketAddr, path::PathBuf, sync::Arc, time::Duration};
+use tarpc::context;
+use tokio::{sync::Mutex, task::JoinHandle};
+use tokio_util::sync::CancellationToken;
+
+struct Cluster {
+    network: ChannelNetwork,
+    data_dir: PathBuf,
+    acceptors: Vec<SocketAddr>,
+    servers: Vec<(Arc<Mutex<Paxos>>, JoinHandle<()>)>,
+}
+
+impl Cluster {
+    async fn start(name: &str) -> Self {
+        Self::start_with_witnesses(name, &[]).await
+    }
+
+    /// Starts the acceptors at the positions in `witnesses` as witnesses.
+    async fn start_with_witnesses(name: &str, witnesses: &[usize]) -> Self {
+        let witnesses: Vec<_> = witnesses.iter().map(|i| Self::address(*i)).collect();
+        Self::start_with(name, |builder| builder.witnesses(witnesses.clone())).await
+    }
+
+    /// The address of the acceptor at position `i`.
+    fn address(i: usize) -> SocketAddr {
+        SocketAddr::from(([10, 0, 0, i as u8 + 1], 8000))
+    }
+
+    /// Starts the acceptors with the options `configure` sets.
+    async fn start_with(name: &str, configure: impl Fn(PaxosBuilder) -> PaxosBuilder) -> Self {
+        let data_dir = data_dir(name);
+
+        let network = ChannelNetwork::default();
+        let acceptors: Vec<_> = (0..3).map(Self::address).collect();
+
+        let mut servers = Vec::new();
+        for (i, addr) in acceptors.iter().enumerate() {
+            let builder = Paxos::builder(i as u32 + 1, *addr, acceptors.clone())
+                .connector(Connector::channels(network.clone()))
+                .data_dir(&data_dir);
+            let paxos = configure(builder).build().await.unwrap();
+            let paxos = Arc::new(Mutex::new(paxos));
+            let server = network.serve(*addr, Arc::clone(&paxos));
+            servers.push((paxos, server));
+        }
+
+        Self {
+            network,
+            data_dir,
+            acceptors,
+            servers,
+        }
+    }
+
+    /// A proposer that isn't one of the acceptors.
+    async fn proposer(&self) -> Paxos {
+        self.proposer_builder(9).build().await.unwrap()
+    }
+
+    fn proposer_builder(&self, id: u8) -> PaxosBuilder {
+        let timeouts = Timeouts {
+            prepare_rpc: Duration::from_millis(100),
+            prepare_phase: Duration::from_millis(200),
+            accept_rpc: Duration::from_millis(100),
+            accept_phase: Duration::from_millis(200),
+        };
+
+        Paxos::builder(
+            id as u32,
+            SocketAddr::from(([10, 0, 0, id], 8000)),
+            self.acceptors.clone(),
+        )
+        .connector(Connector::channels(self.network.clone()))
+        .data_dir(&self.data_dir)
+        .timeouts(timeouts)
+    }
+
+    fn stop(&self, i: usize) {
+        self.network.close(self.acceptors[i]);
+        self.servers[i].1.abort();
+    }
+
+    /// Serves a stopped acceptor again, with the state it had.
+    fn restart(&mut self, i: usize) {
+        let paxos = Arc::clone(&self.servers[i].0);
+        self.servers[i].1 = self.network.serve(self.acceptors[i], paxos);
+    }
+
+    /// Serves every acceptor again as a build that speaks at most `max_version`.
+    fn downgrade(&mut self, max_version: u32) {
+        for i in 0..self.servers.len() {
+            self.stop(i);
+            let paxos = Arc::clone(&self.servers[i].0);
+            self.servers[i].1 = self
+                .network
+                .serve_version(self.acceptors[i], paxos, max_version);
+        }
+    }
+}
+
+impl Drop for Cluster {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.data_dir);
+    }
+}
+
+#[tokio::test]
+async fn a_majority_of_acceptors_is_enough() {
+    let cluster = Cluster::start("majority").await;
+    cluster.stop(2);
+
+    let mut proposer = cluster.proposer().await;
+    proposer.propose(b"value".to_vec()).await.unwrap();
+
+    for (paxos, _) in &cluster.servers[..2] {
+        assert_eq!(paxos.lock().await.accepted_value(), Some(&b"value"[..]));
+    }
+}
+
+#[tokio::test]
+async fn a_minority_of_acceptors_decides_nothing() {
+    let cluster = Cluster::start("minority").await;
+    cluster.stop(1);
+    cluster.stop(2);
+
+    let mut proposer = cluster.proposer().await;
+    assert!(proposer.propose(b"value".to_vec()).await.is_err());
+
+    assert_eq!(cluster.servers[0].0.lock().await.accepted_value(), None);
+}
+
+#[tokio::test]
+async fn the_proposer_adopts_a_value_accepted_by_one_acceptor() {
+    let cluster = Cluster::start("adopt").await;
+
+    cluster.servers[0]
+        .0
+        .lock()
+        .await
+        .on_accept(AcceptRequest {
+            instance: InstanceId::default(),
+            proposal_id: ProposalId::new(1),
+            proposal_value: b"first".to_vec(),
+        })
+        .await
+        .unwrap();
+
+    let mut proposer = cluster.proposer().await;
+    let decided = proposer.propose(b"second".to_vec()).await.unwrap();
+    assert_eq!(decided, Decided::Other(b"first".to_vec()));
+
+    let mut accepted = 0;
+    for (paxos, _) in &cluster.servers {
+        if paxos.lock().await.accepted_value() == Some(&b"first"[..]) {
+            accepted += 1;
+        }
+    }
+    assert!(
+        accepted >= 2,
+        "only {accepted} acceptors accepted the value"
+    );
+}
+
+#[tokio::test]
+async fn acceptors_promise_an_equal_id_again_only_when_configured_to() {
+    for (policy, promised_again) in [
+        (PromisePolicy::StrictlyGreater, false),
+        (PromisePolicy::GreaterOrEqual, true),
+    ] {
+        let cluster = Cluster::start_with(&format!("promise-policy-{policy:?}"), |builder| {
+            builder.promise_policy(policy)
+        })
+        .await;
+        let mut paxos = cluster.servers[0].0.lock().await;
+
+        let request = PrepareRequest {
+            instance: InstanceId::default(),
+            proposal_id: ProposalId::new(1),
+            proposer: None,
+        };
+        assert!(paxos.on_prepare(request.clone()).await.unwrap().promised);
+        assert_eq!(
+            paxos.on_prepare(request).await.unwrap().promised,
+            promised_again,
+            "{policy:?}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn acceptors_answer_with_typed_errors() {
+    let cluster = Cluster::start("errors").await;
+    let connector = Connector::channels(cluster.network.clone());
+    let client = paxos::connect(&connector, cluster.acceptors[0])
+        .await
+        .unwrap();
+
+    let response = client
+        .prepare(
+            context::current(),
+            Credentials::default(),
+            PrepareRequest {
+                instance: "other".parse().unwrap(),
+                proposal_id: ProposalId::new(1),
+                proposer: None,
+            },
+        )
+        .await
+        .unwrap();
+    assert!(matches!(
+        response,
+        Err(AcceptorError::InvalidRequest { .. })
+    ));
+
+    cluster.servers[0].0.lock().await.shutdown().await.unwrap();
+    let response = client
+        .prepare(
+            context::current(),
+            Credentials::default(),
+            PrepareRequest {
+                instance: InstanceId::default(),
+                proposal_id: ProposalId::new(1),
+                proposer: None,
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.unwrap_err(), AcceptorError::ShuttingDown);
+}
+
+#[tokio::test]
+async fn acceptors_refuse_protocol_versions_they_dont_know() {
+    let cluster = Cluster::start("hello").await;
+    let connector = Connector::channels(cluster.network.clone());
+    let client = paxos::connect(&connector, cluster.acceptors[0])
+        .await
+        .unwrap();
+
+    let response = client
+        .hello(
+            context::current(),
+            Hello {
+                protocol_version: PROTOCOL_VERSION + 1,
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        response.unwrap_err(),
+        AcceptorError::UnsupportedVersion {
+            version: PROTOCOL_VERSION + 1,
+            min_supported: MIN_PROTOCOL_VERSION,
+            max_supported: PROTOCOL_VERSION,
+        }
+    );
+}
+
+#[tokio::test]
+async fn proposers_downgrade_to_acceptors_of_older_builds() {
+    for max_version in MIN_PROTOCOL_VERSION..PROTOCOL_VERSION {
+        let mut cluster = Cluster::start(&format!("downgrade-{max_version}")).await;
+        cluster.downgrade(max_version);
+
+        let mut proposer = cluster.proposer().await;
+        assert_eq!(
+            proposer.propose(b"value".to_vec()).await.unwrap(),
+            Decided::Ours(b"value".to_vec()),
+            "version {max_version}"
+        );
+        assert_eq!(
+            cluster.network.unknown_requests(),
+            0,
+            "version {max_version}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn proposers_with_a_message_key_refuse_unsealed_answers() {
+    let mut cluster = Cluster::start_with("downgrade-sealed", |builder| {
+        builder.credentials(credentials(b"cluster key"))
+    })
+    .await;
+    // Like acceptors of a build from before answers were sealed, or anyone
+    // on the path answering the handshake in their place.
+    cluster.downgrade(MIN_PROTOCOL_VERSION);
+
+    let mut proposer = cluster
+        .proposer_builder(9)
+        .credentials(credentials(b"cluster key"))
+        .retry_policy(RetryPolicy {
+            max_attempts: 1,
+            ..Default::default()
+        })
+        .build()
+        .await
+        .unwrap();
+    assert!(proposer.propose(b"value".to_vec()).await.is_err());
+
+    for (paxos, _) in &cluster.servers {
+        assert_eq!(paxos.lock().await.accepted_value(), None);
+    }
+}
+
+#[tokio::test]
+async fn acceptors_answer_proposers_of_older_builds_unsealed() {
+    let cluster = Cluster::start_with("unsealed", |builder| {
+        builder.credentials(credentials(b"cluster key"))
+    })
+    .await;
+    let connector = Connector::channels(cluster.network.clone());
+    let client = paxos::connect(&connector, cluster.acceptors[0])
+        .await
+        .unwrap();
+
+    let response = client
+        .hello(
+            context::current(),
+            Hello {
+                protocol_version: MIN_PROTOCOL_VERSION,
+            },
+        )
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(response.protocol_version, PROTOCOL_VERSION);
+
+    let answer = client
+        .prepare(
+            context::current(),
+            credentials(b"cluster key"),
+            PrepareRequest {
+                instance: InstanceId::default(),
+                proposal_id: ProposalId::new(1),
+                proposer: None,
+            },
+        )
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(answer.unsealed);
+    assert_eq!(answer.mac, None);
+    assert!(answer.value.promised);
+}
+
+#[tokio::test]
+async fn proposals_point_at_the_leader_while_it_holds_the_lease() {
+    let cluster = Cluster::start("leader").await;
+    let lease = LeaseConfig::new(Duration::from_secs(10));
+
+    let leader_addr: SocketAddr = "10.0.0.8:7008".parse().unwrap();
+    let mut leader = cluster
+        .proposer_builder(8)
+        .lease(lease)
+        .forward_to_leader(leader_addr)
+        .build()
+        .await
+        .unwrap();
+    leader.heartbeat().await.unwrap();
+
+    let mut follower = cluster
+        .proposer_builder(9)
+        .lease(lease)
+        .forward_to_leader("10.0.0.9:7009".parse().unwrap())
+        .build()
+        .await
+        .unwrap();
+    let err = follower.propose(b"value".to_vec()).await.unwrap_err();
+    assert_eq!(
+        err.downcast::<NotLeader>().unwrap(),
+        NotLeader {
+            leader: 8,
+            client_addr: leader_addr,
+        }
+    );
+
+    leader.propose(b"value".to_vec()).await.unwrap();
+}
+
+#[tokio::test]
+async fn the_leader_reads_locally_while_it_holds_the_lease() {
+    let cluster = Cluster::start("lease-reads").await;
+
+    let mut leader = cluster
+        .proposer_builder(8)
+        .lease(LeaseConfig::new(Duration::from_secs(10)))
+        .lease_reads(true)
+        .build()
+        .await
+        .unwrap();
+    leader.heartbeat().await.unwrap();
+    assert_eq!(leader.read().await.unwrap(), None);
+
+    // A read round would have the acceptors promise a higher proposal id. The
+    // wait lets the prepare requests of the first round that didn't count
+    // towards the quorum arrive.
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let promised = cluster.servers[0].0.lock().await.on_health().proposal_id;
+    assert_eq!(leader.read().await.unwrap(), None);
+    assert_eq!(
+        cluster.servers[0].0.lock().await.on_health().proposal_id,
+        promised
+    );
+}
+
+#[tokio::test]
+async fn acceptors_refuse_prepares_from_other_nodes_while_the_lease_is_held() {
+    let cluster = Cluster::start("lease-enforced").await;
+
+    let mut leader = cluster
+        .proposer_builder(8)
+        .lease(LeaseConfig::new(Duration::from_secs(10)))
+        .build()
+        .await
+        .unwrap();
+    leader.heartbeat().await.unwrap();
+    // Lets the heartbeats that didn't count towards the quorum arrive.
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    for (server, _) in &cluster.servers {
+        for proposer in [Some(9), None] {
+            let err = server
+                .lock()
+                .await
+                .on_prepare(PrepareRequest {
+                    instance: InstanceId::default(),
+                    proposal_id: ProposalId::new(1_000),
+                    proposer,
+                })
+                .await
+                .unwrap_err();
+            assert!(matches!(
+                err.downcast::<AcceptorError>().unwrap(),
+                AcceptorError::LeaseHeld { holder: 8, .. }
+            ));
+        }
+    }
+
+    leader.propose(b"value".to_vec()).await.unwrap();
+}
+
+#[tokio::test]
+async fn large_values_are_sent_in_chunks() {
+    let cluster = Cluster::start("chunks").await;
+
+    let mut proposer = cluster
+        .proposer_builder(9)
+        .chunk_size(4)
+        .max_value_size(32)
+        .build()
+        .await
+        .unwrap();
+
+    let value = b"a value sent in chunks".to_vec();
+    proposer.propose(value.clone()).await.unwrap();
+    for (paxos, _) in &cluster.servers {
+        assert_eq!(paxos.lock().await.accepted_value(), Some(&value[..]));
+    }
+
+    let err = proposer.propose(vec![0; 33]).await.unwrap_err();
+    assert!(err.is::<ValueTooLarge>());
+}
+
+#[tokio::test]
+async fn round_trips_are_recorded_per_acceptor() {
+    let cluster = Cluster::start("rtt").await;
+
+    let metrics = Arc::new(PrometheusRecorder::default());
+    let mut proposer = cluster
+        .proposer_builder(9)
+        .metrics(Arc::clone(&metrics) as _)
+        .build()
+        .await
+        .unwrap();
+    proposer.propose(b"value".to_vec()).await.unwrap();
+
+    // Acceptors that answer after the quorum are recorded in the background.
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let rendered = metrics.render();
+    for acceptor in &cluster.acceptors {
+        for phase in ["prepare", "accept"] {
+            let count = format!(
+                "paxos_acceptor_rtt_seconds_count{{phase=\"{phase}\",acceptor=\"{acceptor}\"}} 1"
+            );
+            assert!(rendered.contains(&count), "missing {count} in\n{rendered}");
+        }
+    }
+    assert!(rendered.contains("paxos_prepare_quorum_seconds_count 1"));
+    assert!(rendered.contains("paxos_accept_quorum_seconds_count 1"));
+}
+
+#[tokio::test]
+async fn fanned_out_metrics_reach_every_recorder() {
+    let cluster = Cluster::start("fanout").await;
+
+    let recorders = [(); 2].map(|_| Arc::new(PrometheusRecorder::default()));
+    let mut proposer = cluster
+        .proposer_builder(9)
+        .metrics(Arc::new(FanoutRecorder::new(
+            recorders.iter().map(|r| Arc::clone(r) as _).collect(),
+        )))
+        .build()
+        .await
+        .unwrap();
+    proposer.propose(b"value".to_vec()).await.unwrap();
+
+    for recorder in &recorders {
+        let rendered = recorder.render();
+        assert!(rendered.contains("paxos_accept_quorum_seconds_count 1"));
+    }
+}
+
+#[tokio::test]
+async fn auxiliary_acceptors_are_left_out_while_the_main_ones_respond() {
+    let cluster = Cluster::start("auxiliary").await;
+
+    let mut proposer = cluster
+        .proposer_builder(9)
+        .auxiliary_acceptors(vec![cluster.acceptors[2]])
+        .build()
+        .await
+        .unwrap();
+    proposer.propose(b"value".to_vec()).await.unwrap();
+
+    // Give requests that would have gone to the auxiliary acceptor time to land.
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    for (paxos, _) in &cluster.servers[..2] {
+        assert_eq!(paxos.lock().await.accepted_value(), Some(&b"value"[..]));
+    }
+    assert_eq!(cluster.servers[2].0.lock().await.accepted_value(), None);
+}
+
+#[tokio::test]
+async fn auxiliary_acceptors_stand_in_for_a_failed_main_one() {
+    let cluster = Cluster::start("auxiliary-failover").await;
+    cluster.stop(1);
+
+    let mut proposer = cluster
+        .proposer_builder(9)
+        .auxiliary_acceptors(vec![cluster.acceptors[2]])
+        .build()
+        .await
+        .unwrap();
+    proposer.propose(b"value".to_vec()).await.unwrap();
+
+    for i in [0, 2] {
+        assert_eq!(
+            cluster.servers[i].0.lock().await.accepted_value(),
+            Some(&b"value"[..])
+        );
+    }
+}
+
+#[tokio::test]
+async fn auxiliary_acceptors_are_left_out_again_once_a_value_is_decided() {
+    let mut cluster = Cluster::start("auxiliary-recovery").await;
+    cluster.stop(1);
+
+    let mut proposer = cluster
+        .proposer_builder(9)
+        .auxiliary_acceptors(vec![cluster.acceptors[2]])
+        .build()
+        .await
+        .unwrap();
+    let auxiliary_skipped = |proposer: &Paxos| {
+        proposer
+            .peers()
+            .iter()
+            .find(|peer| peer.auxiliary)
+            .unwrap()
+            .skipped
+    };
+    assert!(auxiliary_skipped(&proposer));
+
+    // The main acceptors can't form a quorum, the next rounds ask the
+    // auxiliary acceptor too.
+    assert!(proposer.read().await.is_err());
+    assert!(!auxiliary_skipped(&proposer));
+
+    cluster.restart(1);
+    proposer.propose(b"value".to_vec()).await.unwrap();
+    assert!(auxiliary_skipped(&proposer));
+}
+
+#[tokio::test]
+async fn main_acceptors_must_form_a_quorum() {
+    let cluster = Cluster::start("auxiliary-quorum").await;
+
+    let err = cluster
+        .proposer_builder(9)
+        .auxiliary_acceptors(cluster.acceptors[1..].to_vec())
+        .build()
+        .await
+        .unwrap_err();
+    assert_eq!(
+        err.downcast_ref::<TopologyError>(),
+        Some(&TopologyError::TooFewMainAcceptors { main: 1, quorum: 2 })
+    );
+}
+
+#[tokio::test]
+async fn the_nearest_quorum_is_asked_alone() {
+    let cluster = Cluster::start("nearest").await;
+
+    let mut proposer = cluster
+        .proposer_builder(9)
+        .quorum_selection(QuorumSelection::Nearest { spare: 0 })
+        .build()
+        .await
+        .unwrap();
+    proposer.propose(b"value".to_vec()).await.unwrap();
+
+    // Give requests that would have gone to the third acceptor time to land.
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    for (paxos, _) in &cluster.servers[..2] {
+        assert_eq!(paxos.lock().await.accepted_value(), Some(&b"value"[..]));
+    }
+    assert_eq!(cluster.servers[2].0.lock().await.accepted_value(), None);
+}
+
+#[tokio::test]
+async fn far_acceptors_are_asked_when_the_nearest_cant_decide() {
+    let cluster = Cluster::start("nearest-widened").await;
+    cluster.stop(1);
+
+    let metrics = Arc::new(PrometheusRecorder::default());
+    let mut proposer = cluster
+        .proposer_builder(9)
+        .quorum_selection(QuorumSelection::Nearest { spare: 0 })
+        .metrics(Arc::clone(&metrics) as _)
+        .build()
+        .await
+        .unwrap();
+    proposer.propose(b"value".to_vec()).await.unwrap();
+
+    for i in [0, 2] {
+        assert_eq!(
+            cluster.servers[i].0.lock().await.accepted_value(),
+            Some(&b"value"[..])
+        );
+    }
+    assert!(metrics.render().contains("paxos_quorum_widened_total 1"));
+}
+
+fn credentials(key: &[u8]) -> Credentials {
+    Credentials {
+        message_key: Some(MessageKey::new(key)),
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn relayed_values_are_decided_and_learned() {
+    let cluster = Cluster::start_with("relay", |builder| {
+        builder.credentials(credentials(b"cluster key"))
+    })
+    .await;
+
+    // The first acceptor relays to the other two.
+    let mut proposer = cluster
+        .proposer_builder(9)
+        .credentials(credentials(b"cluster key"))
+        .relay_fanout(1)
+        .build()
+        .await
+        .unwrap();
+    proposer.propose(b"value".to_vec()).await.unwrap();
+
+    // The decided value is handed down the tree in the background.
+    tokio::time::timeout(Duration::from_secs(5), async {
+        for (paxos, _) in &cluster.servers {
+            while paxos.lock().await.decided_value().is_none() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        }
+    })
+    .await
+    .unwrap();
+
+    for (paxos, _) in &cluster.servers {
+        let paxos = paxos.lock().await;
+        assert_eq!(paxos.accepted_value(), Some(&b"value"[..]));
+        assert_eq!(paxos.decided_value(), Some(&b"value"[..]));
+    }
+}
+
+#[tokio::test]
+async fn relayed_acks_the_acceptors_didnt_sign_are_not_counted() {
+    // The acceptors sign nothing, or sign with another key.
+    for (name, key) in [
+        ("relay-unsigned", None),
+        ("relay-other-key", Some(b"other key")),
+    ] {
+        let cluster = Cluster::start_with(name, |builder| match key {
+            Some(key) => builder.credentials(credentials(key)),
+            None => builder,
+        })
+        .await;
+
+        let metrics = Arc::new(PrometheusRecorder::default());
+        let mut proposer = cluster
+            .proposer_builder(9)
+            .credentials(credentials(b"cluster key"))
+            .relay_fanout(1)
+            .retry_policy(RetryPolicy {
+                max_attempts: 1,
+                ..Default::default()
+            })
+            .metrics(Arc::clone(&metrics) as _)
+            .build()
+            .await
+            .unwrap();
+        assert!(proposer.propose(b"value".to_vec()).await.is_err());
+
+        assert!(metrics
+            .render()
+            .contains("paxos_unverified_relayed_responses_total"));
+        assert_eq!(proposer.decided_value(), None);
+    }
+}
+
+#[tokio::test]
+async fn relaying_needs_a_message_key() {
+    let cluster = Cluster::start("relay-no-key").await;
+
+    assert!(cluster
+        .proposer_builder(9)
+        .relay_fanout(1)
+        .build()
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn witnesses_vote_without_storing_values() {
+    let cluster = Cluster::start_with_witnesses("witness", &[2]).await;
+    cluster.stop(1);
+
+    let mut proposer = cluster
+        .proposer_builder(9)
+        .witnesses(vec![cluster.acceptors[2]])
+        .build()
+        .await
+        .unwrap();
+    proposer.propose(b"value".to_vec()).await.unwrap();
+
+    let witness = cluster.servers[2].0.lock().await;
+    assert_eq!(witness.accepted_value(), None);
+    assert!(witness.accepted_id() > ProposalId::ZERO);
+}
+
+#[tokio::test]
+async fn a_value_only_a_witness_reports_is_waited_for() {
+    let mut cluster = Cluster::start_with_witnesses("witness-recovery", &[2]).await;
+
+    // Chosen by acceptor 0 and the witness while acceptor 1 was down.
+    cluster.stop(1);
+    let mut proposer = cluster.proposer().await;
+    proposer.propose(b"first".to_vec()).await.unwrap();
+
+    // The witness and acceptor 1 form a quorum, but neither can say what was chosen.
+    cluster.stop(0);
+    cluster.restart(1);
+    let mut proposer = cluster.proposer_builder(8).build().await.unwrap();
+    assert!(proposer.propose(b"second".to_vec()).await.is_err());
+    assert_eq!(cluster.servers[1].0.lock().await.accepted_value(), None);
+
+    cluster.restart(0);
+    let decided = proposer.propose(b"second".to_vec()).await.unwrap();
+    assert_eq!(decided, Decided::Other(b"first".to_vec()));
+}
+
+#[tokio::test]
+async fn witnesses_cant_form_a_quorum_on_their_own() {
+    let cluster = Cluster::start("witness-quorum").await;
+
+    let err = cluster
+        .proposer_builder(9)
+        .witnesses(cluster.acceptors[1..].to_vec())
+        .build()
+        .await
+        .unwrap_err();
+    assert_eq!(
+        err.downcast_ref::<TopologyError>(),
+        Some(&TopologyError::TooManyWitnesses {
+            witnesses: 2,
+            quorum: 2
+        })
+    );
+}
+
+#[tokio::test]
+async fn a_lagging_acceptor_fetches_the_decided_value() {
+    let mut cluster = Cluster::start("fetch-state").await;
+    cluster.stop(2);
+
+    let mut proposer = cluster.proposer().await;
+    proposer.propose(b"value".to_vec()).await.unwrap();
+
+    cluster.restart(2);
+    // The other acceptors are asked without holding the node.
+    let fetch = cluster.servers[2]
+        .0
+        .lock()
+        .await
+        .begin_fetch_state(&InstanceId::default());
+    let transferred = fetch.run().await;
+    assert_eq!(transferred.len(), 1);
+
+    let mut lagging = cluster.servers[2].0.lock().await;
+    for decided in transferred {
+        lagging.learn_decided(decided).await.unwrap();
+    }
+    assert_eq!(lagging.on_fetch_decided(), Some(b"value".to_vec()));
+}
+
+#[tokio::test]
+async fn anti_entropy_repairs_an_acceptor_that_missed_the_decision() {
+    let mut cluster = Cluster::start("anti-entropy").await;
+    cluster.stop(2);
+
+    let mut proposer = cluster.proposer().await;
+    proposer.propose(b"value".to_vec()).await.unwrap();
+
+    cluster.restart(2);
+    let mut lagging = cluster.servers[2].0.lock().await;
+    assert!(lagging.repair().await.unwrap());
+    assert_eq!(lagging.on_fetch_decided(), Some(b"value".to_vec()));
+
+    // Nothing is left to repair.
+    assert!(!lagging.repair().await.unwrap());
+}
+
+#[tokio::test]
+async fn an_acceptor_that_lost_its_data_catches_up_before_voting() {
+    let cluster = Cluster::start("rejoin").await;
+    let mut proposer = cluster.proposer().await;
+    proposer.propose(b"value".to_vec()).await.unwrap();
+
+    // Acceptor 0 comes back with an empty disk, knowing it ran before.
+    let data_dir = cluster.data_dir.join("wiped");
+    std::fs::create_dir_all(&data_dir).unwrap();
+    std::fs::write(
+        data_dir.join("identity.toml"),
+        "cluster = \"channel\"\ngeneration = 2\nrecovering = true\n",
+    )
+    .unwrap();
+    let rejoin = Rejoin::open(&data_dir, "channel".to_owned()).await.unwrap();
+    rejoin
+        .boot(1, &[], &Connector::default(), &Credentials::default())
+        .await
+        .unwrap();
+
+    let mut wiped = Paxos::builder(1, cluster.acceptors[0], cluster.acceptors.clone())
+        .connector(Connector::channels(cluster.network.clone()))
+        .data_dir(&data_dir)
+        .timeouts(Timeouts {
+            prepare_rpc: Duration::from_millis(100),
+            prepare_phase: Duration::ZERO,
+            accept_rpc: Duration::from_millis(100),
+            accept_phase: Duration::ZERO,
+        })
+        .rejoin(rejoin)
+        .build()
+        .await
+        .unwrap();
+
+    let prepare = PrepareRequest {
+        instance: InstanceId::default(),
+        proposal_id: ProposalId::new(1),
+        proposer: None,
+    };
+    assert!(wiped.on_prepare(prepare).await.is_err());
+
+    // The peers are asked without holding the node, it keeps answering.
+    let fetch = wiped.begin_rejoin().unwrap();
+    let healed = fetch.run().await;
+    assert!(wiped.finish_rejoin(healed).await.unwrap());
+    assert!(wiped.begin_rejoin().is_none());
+    assert_eq!(wiped.on_fetch_decided(), Some(b"value".to_vec()));
+    // The promise it forgot is back.
+    let promised = cluster.servers[1].0.lock().await.proposal_id();
+    assert_eq!(wiped.proposal_id(), promised);
+}
+
+#[tokio::test]
+async fn keepalives_skip_acceptors_that_went_away_until_they_answer_again() {
+    let mut cluster = Cluster::start("keepalive").await;
+    let metrics = Arc::new(PrometheusRecorder::default());
+    let mut proposer = cluster
+        .proposer_builder(9)
+        .metrics(Arc::clone(&metrics) as _)
+        .keepalive(KeepaliveConfig {
+            interval: Duration::from_millis(20),
+            timeout: Duration::from_millis(20),
+            max_misses: 2,
+        })
+        .build()
+        .await
+        .unwrap();
+
+    // Opens the connections the keepalives ping.
+    proposer.read().await.unwrap();
+
+    cluster.stop(2);
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert!(metrics
+        .render()
+        .contains("paxos_keepalive_dead_acceptors_total 1"));
+
+    proposer.propose(b"value".to_vec()).await.unwrap();
+    assert!(metrics
+        .render()
+        .contains("paxos_dead_acceptors_skipped_total"));
+
+    cluster.restart(2);
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert!(metrics
+        .render()
+        .contains("paxos_keepalive_reconnects_total 1"));
+}
+
+#[tokio::test]
+async fn a_cancelled_proposal_leaves_the_node_able_to_propose() {
+    let mut cluster = Cluster::start("cancel").await;
+    let retry_policy = RetryPolicy {
+        max_attempts: u32::MAX,
+        ..Default::default()
+    };
+    let mut proposer = cluster
+        .proposer_builder(9)
+        .retry_policy(retry_policy)
+        .build()
+        .await
+        .unwrap();
+    proposer.sync().await.unwrap();
+
+    cluster.stop(1);
+    cluster.stop(2);
+
+    let cancel = CancellationToken::new();
+    let canceller = {
+        let cancel = cancel.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            cancel.cancel();
+        })
+    };
+
+    // Without a quorum the proposal would be retried forever.
+    let err = tokio::time::timeout(
+        Duration::from_secs(5),
+        proposer.propose_with_cancel(b"cancelled".to_vec(), &cancel),
+    )
+    .await
+    .unwrap()
+    .unwrap_err();
+    assert!(err.is::<Cancelled>(), "{err:?}");
+    canceller.await.unwrap();
+
+    cluster.restart(1);
+    cluster.restart(2);
+
+    let decided = proposer.propose(b"value".to_vec()).await.unwrap();
+    assert_eq!(decided, Decided::Ours(b"value".to_vec()));
+}
+
+#[tokio::test]
+async fn shared_nodes_are_not_held_while_a_proposal_backs_off() {
+    let mut cluster = Cluster::start("backoff").await;
+    let retry_policy = RetryPolicy {
+        max_attempts: u32::MAX,
+        initial_backoff: Duration::from_secs(5),
+        max_backoff: Duration::from_secs(5),
+        deadline: None,
+    };
+    let mut proposer = cluster
+        .proposer_builder(9)
+        .retry_policy(retry_policy)
+        .build()
+        .await
+        .unwrap();
+    proposer.sync().await.unwrap();
+    let proposer = Arc::new(Mutex::new(proposer));
+
+    cluster.stop(1);
+    cluster.stop(2);
+
+    let cancel = CancellationToken::new();
+    let proposal = tokio::spawn({
+        let (proposer, cancel) = (Arc::clone(&proposer), cancel.clone());
+        async move { paxos::propose_shared(&proposer, b"value".to_vec(), &cancel).await }
+    });
+
+    // The first round fails without a quorum, the next one is seconds away.
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    tokio::time::timeout(Duration::from_millis(100), proposer.lock())
+        .await
+        .expect("the node is held while the proposal backs off");
+
+    cancel.cancel();
+    let err = proposal.await.unwrap().unwrap_err();
+    assert!(err.is::<Cancelled>(), "{err:?}");
+}
+
+#[tokio::test]
+async fn nodes_expose_their_state() {
+    let cluster = Cluster::start("inspect").await;
+    let mut proposer = cluster.proposer().await;
+    assert!(!proposer.is_synced());
+    assert_eq!(proposer.decided_value(), None);
+
+    proposer.propose(b"value".to_vec()).await.unwrap();
+
+    assert!(proposer.is_synced());
+    assert_eq!(proposer.decided_value(), Some(&b"value"[..]));
+
+    let mut accepted = 0;
+    for (acceptor, _) in &cluster.servers {
+        let acceptor = acceptor.lock().await;
+        if acceptor.accepted_id() == proposer.current_proposal_id() {
+            assert_eq!(acceptor.accepted_value(), Some(&b"value"[..]));
+            accepted += 1;
+        }
+    }
+    assert!(accepted >= 2);
+
+    let peers = proposer.peers();
+    assert_eq!(
+        peers.iter().map(|peer| peer.acceptor).collect::<Vec<_>>(),
+        cluster.acceptors
+    );
+    assert!(peers.iter().all(|peer| !peer.dead && !peer.skipped));
+    // The round ends once a quorum answered.
+    assert!(peers.iter().filter(|peer| peer.latency.is_some()).count() >= 2);
+}
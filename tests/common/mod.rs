@@ -0,0 +1,17 @@
+//! Fixtures shared by the integration tests.
+
+use std::path::PathBuf;
+
+/// An empty data dir for the test case `name`, named after the test file and
+/// the process so runs never share one. Whatever an earlier run with the same
+/// process id left in it is removed first.
+pub fn data_dir(name: &str) -> PathBuf {
+    let data_dir = std::env::temp_dir().join(format!(
+        "paxos-{}-{name}-{}",
+        env!("CARGO_CRATE_NAME"),
+        std::process::id()
+    ));
+    let _ = std::fs::remove_dir_all(&data_dir);
+    std::fs::create_dir_all(&data_dir).unwrap();
+    data_dir
+}
@@ -0,0 +1,101 @@
+//! Checks which settings of the config file a running node can reload, and
+//! how the acceptors are picked from flags, the file and the genesis document.
+
+mod common;
+
+use common::data_dir;
+use single_decree_paxos::{config::Config, genesis::Genesis, transport::Endpoint};
+use std::net::SocketAddr;
+
+async fn load(name: &str, contents: &str) -> anyhow::Result<Config> {
+    let data_dir = data_dir(name);
+    let path = data_dir.join("config.toml");
+    std::fs::write(&path, contents).unwrap();
+    let config = Config::load(&path).await;
+    let _ = std::fs::remove_dir_all(&data_dir);
+    config
+}
+
+const BASE: &str = r#"
+id = 1
+acceptors = ["127.0.0.1:8001", "127.0.0.1:8002", "127.0.0.1:8003"]
+
+[timeouts]
+prepare_rpc_ms = 2000
+"#;
+
+#[tokio::test]
+async fn timeouts_and_the_log_filter_can_change() {
+    let old = load("old", BASE).await.unwrap();
+    let new = load(
+        "new",
+        &format!(
+            "{}accept_phase_ms = 4000\n\n[log]\nfilter = \"debug\"\n",
+            BASE.replace("2000", "3000")
+        ),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(
+        old.diff(&new).unwrap(),
+        vec![
+            "timeouts.prepare_rpc_ms: 2000 -> 3000",
+            "timeouts.accept_phase_ms: unset -> 4000",
+            "log.filter: unset -> debug",
+        ]
+    );
+    assert!(old.diff(&old).unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn settings_read_at_startup_cant_change() {
+    let old = load("immutable-old", BASE).await.unwrap();
+
+    for new in [
+        BASE.replace("id = 1", "id = 2"),
+        BASE.replace("id = 1", "id = 1\ndata_dir = \"/var/lib/paxos\""),
+        BASE.replace(", \"127.0.0.1:8003\"", ""),
+    ] {
+        let new = load("immutable-new", &new).await.unwrap();
+        assert!(old.diff(&new).is_err(), "{new:?}");
+    }
+}
+
+#[tokio::test]
+async fn invalid_log_filters_are_rejected() {
+    let config = format!("{BASE}\n[log]\nfilter = \"single_decree_paxos=loud\"\n");
+    assert!(load("filter", &config).await.is_err());
+}
+
+#[tokio::test]
+async fn acceptors_come_from_flags_then_the_file_then_genesis() {
+    let addr = |port: u16| SocketAddr::from(([127, 0, 0, 1], port));
+
+    let mut config = load("acceptors", BASE).await.unwrap();
+    let flags = vec![Endpoint::Addr(addr(9001))];
+    let (acceptors, _) = config.resolve_acceptors(flags, None).await.unwrap();
+    assert_eq!(acceptors, vec![addr(9001)]);
+
+    let (acceptors, _) = config.resolve_acceptors(Vec::new(), None).await.unwrap();
+    assert_eq!(acceptors, vec![addr(8001), addr(8002), addr(8003)]);
+
+    let genesis = Genesis {
+        cluster_id: "test".to_owned(),
+        acceptors: vec![addr(9001), addr(9002), addr(9003)],
+        quorum: None,
+        signing_keys: Vec::new(),
+    };
+    // The file's acceptors were taken by the call above.
+    let (acceptors, _) = config
+        .resolve_acceptors(Vec::new(), Some(&genesis))
+        .await
+        .unwrap();
+    assert_eq!(acceptors, genesis.acceptors);
+
+    let mut config = load("mismatch", BASE).await.unwrap();
+    assert!(config
+        .resolve_acceptors(Vec::new(), Some(&genesis))
+        .await
+        .is_err());
+}
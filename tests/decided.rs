@@ -0,0 +1,92 @@
+//! Checks how a node reports decisions against a single node cluster, where
+//! the node is the only acceptor and rounds never leave the process.
+
+mod common;
+
+use common::data_dir;
+use single_decree_paxos::{
+    events::Event,
+    instance::InstanceId,
+    paxos::{Decided, Paxos},
+    proposal::ProposalId,
+};
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+#[tokio::test]
+async fn on_decided_fires_once_per_decision() {
+    let data_dir = data_dir("on-decided");
+    let addr = SocketAddr::from(([127, 0, 0, 1], 8001));
+    let decisions = Arc::new(Mutex::new(Vec::new()));
+
+    let builder = {
+        let decisions = Arc::clone(&decisions);
+        Paxos::builder(1, addr, vec![addr])
+            .data_dir(&data_dir)
+            .on_decided(move |instance, value| {
+                decisions
+                    .lock()
+                    .unwrap()
+                    .push((instance.clone(), value.to_vec()));
+            })
+    };
+
+    let mut paxos = builder.clone().build().await.unwrap();
+    paxos.propose(b"first".to_vec()).await.unwrap();
+    // Learning the same value again is not a new decision.
+    assert_eq!(
+        paxos.propose(b"second".to_vec()).await.unwrap(),
+        Decided::Other(b"first".to_vec())
+    );
+    assert_eq!(
+        *decisions.lock().unwrap(),
+        vec![(InstanceId::default(), b"first".to_vec())]
+    );
+
+    paxos.shutdown().await.unwrap();
+    drop(paxos);
+
+    // A restarted node reports the decision it finds on disk.
+    let _paxos = builder.build().await.unwrap();
+    assert_eq!(decisions.lock().unwrap().len(), 2);
+    assert_eq!(decisions.lock().unwrap()[1].1, b"first");
+}
+
+#[tokio::test]
+async fn subscribers_see_the_round_that_chose_the_value() {
+    let data_dir = data_dir("events");
+    let addr = SocketAddr::from(([127, 0, 0, 1], 8001));
+
+    let mut paxos = Paxos::builder(1, addr, vec![addr])
+        .data_dir(&data_dir)
+        .build()
+        .await
+        .unwrap();
+    let mut events = paxos.subscribe();
+
+    paxos.propose(b"value".to_vec()).await.unwrap();
+
+    let instance = InstanceId::default();
+    let Event::Promised { proposal_id, .. } = events.try_recv().unwrap() else {
+        panic!("expected a promise first");
+    };
+    assert!(proposal_id > ProposalId::ZERO);
+    assert_eq!(
+        events.try_recv().unwrap(),
+        Event::Accepted {
+            instance: instance.clone(),
+            proposal_id,
+            value: b"value".to_vec(),
+        }
+    );
+    assert_eq!(
+        events.try_recv().unwrap(),
+        Event::Chosen {
+            instance,
+            value: b"value".to_vec(),
+        }
+    );
+    assert!(events.try_recv().is_err());
+}
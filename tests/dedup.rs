@@ -0,0 +1,88 @@
+//! Checks that retried client requests are only proposed once.
+
+use single_decree_paxos::dedup::{Deduplicator, RequestId};
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+use tokio::sync::Notify;
+
+fn request(request_id: u64) -> RequestId {
+    RequestId {
+        client_id: 7,
+        request_id,
+    }
+}
+
+#[tokio::test]
+async fn retries_wait_for_the_first_attempt() {
+    let dedup = Deduplicator::default();
+    let runs = Arc::new(AtomicUsize::new(0));
+    let release = Arc::new(Notify::new());
+
+    let propose = |value: &'static [u8]| {
+        let runs = Arc::clone(&runs);
+        let release = Arc::clone(&release);
+        async move {
+            runs.fetch_add(1, Ordering::SeqCst);
+            release.notified().await;
+            Ok(value.to_vec())
+        }
+    };
+
+    let first = tokio::spawn({
+        let dedup = dedup.clone();
+        let propose = propose(b"first");
+        async move { dedup.run(request(1), propose).await }
+    });
+    // The first attempt gives up, as a client that timed out would.
+    while runs.load(Ordering::SeqCst) == 0 {
+        tokio::task::yield_now().await;
+    }
+    first.abort();
+
+    let retry = tokio::spawn({
+        let dedup = dedup.clone();
+        let propose = propose(b"retry");
+        async move { dedup.run(request(1), propose).await }
+    });
+    tokio::task::yield_now().await;
+    release.notify_one();
+
+    assert_eq!(retry.await.unwrap().unwrap(), b"first");
+    assert_eq!(
+        dedup.run(request(1), propose(b"again")).await.unwrap(),
+        b"first"
+    );
+    assert_eq!(runs.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn failed_requests_are_proposed_again() {
+    let dedup = Deduplicator::default();
+
+    let outcome = dedup
+        .run(request(1), async { Err("no quorum".to_owned()) })
+        .await;
+    assert_eq!(outcome, Err("no quorum".to_owned()));
+
+    let outcome = dedup.run(request(1), async { Ok(b"value".to_vec()) }).await;
+    assert_eq!(outcome, Ok(b"value".to_vec()));
+}
+
+#[tokio::test]
+async fn the_oldest_decided_requests_are_forgotten() {
+    let dedup = Deduplicator::new(1);
+
+    for id in 1..=2 {
+        dedup
+            .run(request(id), async { Ok(b"old".to_vec()) })
+            .await
+            .unwrap();
+    }
+
+    let outcome = dedup.run(request(2), async { Ok(b"new".to_vec()) }).await;
+    assert_eq!(outcome, Ok(b"old".to_vec()));
+    let outcome = dedup.run(request(1), async { Ok(b"new".to_vec()) }).await;
+    assert_eq!(outcome, Ok(b"new".to_vec()));
+}
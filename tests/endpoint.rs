@@ -0,0 +1,147 @@
+//! Checks how acceptors configured by host name or unix socket are parsed,
+//! resolved and connected to.
+
+mod common;
+
+use common::data_dir;
+use futures::StreamExt;
+use single_decree_paxos::transport::{self, Connector, Endpoint, Resolver};
+use std::{net::SocketAddr, path::PathBuf};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+#[test]
+fn endpoints_parse_addresses_and_host_names() {
+    let addr: SocketAddr = "127.0.0.1:8001".parse().unwrap();
+    assert_eq!(
+        "127.0.0.1:8001".parse::<Endpoint>().unwrap(),
+        Endpoint::Addr(addr)
+    );
+
+    assert_eq!(
+        "paxos-0.paxos.default.svc:8001"
+            .parse::<Endpoint>()
+            .unwrap(),
+        Endpoint::Host {
+            name: "paxos-0.paxos.default.svc".to_owned(),
+            port: 8001
+        }
+    );
+
+    assert_eq!(
+        "unix:/run/paxos/acceptor-1.sock"
+            .parse::<Endpoint>()
+            .unwrap(),
+        Endpoint::Unix(PathBuf::from("/run/paxos/acceptor-1.sock"))
+    );
+
+    for invalid in ["paxos-0", ":8001", "paxos-0:port", "unix:", "paxos 0:8001"] {
+        assert!(invalid.parse::<Endpoint>().is_err(), "{invalid}");
+    }
+}
+
+#[test]
+fn ipv6_addresses_are_written_in_brackets() {
+    let addr: SocketAddr = "[::1]:8001".parse().unwrap();
+    assert_eq!(
+        "[::1]:8001".parse::<Endpoint>().unwrap(),
+        Endpoint::Addr(addr)
+    );
+    assert_eq!(Endpoint::Addr(addr).to_string(), "[::1]:8001");
+
+    for invalid in ["::1:8001", "[::1", "[not-an-ip]:8001"] {
+        assert!(invalid.parse::<Endpoint>().is_err(), "{invalid}");
+    }
+}
+
+#[tokio::test]
+async fn servers_bind_to_addresses_and_host_names() {
+    let endpoint: Endpoint = "localhost:3001".parse().unwrap();
+    let addr = endpoint.bind_addr().await.unwrap();
+    assert!(addr.ip().is_loopback());
+    assert_eq!(addr.port(), 3001);
+
+    let unix: Endpoint = "unix:/run/paxos/http.sock".parse().unwrap();
+    assert!(unix.bind_addr().await.is_err());
+}
+
+#[tokio::test]
+async fn host_names_resolve_in_order() {
+    let endpoints: Vec<Endpoint> = ["127.0.0.1:8001", "localhost:8002"]
+        .iter()
+        .map(|endpoint| endpoint.parse().unwrap())
+        .collect();
+
+    let (addrs, _) = Resolver::resolve(&endpoints).await.unwrap();
+
+    assert_eq!(addrs[0], "127.0.0.1:8001".parse().unwrap());
+    assert!(addrs[1].ip().is_loopback());
+    assert_eq!(addrs[1].port(), 8002);
+}
+
+#[test]
+fn endpoint_lists_parse_every_kind_of_endpoint() {
+    assert_eq!(
+        Endpoint::parse_list("127.0.0.1:8001, paxos-1:8002,unix:/run/paxos/acceptor-3.sock")
+            .unwrap(),
+        vec![
+            Endpoint::Addr("127.0.0.1:8001".parse().unwrap()),
+            Endpoint::Host {
+                name: "paxos-1".to_owned(),
+                port: 8002
+            },
+            Endpoint::Unix(PathBuf::from("/run/paxos/acceptor-3.sock")),
+        ]
+    );
+
+    assert!(Endpoint::parse_list("127.0.0.1:8001,paxos-1").is_err());
+}
+
+#[tokio::test]
+async fn endpoints_are_known_by_the_address_the_acceptors_resolved_to() {
+    let endpoints: Vec<Endpoint> = [
+        "127.0.0.1:8001",
+        "localhost:8002",
+        "unix:/run/paxos/acceptor-3.sock",
+    ]
+    .iter()
+    .map(|endpoint| endpoint.parse().unwrap())
+    .collect();
+
+    let (addrs, resolver) = Resolver::resolve(&endpoints).await.unwrap();
+
+    for (endpoint, addr) in endpoints.iter().zip(&addrs) {
+        assert_eq!(resolver.known_addr(endpoint).await.unwrap(), *addr);
+    }
+
+    // Endpoints that aren't acceptors, such as StatsD, are looked up.
+    let statsd: Endpoint = "localhost:8125".parse().unwrap();
+    let addr = resolver.known_addr(&statsd).await.unwrap();
+    assert!(addr.ip().is_loopback());
+    assert_eq!(addr.port(), 8125);
+}
+
+#[tokio::test]
+async fn unix_sockets_are_known_by_the_same_address_everywhere() {
+    let path = data_dir("unix").join("acceptor.sock");
+    let endpoint = Endpoint::Unix(path.clone());
+
+    let (addrs, resolver) = Resolver::resolve(&[endpoint.clone()]).await.unwrap();
+    assert_eq!(addrs, vec![Endpoint::unix_addr(&path)]);
+    assert_eq!(endpoint.lookup().await.unwrap(), addrs[0]);
+
+    let mut incoming = Box::pin(transport::listen_unix(&path).await.unwrap());
+    let mut client = Connector::new(None)
+        .with_resolver(resolver)
+        .connect(addrs[0])
+        .await
+        .unwrap();
+    let mut server = incoming.next().await.unwrap();
+
+    client.write_all(b"ping").await.unwrap();
+    let mut buf = [0; 4];
+    server.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"ping");
+    assert_eq!(server.peer_pid(), Some(std::process::id() as i32));
+
+    let _ = std::fs::remove_file(&path);
+}
@@ -0,0 +1,98 @@
+//! Checks that a data dir can only be used by the node and cluster it was initialized for.
+
+mod common;
+
+use common::data_dir;
+use ring::{
+    rand::SystemRandom,
+    signature::{Ed25519KeyPair, KeyPair},
+};
+use single_decree_paxos::genesis::{self, Genesis, SignedGenesis};
+use std::net::SocketAddr;
+
+fn genesis(cluster_id: &str) -> Genesis {
+    Genesis {
+        cluster_id: cluster_id.to_owned(),
+        acceptors: (1..=3)
+            .map(|i| SocketAddr::from(([127, 0, 0, 1], 8000 + i)))
+            .collect(),
+        quorum: None,
+        signing_keys: vec!["00".to_owned()],
+    }
+}
+
+#[tokio::test]
+async fn an_initialized_data_dir_only_boots_its_own_node() {
+    let data_dir = data_dir("init");
+
+    genesis::init(&data_dir, &genesis("prod"), 1).await.unwrap();
+    // Initializing again changes nothing.
+    genesis::init(&data_dir, &genesis("prod"), 1).await.unwrap();
+    assert!(genesis::init(&data_dir, &genesis("prod"), 2).await.is_err());
+
+    genesis::check_manifest(&data_dir, Some(&genesis("prod")), 1)
+        .await
+        .unwrap();
+    assert!(
+        genesis::check_manifest(&data_dir, Some(&genesis("prod")), 2)
+            .await
+            .is_err()
+    );
+    assert!(
+        genesis::check_manifest(&data_dir, Some(&genesis("staging")), 1)
+            .await
+            .is_err()
+    );
+    assert!(genesis::check_manifest(&data_dir, None, 1).await.is_err());
+
+    let _ = std::fs::remove_dir_all(&data_dir);
+}
+
+/// A new signing key, as pkcs8 and its hex encoded public key.
+fn signing_key() -> (Vec<u8>, String) {
+    let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+    let key_pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+    let public_key = key_pair
+        .public_key()
+        .as_ref()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+    (pkcs8.as_ref().to_vec(), public_key)
+}
+
+fn signed(pkcs8: &[u8], public_key: &str) -> SignedGenesis {
+    let mut signed = SignedGenesis {
+        genesis: Genesis {
+            signing_keys: vec![public_key.to_owned()],
+            ..genesis("prod")
+        },
+        signatures: Vec::new(),
+    };
+    signed.sign(pkcs8).unwrap();
+    signed
+}
+
+#[test]
+fn genesis_documents_are_checked_against_the_trusted_keys() {
+    let (trusted, trusted_key) = signing_key();
+    let (other, other_key) = signing_key();
+
+    signed(&trusted, &trusted_key)
+        .verify(&[trusted_key.clone()])
+        .unwrap();
+
+    // A document signed by whoever wrote it only vouches for itself.
+    let forged = signed(&other, &other_key);
+    forged.verify(&[other_key.clone()]).unwrap();
+    assert!(forged.verify(&[trusted_key.clone()]).is_err());
+
+    // Listing the trusted key doesn't help without its signature.
+    let mut forged = signed(&other, &other_key);
+    forged.genesis.signing_keys = vec![trusted_key.clone()];
+    forged.signatures[0].key = trusted_key.clone();
+    assert!(forged.verify(&[trusted_key.clone()]).is_err());
+
+    // Nothing is trusted unless the operator says so.
+    assert!(signed(&trusted, &trusted_key).verify(&[]).is_err());
+}
@@ -0,0 +1,142 @@
+//! Checks that instances driven from one handle don't wait on each other.
+
+mod common;
+
+use common::data_dir;
+use futures::future::join_all;
+use single_decree_paxos::{
+    instance::InstanceId,
+    instances::Instances,
+    paxos::{AcceptorError, Paxos},
+};
+use std::{net::SocketAddr, path::Path, time::Duration};
+
+fn instances(data_dir: &Path) -> Instances {
+    let addr = SocketAddr::from(([127, 0, 0, 1], 8002));
+    Instances::new(Paxos::builder(1, addr, vec![addr]).data_dir(data_dir))
+}
+
+#[tokio::test]
+async fn concurrent_proposals_decide_their_own_instances() {
+    let data_dir = data_dir("concurrent");
+    let instances = instances(&data_dir);
+
+    let proposals = (0..32).map(|i| {
+        let instances = instances.clone();
+        async move {
+            let instance: InstanceId = format!("jobs/{i}").parse().unwrap();
+            instances.propose(&instance, vec![i]).await
+        }
+    });
+
+    for (i, decided) in join_all(proposals).await.into_iter().enumerate() {
+        let decided = decided.unwrap();
+        assert!(decided.is_ours());
+        assert_eq!(decided.value(), [i as u8]);
+    }
+    assert_eq!(instances.ids().await.len(), 32);
+}
+
+#[tokio::test]
+async fn a_busy_instance_does_not_hold_up_the_others() {
+    let data_dir = data_dir("busy");
+    let instances = instances(&data_dir);
+    let busy: InstanceId = "busy".parse().unwrap();
+    let other: InstanceId = "other".parse().unwrap();
+
+    // Holding the node stands in for a round that is stuck on slow acceptors.
+    let node = instances.create(&busy).await.unwrap();
+    let held = node.lock().await;
+
+    let decided = tokio::time::timeout(
+        Duration::from_secs(5),
+        instances.propose(&other, b"value".to_vec()),
+    )
+    .await
+    .expect("the other instance waited on the busy one")
+    .unwrap();
+    assert!(decided.is_ours());
+
+    // Nothing of the other instance leaked into the busy one.
+    drop(held);
+    assert_eq!(node.lock().await.read().await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn fetching_state_follows_numbered_instances() {
+    let data_dir = data_dir("fetch-state");
+    let instances = instances(&data_dir);
+    let log: InstanceId = "log".parse().unwrap();
+    for i in 0..3 {
+        let slot = log.child(&i.to_string()).unwrap();
+        instances.propose(&slot, vec![i]).await.unwrap();
+    }
+
+    let from = log.child("1").unwrap();
+    let transfer = instances.fetch_state(&from).await.unwrap();
+    assert!(!transfer.more);
+    let values: Vec<_> = transfer.decided.iter().map(|d| d.value.clone()).collect();
+    assert_eq!(values, vec![vec![1], vec![2]]);
+
+    // Nothing past the last decided instance was left open.
+    assert_eq!(instances.ids().await.len(), 3);
+    assert_eq!(from.successor().unwrap().to_string(), "log/2");
+    assert_eq!("log/07".parse::<InstanceId>().unwrap().successor(), None);
+}
+
+#[tokio::test]
+async fn instances_opened_while_closing_see_the_closed_node_state() {
+    let data_dir = data_dir("close");
+    let instances = instances(&data_dir);
+    let instance: InstanceId = "closing".parse().unwrap();
+    instances
+        .propose(&instance, b"value".to_vec())
+        .await
+        .unwrap();
+
+    // Opening while the node is being closed waits for it to shut down
+    // instead of opening the same files a second time.
+    for _ in 0..8 {
+        let (closed, reopened) = tokio::join!(instances.close(&instance), instances.get(&instance));
+        closed.unwrap();
+        let node = reopened.unwrap();
+        assert_eq!(
+            node.lock().await.read().await.unwrap(),
+            Some(b"value".to_vec())
+        );
+    }
+}
+
+#[tokio::test]
+async fn fetching_state_of_unknown_instances_creates_no_files() {
+    let data_dir = data_dir("fetch-unknown");
+    let instances = instances(&data_dir);
+    let unknown: InstanceId = "nobody/asked/for/this".parse().unwrap();
+
+    let transfer = instances.fetch_state(&unknown).await.unwrap();
+    assert!(transfer.decided.is_empty());
+    assert!(instances.ids().await.is_empty());
+    assert_eq!(std::fs::read_dir(&data_dir).unwrap().count(), 0);
+}
+
+#[tokio::test]
+async fn unknown_instances_are_only_opened_once_created() {
+    let data_dir = data_dir("get-unknown");
+    let instances = instances(&data_dir);
+    let instance: InstanceId = "nobody/created/this".parse().unwrap();
+
+    let err = instances.get(&instance).await.unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<AcceptorError>(),
+        Some(AcceptorError::InvalidRequest { .. })
+    ));
+    assert!(instances.ids().await.is_empty());
+    assert_eq!(std::fs::read_dir(&data_dir).unwrap().count(), 0);
+
+    instances.create(&instance).await.unwrap();
+    instances.close(&instance).await.unwrap();
+
+    // Instances created before are opened from their files.
+    instances.get(&instance).await.unwrap();
+    assert_eq!(instances.ids().await, vec![instance]);
+}
@@ -0,0 +1,51 @@
+use single_decree_paxos::{latency::LatencyTracker, paxos::QuorumSelection};
+use std::{net::SocketAddr, time::Duration};
+
+fn peer(i: u8) -> SocketAddr {
+    SocketAddr::from(([10, 0, 0, i], 8000))
+}
+
+#[test]
+fn estimates_move_an_eighth_of_the_way_to_each_sample() {
+    let mut latencies = LatencyTracker::default();
+    assert_eq!(latencies.estimate(peer(1)), None);
+
+    latencies.record(peer(1), Duration::from_millis(80));
+    assert_eq!(latencies.estimate(peer(1)), Some(Duration::from_millis(80)));
+
+    latencies.record(peer(1), Duration::from_millis(160));
+    assert_eq!(latencies.estimate(peer(1)), Some(Duration::from_millis(90)));
+}
+
+#[test]
+fn the_nearest_peers_come_after_the_ones_without_an_estimate() {
+    let mut latencies = LatencyTracker::default();
+    latencies.record(peer(1), Duration::from_millis(150));
+    latencies.record(peer(2), Duration::from_millis(5));
+    latencies.record(peer(4), Duration::from_millis(40));
+
+    let peers = [peer(1), peer(2), peer(3), peer(4)];
+    assert_eq!(
+        latencies.nearest(&peers, 3),
+        vec![peer(3), peer(2), peer(4)]
+    );
+    assert_eq!(latencies.nearest(&peers, 9).len(), 4);
+}
+
+#[test]
+fn quorum_selections_parse() {
+    assert_eq!(
+        "all".parse::<QuorumSelection>().unwrap(),
+        QuorumSelection::All
+    );
+    assert_eq!(
+        "nearest".parse::<QuorumSelection>().unwrap(),
+        QuorumSelection::Nearest { spare: 0 }
+    );
+    assert_eq!(
+        "nearest+1".parse::<QuorumSelection>().unwrap(),
+        QuorumSelection::Nearest { spare: 1 }
+    );
+    assert!("nearest+".parse::<QuorumSelection>().is_err());
+    assert!("closest".parse::<QuorumSelection>().is_err());
+}
@@ -0,0 +1,34 @@
+//! Checks the write-once map against a single node cluster.
+
+mod common;
+
+use common::data_dir;
+use single_decree_paxos::{instances::Instances, map::WriteOnceMap, paxos::Paxos};
+use std::net::SocketAddr;
+
+#[tokio::test]
+async fn every_key_is_set_once() {
+    let data_dir = data_dir("set-once");
+
+    let addr = SocketAddr::from(([127, 0, 0, 1], 8001));
+    let instances = Instances::new(Paxos::builder(1, addr, vec![addr]).data_dir(&data_dir));
+    let map = WriteOnceMap::new(instances, "keys".parse().unwrap());
+
+    assert_eq!(map.get("a").await.unwrap(), None);
+
+    assert!(map.put_once("a", b"1".to_vec()).await.unwrap().is_ours());
+    assert!(map.put_once("b", b"2".to_vec()).await.unwrap().is_ours());
+
+    let decided = map.put_once("a", b"3".to_vec()).await.unwrap();
+    assert!(!decided.is_ours());
+    assert_eq!(decided.value(), b"1");
+
+    assert_eq!(map.get("a").await.unwrap(), Some(b"1".to_vec()));
+    assert_eq!(map.get("b").await.unwrap(), Some(b"2".to_vec()));
+    assert!(data_dir.join("acceptor_1.keys.a.state").exists());
+
+    assert!(map.put_once("a/b", b"4".to_vec()).await.is_err());
+    assert!(map.get("a.b").await.is_err());
+
+    let _ = std::fs::remove_dir_all(&data_dir);
+}
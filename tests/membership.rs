@@ -0,0 +1,82 @@
+//! Checks how members merge what they hear from each other, without probing.
+
+use single_decree_paxos::{
+    auth::Credentials,
+    membership::{MemberState, MemberUpdate, Membership, MembershipConfig},
+    transport::Connector,
+};
+use std::net::SocketAddr;
+
+fn addr(i: u8) -> SocketAddr {
+    SocketAddr::from(([10, 0, 0, i], 8000))
+}
+
+fn membership() -> Membership {
+    Membership::new(
+        addr(1),
+        &[addr(1), addr(2), addr(3)],
+        Connector::default(),
+        Credentials::default(),
+        MembershipConfig::default(),
+    )
+}
+
+fn update(i: u8, incarnation: u64, state: MemberState) -> MemberUpdate {
+    MemberUpdate {
+        addr: addr(i),
+        incarnation,
+        state,
+    }
+}
+
+fn state_of(members: &[MemberUpdate], i: u8) -> (u64, MemberState) {
+    let member = members
+        .iter()
+        .find(|member| member.addr == addr(i))
+        .unwrap();
+    (member.incarnation, member.state)
+}
+
+#[tokio::test]
+async fn a_suspected_member_refutes_with_a_higher_incarnation() {
+    let membership = membership();
+
+    let members = membership.on_ping(vec![update(1, 0, MemberState::Suspect)]);
+    assert_eq!(state_of(&members, 1), (1, MemberState::Alive));
+
+    // The refutation overrides the suspicion wherever it gossips to.
+    let other = Membership::new(
+        addr(2),
+        &[addr(1), addr(2), addr(3)],
+        Connector::default(),
+        Credentials::default(),
+        MembershipConfig::default(),
+    );
+    other.on_ping(vec![update(1, 0, MemberState::Suspect)]);
+    other.on_ping(members);
+    assert!(!other.is_dead(addr(1)));
+    assert_eq!(state_of(&other.members(), 1), (1, MemberState::Alive));
+}
+
+#[tokio::test]
+async fn dead_only_yields_to_a_newer_incarnation() {
+    let membership = membership();
+
+    membership.on_ping(vec![update(3, 0, MemberState::Dead)]);
+    assert!(membership.is_dead(addr(3)));
+
+    membership.on_ping(vec![update(3, 0, MemberState::Alive)]);
+    assert!(membership.is_dead(addr(3)));
+
+    membership.on_ping(vec![update(3, 1, MemberState::Alive)]);
+    assert!(!membership.is_dead(addr(3)));
+}
+
+#[tokio::test]
+async fn members_learn_about_members_they_were_not_configured_with() {
+    let membership = membership();
+
+    let members = membership.on_ping(vec![update(4, 0, MemberState::Alive)]);
+    assert_eq!(members.len(), 4);
+    assert_eq!(state_of(&members, 4), (0, MemberState::Alive));
+}
@@ -0,0 +1,59 @@
+//! Checks the offline view of an acceptor's files reads every version of them.
+
+mod common;
+
+use common::data_dir;
+use single_decree_paxos::{
+    format, instance::InstanceId, mmap::StateView, paxos::Paxos, proposal::ProposalId,
+};
+use std::net::SocketAddr;
+
+fn view(data_dir: &std::path::Path) -> StateView {
+    // SAFETY: no acceptor runs on the data dir while the view is alive.
+    unsafe { StateView::open(data_dir, 1, &InstanceId::default()) }.unwrap()
+}
+
+#[tokio::test]
+async fn views_read_what_a_stopped_node_decided() {
+    let data_dir = data_dir("current");
+    let addr = SocketAddr::from(([127, 0, 0, 1], 8000));
+    let mut node = Paxos::builder(1, addr, vec![addr])
+        .data_dir(&data_dir)
+        .build()
+        .await
+        .unwrap();
+    node.propose(b"value".to_vec()).await.unwrap();
+    node.shutdown().await.unwrap();
+    let proposal_id = node.proposal_id();
+    drop(node);
+
+    let view = view(&data_dir);
+    assert_eq!(view.proposal_id(), proposal_id);
+    assert_eq!(view.accepted_id(), Some(proposal_id));
+    assert_eq!(view.proposal_value(), Some(&b"value"[..]));
+    assert_eq!(view.decided_value(), Some(&b"value"[..]));
+
+    let _ = std::fs::remove_dir_all(&data_dir);
+}
+
+#[test]
+fn views_read_baseline_state_files() {
+    let data_dir = data_dir("baseline");
+    let mut contents = ProposalId::new(3).to_bytes().to_vec();
+    contents.extend_from_slice(b"value");
+    std::fs::write(data_dir.join("acceptor_1.state"), contents).unwrap();
+    std::fs::write(
+        data_dir.join("acceptor_1.decided"),
+        format::encode_decided(b""),
+    )
+    .unwrap();
+
+    let view = view(&data_dir);
+    assert_eq!(view.proposal_id(), ProposalId::new(3));
+    // The id may be a later promise, when the value was accepted is unknown.
+    assert_eq!(view.accepted_id(), Some(ProposalId::ZERO));
+    assert_eq!(view.proposal_value(), Some(&b"value"[..]));
+    assert_eq!(view.decided_value(), Some(&b""[..]));
+
+    let _ = std::fs::remove_dir_all(&data_dir);
+}
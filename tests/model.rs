@@ -0,0 +1,336 @@
+//! Model checks the rules in `protocol` with stateright, exploring every
+//! interleaving of the messages between small numbers of proposers and
+//! acceptors, and every message being lost. The exhaustive check is long
+//! running, run it with `cargo test --release --test model -- --ignored`.
+
+use single_decree_paxos::{paxos::PromisePolicy, proposal::ProposalId, protocol};
+use stateright::{Checker, Model, Property};
+use std::collections::{BTreeMap, BTreeSet};
+
+type Value = u8;
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
+enum Message {
+    Prepare {
+        from: usize,
+        to: usize,
+        proposal_id: u64,
+    },
+    Promise {
+        from: usize,
+        to: usize,
+        proposal_id: u64,
+        promised: bool,
+        highest: u64,
+        /// The proposal id and value the acceptor had accepted.
+        accepted: Option<(u64, Value)>,
+    },
+    Accept {
+        from: usize,
+        to: usize,
+        proposal_id: u64,
+        value: Value,
+    },
+    Accepted {
+        from: usize,
+        to: usize,
+        proposal_id: u64,
+        highest: u64,
+    },
+}
+
+#[derive(Clone, Debug, Default, Hash, PartialEq, Eq)]
+struct Proposer {
+    proposal_id: u64,
+
+    /// The highest proposal id the acceptors reported.
+    highest_seen: u64,
+
+    /// The promises for the current round, in the order they were counted.
+    promises: Vec<(usize, Option<(u64, Value)>)>,
+
+    /// The value sent in the accept requests of the current round.
+    value: Option<Value>,
+
+    accepted_by: BTreeSet<usize>,
+    decided: Option<Value>,
+}
+
+#[derive(Clone, Debug, Default, Hash, PartialEq, Eq)]
+struct Acceptor {
+    highest: u64,
+    accepted: Option<(u64, Value)>,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+struct State {
+    proposers: Vec<Proposer>,
+    acceptors: Vec<Acceptor>,
+
+    /// The messages in flight. Every message is delivered at most once.
+    network: BTreeSet<Message>,
+
+    /// Every accept request that was accepted, as (proposal id, value, acceptor).
+    accepted: BTreeSet<(u64, Value, usize)>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum Action {
+    StartRound(usize),
+    Deliver(Message),
+    Lose(Message),
+}
+
+struct PaxosModel {
+    proposers: usize,
+    acceptors: usize,
+
+    /// Proposers don't start rounds past this proposal id, which bounds the state space.
+    max_proposal_id: u64,
+
+    lossy: bool,
+    policy: PromisePolicy,
+}
+
+impl PaxosModel {
+    fn quorum(&self) -> usize {
+        self.acceptors / 2 + 1
+    }
+
+    /// The values accepted by a quorum under the same proposal id.
+    fn chosen(&self, state: &State) -> BTreeSet<Value> {
+        let mut votes: BTreeMap<(u64, Value), usize> = BTreeMap::new();
+        for (proposal_id, value, _) in &state.accepted {
+            *votes.entry((*proposal_id, *value)).or_default() += 1;
+        }
+
+        votes
+            .into_iter()
+            .filter(|(_, count)| *count >= self.quorum())
+            .map(|((_, value), _)| value)
+            .collect()
+    }
+}
+
+impl Model for PaxosModel {
+    type State = State;
+    type Action = Action;
+
+    fn init_states(&self) -> Vec<State> {
+        vec![State {
+            proposers: vec![Proposer::default(); self.proposers],
+            acceptors: vec![Acceptor::default(); self.acceptors],
+            network: BTreeSet::new(),
+            accepted: BTreeSet::new(),
+        }]
+    }
+
+    fn actions(&self, state: &State, actions: &mut Vec<Action>) {
+        for (p, proposer) in state.proposers.iter().enumerate() {
+            let next = proposer.proposal_id.max(proposer.highest_seen) + 1;
+            if proposer.decided.is_none() && next <= self.max_proposal_id {
+                actions.push(Action::StartRound(p));
+            }
+        }
+
+        for message in &state.network {
+            actions.push(Action::Deliver(message.clone()));
+            if self.lossy {
+                actions.push(Action::Lose(message.clone()));
+            }
+        }
+    }
+
+    fn next_state(&self, last: &State, action: Action) -> Option<State> {
+        let mut state = last.clone();
+
+        match action {
+            Action::StartRound(p) => {
+                let proposer = &mut state.proposers[p];
+                proposer.proposal_id = proposer.proposal_id.max(proposer.highest_seen) + 1;
+                proposer.promises.clear();
+                proposer.value = None;
+                proposer.accepted_by.clear();
+
+                for a in 0..self.acceptors {
+                    state.network.insert(Message::Prepare {
+                        from: p,
+                        to: a,
+                        proposal_id: proposer.proposal_id,
+                    });
+                }
+            }
+            Action::Lose(message) => {
+                state.network.remove(&message);
+            }
+            Action::Deliver(message) => {
+                state.network.remove(&message);
+
+                match message {
+                    Message::Prepare {
+                        from,
+                        to,
+                        proposal_id,
+                    } => {
+                        let acceptor = &mut state.acceptors[to];
+                        let promised = protocol::promises(
+                            self.policy,
+                            ProposalId::new(acceptor.highest),
+                            ProposalId::new(proposal_id),
+                        );
+                        acceptor.highest = acceptor.highest.max(proposal_id);
+
+                        state.network.insert(Message::Promise {
+                            from: to,
+                            to: from,
+                            proposal_id,
+                            promised,
+                            highest: acceptor.highest,
+                            accepted: acceptor.accepted,
+                        });
+                    }
+                    Message::Promise {
+                        from,
+                        to,
+                        proposal_id,
+                        promised,
+                        highest,
+                        accepted,
+                    } => {
+                        let proposer = &mut state.proposers[to];
+                        proposer.highest_seen = proposer.highest_seen.max(highest);
+
+                        if !promised
+                            || proposal_id != proposer.proposal_id
+                            || proposer.value.is_some()
+                            || proposer.promises.iter().any(|(a, _)| *a == from)
+                        {
+                            return Some(state);
+                        }
+
+                        proposer.promises.push((from, accepted));
+
+                        if proposer.promises.len() >= self.quorum() {
+                            let value = protocol::choose_value(proposer.promises.iter().map(
+                                |(_, accepted)| {
+                                    accepted.map(|(accepted_id, value)| {
+                                        (ProposalId::new(accepted_id), value)
+                                    })
+                                },
+                            ))
+                            .unwrap_or(to as Value);
+                            proposer.value = Some(value);
+
+                            for a in 0..self.acceptors {
+                                state.network.insert(Message::Accept {
+                                    from: to,
+                                    to: a,
+                                    proposal_id,
+                                    value,
+                                });
+                            }
+                        }
+                    }
+                    Message::Accept {
+                        from,
+                        to,
+                        proposal_id,
+                        value,
+                    } => {
+                        let acceptor = &mut state.acceptors[to];
+                        if protocol::accepts(
+                            ProposalId::new(acceptor.highest),
+                            ProposalId::new(proposal_id),
+                        ) {
+                            acceptor.highest = proposal_id;
+                            acceptor.accepted = Some((proposal_id, value));
+                            state.accepted.insert((proposal_id, value, to));
+                        }
+
+                        state.network.insert(Message::Accepted {
+                            from: to,
+                            to: from,
+                            proposal_id,
+                            highest: acceptor.highest,
+                        });
+                    }
+                    Message::Accepted {
+                        from,
+                        to,
+                        proposal_id,
+                        highest,
+                    } => {
+                        let proposer = &mut state.proposers[to];
+                        proposer.highest_seen = proposer.highest_seen.max(highest);
+
+                        if proposal_id == proposer.proposal_id && highest == proposal_id {
+                            proposer.accepted_by.insert(from);
+                            if proposer.accepted_by.len() >= self.quorum() {
+                                proposer.decided = proposer.value;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        Some(state)
+    }
+
+    fn properties(&self) -> Vec<Property<Self>> {
+        vec![
+            Property::always("agreement", |model: &PaxosModel, state: &State| {
+                let mut decided: BTreeSet<Value> = model.chosen(state);
+                decided.extend(state.proposers.iter().filter_map(|p| p.decided));
+                decided.len() <= 1
+            }),
+            Property::always("validity", |model: &PaxosModel, state: &State| {
+                model
+                    .chosen(state)
+                    .iter()
+                    .all(|value| (*value as usize) < model.proposers)
+            }),
+            Property::sometimes("a value is chosen", |model: &PaxosModel, state: &State| {
+                !model.chosen(state).is_empty()
+            }),
+        ]
+    }
+}
+
+#[test]
+fn two_proposers_three_acceptors_reliable_network() {
+    PaxosModel {
+        proposers: 2,
+        acceptors: 3,
+        max_proposal_id: 2,
+        lossy: false,
+        policy: PromisePolicy::StrictlyGreater,
+    }
+    .checker()
+    .spawn_bfs()
+    .join()
+    .assert_properties();
+}
+
+#[test]
+#[ignore = "explores millions of states, run with --ignored"]
+fn two_proposers_three_acceptors_lossy_network() {
+    PaxosModel {
+        proposers: 2,
+        acceptors: 3,
+        max_proposal_id: 4,
+        lossy: true,
+        policy: PromisePolicy::StrictlyGreater,
+    }
+    .checker()
+    .threads(num_cpus())
+    .spawn_bfs()
+    .join()
+    .assert_properties();
+}
+
+fn num_cpus() -> usize {
+    std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1)
+}
@@ -0,0 +1,312 @@
+//! Runs real acceptor processes on localhost and partitions them. Every node
+//! reaches each of its peers through its own proxy, so a link can be cut in
+//! one direction without touching the others.
+
+mod common;
+
+use common::data_dir;
+use single_decree_paxos::{
+    auth::Credentials, client::PaxosClient, instance::InstanceId, paxos, transport::Connector,
+};
+use std::{
+    net::{Ipv4Addr, SocketAddr},
+    path::PathBuf,
+    process::Stdio,
+    time::Duration,
+};
+use tarpc::context;
+use tokio::{
+    net::{TcpListener, TcpSocket, TcpStream},
+    process::{Child, Command},
+    sync::watch,
+};
+
+/// Forwards the connections a node opens to one of its peers.
+struct Proxy {
+    addr: SocketAddr,
+    blocked: watch::Sender<bool>,
+}
+
+impl Proxy {
+    /// Connections to `target` are opened from `source`, a loopback address of
+    /// their own, since acceptors only serve one connection per ip.
+    async fn spawn(source: Ipv4Addr, target: SocketAddr) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (blocked, blocked_receiver) = watch::channel(false);
+
+        tokio::spawn(async move {
+            loop {
+                let Ok((mut inbound, _)) = listener.accept().await else {
+                    return;
+                };
+
+                // A partitioned link refuses new connections.
+                if *blocked_receiver.borrow() {
+                    continue;
+                }
+
+                let mut blocked = blocked_receiver.clone();
+                tokio::spawn(async move {
+                    let socket = TcpSocket::new_v4().unwrap();
+                    socket.bind(SocketAddr::from((source, 0))).unwrap();
+                    let Ok(mut outbound) = socket.connect(target).await else {
+                        return;
+                    };
+
+                    // Connections that were open when the link is cut are dropped.
+                    tokio::select! {
+                        _ = tokio::io::copy_bidirectional(&mut inbound, &mut outbound) => {}
+                        _ = blocked.wait_for(|blocked| *blocked) => {}
+                    }
+                });
+            }
+        });
+
+        Self { addr, blocked }
+    }
+
+    fn set_blocked(&self, blocked: bool) {
+        self.blocked.send_replace(blocked);
+    }
+}
+
+struct Node {
+    rpc_addr: SocketAddr,
+    client_addr: SocketAddr,
+    /// The proxy this node reaches each peer through, by peer index.
+    proxies: Vec<Option<Proxy>>,
+    _process: Child,
+}
+
+struct Cluster {
+    nodes: Vec<Node>,
+    data_dir: PathBuf,
+}
+
+impl Drop for Cluster {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.data_dir);
+    }
+}
+
+async fn free_addr() -> SocketAddr {
+    TcpListener::bind("127.0.0.1:0")
+        .await
+        .unwrap()
+        .local_addr()
+        .unwrap()
+}
+
+impl Cluster {
+    async fn start(size: usize) -> Self {
+        let data_dir = data_dir(&size.to_string());
+
+        let mut rpc_addrs = Vec::with_capacity(size);
+        for _ in 0..size {
+            rpc_addrs.push(free_addr().await);
+        }
+
+        let mut nodes = Vec::with_capacity(size);
+        for i in 0..size {
+            let mut proxies = Vec::with_capacity(size);
+            let mut acceptors = Vec::with_capacity(size);
+
+            for (j, target) in rpc_addrs.iter().enumerate() {
+                if i == j {
+                    proxies.push(None);
+                    acceptors.push(*target);
+                    continue;
+                }
+
+                let source = Ipv4Addr::new(127, 0, 10 + i as u8, 10 + j as u8);
+                let proxy = Proxy::spawn(source, *target).await;
+                acceptors.push(proxy.addr);
+                proxies.push(Some(proxy));
+            }
+
+            let client_addr = free_addr().await;
+            let acceptors: Vec<String> = acceptors.iter().map(ToString::to_string).collect();
+
+            let process = Command::new(env!("CARGO_BIN_EXE_single-decree-paxos"))
+                .arg("node")
+                .args(["--id", &(i + 1).to_string()])
+                .args(["--listen", &rpc_addrs[i].to_string()])
+                .args(["--client-listen", &client_addr.to_string()])
+                .arg("--disable-http")
+                .args(["--data-dir", data_dir.to_str().unwrap()])
+                .args(["--acceptors", &acceptors.join(",")])
+                .env("PREPARE_RPC_TIMEOUT_MS", "200")
+                .env("PREPARE_PHASE_TIMEOUT_MS", "500")
+                .env("ACCEPT_RPC_TIMEOUT_MS", "200")
+                .env("ACCEPT_PHASE_TIMEOUT_MS", "500")
+                .env("PROPOSE_MAX_ATTEMPTS", "3")
+                .env("RUST_LOG", "warn")
+                .stdout(Stdio::null())
+                .kill_on_drop(true)
+                .spawn()
+                .unwrap();
+
+            nodes.push(Node {
+                rpc_addr: rpc_addrs[i],
+                client_addr,
+                proxies,
+                _process: process,
+            });
+        }
+
+        for node in &nodes {
+            for addr in [node.rpc_addr, node.client_addr] {
+                wait_until_listening(addr).await;
+            }
+        }
+
+        Self { nodes, data_dir }
+    }
+
+    /// Cuts every link between `side` and the rest of the cluster, both ways.
+    fn partition(&self, side: &[usize]) {
+        self.set_partitioned(side, true);
+    }
+
+    fn heal(&self, side: &[usize]) {
+        self.set_partitioned(side, false);
+    }
+
+    fn set_partitioned(&self, side: &[usize], blocked: bool) {
+        for (i, node) in self.nodes.iter().enumerate() {
+            for (j, proxy) in node.proxies.iter().enumerate() {
+                let Some(proxy) = proxy else {
+                    continue;
+                };
+
+                if side.contains(&i) != side.contains(&j) {
+                    proxy.set_blocked(blocked);
+                }
+            }
+        }
+    }
+
+    async fn propose(&self, node: usize, value: &str) -> anyhow::Result<Vec<u8>> {
+        let client = PaxosClient::connect(
+            &Connector::default(),
+            self.nodes[node].client_addr,
+            Credentials::default(),
+        )
+        .await?;
+
+        Ok(client.propose(value.as_bytes().to_vec()).await?.value)
+    }
+
+    /// The value `node` has learned was decided, if any.
+    async fn learned(&self, node: usize) -> Option<Vec<u8>> {
+        let client = paxos::connect(&Connector::default(), self.nodes[node].rpc_addr)
+            .await
+            .ok()?;
+
+        client
+            .fetch_decided(
+                context::current(),
+                Credentials::default(),
+                InstanceId::default(),
+            )
+            .await
+            .ok()?
+            .ok()
+            .and_then(|signed| signed.value)
+    }
+
+    /// Waits until every node has learned `value` was decided.
+    async fn wait_until_learned(&self, value: &[u8]) {
+        for node in 0..self.nodes.len() {
+            let mut attempts = 0;
+            loop {
+                match self.learned(node).await {
+                    Some(learned) => {
+                        assert_eq!(learned, value, "node {node} learned a different value");
+                        break;
+                    }
+                    None if attempts < 100 => {
+                        attempts += 1;
+                        tokio::time::sleep(Duration::from_millis(200)).await;
+                    }
+                    None => panic!("node {node} never learned the decided value"),
+                }
+            }
+        }
+    }
+}
+
+async fn wait_until_listening(addr: SocketAddr) {
+    for _ in 0..100 {
+        if TcpStream::connect(addr).await.is_ok() {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("nothing is listening on {addr}");
+}
+
+async fn majority_decides_and_minority_catches_up(size: usize, minority: &[usize]) {
+    let cluster = Cluster::start(size).await;
+    let majority = (0..size).find(|i| !minority.contains(i)).unwrap();
+
+    cluster.partition(minority);
+
+    let decided = cluster.propose(majority, "majority").await.unwrap();
+    assert_eq!(decided, b"majority");
+
+    for &node in minority {
+        assert!(
+            cluster.propose(node, "minority").await.is_err(),
+            "node {node} decided without a quorum"
+        );
+    }
+
+    cluster.heal(minority);
+
+    for &node in minority {
+        let decided = cluster.propose(node, "minority").await.unwrap();
+        assert_eq!(
+            decided, b"majority",
+            "node {node} decided a different value"
+        );
+    }
+
+    cluster.wait_until_learned(b"majority").await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn three_nodes_one_isolated() {
+    majority_decides_and_minority_catches_up(3, &[0]).await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn five_nodes_split_two_three() {
+    majority_decides_and_minority_catches_up(5, &[0, 1]).await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn nothing_is_decided_without_a_majority() {
+    let cluster = Cluster::start(5).await;
+
+    // No side has a quorum of three.
+    cluster.partition(&[0, 1]);
+    cluster.partition(&[2, 3]);
+
+    for node in 0..5 {
+        assert!(
+            cluster
+                .propose(node, &format!("value-{node}"))
+                .await
+                .is_err(),
+            "node {node} decided without a quorum"
+        );
+    }
+
+    cluster.heal(&[0, 1]);
+    cluster.heal(&[2, 3]);
+
+    let decided = cluster.propose(4, "value-4").await.unwrap();
+    cluster.wait_until_learned(&decided).await;
+}
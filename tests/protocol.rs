@@ -0,0 +1,240 @@
+//! Checks the protocol core on its own, without files or connections, over
+//! every sequence of requests with small proposal ids.
+
+use single_decree_paxos::{
+    format,
+    paxos::{PrepareResponse, PromisePolicy},
+    proposal::ProposalId,
+    protocol::{Acceptor, Persist, Promises},
+};
+
+const MAX_ID: u64 = 3;
+const DEPTH: usize = 4;
+
+#[derive(Debug, Clone, Copy)]
+enum Request {
+    Prepare(u64),
+    Accept(u64),
+}
+
+fn requests() -> Vec<Request> {
+    (1..=MAX_ID)
+        .flat_map(|id| [Request::Prepare(id), Request::Accept(id)])
+        .collect()
+}
+
+/// Applies every sequence of up to [DEPTH] requests to `acceptor` and checks
+/// each step.
+fn explore(acceptor: &Acceptor, depth: usize) {
+    if depth == 0 {
+        return;
+    }
+
+    for request in requests() {
+        let mut next = acceptor.clone();
+        let persist = match request {
+            Request::Prepare(id) => {
+                let step = next.on_prepare(ProposalId::new(id));
+                assert_eq!(step.response.proposal_id, next.promised);
+                assert_eq!(step.response.accepted_id, next.accepted_id);
+                assert_eq!(step.response.witness, acceptor.witness);
+                if step.response.promised {
+                    assert!(ProposalId::new(id) >= acceptor.promised);
+                }
+                step.persist
+            }
+            Request::Accept(id) => {
+                let value = format!("value {id}").into_bytes();
+                let step = next.on_accept(ProposalId::new(id), value.clone());
+                if step.persist == Persist::State {
+                    assert_eq!(next.accepted_id, ProposalId::new(id));
+                    let stored = (!acceptor.witness).then_some(value);
+                    assert_eq!(next.accepted_value, stored);
+                } else {
+                    assert!(ProposalId::new(id) < acceptor.promised);
+                    assert_eq!(step.response.proposal_id, acceptor.promised);
+                }
+                step.persist
+            }
+        };
+
+        // Promises never go back and nothing is accepted above them.
+        assert!(next.promised >= acceptor.promised);
+        assert!(next.accepted_id <= next.promised);
+
+        // Whatever changed has to be written before the answer is sent.
+        match persist {
+            Persist::Nothing => assert_eq!(&next, acceptor),
+            Persist::Promise => {
+                assert!(next.promised > acceptor.promised);
+                assert_eq!(next.accepted_id, acceptor.accepted_id);
+            }
+            Persist::State => {}
+        }
+
+        explore(&next, depth - 1);
+    }
+}
+
+#[test]
+fn acceptors_keep_their_promises() {
+    for policy in [
+        PromisePolicy::StrictlyGreater,
+        PromisePolicy::GreaterOrEqual,
+    ] {
+        for witness in [false, true] {
+            let acceptor = Acceptor {
+                policy,
+                witness,
+                ..Default::default()
+            };
+            explore(&acceptor, DEPTH);
+        }
+    }
+}
+
+#[test]
+fn learned_values_are_reported_above_everything_else() {
+    let mut acceptor = Acceptor::default();
+    acceptor.on_prepare(ProposalId::new(3));
+    acceptor.on_accept(ProposalId::new(3), b"accepted".to_vec());
+
+    assert_eq!(
+        acceptor.learn(ProposalId::new(2), b"decided".to_vec()),
+        Persist::State
+    );
+    assert_eq!(acceptor.promised, ProposalId::new(3));
+    assert_eq!(acceptor.accepted_id, ProposalId::new(3));
+    assert_eq!(acceptor.accepted_value, Some(b"decided".to_vec()));
+
+    acceptor.reset();
+    assert_eq!(acceptor, Acceptor::default());
+}
+
+#[test]
+fn restarted_acceptors_know_what_they_wrote() {
+    for witness in [false, true] {
+        let mut acceptor = Acceptor {
+            witness,
+            ..Default::default()
+        };
+        acceptor.on_prepare(ProposalId::new(2));
+        acceptor.on_accept(ProposalId::new(2), b"value".to_vec());
+        acceptor.on_prepare(ProposalId::new(4));
+
+        // The promise is written over the accepted proposal.
+        let mut file = format::encode(&acceptor.state());
+        let promise = format::encode_promise(acceptor.promised);
+        file[..promise.len()].copy_from_slice(&promise);
+
+        let (state, _) = format::decode(&file).unwrap();
+        let restarted = Acceptor::restore(acceptor.policy, witness, state);
+        assert_eq!(restarted, acceptor);
+    }
+}
+
+fn promise(accepted_id: u64, value: Option<&[u8]>, witness: bool) -> PrepareResponse {
+    PrepareResponse {
+        proposal_id: ProposalId::new(5),
+        accepted_id: ProposalId::new(accepted_id),
+        proposal_value: value.map(<[u8]>::to_vec),
+        promised: true,
+        witness,
+    }
+}
+
+#[test]
+fn promises_pick_the_value_accepted_in_the_highest_proposal() {
+    let mut promises = Promises::default();
+    assert!(promises.count(promise(1, Some(b"a"), false)));
+    assert!(!promises.complete(2));
+
+    let rejection = PrepareResponse {
+        proposal_id: ProposalId::new(7),
+        promised: false,
+        ..promise(0, None, false)
+    };
+    assert!(!promises.count(rejection));
+    assert_eq!(promises.highest_rejection, Some(ProposalId::new(7)));
+    assert_eq!(promises.highest_proposal_id, ProposalId::new(7));
+
+    assert!(promises.count(promise(2, Some(b"b"), false)));
+    assert!(promises.complete(2));
+    assert_eq!(promises.value(), Some(b"b".to_vec()));
+}
+
+#[test]
+fn promises_wait_for_a_value_only_a_witness_reported() {
+    let mut promises = Promises::default();
+    promises.count(promise(1, Some(b"a"), false));
+    promises.count(promise(2, None, true));
+    assert!(promises.missing_value());
+    assert!(!promises.complete(2));
+
+    promises.count(promise(2, Some(b"b"), false));
+    assert!(promises.complete(2));
+    assert_eq!(promises.value(), Some(b"b".to_vec()));
+}
+
+/// Two proposers that picked the same id run their rounds interleaved over
+/// three acceptors. Each only sends accept requests once a quorum promised,
+/// with the value the promises carry if any. Returns whether each got its
+/// value accepted by a quorum.
+fn interleave_rounds_with_a_shared_id(policy: PromisePolicy) -> [bool; 2] {
+    let id = ProposalId::new(1);
+    let mut acceptors = vec![
+        Acceptor {
+            policy,
+            ..Default::default()
+        };
+        3
+    ];
+    let proposers = [(b"a".to_vec(), [0, 1]), (b"b".to_vec(), [1, 2])];
+
+    // Both prepare phases run before either accept phase.
+    let values: Vec<Option<Vec<u8>>> = proposers
+        .iter()
+        .map(|(value, quorum)| {
+            let mut promises = Promises::default();
+            for i in quorum {
+                promises.count(acceptors[*i].on_prepare(id).response);
+            }
+            promises
+                .complete(2)
+                .then(|| promises.value().unwrap_or_else(|| value.clone()))
+        })
+        .collect();
+
+    // The second proposer's accepts land first.
+    let mut accepted = [0; 2];
+    for p in [1, 0] {
+        let Some(value) = &values[p] else {
+            continue;
+        };
+        for i in proposers[p].1 {
+            if acceptors[i].on_accept(id, value.clone()).persist == Persist::State {
+                accepted[p] += 1;
+            }
+        }
+    }
+
+    accepted.map(|acks| acks >= 2)
+}
+
+#[test]
+fn proposers_sharing_an_id_both_get_chosen_unless_promises_are_strict() {
+    // Re-promising the id lets the second proposer into the round the first
+    // one owns, and each gets its own value accepted by a quorum: "b" by
+    // acceptors 1 and 2, then "a" by acceptors 0 and 1.
+    assert_eq!(
+        interleave_rounds_with_a_shared_id(PromisePolicy::GreaterOrEqual),
+        [true, true]
+    );
+
+    // Acceptor 1 refuses the second prepare, only the first proposer's value
+    // is chosen.
+    assert_eq!(
+        interleave_rounds_with_a_shared_id(PromisePolicy::StrictlyGreater),
+        [true, false]
+    );
+}
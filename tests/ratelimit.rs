@@ -0,0 +1,49 @@
+use std::time::Duration;
+
+use single_decree_paxos::{
+    paxos::AcceptorError,
+    ratelimit::{RateLimit, RateLimiter, RateLimits},
+};
+
+#[tokio::test(start_paused = true)]
+async fn requests_over_the_burst_wait_for_tokens() {
+    let limiter = RateLimiter::new(RateLimits {
+        global: Some(RateLimit::new(10.0).with_burst(2)),
+        per_connection: None,
+    });
+
+    assert!(limiter.check().is_ok());
+    assert!(limiter.check().is_ok());
+    let retry_after = limiter.check().unwrap_err();
+    assert_eq!(Duration::from_millis(100), retry_after);
+
+    tokio::time::advance(retry_after).await;
+    assert!(limiter.check().is_ok());
+    assert!(limiter.check().is_err());
+}
+
+#[tokio::test(start_paused = true)]
+async fn connections_share_the_global_limit_only() {
+    let limiter = RateLimiter::new(RateLimits {
+        global: Some(RateLimit::new(1.0).with_burst(3)),
+        per_connection: Some(RateLimit::new(1.0).with_burst(2)),
+    });
+    let first = limiter.for_connection();
+    let second = limiter.for_connection();
+
+    assert!(first.check().is_ok());
+    assert!(first.check().is_ok());
+    // The connection's own limit throttles it before the global one does.
+    assert!(first.check().is_err());
+
+    assert!(second.check().is_ok());
+    // The global bucket is empty now.
+    assert!(second.check().is_err());
+}
+
+#[test]
+fn throttled_errors_say_how_long_to_wait() {
+    let err = AcceptorError::throttled(Duration::from_micros(1500));
+    assert_eq!(Some(Duration::from_millis(2)), err.retry_after());
+    assert_eq!(None, AcceptorError::ShuttingDown.retry_after());
+}
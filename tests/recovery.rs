@@ -0,0 +1,500 @@
+//! Crashes an acceptor at different points, restarts it from its files and
+//! checks it still honors every promise it made and every value it accepted
+//! before the crash. A crash is the acceptor being dropped without
+//! [Paxos::shutdown], a crash in the middle of a write is simulated by leaving
+//! the state file the way a partial write would.
+
+mod common;
+
+use common::data_dir;
+use single_decree_paxos::{
+    encryption::StateKey,
+    format,
+    instance::InstanceId,
+    lease::HeartbeatRequest,
+    paxos::{
+        AcceptRequest, AcceptResponse, AcceptorError, DecidedInstance, DumpedValue, Paxos,
+        PrepareRequest, PrepareResponse,
+    },
+    proposal::ProposalId,
+};
+use std::{
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+const ID: u32 = 1;
+
+fn state_file(data_dir: &Path) -> PathBuf {
+    data_dir.join(format!("acceptor_{ID}.state"))
+}
+
+async fn start(data_dir: &Path) -> anyhow::Result<Paxos> {
+    let addr = SocketAddr::from(([10, 0, 0, 1], 8000));
+    let others = [2, 3].map(|i| SocketAddr::from(([10, 0, 0, i], 8000)));
+
+    Paxos::builder(ID, addr, [addr].into_iter().chain(others).collect())
+        .data_dir(data_dir)
+        .build()
+        .await
+}
+
+async fn prepare(acceptor: &mut Paxos, proposal_id: u64) -> PrepareResponse {
+    acceptor
+        .on_prepare(PrepareRequest {
+            instance: InstanceId::default(),
+            proposal_id: ProposalId::new(proposal_id),
+            proposer: None,
+        })
+        .await
+        .unwrap()
+}
+
+async fn accept(acceptor: &mut Paxos, proposal_id: u64, value: &[u8]) -> AcceptResponse {
+    acceptor
+        .on_accept(AcceptRequest {
+            instance: InstanceId::default(),
+            proposal_id: ProposalId::new(proposal_id),
+            proposal_value: value.to_vec(),
+        })
+        .await
+        .unwrap()
+}
+
+/// Checks that `acceptor` rejects every proposal below `promised`, in both phases.
+async fn assert_honors(acceptor: &mut Paxos, promised: u64) {
+    for older in 1..promised {
+        assert!(
+            !prepare(acceptor, older).await.promised,
+            "promised {older} after promising {promised}"
+        );
+
+        let response = accept(acceptor, older, b"older").await;
+        assert_eq!(response.proposal_id, ProposalId::new(promised));
+        assert_ne!(acceptor.accepted_value(), Some(&b"older"[..]));
+    }
+}
+
+#[tokio::test]
+async fn promises_survive_a_crash() {
+    let data_dir = data_dir("promise");
+
+    let mut acceptor = start(&data_dir).await.unwrap();
+    assert!(prepare(&mut acceptor, 5).await.promised);
+    drop(acceptor);
+
+    let mut acceptor = start(&data_dir).await.unwrap();
+    assert_eq!(acceptor.proposal_id(), ProposalId::new(5));
+    assert_eq!(acceptor.accepted_value(), None);
+    assert_honors(&mut acceptor, 5).await;
+
+    let _ = std::fs::remove_dir_all(&data_dir);
+}
+
+#[tokio::test]
+async fn accepted_values_survive_a_crash() {
+    let data_dir = data_dir("accepted");
+
+    let mut acceptor = start(&data_dir).await.unwrap();
+    assert!(prepare(&mut acceptor, 3).await.promised);
+    accept(&mut acceptor, 3, b"value").await;
+    drop(acceptor);
+
+    let mut acceptor = start(&data_dir).await.unwrap();
+    assert_eq!(acceptor.accepted_id(), ProposalId::new(3));
+    assert_eq!(acceptor.accepted_value(), Some(&b"value"[..]));
+    assert_honors(&mut acceptor, 3).await;
+
+    // The next proposer still learns about the value.
+    let response = prepare(&mut acceptor, 4).await;
+    assert!(response.promised);
+    assert_eq!(response.accepted_id, ProposalId::new(3));
+    assert_eq!(response.proposal_value, Some(b"value".to_vec()));
+
+    let _ = std::fs::remove_dir_all(&data_dir);
+}
+
+#[tokio::test]
+async fn a_promise_made_after_accepting_survives_a_crash() {
+    let data_dir = data_dir("promise-after-accept");
+
+    let mut acceptor = start(&data_dir).await.unwrap();
+    accept(&mut acceptor, 2, b"value").await;
+    assert!(prepare(&mut acceptor, 6).await.promised);
+    drop(acceptor);
+
+    let mut acceptor = start(&data_dir).await.unwrap();
+    assert_eq!(acceptor.proposal_id(), ProposalId::new(6));
+    assert_eq!(acceptor.accepted_id(), ProposalId::new(2));
+    assert_eq!(acceptor.accepted_value(), Some(&b"value"[..]));
+    assert_honors(&mut acceptor, 6).await;
+
+    let _ = std::fs::remove_dir_all(&data_dir);
+}
+
+/// The acceptor promised 4, accepted a value in 4, promised 7 and crashed
+/// while writing the value of proposal 7. Writes overwrite the file in place,
+/// so whatever part of the new state made it to disk sits on top of the old
+/// one. The proposal id fits in a single sector so it is written whole.
+#[tokio::test]
+async fn crashing_while_writing_an_accepted_value_keeps_the_promise() {
+    let data_dir = data_dir("torn-accept");
+
+    let mut acceptor = start(&data_dir).await.unwrap();
+    accept(&mut acceptor, 4, b"the first value").await;
+    assert!(prepare(&mut acceptor, 7).await.promised);
+    drop(acceptor);
+
+    let before = std::fs::read(state_file(&data_dir)).unwrap();
+
+    let mut after = format::header().to_vec();
+    after.extend_from_slice(&ProposalId::new(7).to_bytes());
+    after.extend_from_slice(&ProposalId::new(7).to_bytes());
+    after.extend_from_slice(b"second");
+
+    for written in format::HEADER_LEN + ProposalId::ENCODED_LEN..=after.len() {
+        let mut torn = before.clone();
+        torn.resize(std::cmp::max(before.len(), written), 0);
+        torn[..written].copy_from_slice(&after[..written]);
+        std::fs::write(state_file(&data_dir), &torn).unwrap();
+
+        let mut acceptor = start(&data_dir).await.unwrap();
+        assert_eq!(
+            acceptor.proposal_id(),
+            ProposalId::new(7),
+            "{written} bytes"
+        );
+        assert_honors(&mut acceptor, 7).await;
+    }
+
+    let _ = std::fs::remove_dir_all(&data_dir);
+}
+
+/// A crash while writing the very first promise can leave part of the
+/// proposal id behind. Reading it as a lower id would forget the promise, so
+/// the acceptor refuses to start instead.
+#[tokio::test]
+async fn a_torn_first_promise_is_refused() {
+    let data_dir = data_dir("torn-promise");
+
+    let mut acceptor = start(&data_dir).await.unwrap();
+    assert!(prepare(&mut acceptor, 300).await.promised);
+    drop(acceptor);
+
+    let promise = std::fs::read(state_file(&data_dir)).unwrap();
+    for written in 1..promise.len() {
+        std::fs::write(state_file(&data_dir), &promise[..written]).unwrap();
+        assert!(start(&data_dir).await.is_err(), "{written} bytes");
+    }
+
+    let _ = std::fs::remove_dir_all(&data_dir);
+}
+
+#[tokio::test]
+async fn the_state_dump_shows_what_a_restarted_acceptor_read() {
+    let data_dir = data_dir("dump");
+
+    let acceptor = start(&data_dir).await.unwrap();
+    let dump = acceptor.on_dump_state();
+    assert_eq!(dump.accepted_value, None);
+    assert_eq!(dump.persisted_at, None);
+    drop(acceptor);
+
+    let mut acceptor = start(&data_dir).await.unwrap();
+    accept(&mut acceptor, 3, &[0xff, 0x00]).await;
+    assert!(prepare(&mut acceptor, 5).await.promised);
+    drop(acceptor);
+
+    let acceptor = start(&data_dir).await.unwrap();
+    let dump = acceptor.on_dump_state();
+    assert_eq!(dump.proposal_id, ProposalId::new(5));
+    assert_eq!(dump.accepted_id, ProposalId::new(3));
+    assert_eq!(
+        dump.accepted_value,
+        Some(DumpedValue::Hex("ff00".to_owned()))
+    );
+    assert_eq!(dump.state_file, state_file(&data_dir));
+    assert!(dump.persisted_at.is_some());
+
+    let _ = std::fs::remove_dir_all(&data_dir);
+}
+
+#[tokio::test]
+async fn a_reset_acceptor_starts_over_after_a_restart() {
+    let data_dir = data_dir("reset");
+
+    let mut acceptor = start(&data_dir).await.unwrap();
+    accept(&mut acceptor, 3, b"value").await;
+    assert!(prepare(&mut acceptor, 5).await.promised);
+
+    assert_eq!(acceptor.reset_confirmation(), "reset acceptor 1 default");
+    acceptor.reset_state().await.unwrap();
+    assert_eq!(acceptor.proposal_id(), ProposalId::ZERO);
+    assert_eq!(acceptor.accepted_value(), None);
+    drop(acceptor);
+
+    let mut acceptor = start(&data_dir).await.unwrap();
+    assert_eq!(acceptor.proposal_id(), ProposalId::ZERO);
+    assert_eq!(acceptor.accepted_value(), None);
+    assert!(prepare(&mut acceptor, 1).await.promised);
+
+    let _ = std::fs::remove_dir_all(&data_dir);
+}
+
+#[tokio::test]
+async fn an_empty_decided_value_survives_a_restart() {
+    let data_dir = data_dir("empty-decided");
+
+    let mut acceptor = start(&data_dir).await.unwrap();
+    assert_eq!(acceptor.decided_value(), None);
+    acceptor
+        .learn_decided(DecidedInstance {
+            instance: InstanceId::default(),
+            proposal_id: ProposalId::new(3),
+            value: Vec::new(),
+        })
+        .await
+        .unwrap();
+    drop(acceptor);
+
+    let acceptor = start(&data_dir).await.unwrap();
+    assert_eq!(acceptor.decided_value(), Some(&[][..]));
+    drop(acceptor);
+
+    // Decided files written before the marker hold just the value.
+    let decided_file = data_dir.join(format!("acceptor_{ID}.decided"));
+    std::fs::write(&decided_file, b"value").unwrap();
+    let acceptor = start(&data_dir).await.unwrap();
+    assert_eq!(acceptor.decided_value(), Some(&b"value"[..]));
+
+    let _ = std::fs::remove_dir_all(&data_dir);
+}
+
+/// Starts a node that is the only acceptor, so its proposals are decided
+/// without talking to anyone.
+async fn start_alone(data_dir: &Path, key: StateKey) -> anyhow::Result<Paxos> {
+    let addr = SocketAddr::from(([127, 0, 0, 1], 8000));
+    Paxos::builder(ID, addr, vec![addr])
+        .data_dir(data_dir)
+        .state_key(key)
+        .build()
+        .await
+}
+
+#[tokio::test]
+async fn encrypted_values_are_not_readable_from_the_files() {
+    let data_dir = data_dir("encrypted");
+    let key = StateKey::new([7; 32]);
+    let secret = b"postgres://admin:hunter2@db".to_vec();
+
+    let mut node = start_alone(&data_dir, key.clone()).await.unwrap();
+    assert!(node.propose(secret.clone()).await.unwrap().is_ours());
+    drop(node);
+
+    for file in ["state", "decided"] {
+        let contents = std::fs::read(data_dir.join(format!("acceptor_{ID}.{file}"))).unwrap();
+        assert!(!contents.is_empty(), "the {file} file is empty");
+        assert!(
+            !contents.windows(7).any(|window| window == b"hunter2"),
+            "the {file} file holds the value in the clear"
+        );
+    }
+
+    let mut node = start_alone(&data_dir, key).await.unwrap();
+    assert_eq!(node.accepted_value(), Some(&secret[..]));
+    assert_eq!(node.read().await.unwrap(), Some(secret));
+    drop(node);
+
+    assert!(start_alone(&data_dir, StateKey::new([8; 32]))
+        .await
+        .is_err());
+
+    let _ = std::fs::remove_dir_all(&data_dir);
+}
+
+#[tokio::test]
+async fn state_files_without_a_header_are_migrated() {
+    let data_dir = data_dir("headerless");
+
+    // A single id for the promise and the accepted value, which may have
+    // been accepted in any id up to it.
+    let mut legacy = Vec::new();
+    legacy.extend_from_slice(&ProposalId::new(5).to_bytes());
+    legacy.extend_from_slice(b"value");
+    std::fs::write(state_file(&data_dir), &legacy).unwrap();
+
+    let mut acceptor = start(&data_dir).await.unwrap();
+    assert_eq!(acceptor.proposal_id(), ProposalId::new(5));
+    assert_eq!(acceptor.accepted_id(), ProposalId::ZERO);
+    assert_eq!(acceptor.accepted_value(), Some(&b"value"[..]));
+    assert_honors(&mut acceptor, 5).await;
+    drop(acceptor);
+
+    let mut migrated = Vec::new();
+    migrated.extend_from_slice(&ProposalId::new(5).to_bytes());
+    migrated.extend_from_slice(&ProposalId::ZERO.to_bytes());
+    migrated.extend_from_slice(b"value");
+    let contents = std::fs::read(state_file(&data_dir)).unwrap();
+    assert_eq!(
+        format::split(&contents).unwrap(),
+        (format::CURRENT_VERSION, &migrated[..])
+    );
+
+    // Offline migration finds nothing left to do.
+    assert!(format::migrate_dir(&data_dir).unwrap().is_empty());
+
+    let _ = std::fs::remove_dir_all(&data_dir);
+}
+
+#[tokio::test]
+async fn baseline_state_files_are_migrated_offline() {
+    let data_dir = data_dir("baseline");
+
+    let mut baseline = Vec::new();
+    baseline.extend_from_slice(&ProposalId::new(7).to_bytes());
+    baseline.extend_from_slice(b"value");
+    std::fs::write(state_file(&data_dir), &baseline).unwrap();
+
+    let promise_only = data_dir.join("acceptor_2.state");
+    std::fs::write(&promise_only, ProposalId::new(4).to_bytes()).unwrap();
+
+    assert_eq!(format::migrate_dir(&data_dir).unwrap().len(), 2);
+
+    let mut records = Vec::new();
+    records.extend_from_slice(&ProposalId::new(7).to_bytes());
+    records.extend_from_slice(&ProposalId::ZERO.to_bytes());
+    records.extend_from_slice(b"value");
+    let contents = std::fs::read(state_file(&data_dir)).unwrap();
+    assert_eq!(
+        format::split(&contents).unwrap(),
+        (format::CURRENT_VERSION, &records[..])
+    );
+    let contents = std::fs::read(&promise_only).unwrap();
+    assert_eq!(
+        format::split(&contents).unwrap(),
+        (format::CURRENT_VERSION, &ProposalId::new(4).to_bytes()[..])
+    );
+
+    let acceptor = start(&data_dir).await.unwrap();
+    assert_eq!(acceptor.proposal_id(), ProposalId::new(7));
+    assert_eq!(acceptor.accepted_id(), ProposalId::ZERO);
+    assert_eq!(acceptor.accepted_value(), Some(&b"value"[..]));
+    drop(acceptor);
+
+    // A torn baseline file is refused rather than guessed at.
+    std::fs::write(state_file(&data_dir), [0; 3]).unwrap();
+    assert!(format::migrate_dir(&data_dir).is_err());
+
+    let _ = std::fs::remove_dir_all(&data_dir);
+}
+
+#[tokio::test]
+async fn answers_that_wrote_nothing_wait_for_the_writes_they_report() {
+    let data_dir = data_dir("pending-sync");
+    let addr = SocketAddr::from(([10, 0, 0, 1], 8000));
+    let mut acceptor = Paxos::builder(ID, addr, vec![addr])
+        .data_dir(&data_dir)
+        .group_commit(Duration::from_millis(500))
+        .build()
+        .await
+        .unwrap();
+    let prepare = |proposal_id| PrepareRequest {
+        instance: InstanceId::default(),
+        proposal_id: ProposalId::new(proposal_id),
+        proposer: None,
+    };
+
+    let (response, promised) = acceptor.begin_prepare(prepare(5)).await.unwrap();
+    assert!(response.promised);
+
+    // Refusing 3 reports the promise of 5, which isn't on disk yet.
+    let (response, refused) = acceptor.begin_prepare(prepare(3)).await.unwrap();
+    assert!(!response.promised);
+    assert_eq!(response.proposal_id, ProposalId::new(5));
+    assert!(
+        tokio::time::timeout(Duration::from_millis(100), refused.wait())
+            .await
+            .is_err()
+    );
+
+    promised.wait().await.unwrap();
+    let (_, refused) = acceptor.begin_prepare(prepare(3)).await.unwrap();
+    tokio::time::timeout(Duration::from_millis(100), refused.wait())
+        .await
+        .unwrap()
+        .unwrap();
+
+    let _ = std::fs::remove_dir_all(&data_dir);
+}
+
+#[tokio::test]
+async fn restarted_acceptors_keep_the_lease_they_granted() {
+    let data_dir = data_dir("lease");
+
+    let mut acceptor = start(&data_dir).await.unwrap();
+    let heartbeat = |proposer| HeartbeatRequest {
+        instance: InstanceId::default(),
+        proposer,
+        duration: Duration::from_secs(10),
+        client_addr: None,
+    };
+    assert!(acceptor.on_heartbeat(heartbeat(2)).await.unwrap().granted);
+    drop(acceptor);
+
+    let mut acceptor = start(&data_dir).await.unwrap();
+    let response = acceptor.on_heartbeat(heartbeat(3)).await.unwrap();
+    assert!(!response.granted);
+    assert_eq!(response.holder, 2);
+
+    let err = acceptor
+        .on_prepare(PrepareRequest {
+            instance: InstanceId::default(),
+            proposal_id: ProposalId::new(1),
+            proposer: Some(3),
+        })
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err.downcast::<AcceptorError>().unwrap(),
+        AcceptorError::LeaseHeld { holder: 2, .. }
+    ));
+
+    let response = acceptor
+        .on_prepare(PrepareRequest {
+            instance: InstanceId::default(),
+            proposal_id: ProposalId::new(1),
+            proposer: Some(2),
+        })
+        .await
+        .unwrap();
+    assert!(response.promised);
+
+    let _ = std::fs::remove_dir_all(&data_dir);
+}
+
+/// Contents no acceptor writes are refused instead of being read as a state
+/// that forgets promises.
+#[test]
+fn state_files_no_acceptor_writes_are_refused() {
+    let mut inverted = format::header().to_vec();
+    inverted.extend_from_slice(&ProposalId::new(3).to_bytes());
+    inverted.extend_from_slice(&ProposalId::new(5).to_bytes());
+    inverted.extend_from_slice(b"value");
+    assert!(format::decode(&inverted).is_err());
+
+    let mut unknown_version = format::header().to_vec();
+    *unknown_version.last_mut().unwrap() = 0;
+    unknown_version.extend_from_slice(&ProposalId::new(3).to_bytes());
+    assert!(format::decode(&unknown_version).is_err());
+
+    let mut torn = format::header().to_vec();
+    torn.extend_from_slice(&ProposalId::new(3).to_bytes());
+    torn.extend_from_slice(&[0; 3]);
+    assert!(format::decode(&torn).is_err());
+
+    assert_eq!(
+        format::decode(&[]).unwrap(),
+        (None, format::CURRENT_VERSION)
+    );
+}
@@ -0,0 +1,76 @@
+//! Checks compare-and-set writes against a single node cluster.
+
+mod common;
+
+use common::data_dir;
+use single_decree_paxos::{
+    instance::InstanceId,
+    instances::Instances,
+    paxos::Paxos,
+    register::{CasOutcome, Register},
+};
+use std::net::SocketAddr;
+
+#[tokio::test]
+async fn only_writers_expecting_the_current_value_swap() {
+    let data_dir = data_dir("cas");
+
+    let addr = SocketAddr::from(([127, 0, 0, 1], 8001));
+    let instances = Instances::new(Paxos::builder(1, addr, vec![addr]).data_dir(&data_dir));
+    let instance: InstanceId = "lock".parse().unwrap();
+    let mut alice = Register::new(instances.clone(), instance.clone());
+    let mut bob = Register::new(instances, instance);
+
+    assert_eq!(alice.read().await.unwrap(), None);
+    assert_eq!(
+        alice.propose_if_unset(b"alice".to_vec()).await.unwrap(),
+        CasOutcome::Swapped
+    );
+    assert_eq!(
+        bob.propose_if_unset(b"bob".to_vec()).await.unwrap(),
+        CasOutcome::Mismatch {
+            current: Some(b"alice".to_vec())
+        }
+    );
+
+    assert!(bob
+        .cas(Some(b"alice"), b"bob".to_vec())
+        .await
+        .unwrap()
+        .swapped());
+    assert_eq!(
+        alice.cas(Some(b"alice"), b"carol".to_vec()).await.unwrap(),
+        CasOutcome::Mismatch {
+            current: Some(b"bob".to_vec())
+        }
+    );
+    assert_eq!(alice.read().await.unwrap(), Some(b"bob".to_vec()));
+
+    let _ = std::fs::remove_dir_all(&data_dir);
+}
+
+#[tokio::test]
+async fn a_closed_instance_is_reopened_from_its_files() {
+    let data_dir = data_dir("close");
+
+    let addr = SocketAddr::from(([127, 0, 0, 1], 8001));
+    let instances = Instances::new(Paxos::builder(1, addr, vec![addr]).data_dir(&data_dir));
+    let instance: InstanceId = "resources/a".parse().unwrap();
+
+    assert!(instances
+        .propose(&instance, b"value".to_vec())
+        .await
+        .unwrap()
+        .is_ours());
+
+    instances.close(&instance).await.unwrap();
+    assert!(instances.ids().await.is_empty());
+
+    let node = instances.get(&instance).await.unwrap();
+    assert_eq!(
+        node.lock().await.read().await.unwrap(),
+        Some(b"value".to_vec())
+    );
+
+    let _ = std::fs::remove_dir_all(&data_dir);
+}
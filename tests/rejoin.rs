@@ -0,0 +1,202 @@
+//! Checks that an acceptor only votes once it knows whether it lost its data.
+
+mod common;
+
+use common::data_dir;
+use single_decree_paxos::{
+    auth::Credentials,
+    channel::ChannelNetwork,
+    paxos::{AcceptRequest, AcceptorError, Decided, Paxos, PrepareRequest},
+    proposal::ProposalId,
+    rejoin::{Announcement, Rejoin, Status},
+    timeout::Timeouts,
+    transport::Connector,
+};
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+use tokio::sync::Mutex;
+
+fn announcement(generation: Option<u64>) -> Announcement {
+    Announcement {
+        id: 2,
+        cluster: "test".to_owned(),
+        generation,
+    }
+}
+
+fn prepare(proposal_id: u64) -> PrepareRequest {
+    PrepareRequest {
+        instance: Default::default(),
+        proposal_id: ProposalId::new(proposal_id),
+        proposer: None,
+    }
+}
+
+fn timeouts() -> Timeouts {
+    Timeouts {
+        prepare_rpc: Duration::from_millis(50),
+        prepare_phase: Duration::from_millis(50),
+        accept_rpc: Duration::from_millis(50),
+        accept_phase: Duration::from_millis(50),
+    }
+}
+
+/// A node that lost its data, its peers remember it.
+async fn recovering(data_dir: &std::path::Path) -> Rejoin {
+    std::fs::write(
+        data_dir.join("identity.toml"),
+        "cluster = \"test\"\ngeneration = 2\nrecovering = true\n",
+    )
+    .unwrap();
+
+    let rejoin = Rejoin::open(data_dir, "test".to_owned()).await.unwrap();
+    rejoin
+        .boot(1, &[], &Connector::default(), &Credentials::default())
+        .await
+        .unwrap();
+    assert!(matches!(rejoin.status(), Status::Recovering { .. }));
+    rejoin
+}
+
+#[tokio::test]
+async fn peers_remember_the_generations_announced_to_them() {
+    let data_dir = data_dir("announce");
+    let peer = Rejoin::open(&data_dir, "test".to_owned()).await.unwrap();
+
+    let answer = peer.on_announce(announcement(None)).await.unwrap();
+    assert_eq!(answer.generation, None);
+
+    peer.on_announce(announcement(Some(2))).await.unwrap();
+    // An older generation doesn't replace a newer one.
+    peer.on_announce(announcement(Some(1))).await.unwrap();
+
+    // The records survive a restart of the peer.
+    let peer = Rejoin::open(&data_dir, "test".to_owned()).await.unwrap();
+    let answer = peer.on_announce(announcement(None)).await.unwrap();
+    assert_eq!(answer.generation, Some(2));
+
+    let mut other_cluster = announcement(None);
+    other_cluster.cluster = "other".to_owned();
+    assert!(matches!(
+        peer.on_announce(other_cluster).await,
+        Err(AcceptorError::InvalidRequest { .. })
+    ));
+}
+
+#[tokio::test]
+async fn a_booting_acceptor_votes_once_it_knows_it_didnt_lose_data() {
+    let data_dir = data_dir("boot");
+    let addr = SocketAddr::from(([127, 0, 0, 1], 8001));
+    let rejoin = Rejoin::open(&data_dir, "test".to_owned()).await.unwrap();
+
+    let mut paxos = Paxos::builder(1, addr, vec![addr])
+        .data_dir(&data_dir)
+        .rejoin(rejoin.clone())
+        .build()
+        .await
+        .unwrap();
+
+    let err = paxos.on_prepare(prepare(1)).await.unwrap_err();
+    assert_eq!(
+        err.downcast_ref::<AcceptorError>(),
+        Some(&AcceptorError::Recovering)
+    );
+
+    let identity = rejoin
+        .boot(1, &[], &Connector::default(), &Credentials::default())
+        .await
+        .unwrap();
+    assert_eq!(identity.generation, 1);
+    assert!(!identity.recovering);
+    assert_eq!(rejoin.status(), Status::Voting);
+
+    assert!(paxos.on_prepare(prepare(1)).await.unwrap().promised);
+}
+
+#[tokio::test]
+async fn a_recovering_acceptor_waits_out_rounds_in_flight() {
+    let data_dir = data_dir("recovering");
+    let rejoin = recovering(&data_dir).await;
+
+    let addr = SocketAddr::from(([127, 0, 0, 1], 8001));
+    let mut paxos = Paxos::builder(1, addr, vec![addr])
+        .data_dir(&data_dir)
+        .timeouts(timeouts())
+        .rejoin(rejoin)
+        .build()
+        .await
+        .unwrap();
+
+    assert!(paxos.on_prepare(prepare(1)).await.is_err());
+    assert!(!paxos.rejoin().await.unwrap());
+
+    tokio::time::sleep(Duration::from_millis(150)).await;
+    assert!(paxos.rejoin().await.unwrap());
+    assert!(paxos.on_prepare(prepare(1)).await.unwrap().promised);
+}
+
+#[tokio::test]
+async fn a_rejoining_acceptor_takes_on_a_value_that_may_have_been_chosen() {
+    let data_dir = data_dir("chosen");
+    let network = ChannelNetwork::default();
+    let acceptors: Vec<_> = (1..=3)
+        .map(|i| SocketAddr::from(([127, 0, 0, 1], 8000 + i)))
+        .collect();
+    let builder = |id: u32, addr: SocketAddr| {
+        Paxos::builder(id, addr, acceptors.clone())
+            .connector(Connector::channels(network.clone()))
+            .data_dir(&data_dir)
+            .timeouts(timeouts())
+    };
+
+    let mut peers = Vec::new();
+    for (i, addr) in acceptors.iter().enumerate().skip(1) {
+        let paxos = Arc::new(Mutex::new(
+            builder(i as u32 + 1, *addr).build().await.unwrap(),
+        ));
+        network.serve(*addr, Arc::clone(&paxos));
+        peers.push(paxos);
+    }
+
+    // The first and second acceptor accepted a value at id 5, so it is
+    // chosen, but the proposer went down before anyone learned it. Then the
+    // first acceptor lost its data.
+    {
+        let mut peer = peers[0].lock().await;
+        assert!(peer.on_prepare(prepare(5)).await.unwrap().promised);
+        peer.on_accept(AcceptRequest {
+            instance: Default::default(),
+            proposal_id: ProposalId::new(5),
+            proposal_value: b"chosen".to_vec(),
+        })
+        .await
+        .unwrap();
+    }
+
+    let wiped_dir = data_dir.join("wiped");
+    std::fs::create_dir_all(&wiped_dir).unwrap();
+    let mut wiped = builder(1, acceptors[0])
+        .data_dir(&wiped_dir)
+        .rejoin(recovering(&wiped_dir).await)
+        .build()
+        .await
+        .unwrap();
+
+    tokio::time::sleep(Duration::from_millis(150)).await;
+    assert!(wiped.rejoin().await.unwrap());
+    assert_eq!(wiped.accepted_id(), ProposalId::new(5));
+    assert_eq!(wiped.accepted_value(), Some(&b"chosen"[..]));
+
+    // With the second acceptor down, a quorum of the rejoined acceptor and
+    // the third one must not let another value be chosen.
+    network.close(acceptors[1]);
+    network.serve(acceptors[0], Arc::new(Mutex::new(wiped)));
+
+    let mut proposer = builder(9, SocketAddr::from(([10, 0, 0, 9], 8000)))
+        .build()
+        .await
+        .unwrap();
+    assert_eq!(
+        proposer.propose(b"other".to_vec()).await.unwrap(),
+        Decided::Other(b"chosen".to_vec())
+    );
+}
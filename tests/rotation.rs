@@ -0,0 +1,76 @@
+//! Checks that nodes taking turns on slots don't hold each other's batches up.
+//! The queues share one acceptor, standing in for nodes with the same acceptors.
+
+mod common;
+
+use common::data_dir;
+use single_decree_paxos::{
+    instances::Instances,
+    learner::{Applied, Learner},
+    paxos::Paxos,
+    queue::{BatchConfig, Priority, ProposalQueue, Rotation},
+};
+use std::{net::SocketAddr, time::Duration};
+use tokio::sync::mpsc;
+
+fn instances(name: &str) -> Instances {
+    let addr = SocketAddr::from(([127, 0, 0, 1], 8003));
+    Instances::new(Paxos::builder(1, addr, vec![addr]).data_dir(data_dir(name)))
+}
+
+fn queue(
+    instances: &Instances,
+    rotation: Rotation,
+) -> (ProposalQueue, mpsc::UnboundedReceiver<Applied>) {
+    let (learner, applied) = Learner::new();
+    let config = BatchConfig {
+        max_size: 1,
+        rotation: Some(rotation),
+        ..BatchConfig::default()
+    };
+    let queue =
+        ProposalQueue::spawn_batched(instances.clone(), "log".parse().unwrap(), config, learner);
+    (queue, applied)
+}
+
+async fn next_applied(applied: &mut mpsc::UnboundedReceiver<Applied>) -> Applied {
+    tokio::time::timeout(Duration::from_secs(5), applied.recv())
+        .await
+        .expect("no batch was applied")
+        .unwrap()
+}
+
+#[tokio::test]
+async fn an_idle_node_skips_its_slots() {
+    let instances = instances("idle");
+    let (_idle, _) = queue(&instances, Rotation::new(0, 2).revoke_after(Duration::MAX));
+    let (busy, mut applied) = queue(&instances, Rotation::new(1, 2).revoke_after(Duration::MAX));
+
+    for i in 0..3 {
+        busy.propose(vec![i], Priority::Normal).await.unwrap();
+    }
+
+    // Only the busy node's slots hold values, the idle node skipped its own.
+    for i in 0..3 {
+        let applied = next_applied(&mut applied).await;
+        assert_eq!(applied.slot, 2 * i as u64 + 1);
+        assert_eq!(applied.values, vec![vec![i]]);
+    }
+}
+
+#[tokio::test]
+async fn a_silent_node_is_skipped_by_the_others() {
+    let instances = instances("silent");
+    let rotation = Rotation::new(1, 2).revoke_after(Duration::from_millis(100));
+    let (queue, mut applied) = queue(&instances, rotation);
+
+    queue
+        .propose(b"value".to_vec(), Priority::Normal)
+        .await
+        .unwrap();
+
+    // Slot 0 belongs to a node that never shows up.
+    let applied = next_applied(&mut applied).await;
+    assert_eq!(applied.slot, 1);
+    assert_eq!(applied.values, vec![b"value".to_vec()]);
+}
@@ -0,0 +1,297 @@
+//! Drives acceptors with arbitrary interleavings of prepare and accept requests
+//! from competing proposers and checks that once a value is chosen, no other
+//! value is ever chosen.
+
+mod common;
+
+use common::data_dir;
+use proptest::prelude::*;
+use single_decree_paxos::{
+    instance::InstanceId,
+    paxos::{AcceptRequest, Paxos, PrepareRequest},
+    proposal::ProposalId,
+    protocol,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    net::SocketAddr,
+    path::Path,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+const ACCEPTORS: usize = 3;
+const PROPOSERS: usize = 3;
+const QUORUM: usize = ACCEPTORS / 2 + 1;
+
+#[derive(Debug, Clone)]
+enum Op {
+    /// The proposer sends a prepare request for its current round to the acceptor.
+    Prepare { proposer: usize, acceptor: usize },
+
+    /// The proposer sends an accept request to the acceptor, once it has
+    /// promises from a quorum.
+    Accept { proposer: usize, acceptor: usize },
+
+    /// The proposer gives up on its round and starts a new one.
+    NewRound { proposer: usize },
+}
+
+fn op() -> impl Strategy<Value = Op> {
+    prop_oneof![
+        (0..PROPOSERS, 0..ACCEPTORS)
+            .prop_map(|(proposer, acceptor)| Op::Prepare { proposer, acceptor }),
+        (0..PROPOSERS, 0..ACCEPTORS)
+            .prop_map(|(proposer, acceptor)| Op::Accept { proposer, acceptor }),
+        (0..PROPOSERS).prop_map(|proposer| Op::NewRound { proposer }),
+    ]
+}
+
+/// Follows the same rules as [Paxos] does when it proposes.
+#[derive(Debug, Default)]
+struct Proposer {
+    round: u64,
+
+    /// The proposal id and value each acceptor that promised in the current round had accepted.
+    promises: HashMap<usize, Option<(ProposalId, Vec<u8>)>>,
+
+    /// The value sent in the accept requests of the current round, fixed by the first one.
+    value: Option<Vec<u8>>,
+}
+
+impl Proposer {
+    /// Proposal ids are unique across proposers.
+    fn proposal_id(&self, proposer: usize) -> ProposalId {
+        ProposalId::new(self.round * PROPOSERS as u64 + proposer as u64 + 1)
+    }
+}
+
+async fn acceptors(data_dir: &Path) -> Vec<Paxos> {
+    let addrs: Vec<SocketAddr> = (0..ACCEPTORS)
+        .map(|i| SocketAddr::from(([10, 0, 0, i as u8 + 1], 8000)))
+        .collect();
+
+    let mut acceptors = Vec::with_capacity(ACCEPTORS);
+    for (i, addr) in addrs.iter().enumerate() {
+        acceptors.push(
+            Paxos::builder(i as u32 + 1, *addr, addrs.clone())
+                .data_dir(data_dir)
+                .build()
+                .await
+                .unwrap(),
+        );
+    }
+    acceptors
+}
+
+async fn run(ops: Vec<Op>) -> Result<(), TestCaseError> {
+    // Every case gets its own dir, cases of one test run one after another.
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+    let data_dir = data_dir(&format!("case-{}", NEXT.fetch_add(1, Ordering::Relaxed)));
+
+    let result = check(ops, &data_dir).await;
+
+    let _ = tokio::fs::remove_dir_all(&data_dir).await;
+
+    result
+}
+
+async fn check(ops: Vec<Op>, data_dir: &Path) -> Result<(), TestCaseError> {
+    let mut acceptors = acceptors(data_dir).await;
+    let mut highest = vec![ProposalId::ZERO; ACCEPTORS];
+    let mut proposers: Vec<Proposer> = (0..PROPOSERS).map(|_| Proposer::default()).collect();
+
+    // The acceptors that accepted each proposal id and the value they accepted.
+    let mut accepted: HashMap<ProposalId, (Vec<u8>, HashSet<usize>)> = HashMap::new();
+    let mut chosen: HashSet<Vec<u8>> = HashSet::new();
+
+    for op in ops {
+        match op {
+            Op::NewRound { proposer } => {
+                let state = &mut proposers[proposer];
+                state.round += 1;
+                state.promises.clear();
+                state.value = None;
+            }
+            Op::Prepare { proposer, acceptor } => {
+                let proposal_id = proposers[proposer].proposal_id(proposer);
+                let response = acceptors[acceptor]
+                    .on_prepare(PrepareRequest {
+                        instance: InstanceId::default(),
+                        proposal_id,
+                        proposer: None,
+                    })
+                    .await
+                    .unwrap();
+
+                if response.promised {
+                    let accepted = response
+                        .proposal_value
+                        .map(|value| (response.accepted_id, value));
+                    proposers[proposer].promises.insert(acceptor, accepted);
+                }
+            }
+            Op::Accept { proposer, acceptor } => {
+                let state = &mut proposers[proposer];
+                if state.promises.len() < QUORUM {
+                    continue;
+                }
+
+                let proposal_id = state.proposal_id(proposer);
+                let value = state
+                    .value
+                    .get_or_insert_with(|| {
+                        // A value an acceptor that promised had accepted, or else
+                        // the proposer's own.
+                        protocol::choose_value(
+                            (0..ACCEPTORS)
+                                .map(|acceptor| state.promises.get(&acceptor).cloned().flatten()),
+                        )
+                        .unwrap_or_else(|| format!("value-{proposer}").into_bytes())
+                    })
+                    .clone();
+
+                let response = acceptors[acceptor]
+                    .on_accept(AcceptRequest {
+                        instance: InstanceId::default(),
+                        proposal_id,
+                        proposal_value: value.clone(),
+                    })
+                    .await
+                    .unwrap();
+
+                if response.proposal_id == proposal_id {
+                    prop_assert_eq!(acceptors[acceptor].accepted_value(), Some(&value[..]));
+
+                    let (_, by) = accepted
+                        .entry(proposal_id)
+                        .or_insert_with(|| (value.clone(), HashSet::new()));
+                    by.insert(acceptor);
+
+                    if by.len() >= QUORUM {
+                        chosen.insert(value);
+                    }
+                }
+            }
+        }
+
+        for (acceptor, highest) in acceptors.iter().zip(highest.iter_mut()) {
+            prop_assert!(
+                acceptor.proposal_id() >= *highest,
+                "an acceptor went back from proposal id {} to {}",
+                highest,
+                acceptor.proposal_id()
+            );
+            *highest = acceptor.proposal_id();
+        }
+
+        prop_assert!(
+            chosen.len() <= 1,
+            "more than one value was chosen: {:?}",
+            chosen
+                .iter()
+                .map(|value| String::from_utf8_lossy(value).into_owned())
+                .collect::<Vec<_>>()
+        );
+    }
+
+    Ok(())
+}
+
+proptest! {
+    #[test]
+    fn at_most_one_value_is_chosen(ops in proptest::collection::vec(op(), 1..80)) {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(run(ops))?;
+    }
+}
+
+async fn prepare(acceptor: &mut Paxos, proposal_id: u64) -> Option<(ProposalId, Vec<u8>)> {
+    let response = acceptor
+        .on_prepare(PrepareRequest {
+            instance: InstanceId::default(),
+            proposal_id: ProposalId::new(proposal_id),
+            proposer: None,
+        })
+        .await
+        .unwrap();
+    assert!(response.promised);
+
+    response
+        .proposal_value
+        .map(|value| (response.accepted_id, value))
+}
+
+async fn accept(acceptor: &mut Paxos, proposal_id: u64, value: &[u8]) {
+    let response = acceptor
+        .on_accept(AcceptRequest {
+            instance: InstanceId::default(),
+            proposal_id: ProposalId::new(proposal_id),
+            proposal_value: value.to_vec(),
+        })
+        .await
+        .unwrap();
+    assert_eq!(response.proposal_id, ProposalId::new(proposal_id));
+}
+
+/// Acceptor 0 accepted "a" in proposal 1 and acceptor 1 accepted "b" in
+/// proposal 2. "b" may have been chosen, "a" can't be anymore, so a proposer
+/// that hears from both must send "b" whatever order the promises arrive in.
+#[tokio::test]
+async fn adopts_the_value_accepted_in_the_highest_proposal() {
+    let data_dir = data_dir("highest-proposal");
+
+    let mut acceptors = acceptors(&data_dir).await;
+
+    prepare(&mut acceptors[0], 1).await;
+    accept(&mut acceptors[0], 1, b"a").await;
+
+    prepare(&mut acceptors[1], 2).await;
+    prepare(&mut acceptors[2], 2).await;
+    accept(&mut acceptors[1], 2, b"b").await;
+
+    let from_0 = prepare(&mut acceptors[0], 3).await;
+    let from_1 = prepare(&mut acceptors[1], 3).await;
+
+    assert_eq!(from_0, Some((ProposalId::new(1), b"a".to_vec())));
+    assert_eq!(from_1, Some((ProposalId::new(2), b"b".to_vec())));
+
+    for promises in [
+        vec![from_0.clone(), from_1.clone()],
+        vec![from_1.clone(), from_0.clone()],
+    ] {
+        assert_eq!(protocol::choose_value(promises), Some(b"b".to_vec()));
+    }
+
+    // The id a value was accepted in survives a restart.
+    drop(acceptors);
+    let acceptors = self::acceptors(&data_dir).await;
+    assert_eq!(acceptors[1].accepted_id(), ProposalId::new(2));
+    assert_eq!(acceptors[1].accepted_value(), Some(&b"b"[..]));
+    assert_eq!(acceptors[1].proposal_id(), ProposalId::new(3));
+
+    let _ = tokio::fs::remove_dir_all(&data_dir).await;
+}
+
+/// A promise alone doesn't report a value as accepted.
+#[tokio::test]
+async fn promises_without_an_accepted_value_leave_the_choice_to_the_proposer() {
+    let data_dir = data_dir("no-accepted-value");
+
+    let mut acceptors = acceptors(&data_dir).await;
+
+    let mut promises = Vec::new();
+    for acceptor in &mut acceptors {
+        promises.push(prepare(acceptor, 1).await);
+    }
+    assert_eq!(protocol::choose_value(promises), None::<Vec<u8>>);
+
+    drop(acceptors);
+    let acceptors = self::acceptors(&data_dir).await;
+    assert_eq!(acceptors[0].proposal_id(), ProposalId::new(1));
+    assert_eq!(acceptors[0].accepted_value(), None);
+
+    let _ = tokio::fs::remove_dir_all(&data_dir).await;
+}
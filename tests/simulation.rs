@@ -0,0 +1,130 @@
+//! Run with `cargo test --features sim --test simulation`. SIM_SEEDS sets how
+//! many seeds are tried, a failing seed can be replayed with SIM_SEED.
+
+use single_decree_paxos::{
+    fault::LinkFaults,
+    lease::LeaseConfig,
+    sim::{self, SimConfig},
+};
+use std::time::Duration;
+
+fn seeds() -> Vec<u64> {
+    if let Ok(seed) = std::env::var("SIM_SEED") {
+        return vec![seed.parse().expect("SIM_SEED must be an integer")];
+    }
+
+    let count = std::env::var("SIM_SEEDS")
+        .map(|count| count.parse().expect("SIM_SEEDS must be an integer"))
+        .unwrap_or(1000);
+
+    (0..count).collect()
+}
+
+async fn check_seeds(config: SimConfig) {
+    for seed in seeds() {
+        let report = sim::run(seed, &config)
+            .await
+            .unwrap_or_else(|err| panic!("seed {seed}: {err:#}"));
+
+        if let Err(err) = report.check() {
+            panic!("{err:#}\n{report:#?}");
+        }
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn single_proposer_without_faults_decides_its_value() {
+    let config = SimConfig {
+        proposers: 1,
+        drop_probability: 0.0,
+        crashes: 0,
+        ..SimConfig::default()
+    };
+
+    for seed in 0..10 {
+        let report = sim::run(seed, &config).await.unwrap();
+        report.check().unwrap();
+        assert_eq!(report.decided(), Some(&b"value-0"[..]), "seed {seed}");
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn competing_proposers_agree() {
+    check_seeds(SimConfig {
+        proposers: 3,
+        drop_probability: 0.0,
+        crashes: 0,
+        ..SimConfig::default()
+    })
+    .await;
+}
+
+#[tokio::test(start_paused = true)]
+async fn agreement_survives_lost_messages_and_crashes() {
+    check_seeds(SimConfig::default()).await;
+}
+
+#[tokio::test(start_paused = true)]
+async fn agreement_survives_a_larger_cluster_under_heavy_faults() {
+    check_seeds(SimConfig {
+        acceptors: 5,
+        proposers: 3,
+        max_delay: Duration::from_millis(50),
+        drop_probability: 0.2,
+        crashes: 4,
+        ..SimConfig::default()
+    })
+    .await;
+}
+
+#[tokio::test(start_paused = true)]
+async fn duplicated_and_stale_requests_do_not_break_agreement() {
+    check_seeds(SimConfig {
+        proposers: 3,
+        drop_probability: 0.0,
+        crashes: 0,
+        faults: LinkFaults {
+            drop: 0.1,
+            duplicate: 0.3,
+            reorder: 0.3,
+            delay: 0.3,
+            // Longer than the rpc timeouts so delayed requests arrive after
+            // their round was given up on.
+            max_delay: Duration::from_millis(500),
+        },
+        ..SimConfig::default()
+    })
+    .await;
+}
+
+#[tokio::test(start_paused = true)]
+async fn contending_proposers_converge() {
+    // Without faults every round that fails was preempted by another
+    // proposer's, every proposer finishing shows the backoff breaks the duel.
+    let config = SimConfig {
+        proposers: 5,
+        max_delay: Duration::from_millis(1),
+        drop_probability: 0.0,
+        crashes: 0,
+        ..SimConfig::default()
+    };
+
+    for seed in seeds() {
+        let report = sim::run(seed, &config).await.unwrap();
+        report.check().unwrap();
+        assert!(
+            report.outcomes.iter().all(Option::is_some),
+            "seed {seed}: a proposer never finished\n{report:#?}"
+        );
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn proposers_taking_turns_through_leases_agree() {
+    check_seeds(SimConfig {
+        proposers: 3,
+        lease: Some(LeaseConfig::new(Duration::from_millis(300))),
+        ..SimConfig::default()
+    })
+    .await;
+}
@@ -0,0 +1,67 @@
+//! Checks that nodes refuse acceptor lists that would break the quorum math.
+
+mod common;
+
+use common::data_dir;
+use single_decree_paxos::paxos::{self, Paxos, TopologyError};
+use std::net::SocketAddr;
+
+fn addr(i: u8) -> SocketAddr {
+    SocketAddr::from(([10, 0, 0, i], 8000))
+}
+
+async fn build(
+    name: &str,
+    acceptors: Vec<SocketAddr>,
+    quorum: Option<usize>,
+) -> Option<TopologyError> {
+    let mut builder = Paxos::builder(1, addr(1), acceptors).data_dir(data_dir(name));
+    if let Some(quorum) = quorum {
+        builder = builder.quorum(quorum);
+    }
+
+    match builder.build().await {
+        Ok(_) => None,
+        Err(err) => Some(
+            err.downcast::<TopologyError>()
+                .expect("expected a topology error"),
+        ),
+    }
+}
+
+#[tokio::test]
+async fn invalid_topologies_are_refused() {
+    assert_eq!(
+        build("no-acceptors", vec![], None).await,
+        Some(TopologyError::NoAcceptors)
+    );
+
+    assert_eq!(
+        build("duplicate", vec![addr(1), addr(2), addr(1)], None).await,
+        Some(TopologyError::DuplicateAcceptor(addr(1)))
+    );
+
+    assert_eq!(
+        build("quorum", vec![addr(1), addr(2), addr(3), addr(4)], Some(2)).await,
+        Some(TopologyError::InvalidQuorum {
+            quorum: 2,
+            acceptors: 4
+        })
+    );
+}
+
+#[test]
+fn acceptors_must_be_in_the_acceptor_list() {
+    let acceptors = [addr(1), addr(2), addr(3)];
+
+    assert_eq!(
+        paxos::check_topology(&acceptors, None, Some(addr(4))),
+        Err(TopologyError::NotAnAcceptor(addr(4)))
+    );
+    assert_eq!(
+        paxos::check_topology(&acceptors, None, Some(addr(2))),
+        Ok(())
+    );
+    // Proposers don't have to be acceptors.
+    assert_eq!(paxos::check_topology(&acceptors, None, None), Ok(()));
+}
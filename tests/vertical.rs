@@ -0,0 +1,137 @@
+//! Moves an instance between acceptors with ballots from a configuration master.
+
+mod common;
+
+use common::data_dir;
+use single_decree_paxos::{
+    channel::ChannelNetwork,
+    instance::InstanceId,
+    paxos::{Decided, Paxos},
+    transport::Connector,
+    vertical::{ConfigurationMaster, Master, VerticalProposer},
+};
+use std::{net::SocketAddr, path::PathBuf, sync::Arc};
+use tokio::{sync::Mutex, task::JoinHandle};
+
+struct Acceptors {
+    network: ChannelNetwork,
+    data_dir: PathBuf,
+    addrs: Vec<SocketAddr>,
+    servers: Vec<(Arc<Mutex<Paxos>>, JoinHandle<()>)>,
+}
+
+impl Acceptors {
+    async fn start(name: &str, count: u8) -> Self {
+        let data_dir = data_dir(name);
+
+        let network = ChannelNetwork::default();
+        let addrs: Vec<_> = (1..=count)
+            .map(|i| SocketAddr::from(([10, 0, 0, i], 8000)))
+            .collect();
+
+        let mut servers = Vec::new();
+        for (i, addr) in addrs.iter().enumerate() {
+            let paxos = Paxos::builder(i as u32 + 1, *addr, addrs.clone())
+                .connector(Connector::channels(network.clone()))
+                .data_dir(&data_dir)
+                .build()
+                .await
+                .unwrap();
+            let paxos = Arc::new(Mutex::new(paxos));
+            let server = network.serve(*addr, Arc::clone(&paxos));
+            servers.push((paxos, server));
+        }
+
+        Self {
+            network,
+            data_dir,
+            addrs,
+            servers,
+        }
+    }
+
+    fn proposer(&self, master: &ConfigurationMaster) -> VerticalProposer {
+        VerticalProposer::new(
+            Master::Local(master.clone()),
+            InstanceId::default(),
+            Connector::channels(self.network.clone()),
+        )
+    }
+
+    fn stop(&self, i: usize) {
+        self.network.close(self.addrs[i]);
+        self.servers[i].1.abort();
+    }
+
+    async fn accepted(&self, i: usize) -> Option<Vec<u8>> {
+        self.servers[i]
+            .0
+            .lock()
+            .await
+            .accepted_value()
+            .map(<[u8]>::to_vec)
+    }
+}
+
+impl Drop for Acceptors {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.data_dir);
+    }
+}
+
+#[tokio::test]
+async fn a_new_configuration_takes_over_the_chosen_value() {
+    let acceptors = Acceptors::start("takeover", 5).await;
+    let master = ConfigurationMaster::in_memory(acceptors.addrs[..3].to_vec());
+
+    let decided = acceptors
+        .proposer(&master)
+        .propose(b"first".to_vec())
+        .await
+        .unwrap();
+    assert_eq!(decided, Decided::Ours(b"first".to_vec()));
+
+    // Backups 3 and 4 replace 0 and 1 in the middle of the instance.
+    master.reconfigure(acceptors.addrs[2..].to_vec()).unwrap();
+    let decided = acceptors
+        .proposer(&master)
+        .propose(b"second".to_vec())
+        .await
+        .unwrap();
+    assert_eq!(decided, Decided::Other(b"first".to_vec()));
+
+    acceptors.stop(0);
+    acceptors.stop(1);
+    for i in 3..5 {
+        assert_eq!(acceptors.accepted(i).await, Some(b"first".to_vec()));
+    }
+
+    // The old acceptors are no longer needed.
+    let decided = acceptors
+        .proposer(&master)
+        .propose(b"third".to_vec())
+        .await
+        .unwrap();
+    assert_eq!(decided, Decided::Other(b"first".to_vec()));
+}
+
+#[tokio::test]
+async fn only_the_newest_ballot_completes() {
+    let master = ConfigurationMaster::in_memory(vec![SocketAddr::from(([10, 0, 0, 1], 8000))]);
+    let instance = InstanceId::default();
+
+    let old = master.new_ballot(&instance).unwrap();
+    let new = master.new_ballot(&instance).unwrap();
+    assert!(new.configuration.ballot > old.configuration.ballot);
+
+    assert!(!master
+        .complete(&instance, old.configuration.ballot)
+        .unwrap());
+    assert!(master
+        .complete(&instance, new.configuration.ballot)
+        .unwrap());
+    assert_eq!(
+        master.new_ballot(&instance).unwrap().previous,
+        Some(new.configuration)
+    );
+}